@@ -2,31 +2,335 @@
 //!
 //! The Broker is the central component that coordinates all operations.
 
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Utc};
 use flowq_storage::StorageEngine;
-use flowq_types::{Message, MessageId, Queue, QueueConfig, QueueStats, Result};
-use tracing::info;
+use flowq_types::{
+    Durability, Error, IdGenerator, Message, MessageId, MessagePage, MessageStateFilter, PopFilter,
+    Queue, QueueConfig, QueueSample, QueueSelector, QueueSnapshot, QueueStats, RandomIdGenerator,
+    ReconcileSnapshot, Result, SearchCriteria, Snapshot, BROKER_ATTRIBUTES,
+    DEFAULT_RESERVED_ATTRIBUTE_PREFIX, SNAPSHOT_VERSION,
+};
+use parking_lot::{Mutex, RwLock};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::dedup::DedupCache;
+use crate::group::ConsumerGroup;
+use crate::timeout::TimeoutStorage;
+
+/// Default interval between maintenance runs (expiry, timeouts, retries, dedup eviction)
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Interval between storage compaction runs, which are rarely needed
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Default limit on the length of a published attribute key, in bytes
+const MAX_ATTRIBUTE_KEY_LEN: usize = 256;
+
+/// Shortest interval between visibility extensions in `receive_with_keepalive`
+const MIN_KEEPALIVE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often `receive_wait` polls an empty queue
+const LONG_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Buffered snapshots per stats subscriber before ticks start waiting
+const STATS_CHANNEL_CAPACITY: usize = 64;
 
 /// Main message broker
 pub struct Broker {
     /// Storage backend
     storage: Arc<dyn StorageEngine>,
+    /// Create missing queues with default config on publish
+    auto_create_queues: bool,
+    /// Maximum number of queues (0 = unlimited)
+    max_queues: usize,
+    /// Queues being created, counted against `max_queues` until stored
+    reserved_queues: AtomicUsize,
+    /// Expired messages removed per maintenance pass (0 = unlimited)
+    max_cleanup_per_tick: usize,
+    /// Consumer groups by source queue name
+    groups: RwLock<HashMap<String, Vec<ConsumerGroup>>>,
+    /// Generates IDs for messages created through the broker
+    id_generator: Arc<dyn IdGenerator>,
+    /// Interval between maintenance runs
+    maintenance_interval: Duration,
+    /// Named queue config templates
+    templates: RwLock<HashMap<String, QueueConfig>>,
+    /// Signals fired when a queue is deleted, by queue name (only while someone waits)
+    deletion_signals: Mutex<HashMap<String, Arc<Notify>>>,
+    /// Reject every operation that changes queues or messages
+    read_only: bool,
+    /// Dedup IDs published to any queue, when global dedup is on
+    global_dedup: Option<Mutex<DedupCache>>,
+    /// Processing tokens of acked messages, when tracking is on
+    processed: Option<Mutex<DedupCache<()>>>,
+    /// Whether storage operations are bounded by `op_timeout`
+    op_timeout: bool,
+    /// When the broker was built
+    started_at: Instant,
+    /// Attribute key prefix producers may not use (empty = none)
+    reserved_attribute_prefix: String,
+    /// Longest attribute key accepted on publish, in bytes (0 = unlimited)
+    max_attribute_key_len: usize,
+}
+
+/// Builder for a [`Broker`] with non-default options
+///
+/// ```ignore
+/// let broker = Broker::builder(storage)
+///     .auto_create_queues(true)
+///     .max_queues(100)
+///     .build();
+/// ```
+pub struct BrokerBuilder {
+    storage: Arc<dyn StorageEngine>,
+    auto_create_queues: bool,
+    max_queues: usize,
+    max_cleanup_per_tick: usize,
+    id_generator: Arc<dyn IdGenerator>,
+    maintenance_interval: Duration,
+    op_timeout: Option<Duration>,
+    read_only: bool,
+    global_dedup: Option<(Duration, usize)>,
+    processed: Option<(Duration, usize)>,
+    reserved_attribute_prefix: String,
+    max_attribute_key_len: usize,
+}
+
+impl BrokerBuilder {
+    /// Start building a broker over the given storage, with default options
+    pub fn new(storage: Arc<dyn StorageEngine>) -> Self {
+        Self {
+            storage,
+            auto_create_queues: false,
+            max_queues: 0,
+            max_cleanup_per_tick: 0,
+            id_generator: Arc::new(RandomIdGenerator),
+            maintenance_interval: MAINTENANCE_INTERVAL,
+            op_timeout: None,
+            read_only: false,
+            global_dedup: None,
+            processed: None,
+            reserved_attribute_prefix: DEFAULT_RESERVED_ATTRIBUTE_PREFIX.to_string(),
+            max_attribute_key_len: MAX_ATTRIBUTE_KEY_LEN,
+        }
+    }
+
+    /// Create missing queues with default config when publishing to them
+    pub fn auto_create_queues(mut self, enabled: bool) -> Self {
+        self.auto_create_queues = enabled;
+        self
+    }
+
+    /// Limit the total number of queues (0 = unlimited)
+    pub fn max_queues(mut self, max_queues: usize) -> Self {
+        self.max_queues = max_queues;
+        self
+    }
+
+    /// Limit expired messages removed per maintenance pass (0 = unlimited)
+    ///
+    /// Keeps each pass short on huge queues; the rest is cleaned on later passes.
+    pub fn max_cleanup_per_tick(mut self, max: usize) -> Self {
+        self.max_cleanup_per_tick = max;
+        self
+    }
+
+    /// Use a custom generator for message IDs (UUIDv4 by default)
+    pub fn id_generator(mut self, generator: impl IdGenerator + 'static) -> Self {
+        self.id_generator = Arc::new(generator);
+        self
+    }
+
+    /// Interval between maintenance runs started by `start_maintenance` (default 60s)
+    pub fn maintenance_interval(mut self, interval: Duration) -> Self {
+        self.maintenance_interval = interval;
+        self
+    }
+
+    /// Fail storage calls taking longer than `timeout` with `Error::Timeout`
+    /// (no limit by default)
+    ///
+    /// Guards against a stalled backend hanging every request. The memory
+    /// backend never waits on I/O, so in practice it never times out.
+    pub fn op_timeout(mut self, timeout: Duration) -> Self {
+        self.op_timeout = Some(timeout);
+        self
+    }
+
+    /// Serve reads only, rejecting anything that changes queues or messages
+    ///
+    /// Meant for instances scaling out dashboards over a backend shared with
+    /// writable brokers. Receiving counts as a write, as it moves messages in
+    /// flight; peeks, stats and searches are allowed. Mutating calls fail with
+    /// `Error::ReadOnly` and maintenance is left to the writable brokers.
+    pub fn read_only(mut self, enabled: bool) -> Self {
+        self.read_only = enabled;
+        self
+    }
+
+    /// Deduplicate publishes by dedup ID across all queues (off by default)
+    ///
+    /// A message whose dedup ID was published to any queue within `window`
+    /// is dropped and the original message ID returned, as with per-queue
+    /// dedup. Checked before the queue's own dedup, whether or not the queue
+    /// enables it. At most `capacity` IDs are remembered; beyond that the
+    /// oldest are forgotten early.
+    pub fn global_dedup(mut self, window: Duration, capacity: usize) -> Self {
+        self.global_dedup = Some((window, capacity));
+        self
+    }
+
+    /// Remember the processing token of each message acked through the
+    /// broker for `window`, up to `capacity` tokens (off by default)
+    ///
+    /// Consumer group acks are not recorded, since every group processes its
+    /// own copy under the same token.
+    pub fn track_processed(mut self, window: Duration, capacity: usize) -> Self {
+        self.processed = Some((window, capacity));
+        self
+    }
+
+    /// Attribute key prefix reserved for broker metadata (default `x-flowq-`,
+    /// empty = none)
+    ///
+    /// Publishes with an attribute under the prefix, or one of the
+    /// attributes the broker sets itself, are rejected.
+    pub fn reserved_attribute_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.reserved_attribute_prefix = prefix.into();
+        self
+    }
+
+    /// Limit the length of published attribute keys, in bytes (default 256,
+    /// 0 = unlimited)
+    pub fn max_attribute_key_len(mut self, max: usize) -> Self {
+        self.max_attribute_key_len = max;
+        self
+    }
+
+    /// Build the broker
+    pub fn build(self) -> Broker {
+        info!("Initializing FlowQ broker");
+        let op_timeout = self.op_timeout.is_some();
+        let storage: Arc<dyn StorageEngine> = match self.op_timeout {
+            Some(timeout) => Arc::new(TimeoutStorage::new(self.storage, timeout)),
+            None => self.storage,
+        };
+        Broker {
+            storage,
+            auto_create_queues: self.auto_create_queues,
+            max_queues: self.max_queues,
+            reserved_queues: AtomicUsize::new(0),
+            max_cleanup_per_tick: self.max_cleanup_per_tick,
+            groups: RwLock::new(HashMap::new()),
+            id_generator: self.id_generator,
+            maintenance_interval: self.maintenance_interval,
+            templates: RwLock::new(HashMap::new()),
+            deletion_signals: Mutex::new(HashMap::new()),
+            read_only: self.read_only,
+            global_dedup: self
+                .global_dedup
+                .map(|(window, capacity)| Mutex::new(DedupCache::new(window, capacity))),
+            processed: self
+                .processed
+                .map(|(window, capacity)| Mutex::new(DedupCache::new(window, capacity))),
+            op_timeout,
+            started_at: Instant::now(),
+            reserved_attribute_prefix: self.reserved_attribute_prefix,
+            max_attribute_key_len: self.max_attribute_key_len,
+        }
+    }
 }
 
 impl Broker {
     /// Create a new broker with the given storage backend
     pub fn new(storage: impl StorageEngine + 'static) -> Self {
-        info!("Initializing FlowQ broker");
-        Self {
-            storage: Arc::new(storage),
-        }
+        Self::with_storage(Arc::new(storage))
     }
 
     /// Create a new broker with an Arc storage
     pub fn with_storage(storage: Arc<dyn StorageEngine>) -> Self {
-        info!("Initializing FlowQ broker");
-        Self { storage }
+        BrokerBuilder::new(storage).build()
+    }
+
+    /// Start building a broker with non-default options
+    pub fn builder(storage: Arc<dyn StorageEngine>) -> BrokerBuilder {
+        BrokerBuilder::new(storage)
+    }
+
+    /// Whether a message with this processing token was acked or marked
+    /// processed recently
+    ///
+    /// See `Message::processing_token`. Always false unless processed
+    /// tracking is on; tokens older than its window are forgotten.
+    pub fn was_processed(&self, token: &str) -> bool {
+        let now = tokio::time::Instant::now();
+        self.processed
+            .as_ref()
+            .is_some_and(|processed| processed.lock().contains(token, now))
+    }
+
+    /// Record a processing token as processed without acking
+    ///
+    /// Call this once a message's side effects are done and before acking,
+    /// so a redelivery after a failed or lost ack is still recognised by
+    /// `was_processed`. A no-op unless processed tracking is on.
+    pub fn mark_processed(&self, token: &str) {
+        if let Some(processed) = &self.processed {
+            let now = tokio::time::Instant::now();
+            processed.lock().record(token, (), now);
+        }
+    }
+
+    /// Whether mutating operations are rejected
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Reject producer attributes that are too long or could pass for broker
+    /// metadata
+    fn check_attributes(&self, message: &Message) -> Result<()> {
+        let keys = message
+            .attributes
+            .keys()
+            .chain(message.typed_attributes.keys());
+        for key in keys {
+            if self.max_attribute_key_len > 0 && key.len() > self.max_attribute_key_len {
+                return Err(Error::InvalidMessage(format!(
+                    "Attribute key of {} bytes exceeds the limit of {} bytes",
+                    key.len(),
+                    self.max_attribute_key_len
+                )));
+            }
+            let reserved = !self.reserved_attribute_prefix.is_empty()
+                && key.starts_with(&self.reserved_attribute_prefix);
+            if reserved || BROKER_ATTRIBUTES.contains(&key.as_str()) {
+                return Err(Error::InvalidMessage(format!(
+                    "Attribute {} is reserved for the broker",
+                    key
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail with `Error::ReadOnly` if this broker only serves reads
+    fn ensure_writable(&self, operation: &str) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly(operation.to_string()));
+        }
+        Ok(())
     }
 
     /// Get a reference to the storage engine
@@ -34,12 +338,31 @@ impl Broker {
         self.storage.as_ref()
     }
 
+    /// Time since the broker was built
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Names of the optional broker features turned on, for diagnostics
+    pub fn enabled_features(&self) -> Vec<&'static str> {
+        [
+            ("auto_create_queues", self.auto_create_queues),
+            ("read_only", self.read_only),
+            ("global_dedup", self.global_dedup.is_some()),
+            ("track_processed", self.processed.is_some()),
+            ("op_timeout", self.op_timeout),
+        ]
+        .into_iter()
+        .filter_map(|(name, enabled)| enabled.then_some(name))
+        .collect()
+    }
+
     // ==================== Queue Operations ====================
 
     /// Create a new queue with default configuration
     pub async fn create_queue(&self, name: impl Into<String>) -> Result<Queue> {
         let queue = Queue::new(name);
-        self.storage.create_queue(queue).await
+        self.insert_queue(queue).await
     }
 
     /// Create a new queue with custom configuration
@@ -48,10 +371,119 @@ impl Broker {
         name: impl Into<String>,
         config: QueueConfig,
     ) -> Result<Queue> {
+        config.validate()?;
+        let queue = Queue::with_config(name, config);
+        self.insert_queue(queue).await
+    }
+
+    /// Create a queue, also creating its `dead_letter_queue` if missing
+    ///
+    /// The dead letter queue gets the default config. Both are created in one
+    /// storage operation, so on backends that support it a failed call leaves
+    /// no half-made setup behind, and an existing dead letter queue, perhaps
+    /// shared with other queues, is never touched.
+    pub async fn create_queue_with_dlq(
+        &self,
+        name: impl Into<String>,
+        config: QueueConfig,
+    ) -> Result<Queue> {
+        let name = name.into();
+        config.validate()?;
+
+        let dlq = config.dead_letter_queue.clone().filter(|dlq| *dlq != name);
         let queue = Queue::with_config(name, config);
+        let Some(dlq) = dlq else {
+            return self.insert_queue(queue).await;
+        };
+        self.ensure_writable("create_queue")?;
+        validate_queue_name(&queue.name)?;
+        validate_queue_name(&dlq)?;
+        // Count the dead letter queue too when it will be created here
+        let creating = match self.max_queues {
+            0 => 0,
+            _ if self.storage.get_queue(&dlq).await?.is_some() => 1,
+            _ => 2,
+        };
+        let _slots = self.reserve_queue_slots(creating).await?;
+        self.storage
+            .create_queue_with_dlq(queue, Queue::new(dlq))
+            .await
+    }
+
+    /// Register (or replace) a named config template for creating queues
+    pub fn register_template(&self, name: impl Into<String>, config: QueueConfig) -> Result<()> {
+        config.validate()?;
+        self.templates.write().insert(name.into(), config);
+        Ok(())
+    }
+
+    /// Get a registered config template by name
+    pub fn template(&self, name: &str) -> Option<QueueConfig> {
+        self.templates.read().get(name).cloned()
+    }
+
+    /// Create a queue from a registered template, with optional overrides
+    ///
+    /// `overrides` is a partial `QueueConfig` JSON object; its fields replace
+    /// the template's.
+    pub async fn create_queue_from_template(
+        &self,
+        name: impl Into<String>,
+        template: &str,
+        overrides: Option<&serde_json::Value>,
+    ) -> Result<Queue> {
+        let config = self.template_config(template, overrides)?;
+        self.create_queue_with_config(name, config).await
+    }
+
+    /// Resolve a registered template and optional overrides into a config
+    pub fn template_config(
+        &self,
+        template: &str,
+        overrides: Option<&serde_json::Value>,
+    ) -> Result<QueueConfig> {
+        let base = self
+            .template(template)
+            .ok_or_else(|| Error::InvalidConfig(format!("Unknown queue template: {}", template)))?;
+        match overrides {
+            Some(overrides) => base.merged(overrides),
+            None => Ok(base),
+        }
+    }
+
+    /// Store a new queue, enforcing name rules and the broker-wide queue limit
+    async fn insert_queue(&self, queue: Queue) -> Result<Queue> {
+        self.ensure_writable("create_queue")?;
+        validate_queue_name(&queue.name)?;
+        let _slots = self.reserve_queue_slots(1).await?;
         self.storage.create_queue(queue).await
     }
 
+    /// Reserve room for `count` new queues under `max_queues`
+    ///
+    /// The slots count against the limit until the returned guard drops, so
+    /// concurrent creates cannot all pass the check and overshoot it. Near
+    /// the limit, a create racing another may be refused even though one
+    /// of them would have fit.
+    async fn reserve_queue_slots(&self, count: usize) -> Result<QueueSlots<'_>> {
+        if self.max_queues == 0 || count == 0 {
+            return Ok(QueueSlots {
+                reserved: &self.reserved_queues,
+                count: 0,
+            });
+        }
+
+        let reserved = self.reserved_queues.fetch_add(count, Ordering::SeqCst) + count;
+        let slots = QueueSlots {
+            reserved: &self.reserved_queues,
+            count,
+        };
+        if self.storage.queue_count().await? + reserved > self.max_queues {
+            return Err(Error::QueueLimitReached(self.max_queues));
+        }
+        Ok(slots)
+    }
+
     /// Get a queue by name
     pub async fn get_queue(&self, name: &str) -> Result<Option<Queue>> {
         self.storage.get_queue(name).await
@@ -62,9 +494,121 @@ impl Broker {
         self.storage.list_queues().await
     }
 
-    /// Delete a queue
+    /// List all queues together with their statistics
+    pub async fn list_queues_with_stats(&self) -> Result<Vec<(Queue, QueueStats)>> {
+        self.storage.list_queues_with_stats().await
+    }
+
+    /// Delete a queue along with its consumer groups
     pub async fn delete_queue(&self, name: &str) -> Result<()> {
-        self.storage.delete_queue(name).await
+        self.ensure_writable("delete_queue")?;
+        self.storage.delete_queue(name).await?;
+        self.release_deleted_queue(name).await
+    }
+
+    /// Delete empty queues idle for longer than their
+    /// `auto_delete_after_idle_secs`, returning how many were deleted
+    ///
+    /// Each deletion gets the same cleanup as `delete_queue`. Consumer group
+    /// backing queues are left to be deleted with their source queue.
+    pub async fn delete_idle_queues(&self) -> Result<u64> {
+        self.ensure_writable("delete_idle_queues")?;
+        let backing: HashSet<String> = self
+            .groups
+            .read()
+            .values()
+            .flatten()
+            .map(ConsumerGroup::backing_queue)
+            .collect();
+        let mut deleted = 0;
+        for name in self.storage.idle_queues().await? {
+            if backing.contains(&name) {
+                continue;
+            }
+            if self.storage.delete_idle_queue(&name).await? {
+                self.release_deleted_queue(&name).await?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Wake waiters on a just-deleted queue and delete its consumer groups
+    async fn release_deleted_queue(&self, name: &str) -> Result<()> {
+        // Wake receivers waiting on the queue so they can stop
+        if let Some(signal) = self.deletion_signals.lock().remove(name) {
+            signal.notify_waiters();
+        }
+
+        let groups = self.groups.write().remove(name).unwrap_or_default();
+        for group in groups {
+            match self.storage.delete_queue(&group.backing_queue()).await {
+                Ok(()) | Err(Error::QueueNotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rename a queue, keeping its messages
+    ///
+    /// Other queues' `dead_letter_queue`, `expiry_queue` and `retry_queue`
+    /// settings that name the old queue are not rewritten. Queues with
+    /// consumer groups cannot be renamed, as their backing queues are
+    /// derived from the name.
+    pub async fn rename_queue(&self, name: &str, new_name: &str) -> Result<Queue> {
+        self.ensure_writable("rename_queue")?;
+        validate_queue_name(new_name)?;
+        if self
+            .groups
+            .read()
+            .get(name)
+            .is_some_and(|groups| !groups.is_empty())
+        {
+            return Err(Error::InvalidConfig(format!(
+                "Queue {} has consumer groups and cannot be renamed",
+                name
+            )));
+        }
+        self.storage.rename_queue(name, new_name).await
+    }
+
+    /// Delete several queues, reporting the outcome for each
+    ///
+    /// One failure does not stop the rest. Consumer group backing queues are
+    /// removed with their source queue rather than matched by prefix.
+    pub async fn delete_queues(
+        &self,
+        selector: &QueueSelector,
+    ) -> Result<Vec<(String, Result<()>)>> {
+        self.ensure_writable("delete_queues")?;
+        let names = match selector {
+            QueueSelector::Names(names) => names.clone(),
+            QueueSelector::Prefix(prefix) => {
+                let backing: HashSet<String> = self
+                    .groups
+                    .read()
+                    .values()
+                    .flatten()
+                    .map(ConsumerGroup::backing_queue)
+                    .collect();
+                self.storage
+                    .list_queues()
+                    .await?
+                    .into_iter()
+                    .map(|queue| queue.name)
+                    .filter(|name| name.starts_with(prefix.as_str()) && !backing.contains(name))
+                    .collect()
+            }
+        };
+
+        let mut results = Vec::with_capacity(names.len());
+        for name in names {
+            let result = self.delete_queue(&name).await;
+            results.push((name, result));
+        }
+        Ok(results)
     }
 
     /// Get queue statistics
@@ -72,122 +616,2187 @@ impl Broker {
         self.storage.get_queue_stats(name).await
     }
 
+    /// Replace a queue's whole config after validating it
+    ///
+    /// Besides `QueueConfig::validate`, rejects configs routing messages back
+    /// to the queue itself, directly or through a chain of dead letter queues.
+    /// On any error the current config is left untouched.
+    pub async fn replace_queue_config(&self, name: &str, config: QueueConfig) -> Result<Queue> {
+        self.ensure_writable("replace_queue_config")?;
+        config.validate()?;
+        if self.storage.get_queue(name).await?.is_none() {
+            return Err(Error::QueueNotFound(name.to_string()));
+        }
+
+        let self_targets = [
+            ("expiry_queue", &config.expiry_queue),
+            ("retry_queue", &config.retry_queue),
+        ];
+        for (field, target) in self_targets {
+            if target.as_deref() == Some(name) {
+                return Err(Error::InvalidConfig(format!(
+                    "{} cannot be the queue itself",
+                    field
+                )));
+            }
+        }
+
+        // Follow the dead letter chain looking for a way back to this queue
+        let mut chain = vec![name.to_string()];
+        let mut next = config.dead_letter_queue.clone();
+        while let Some(dlq) = next {
+            if chain.contains(&dlq) {
+                chain.push(dlq);
+                return Err(Error::InvalidConfig(format!(
+                    "dead_letter_queue forms a cycle: {}",
+                    chain.join(" -> ")
+                )));
+            }
+            next = match self.storage.get_queue(&dlq).await? {
+                Some(queue) => queue.config.dead_letter_queue,
+                None => None,
+            };
+            chain.push(dlq);
+        }
+
+        self.storage.replace_queue_config(name, config).await
+    }
+
+    /// Reset a queue's rate counters without touching its messages
+    pub async fn reset_metrics(&self, name: &str) -> Result<()> {
+        self.ensure_writable("reset_metrics")?;
+        self.storage.reset_metrics(name).await
+    }
+
     /// Purge all messages from a queue
     pub async fn purge_queue(&self, name: &str) -> Result<u64> {
+        self.ensure_writable("purge_queue")?;
         self.storage.purge_queue(name).await
     }
 
+    /// Purge pending messages created before `cutoff`
+    pub async fn purge_older_than(&self, name: &str, cutoff: DateTime<Utc>) -> Result<u64> {
+        self.ensure_writable("purge")?;
+        self.storage.purge_older_than(name, cutoff).await
+    }
+
     // ==================== Message Operations ====================
 
     /// Publish a message to a queue
-    pub async fn publish(&self, queue_name: &str, message: Message) -> Result<MessageId> {
-        self.storage.push_message(queue_name, message).await
+    ///
+    /// With `Durability::Fsync` this returns only after the storage backend
+    /// has flushed the message.
+    ///
+    /// Each consumer group of the queue gets its own copy. The body is a
+    /// refcounted `Bytes` and the attributes sit behind an `Arc`, so all
+    /// copies share the publisher's buffers and fan-out costs only the
+    /// fixed-size per-copy metadata, whatever the body and attribute sizes.
+    pub async fn publish(
+        &self,
+        queue_name: &str,
+        message: Message,
+        durability: Durability,
+    ) -> Result<MessageId> {
+        self.ensure_writable("publish")?;
+        self.check_attributes(&message)?;
+        if self.auto_create_queues {
+            self.ensure_queue(queue_name).await?;
+        }
+
+        let global_dedup_id = match (&self.global_dedup, &message.dedup_id) {
+            (Some(cache), Some(dedup_id)) => {
+                let now = tokio::time::Instant::now();
+                if let Some(original) = cache.lock().check_and_record(dedup_id, &message.id, now) {
+                    debug!(
+                        queue = %queue_name,
+                        dedup_id = %dedup_id,
+                        message_id = %original,
+                        "Duplicate message dropped by global dedup"
+                    );
+                    return Ok(original);
+                }
+                Some(dedup_id.clone())
+            }
+            _ => None,
+        };
+        let message_id = message.id.clone();
+        let result = self.publish_recorded(queue_name, message, durability).await;
+        if let (Err(_), Some(dedup_id), Some(cache)) =
+            (&result, global_dedup_id, &self.global_dedup)
+        {
+            // Let a retry of the failed publish through
+            cache.lock().forget(&dedup_id, &message_id);
+        }
+        result
+    }
+
+    /// Store a message that passed global dedup, fanning it out to consumer groups
+    ///
+    /// Fails only if the queue itself rejects the message. Once it is stored
+    /// the publish has happened, so a group whose backing queue rejects its
+    /// copy is logged and skipped rather than failing (and inviting a retry
+    /// of) a message the queue and the other groups already hold.
+    async fn publish_recorded(
+        &self,
+        queue_name: &str,
+        message: Message,
+        durability: Durability,
+    ) -> Result<MessageId> {
+        // Fan out a copy to every consumer group of this queue; the last
+        // group takes the original instead of a clone
+        let group_queues = self.group_queues(queue_name);
+        let message_id = match group_queues.split_last() {
+            None => self.storage.push_message(queue_name, message).await?,
+            Some((last_queue, other_queues)) => {
+                let message_id = self
+                    .storage
+                    .push_message(queue_name, message.clone())
+                    .await?;
+                for backing_queue in other_queues {
+                    self.push_group_copy(queue_name, backing_queue, message.clone())
+                        .await;
+                }
+                self.push_group_copy(queue_name, last_queue, message).await;
+                message_id
+            }
+        };
+
+        if durability == Durability::Fsync {
+            self.storage.flush().await?;
+        }
+
+        Ok(message_id)
+    }
+
+    /// Push a consumer group's copy of a published message, logging a failure
+    async fn push_group_copy(&self, queue_name: &str, backing_queue: &str, message: Message) {
+        let message_id = message.id.clone();
+        if let Err(e) = self.storage.push_message(backing_queue, message).await {
+            warn!(
+                queue = %queue_name,
+                backing_queue = %backing_queue,
+                message_id = %message_id,
+                error = %e,
+                "Consumer group missed a published message"
+            );
+        }
+    }
+
+    /// Publish unless a message with the same ID is already pending or in flight
+    ///
+    /// Returns the ID and whether a new message was stored. Meant for
+    /// client-supplied IDs; concurrent publishes of one ID are not serialized.
+    pub async fn publish_if_absent(
+        &self,
+        queue_name: &str,
+        message: Message,
+        durability: Durability,
+    ) -> Result<(MessageId, bool)> {
+        self.ensure_writable("publish")?;
+        match self.storage.get_message(queue_name, &message.id).await {
+            Ok(Some(existing)) => return Ok((existing.id, false)),
+            Ok(None) => {}
+            Err(Error::QueueNotFound(_)) if self.auto_create_queues => {}
+            Err(e) => return Err(e),
+        }
+
+        let message_id = self.publish(queue_name, message, durability).await?;
+        Ok((message_id, true))
+    }
+
+    /// Create a queue with default config if it does not exist yet
+    async fn ensure_queue(&self, name: &str) -> Result<()> {
+        if self.storage.get_queue(name).await?.is_some() {
+            return Ok(());
+        }
+
+        match self.insert_queue(Queue::new(name)).await {
+            Ok(_) => {
+                info!(queue = %name, "Queue auto-created on publish");
+                Ok(())
+            }
+            // Another publisher created it concurrently
+            Err(Error::QueueAlreadyExists(_)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Create a message whose ID comes from the broker's ID generator
+    pub fn new_message(&self, body: impl Into<bytes::Bytes>) -> Message {
+        let mut message = Message::new(body);
+        message.id = self.id_generator.generate();
+        message
     }
 
     /// Publish raw bytes to a queue
+    ///
+    /// Bodies over the queue's `max_message_bytes` are rejected before a
+    /// message is built.
     pub async fn publish_bytes(
         &self,
         queue_name: &str,
         body: impl Into<bytes::Bytes>,
     ) -> Result<MessageId> {
-        let message = Message::new(body);
-        self.publish(queue_name, message).await
+        let max_bytes = self
+            .storage
+            .get_queue(queue_name)
+            .await?
+            .map_or(0, |queue| queue.config.max_message_bytes);
+        let mut message = Message::new_checked(body, max_bytes)?;
+        message.id = self.id_generator.generate();
+        self.publish(queue_name, message, Durability::None).await
     }
 
     /// Receive a single message from a queue
     pub async fn receive(&self, queue_name: &str) -> Result<Option<Message>> {
+        self.ensure_writable("receive")?;
         self.storage.pop_message(queue_name).await
     }
 
     /// Receive multiple messages from a queue
     pub async fn receive_batch(&self, queue_name: &str, max: usize) -> Result<Vec<Message>> {
+        self.ensure_writable("receive")?;
         self.storage.pop_messages(queue_name, max).await
     }
 
-    /// Peek at the next message without removing it
-    pub async fn peek(&self, queue_name: &str) -> Result<Option<Message>> {
-        self.storage.peek_message(queue_name).await
+    /// Receive up to `max` messages matching `filter`
+    ///
+    /// Messages the filter skips, e.g. lower priorities or messages published
+    /// after a `published_before` cutoff, stay queued for other consumers.
+    pub async fn receive_filtered(
+        &self,
+        queue_name: &str,
+        max: usize,
+        filter: &PopFilter,
+    ) -> Result<Vec<Message>> {
+        self.ensure_writable("receive")?;
+        self.storage
+            .pop_messages_filtered(queue_name, max, filter)
+            .await
     }
 
-    /// Acknowledge a message (mark as successfully processed)
-    pub async fn ack(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
-        self.storage.ack_message(queue_name, message_id).await
+    /// Acknowledge a message and receive the next batch in one call
+    ///
+    /// A failed ack (e.g. a stale ID) does not stop the receive; its error is
+    /// returned alongside the batch.
+    pub async fn ack_and_receive(
+        &self,
+        queue_name: &str,
+        ack_id: &MessageId,
+        max: usize,
+    ) -> Result<(Result<()>, Vec<Message>)> {
+        let acked = self.ack(queue_name, ack_id).await;
+        let messages = self.receive_batch(queue_name, max).await?;
+        Ok((acked, messages))
     }
 
-    /// Negative acknowledge (return to queue for retry)
-    pub async fn nack(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
-        self.storage.nack_message(queue_name, message_id).await
+    /// Receive up to `max` messages, waiting up to `wait` for the first to arrive
+    ///
+    /// Returns an empty batch if nothing arrives in time, and fails with
+    /// `QueueDeleted` if the queue is deleted while waiting.
+    pub async fn receive_wait(
+        &self,
+        queue_name: &str,
+        max: usize,
+        wait: Duration,
+    ) -> Result<Vec<Message>> {
+        self.receive_wait_filtered(queue_name, max, &PopFilter::default(), wait)
+            .await
     }
 
-    // ==================== Maintenance ====================
+    /// Like `receive_wait`, but only for messages matching `filter`
+    pub async fn receive_wait_filtered(
+        &self,
+        queue_name: &str,
+        max: usize,
+        filter: &PopFilter,
+        wait: Duration,
+    ) -> Result<Vec<Message>> {
+        let signal = Arc::clone(
+            self.deletion_signals
+                .lock()
+                .entry(queue_name.to_string())
+                .or_default(),
+        );
+        let result = self
+            .poll_until(queue_name, max, filter, wait, &signal)
+            .await;
 
-    /// Start background maintenance tasks
-    pub async fn start_maintenance(&self) {
-        let storage = Arc::clone(&self.storage);
+        // Drop the signal once the last waiter is done with it
+        drop(signal);
+        let mut signals = self.deletion_signals.lock();
+        if signals
+            .get(queue_name)
+            .is_some_and(|signal| Arc::strong_count(signal) == 1)
+        {
+            signals.remove(queue_name);
+        }
+        result
+    }
 
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+    /// Poll for messages until some arrive, `wait` passes or `deleted` fires
+    async fn poll_until(
+        &self,
+        queue_name: &str,
+        max: usize,
+        filter: &PopFilter,
+        wait: Duration,
+        deleted: &Notify,
+    ) -> Result<Vec<Message>> {
+        // Register before the first poll so a deletion in between is not missed
+        let notified = deleted.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
 
-            loop {
-                interval.tick().await;
-                if let Err(e) = storage.cleanup_expired().await {
-                    tracing::error!(error = %e, "Failed to cleanup expired messages");
-                }
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            let messages = self.receive_filtered(queue_name, max, filter).await?;
+            let now = tokio::time::Instant::now();
+            if !messages.is_empty() || now >= deadline {
+                return Ok(messages);
             }
-        });
 
-        info!("Background maintenance started");
+            tokio::select! {
+                _ = &mut notified => return Err(Error::QueueDeleted(queue_name.to_string())),
+                _ = tokio::time::sleep(LONG_POLL_INTERVAL.min(deadline - now)) => {}
+            }
+        }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use flowq_storage::MemoryStorage;
 
-    fn create_test_broker() -> Broker {
-        Broker::new(MemoryStorage::new())
+    /// Receive up to `max` messages on behalf of a consumer token
+    ///
+    /// With `reclaim`, a reconnecting consumer first gets back the messages it
+    /// still holds in flight instead of waiting for their visibility timeout.
+    pub async fn receive_as(
+        &self,
+        queue_name: &str,
+        max: usize,
+        consumer: &str,
+        reclaim: bool,
+    ) -> Result<Vec<Message>> {
+        self.ensure_writable("receive")?;
+        self.storage
+            .pop_messages_as(queue_name, max, consumer, reclaim)
+            .await
     }
 
-    #[tokio::test]
-    async fn test_create_queue() {
-        let broker = create_test_broker();
+    /// Receive one message and run `handler` on it, extending its visibility
+    /// while the handler runs
+    ///
+    /// The visibility deadline is pushed out every half visibility timeout so
+    /// slow handlers are not redelivered to other consumers. The message is
+    /// acked if the handler succeeds and nacked if it fails. Returns `None`
+    /// when the queue is empty, otherwise the handler's result.
+    pub async fn receive_with_keepalive<F, Fut, T, E>(
+        &self,
+        queue_name: &str,
+        handler: F,
+    ) -> Result<Option<std::result::Result<T, E>>>
+    where
+        F: FnOnce(Message) -> Fut,
+        Fut: Future<Output = std::result::Result<T, E>>,
+    {
+        let timeout_secs = self
+            .get_queue(queue_name)
+            .await?
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?
+            .config
+            .visibility_timeout_secs;
 
-        let queue = broker.create_queue("test-queue").await.unwrap();
-        assert_eq!(queue.name, "test-queue");
+        let Some(message) = self.receive(queue_name).await? else {
+            return Ok(None);
+        };
+        let message_id = message.id.clone();
 
-        let queues = broker.list_queues().await.unwrap();
-        assert_eq!(queues.len(), 1);
+        let interval = (Duration::from_secs(timeout_secs) / 2).max(MIN_KEEPALIVE_INTERVAL);
+        let start = tokio::time::Instant::now() + interval;
+        let mut keepalive = tokio::time::interval_at(start, interval);
+        let handler = handler(message);
+        tokio::pin!(handler);
+
+        let outcome = loop {
+            tokio::select! {
+                outcome = &mut handler => break outcome,
+                _ = keepalive.tick() => {
+                    if let Err(e) = self
+                        .storage
+                        .change_visibility(queue_name, &message_id, timeout_secs)
+                        .await
+                    {
+                        warn!(
+                            queue = %queue_name,
+                            message_id = %message_id,
+                            error = %e,
+                            "Failed to extend message visibility"
+                        );
+                    }
+                }
+            }
+        };
+
+        match &outcome {
+            Ok(_) => self.ack(queue_name, &message_id).await?,
+            Err(_) => self.nack(queue_name, &message_id).await?,
+        }
+        Ok(Some(outcome))
     }
 
-    #[tokio::test]
-    async fn test_publish_and_receive() {
-        let broker = create_test_broker();
-        broker.create_queue("test").await.unwrap();
+    /// Peek at the next message without removing it
+    pub async fn peek(&self, queue_name: &str) -> Result<Option<Message>> {
+        self.storage.peek_message(queue_name).await
+    }
 
-        // Publish
-        let msg_id = broker.publish_bytes("test", "Hello!").await.unwrap();
+    /// Peek at a page of up to `limit` pending messages in arrival order
+    ///
+    /// Pass the returned `next_cursor` to get the following page; pages stay
+    /// consistent while the head of the queue is being consumed.
+    pub async fn peek_page(
+        &self,
+        queue_name: &str,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> Result<MessagePage> {
+        self.storage.peek_page(queue_name, cursor, limit).await
+    }
 
-        // Receive
-        let received = broker.receive("test").await.unwrap();
-        assert!(received.is_some());
+    /// Peek at up to `max` messages in the queue's dead letter queue
+    ///
+    /// Fails with `InvalidConfig` if the queue has no dead letter queue.
+    pub async fn peek_dlq(&self, queue_name: &str, max: usize) -> Result<Vec<Message>> {
+        let queue = self
+            .storage
+            .get_queue(queue_name)
+            .await?
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+        let dlq = queue.config.dead_letter_queue.ok_or_else(|| {
+            Error::InvalidConfig(format!("Queue {} has no dead letter queue", queue_name))
+        })?;
+        self.storage.peek_messages(&dlq, max).await
+    }
 
-        let msg = received.unwrap();
-        assert_eq!(msg.id, msg_id);
-        assert_eq!(msg.body_as_str(), Some("Hello!"));
+    /// Gather counts and up to `samples` message IDs from a queue and its
+    /// dead letter queue
+    ///
+    /// The two queues are read one after the other, so messages moving to the
+    /// dead letter queue meanwhile may show up in both or neither. Fails with
+    /// `InvalidConfig` if the queue has no dead letter queue.
+    pub async fn reconcile(&self, queue_name: &str, samples: usize) -> Result<ReconcileSnapshot> {
+        let queue = self
+            .storage
+            .get_queue(queue_name)
+            .await?
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+        let dlq = queue.config.dead_letter_queue.ok_or_else(|| {
+            Error::InvalidConfig(format!("Queue {} has no dead letter queue", queue_name))
+        })?;
 
-        // Ack
-        broker.ack("test", &msg.id).await.unwrap();
+        let sample = |name: &str, filter: MessageStateFilter| {
+            let name = name.to_string();
+            async move {
+                let stats = self.storage.get_queue_stats(&name).await?;
+                let sample_ids = self
+                    .storage
+                    .list_message_ids(&name, filter, 0, samples)
+                    .await?;
+                Ok::<_, Error>(QueueSample {
+                    pending: stats.pending_count,
+                    in_flight: stats.in_flight_count,
+                    sample_ids,
+                })
+            }
+        };
+        Ok(ReconcileSnapshot {
+            main: sample(queue_name, MessageStateFilter::All).await?,
+            dlq: sample(&dlq, MessageStateFilter::Pending).await?,
+        })
+    }
 
-        // Queue should be empty
-        let stats = broker.get_queue_stats("test").await.unwrap();
-        assert_eq!(stats.message_count, 0);
+    /// Look up a pending or in-flight message by ID without consuming it
+    pub async fn get_message(
+        &self,
+        queue_name: &str,
+        message_id: &MessageId,
+    ) -> Result<Option<Message>> {
+        self.storage.get_message(queue_name, message_id).await
     }
 
-    #[tokio::test]
-    async fn test_receive_batch() {
-        let broker = create_test_broker();
-        broker.create_queue("test").await.unwrap();
+    /// Acknowledge a message (mark as successfully processed)
+    pub async fn ack(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
+        self.ensure_writable("ack")?;
+        self.storage.ack_message(queue_name, message_id).await?;
+        self.mark_processed(&message_id.to_string());
+        Ok(())
+    }
+
+    /// Negative acknowledge (return to queue for retry)
+    pub async fn nack(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
+        self.ensure_writable("nack")?;
+        self.storage.nack_message(queue_name, message_id).await
+    }
+
+    /// Negative acknowledge, requeueing the message with a new priority
+    /// (clamped to 1 through the queue's `max_priority`)
+    pub async fn nack_with_priority(
+        &self,
+        queue_name: &str,
+        message_id: &MessageId,
+        priority: u8,
+    ) -> Result<()> {
+        self.ensure_writable("nack")?;
+        self.storage
+            .nack_with_priority(queue_name, message_id, priority)
+            .await
+    }
+
+    /// Move an in-flight message to the queue's dead letter queue
+    pub async fn move_to_dlq(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
+        self.ensure_writable("move_to_dlq")?;
+        self.storage.move_to_dlq(queue_name, message_id).await
+    }
+
+    /// List messages quarantined after repeated failures
+    pub async fn list_quarantined(&self, queue_name: &str) -> Result<Vec<Message>> {
+        self.storage.list_quarantined(queue_name).await
+    }
+
+    /// List acked messages retained for `acked_retention_secs`
+    pub async fn list_acked(&self, queue_name: &str) -> Result<Vec<Message>> {
+        self.storage.list_acked(queue_name).await
+    }
+
+    /// Release quarantined messages back to the queue
+    pub async fn release_quarantined(&self, queue_name: &str) -> Result<u64> {
+        self.ensure_writable("release_quarantined")?;
+        self.storage.release_quarantined(queue_name).await
+    }
+
+    /// Reset a message's delivery count so it gets fresh retries
+    pub async fn reset_delivery_count(
+        &self,
+        queue_name: &str,
+        message_id: &MessageId,
+    ) -> Result<()> {
+        self.ensure_writable("reset_delivery_count")?;
+        self.storage
+            .reset_delivery_count(queue_name, message_id)
+            .await
+    }
+
+    /// Move an in-flight message's visibility deadline to `timeout_secs` from now
+    pub async fn change_visibility(
+        &self,
+        queue_name: &str,
+        message_id: &MessageId,
+        timeout_secs: u64,
+    ) -> Result<DateTime<Utc>> {
+        self.ensure_writable("change_visibility")?;
+        self.storage
+            .change_visibility(queue_name, message_id, timeout_secs)
+            .await
+    }
+
+    /// Search pending and in-flight messages without consuming them
+    pub async fn search(
+        &self,
+        queue_name: &str,
+        criteria: &SearchCriteria,
+    ) -> Result<Vec<Message>> {
+        self.storage.search_messages(queue_name, criteria).await
+    }
+
+    /// Count pending and in-flight messages matching the criteria without
+    /// transferring them; `offset` and `limit` are ignored
+    pub async fn count_messages(&self, queue_name: &str, criteria: &SearchCriteria) -> Result<u64> {
+        self.storage.count_messages(queue_name, criteria).await
+    }
+
+    /// List message IDs without bodies, paginated
+    pub async fn list_message_ids(
+        &self,
+        queue_name: &str,
+        filter: MessageStateFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<MessageId>> {
+        self.storage
+            .list_message_ids(queue_name, filter, offset, limit)
+            .await
+    }
+
+    // ==================== Consumer Groups ====================
+
+    /// Subscribe a consumer group to a queue
+    ///
+    /// The group receives its own copy of every message published after it
+    /// subscribed. Subscribing an existing group is a no-op. The backing queue
+    /// takes the source's config without its delivery webhook, so copies go to
+    /// the group's consumers rather than being POSTed and acked on their behalf.
+    pub async fn subscribe_group(&self, queue_name: &str, group_id: &str) -> Result<ConsumerGroup> {
+        self.ensure_writable("subscribe_group")?;
+        let queue = self
+            .storage
+            .get_queue(queue_name)
+            .await?
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        let group = ConsumerGroup::new(queue_name, group_id);
+        let config = QueueConfig {
+            delivery_webhook: None,
+            ..queue.config
+        };
+        let backing = Queue::with_config(group.backing_queue(), config);
+        match self.insert_queue(backing).await {
+            Ok(_) | Err(Error::QueueAlreadyExists(_)) => {}
+            Err(e) => return Err(e),
+        }
+
+        let mut groups = self.groups.write();
+        let queue_groups = groups.entry(queue_name.to_string()).or_default();
+        if !queue_groups.contains(&group) {
+            queue_groups.push(group.clone());
+            info!(queue = %queue_name, group = %group_id, "Consumer group subscribed");
+        }
+
+        Ok(group)
+    }
+
+    /// Unsubscribe a consumer group and drop its pending copies
+    pub async fn unsubscribe_group(&self, group: &ConsumerGroup) -> Result<()> {
+        self.ensure_writable("unsubscribe_group")?;
+        if let Some(queue_groups) = self.groups.write().get_mut(group.queue()) {
+            queue_groups.retain(|g| g != group);
+        }
+        self.storage.delete_queue(&group.backing_queue()).await
+    }
+
+    /// Receive up to `max` messages for a consumer group
+    pub async fn receive_group(&self, group: &ConsumerGroup, max: usize) -> Result<Vec<Message>> {
+        self.ensure_writable("receive")?;
+        self.storage.pop_messages(&group.backing_queue(), max).await
+    }
+
+    /// Acknowledge a message for a consumer group
+    pub async fn ack_group(&self, group: &ConsumerGroup, message_id: &MessageId) -> Result<()> {
+        self.ensure_writable("ack")?;
+        self.storage
+            .ack_message(&group.backing_queue(), message_id)
+            .await
+    }
+
+    /// Negative acknowledge a message for a consumer group
+    pub async fn nack_group(&self, group: &ConsumerGroup, message_id: &MessageId) -> Result<()> {
+        self.ensure_writable("nack")?;
+        self.storage
+            .nack_message(&group.backing_queue(), message_id)
+            .await
+    }
+
+    /// Backing queue names of every group subscribed to a queue
+    fn group_queues(&self, queue_name: &str) -> Vec<String> {
+        self.groups
+            .read()
+            .get(queue_name)
+            .map(|groups| groups.iter().map(ConsumerGroup::backing_queue).collect())
+            .unwrap_or_default()
+    }
+
+    // ==================== Snapshots ====================
+
+    /// Stream a versioned snapshot of every queue and its pending, in-flight
+    /// and quarantined messages
+    ///
+    /// Queues are exported and written one at a time, so only the largest
+    /// queue is held in memory. Consumer group subscriptions are not
+    /// included; their backing queues are restored as plain queues.
+    pub async fn snapshot<W: AsyncWrite + Unpin + Send>(&self, mut writer: W) -> Result<()> {
+        let queues = self.storage.list_queues().await?;
+        let header = format!(
+            r#"{{"version":{},"created_at":{},"queues":["#,
+            SNAPSHOT_VERSION,
+            serde_json::to_string(&Utc::now())?
+        );
+        write_snapshot(&mut writer, header.as_bytes()).await?;
+
+        for (index, queue) in queues.iter().enumerate() {
+            let mut messages = self.storage.export_messages(&queue.name).await?;
+            messages.extend(self.storage.list_quarantined(&queue.name).await?);
+            let queue_snapshot = QueueSnapshot::new(queue.clone(), messages);
+
+            if index > 0 {
+                write_snapshot(&mut writer, b",").await?;
+            }
+            write_snapshot(&mut writer, &serde_json::to_vec(&queue_snapshot)?).await?;
+        }
+
+        write_snapshot(&mut writer, b"]}").await?;
+        writer
+            .flush()
+            .await
+            .map_err(|e| Error::Snapshot(format!("write failed: {}", e)))?;
+
+        info!(queues = queues.len(), "Broker snapshot written");
+        Ok(())
+    }
+
+    /// Restore queues and messages from a snapshot into an empty broker
+    ///
+    /// Messages keep their stored fields (sequence, expiry, dedup ID); nothing
+    /// is re-applied as on publish. In-flight messages are restored as
+    /// pending and quarantined messages go back into quarantine. Queues are
+    /// created as by `create_queue`, so names are checked and `max_queues`
+    /// applies, and with global dedup on, restored dedup IDs start a fresh
+    /// window.
+    pub async fn restore<R: Read + Send>(&self, reader: R) -> Result<()> {
+        self.ensure_writable("restore")?;
+        // Check the envelope version before interpreting the rest of the format
+        let value: serde_json::Value = serde_json::from_reader(reader)?;
+        let version = value.get("version").and_then(serde_json::Value::as_u64);
+        if version != Some(u64::from(SNAPSHOT_VERSION)) {
+            return Err(Error::Snapshot(format!(
+                "unsupported snapshot version {:?}, expected {}",
+                version, SNAPSHOT_VERSION
+            )));
+        }
+        let snapshot: Snapshot = serde_json::from_value(value)?;
+
+        if self.storage.queue_count().await? > 0 {
+            return Err(Error::Snapshot(
+                "restore requires a broker without queues".to_string(),
+            ));
+        }
+        // Refuse up front rather than part way through
+        if self.max_queues > 0 && snapshot.queues.len() > self.max_queues {
+            return Err(Error::QueueLimitReached(self.max_queues));
+        }
+
+        let now = tokio::time::Instant::now();
+        for queue_snapshot in snapshot.queues {
+            let name = queue_snapshot.queue.name.clone();
+            self.insert_queue(queue_snapshot.queue).await?;
+
+            for snapshot_message in queue_snapshot.messages {
+                let message = snapshot_message.into_message()?;
+                if let (Some(cache), Some(dedup_id)) = (&self.global_dedup, &message.dedup_id) {
+                    cache.lock().record(dedup_id, message.id.clone(), now);
+                }
+                self.storage.restore_message(&name, message).await?;
+            }
+        }
+
+        info!("Broker restored from snapshot");
+        Ok(())
+    }
+
+    // ==================== Maintenance ====================
+
+    /// Remove every expired message now instead of on the next maintenance
+    /// pass, returning how many were removed
+    ///
+    /// Ignores `max_cleanup_per_tick`.
+    pub async fn cleanup_expired(&self) -> Result<u64> {
+        self.ensure_writable("cleanup_expired")?;
+        self.storage.cleanup_expired(0).await
+    }
+
+    /// Start background maintenance tasks
+    ///
+    /// The tasks run until `MaintenanceHandle::stop` is called or the broker
+    /// is dropped; dropping the handle leaves them running. Does nothing on a
+    /// read-only broker.
+    pub async fn start_maintenance(self: &Arc<Self>) -> MaintenanceHandle {
+        let cancel = CancellationToken::new();
+        if self.read_only {
+            info!("Read-only broker, background maintenance not started");
+            return MaintenanceHandle {
+                cancel,
+                tasks: Vec::new(),
+            };
+        }
+
+        let broker = Arc::downgrade(self);
+        let max_cleanup = self.max_cleanup_per_tick;
+
+        let maintenance = spawn_periodic(self.maintenance_interval, cancel.clone(), move || {
+            let broker = broker.upgrade();
+            async move {
+                let Some(broker) = broker else {
+                    return false;
+                };
+                let storage = &broker.storage;
+                if let Err(e) = storage.cleanup_expired(max_cleanup).await {
+                    tracing::error!(error = %e, "Failed to cleanup expired messages");
+                }
+                if let Err(e) = storage.dead_letter_overdue().await {
+                    tracing::error!(error = %e, "Failed to dead-letter overdue messages");
+                }
+                if let Err(e) = storage.requeue_timed_out().await {
+                    tracing::error!(error = %e, "Failed to requeue timed-out messages");
+                }
+                if let Err(e) = storage.promote_retries().await {
+                    tracing::error!(error = %e, "Failed to promote retried messages");
+                }
+                if let Err(e) = storage.cleanup_dedup().await {
+                    tracing::error!(error = %e, "Failed to evict expired dedup IDs");
+                }
+                if let Err(e) = storage.prune_acked().await {
+                    tracing::error!(error = %e, "Failed to prune acked messages");
+                }
+                if let Err(e) = broker.delete_idle_queues().await {
+                    tracing::error!(error = %e, "Failed to delete idle queues");
+                }
+                true
+            }
+        });
+
+        let broker = Arc::downgrade(self);
+        let compaction = spawn_periodic(COMPACTION_INTERVAL, cancel.clone(), move || {
+            let broker = broker.upgrade();
+            async move {
+                let Some(broker) = broker else {
+                    return false;
+                };
+                if let Err(e) = broker.storage.compact().await {
+                    tracing::error!(error = %e, "Failed to compact storage");
+                }
+                true
+            }
+        });
+
+        info!("Background maintenance started");
+        MaintenanceHandle {
+            cancel,
+            tasks: vec![maintenance, compaction],
+        }
+    }
+
+    /// Return every in-flight message to pending, for graceful shutdown
+    ///
+    /// With a shared backend, another broker can then deliver the messages
+    /// right away instead of after their visibility timeouts. A read-only
+    /// broker has delivered nothing, so it leaves other brokers' deliveries
+    /// alone and returns 0.
+    pub async fn requeue_all_inflight(&self) -> Result<u64> {
+        if self.read_only {
+            return Ok(0);
+        }
+        self.storage.requeue_all_inflight().await
+    }
+
+    /// Stream `(queue name, stats)` snapshots for every queue each `period`
+    ///
+    /// The background work stops once the returned stream is dropped.
+    /// Must be called from within a Tokio runtime.
+    pub fn stats_stream(&self, period: Duration) -> impl Stream<Item = (String, QueueStats)> {
+        let (tx, rx) = mpsc::channel(STATS_CHANNEL_CAPACITY);
+        let storage = Arc::clone(&self.storage);
+
+        spawn_periodic(period, CancellationToken::new(), move || {
+            let storage = Arc::clone(&storage);
+            let tx = tx.clone();
+            async move {
+                let queues = match storage.list_queues().await {
+                    Ok(queues) => queues,
+                    Err(e) => {
+                        tracing::error!(error = %e, "Failed to list queues for stats snapshot");
+                        return !tx.is_closed();
+                    }
+                };
+
+                for queue in queues {
+                    // The queue may have been deleted since it was listed
+                    let Ok(stats) = storage.get_queue_stats(&queue.name).await else {
+                        continue;
+                    };
+                    if tx.send((queue.name, stats)).await.is_err() {
+                        return false;
+                    }
+                }
+
+                !tx.is_closed()
+            }
+        });
+
+        ReceiverStream::new(rx)
+    }
+}
+
+/// Write one chunk of a streamed snapshot
+async fn write_snapshot<W: AsyncWrite + Unpin>(writer: &mut W, bytes: &[u8]) -> Result<()> {
+    writer
+        .write_all(bytes)
+        .await
+        .map_err(|e| Error::Snapshot(format!("write failed: {}", e)))
+}
+
+/// Check a name given to a new or renamed queue
+///
+/// Names must be non-empty and free of whitespace, control characters and
+/// `/`, which would not survive the HTTP path.
+fn validate_queue_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(Error::InvalidConfig(
+            "Queue name must not be empty".to_string(),
+        ));
+    }
+    if name
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control() || c == '/')
+    {
+        return Err(Error::InvalidConfig(format!(
+            "Invalid queue name: {:?}",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Queue slots reserved by `Broker::reserve_queue_slots`, released on drop
+struct QueueSlots<'a> {
+    reserved: &'a AtomicUsize,
+    count: usize,
+}
+
+impl Drop for QueueSlots<'_> {
+    fn drop(&mut self) {
+        self.reserved.fetch_sub(self.count, Ordering::SeqCst);
+    }
+}
+
+/// Background maintenance tasks started by `Broker::start_maintenance`
+pub struct MaintenanceHandle {
+    cancel: CancellationToken,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl MaintenanceHandle {
+    /// Signal the tasks to exit and wait until they have
+    ///
+    /// A maintenance pass already running is finished first.
+    pub async fn stop(self) {
+        self.cancel.cancel();
+        for task in self.tasks {
+            if let Err(e) = task.await {
+                warn!(error = %e, "Maintenance task failed");
+            }
+        }
+        info!("Background maintenance stopped");
+    }
+
+    /// Whether every task has exited
+    pub fn is_finished(&self) -> bool {
+        self.tasks.iter().all(JoinHandle::is_finished)
+    }
+}
+
+/// Run `job` every `period` on a background task until it returns `false`
+/// or `cancel` is cancelled
+fn spawn_periodic<F, Fut>(period: Duration, cancel: CancellationToken, mut job: F) -> JoinHandle<()>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = bool> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            if !job().await {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowq_storage::MemoryStorage;
+    use flowq_types::{MessageStatus, TimeOrderedIdGenerator};
+    use tokio_stream::StreamExt;
+
+    fn create_test_broker() -> Broker {
+        Broker::new(MemoryStorage::new())
+    }
+
+    #[tokio::test]
+    async fn test_create_queue() {
+        let broker = create_test_broker();
+
+        let queue = broker.create_queue("test-queue").await.unwrap();
+        assert_eq!(queue.name, "test-queue");
+
+        let queues = broker.list_queues().await.unwrap();
+        assert_eq!(queues.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_queues_limit() {
+        let broker = Broker::builder(Arc::new(MemoryStorage::new()))
+            .max_queues(2)
+            .build();
+
+        broker.create_queue("a").await.unwrap();
+        broker.create_queue("b").await.unwrap();
+
+        let result = broker.create_queue("c").await;
+        assert!(matches!(result, Err(Error::QueueLimitReached(2))));
+
+        // Deleting a queue frees up a slot
+        broker.delete_queue("a").await.unwrap();
+        broker.create_queue("c").await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_max_queues_limit_under_concurrent_creates() {
+        let broker = Arc::new(
+            Broker::builder(Arc::new(MemoryStorage::new()))
+                .max_queues(3)
+                .build(),
+        );
+
+        let creates: Vec<_> = (0..32)
+            .map(|i| {
+                let broker = Arc::clone(&broker);
+                tokio::spawn(async move { broker.create_queue(format!("q{}", i)).await })
+            })
+            .collect();
+        for create in creates {
+            let _ = create.await.unwrap();
+        }
+        let created = broker.list_queues().await.unwrap().len();
+        assert!(created <= 3);
+
+        // Every reservation was released again
+        for i in created..3 {
+            broker.create_queue(format!("extra{}", i)).await.unwrap();
+        }
+        let result = broker.create_queue("over").await;
+        assert!(matches!(result, Err(Error::QueueLimitReached(3))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_builder_options() {
+        let broker = Broker::builder(Arc::new(MemoryStorage::new()))
+            .auto_create_queues(true)
+            .max_queues(2)
+            .id_generator(TimeOrderedIdGenerator::default())
+            .maintenance_interval(Duration::from_millis(20))
+            .build();
+
+        // Publishing auto-creates the queue, IDs come from the custom generator
+        let first = broker.publish_bytes("orders", "a").await.unwrap();
+        let second = broker.publish_bytes("orders", "b").await.unwrap();
+        assert!(first < second);
+
+        let config = QueueConfig {
+            visibility_timeout_secs: 0,
+            ..Default::default()
+        };
+        broker
+            .create_queue_with_config("fast", config)
+            .await
+            .unwrap();
+        let result = broker.create_queue("events").await;
+        assert!(matches!(result, Err(Error::QueueLimitReached(2))));
+
+        // Maintenance runs on the configured interval and requeues the timed-out message
+        broker.publish_bytes("fast", "c").await.unwrap();
+        broker.receive("fast").await.unwrap().unwrap();
+        let broker = Arc::new(broker);
+        broker.start_maintenance().await;
+        // With the clock paused this returns once the runtime is idle, after
+        // the first pass, without waiting in real time
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(
+            broker.get_queue_stats("fast").await.unwrap().pending_count,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stop_maintenance() {
+        let broker = Arc::new(create_test_broker());
+        let maintenance = broker.start_maintenance().await;
+        assert!(!maintenance.is_finished());
+
+        // Stopping wakes the tasks between passes instead of waiting out the interval
+        tokio::time::timeout(Duration::from_secs(5), maintenance.stop())
+            .await
+            .expect("maintenance tasks did not exit");
+
+        let broker = Arc::new(
+            Broker::builder(Arc::new(MemoryStorage::new()))
+                .read_only(true)
+                .build(),
+        );
+        let maintenance = broker.start_maintenance().await;
+        assert!(maintenance.is_finished());
+        maintenance.stop().await;
+
+        // Dropping the broker ends the tasks
+        let broker = Broker::builder(Arc::new(MemoryStorage::new()))
+            .maintenance_interval(Duration::from_millis(10))
+            .build();
+        let maintenance = Arc::new(broker).start_maintenance().await;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !maintenance.is_finished() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("maintenance tasks outlived the broker");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_queue_deletion_removes_groups() {
+        let broker = create_test_broker();
+        let config = QueueConfig {
+            auto_delete_after_idle_secs: 1,
+            ..Default::default()
+        };
+        broker
+            .create_queue_with_config("idle", config)
+            .await
+            .unwrap();
+        let group = broker.subscribe_group("idle", "audit").await.unwrap();
+        assert_eq!(broker.delete_idle_queues().await.unwrap(), 0);
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(broker.delete_idle_queues().await.unwrap(), 1);
+        assert!(broker.get_queue("idle").await.unwrap().is_none());
+        assert!(broker
+            .get_queue(&group.backing_queue())
+            .await
+            .unwrap()
+            .is_none());
+        // The name is free for a fresh queue without stale groups
+        broker.create_queue("idle").await.unwrap();
+        broker.publish_bytes("idle", "x").await.unwrap();
+        assert!(broker.receive_group(&group, 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_create_queue_with_dlq() {
+        let broker = create_test_broker();
+        let config = QueueConfig {
+            dead_letter_queue: Some("orders-dlq".to_string()),
+            ..Default::default()
+        };
+        let queue = broker
+            .create_queue_with_dlq("orders", config.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            queue.config.dead_letter_queue.as_deref(),
+            Some("orders-dlq")
+        );
+        let dlq = broker.get_queue("orders-dlq").await.unwrap().unwrap();
+        assert!(dlq.config.dead_letter_queue.is_none());
+
+        // An existing dead letter queue is shared, not replaced
+        broker
+            .create_queue_with_dlq("payments", config.clone())
+            .await
+            .unwrap();
+        assert_eq!(broker.list_queues().await.unwrap().len(), 3);
+
+        // A failed create makes no dead letter queue
+        let events = QueueConfig {
+            dead_letter_queue: Some("events-dlq".to_string()),
+            ..Default::default()
+        };
+        let result = broker.create_queue_with_dlq("orders", events).await;
+        assert!(matches!(result, Err(Error::QueueAlreadyExists(_))));
+        assert!(broker.get_queue("events-dlq").await.unwrap().is_none());
+
+        // Nor does it touch a dead letter queue other queues rely on
+        let result = broker.create_queue_with_dlq("payments", config).await;
+        assert!(matches!(result, Err(Error::QueueAlreadyExists(_))));
+        assert!(broker.get_queue("orders-dlq").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_create_queue_with_dlq_counts_both_queues() {
+        let broker = Broker::builder(Arc::new(MemoryStorage::new()))
+            .max_queues(2)
+            .build();
+        broker.create_queue("events").await.unwrap();
+        let config = QueueConfig {
+            dead_letter_queue: Some("orders-dlq".to_string()),
+            ..Default::default()
+        };
+
+        // One slot left, but the queue and its DLQ need two
+        let result = broker.create_queue_with_dlq("orders", config.clone()).await;
+        assert!(matches!(result, Err(Error::QueueLimitReached(2))));
+        assert_eq!(broker.list_queues().await.unwrap().len(), 1);
+
+        // An existing DLQ takes no new slot
+        broker.delete_queue("events").await.unwrap();
+        broker.create_queue("orders-dlq").await.unwrap();
+        broker
+            .create_queue_with_dlq("orders", config)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_creates_share_dlq() {
+        let broker = Arc::new(create_test_broker());
+        let config = QueueConfig {
+            dead_letter_queue: Some("shared-dlq".to_string()),
+            ..Default::default()
+        };
+        let tasks: Vec<_> = (0..8)
+            .map(|i| {
+                let broker = Arc::clone(&broker);
+                let config = config.clone();
+                // Two attempts per name, so half of them fail
+                tokio::spawn(async move {
+                    broker
+                        .create_queue_with_dlq(format!("q{}", i % 4), config)
+                        .await
+                })
+            })
+            .collect();
+
+        let mut created = 0;
+        for task in tasks {
+            if task.await.unwrap().is_ok() {
+                created += 1;
+            }
+        }
+        assert_eq!(created, 4);
+        assert!(broker.get_queue("shared-dlq").await.unwrap().is_some());
+        assert_eq!(broker.list_queues().await.unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_create_queue_from_template() {
+        let broker = create_test_broker();
+        let template = QueueConfig {
+            max_retries: 7,
+            visibility_timeout_secs: 90,
+            ..Default::default()
+        };
+        broker.register_template("standard", template).unwrap();
+
+        let plain = broker
+            .create_queue_from_template("orders", "standard", None)
+            .await
+            .unwrap();
+        assert_eq!(plain.config.max_retries, 7);
+        assert_eq!(plain.config.visibility_timeout_secs, 90);
+
+        let overrides = serde_json::json!({"max_retries": 1});
+        let custom = broker
+            .create_queue_from_template("payments", "standard", Some(&overrides))
+            .await
+            .unwrap();
+        assert_eq!(custom.config.max_retries, 1);
+        assert_eq!(custom.config.visibility_timeout_secs, 90);
+
+        let result = broker
+            .create_queue_from_template("events", "missing", None)
+            .await;
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        let invalid = QueueConfig {
+            poison_threshold: 3,
+            poison_window_secs: 0,
+            ..Default::default()
+        };
+        assert!(broker.register_template("broken", invalid).is_err());
+        assert!(broker.template("broken").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_requeues_inflight_for_shared_backend() {
+        let storage: Arc<dyn StorageEngine> = Arc::new(MemoryStorage::new());
+        let stopping = Broker::with_storage(Arc::clone(&storage));
+        let successor = Broker::with_storage(storage);
+
+        stopping.create_queue("orders").await.unwrap();
+        for body in ["a", "b", "c"] {
+            stopping.publish_bytes("orders", body).await.unwrap();
+        }
+        let held = stopping.receive_batch("orders", 2).await.unwrap();
+        assert_eq!(held.len(), 2);
+
+        assert_eq!(stopping.requeue_all_inflight().await.unwrap(), 2);
+
+        // The other broker sees every message again, in the original order
+        let bodies: Vec<_> = successor
+            .receive_batch("orders", 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|m| m.body_as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(bodies, ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_ack_and_receive() {
+        let broker = create_test_broker();
+        broker.create_queue("orders").await.unwrap();
+        for body in ["a", "b", "c"] {
+            broker.publish_bytes("orders", body).await.unwrap();
+        }
+
+        let first = broker.receive("orders").await.unwrap().unwrap();
+        let (acked, next) = broker
+            .ack_and_receive("orders", &first.id, 2)
+            .await
+            .unwrap();
+        assert!(acked.is_ok());
+        assert_eq!(next.len(), 2);
+        assert_eq!(next[0].body_as_str(), Some("b"));
+
+        // A stale ack is reported, the receive still happens
+        broker.ack("orders", &next[0].id).await.unwrap();
+        broker.publish_bytes("orders", "d").await.unwrap();
+        let (acked, next) = broker
+            .ack_and_receive("orders", &first.id, 2)
+            .await
+            .unwrap();
+        assert!(matches!(acked, Err(Error::MessageNotFound(_))));
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[0].body_as_str(), Some("d"));
+
+        let result = broker.ack_and_receive("missing", &first.id, 1).await;
+        assert!(matches!(result, Err(Error::QueueNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_receive_wait() {
+        let broker = Arc::new(create_test_broker());
+        broker.create_queue("orders").await.unwrap();
+
+        // Times out with an empty batch
+        let received = broker
+            .receive_wait("orders", 10, Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert!(received.is_empty());
+
+        // Returns as soon as a message is published
+        let waiter = {
+            let broker = Arc::clone(&broker);
+            tokio::spawn(async move {
+                broker
+                    .receive_wait("orders", 10, Duration::from_secs(10))
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        broker.publish_bytes("orders", "hello").await.unwrap();
+        assert_eq!(waiter.await.unwrap().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_delete_queue_ends_receive_wait() {
+        let broker = Arc::new(create_test_broker());
+        broker.create_queue("orders").await.unwrap();
+
+        let waiter = {
+            let broker = Arc::clone(&broker);
+            tokio::spawn(async move {
+                broker
+                    .receive_wait("orders", 10, Duration::from_secs(30))
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        broker.delete_queue("orders").await.unwrap();
+
+        let result = tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("waiter should end promptly")
+            .unwrap();
+        assert!(matches!(result, Err(Error::QueueDeleted(name)) if name == "orders"));
+        assert!(broker.deletion_signals.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_replace_queue_config() {
+        let broker = create_test_broker();
+        broker.create_queue("orders").await.unwrap();
+        broker.publish_bytes("orders", "a").await.unwrap();
+
+        let config = QueueConfig {
+            max_retries: 2,
+            dead_letter_queue: Some("orders-dlq".to_string()),
+            ..Default::default()
+        };
+        let queue = broker.replace_queue_config("orders", config).await.unwrap();
+        assert_eq!(queue.config.max_retries, 2);
+        assert_eq!(
+            broker
+                .get_queue_stats("orders")
+                .await
+                .unwrap()
+                .message_count,
+            1
+        );
+
+        let stored = broker.get_queue("orders").await.unwrap().unwrap();
+        assert_eq!(
+            stored.config.dead_letter_queue.as_deref(),
+            Some("orders-dlq")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replace_queue_config_rejects_without_changes() {
+        let broker = create_test_broker();
+        let dlq_config = QueueConfig {
+            dead_letter_queue: Some("orders".to_string()),
+            ..Default::default()
+        };
+        broker
+            .create_queue_with_config("orders-dlq", dlq_config)
+            .await
+            .unwrap();
+        let original = QueueConfig {
+            max_retries: 7,
+            ..Default::default()
+        };
+        broker
+            .create_queue_with_config("orders", original)
+            .await
+            .unwrap();
+
+        let invalid = [
+            // orders -> orders-dlq -> orders
+            QueueConfig {
+                max_retries: 1,
+                dead_letter_queue: Some("orders-dlq".to_string()),
+                ..Default::default()
+            },
+            QueueConfig {
+                retry_queue: Some("orders".to_string()),
+                ..Default::default()
+            },
+            QueueConfig {
+                max_size_bytes: 10,
+                max_message_bytes: 20,
+                ..Default::default()
+            },
+        ];
+        for config in invalid {
+            let err = broker
+                .replace_queue_config("orders", config)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::InvalidConfig(_)), "{:?}", err);
+        }
+
+        let queue = broker.get_queue("orders").await.unwrap().unwrap();
+        assert_eq!(queue.config.max_retries, 7);
+        assert!(queue.config.dead_letter_queue.is_none());
+        assert_eq!(queue.created_at, queue.updated_at);
+    }
+
+    #[tokio::test]
+    async fn test_rename_queue_keeps_messages() {
+        let broker = create_test_broker();
+        let created = broker.create_queue("orders").await.unwrap();
+        broker.publish_bytes("orders", "a").await.unwrap();
+
+        let renamed = broker.rename_queue("orders", "orders-v2").await.unwrap();
+        assert_eq!(renamed.name, "orders-v2");
+        assert_eq!(renamed.id, created.id);
+        assert!(renamed.updated_at >= created.updated_at);
+
+        assert!(broker.get_queue("orders").await.unwrap().is_none());
+        let message = broker.receive("orders-v2").await.unwrap().unwrap();
+        assert_eq!(message.body_as_str(), Some("a"));
+    }
+
+    #[tokio::test]
+    async fn test_rename_queue_rejects_taken_or_invalid_name() {
+        let broker = create_test_broker();
+        broker.create_queue("a").await.unwrap();
+        broker.create_queue("b").await.unwrap();
+        broker.publish_bytes("a", "kept").await.unwrap();
+
+        let err = broker.rename_queue("a", "b").await.unwrap_err();
+        assert!(matches!(err, Error::QueueAlreadyExists(name) if name == "b"));
+        for name in ["", "has space", "a/b"] {
+            let err = broker.rename_queue("a", name).await.unwrap_err();
+            assert!(matches!(err, Error::InvalidConfig(_)), "{:?}", name);
+        }
+        let err = broker.rename_queue("missing", "c").await.unwrap_err();
+        assert!(matches!(err, Error::QueueNotFound(_)));
+
+        // Nothing moved
+        assert_eq!(broker.get_queue_stats("a").await.unwrap().message_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_delete_queues_by_name_partial_success() {
+        let broker = create_test_broker();
+        broker.create_queue("a").await.unwrap();
+        broker.create_queue("b").await.unwrap();
+
+        let selector = QueueSelector::Names(vec!["a".into(), "missing".into(), "b".into()]);
+        let results = broker.delete_queues(&selector).await.unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert!(results[0].1.is_ok());
+        assert!(matches!(results[1].1, Err(Error::QueueNotFound(_))));
+        assert!(results[2].1.is_ok());
+        assert!(broker.list_queues().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_queues_by_prefix() {
+        let broker = create_test_broker();
+        broker.create_queue("test-a").await.unwrap();
+        broker.create_queue("test-b").await.unwrap();
+        broker.create_queue("prod").await.unwrap();
+        broker.subscribe_group("test-a", "workers").await.unwrap();
+
+        let selector = QueueSelector::Prefix("test-".to_string());
+        let mut deleted: Vec<String> = broker
+            .delete_queues(&selector)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(name, result)| {
+                assert!(result.is_ok());
+                name
+            })
+            .collect();
+        deleted.sort();
+
+        assert_eq!(deleted, vec!["test-a", "test-b"]);
+        let remaining: Vec<String> = broker
+            .list_queues()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|q| q.name)
+            .collect();
+        assert_eq!(remaining, vec!["prod"]);
+    }
+
+    #[tokio::test]
+    async fn test_receive_with_keepalive_outlives_visibility_timeout() {
+        let broker = create_test_broker();
+        let config = QueueConfig {
+            visibility_timeout_secs: 1,
+            ..Default::default()
+        };
+        broker
+            .create_queue_with_config("test", config)
+            .await
+            .unwrap();
+        let msg_id = broker.publish_bytes("test", "slow").await.unwrap();
+
+        let broker_ref = &broker;
+        let handler = move |message: Message| async move {
+            for _ in 0..3 {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                // A maintenance pass would redeliver the message if it had timed out
+                broker_ref.storage.requeue_timed_out().await.unwrap();
+                assert!(broker_ref.receive("test").await.unwrap().is_none());
+            }
+            Ok::<_, ()>(message.id)
+        };
+
+        let outcome = broker
+            .receive_with_keepalive("test", handler)
+            .await
+            .unwrap();
+        assert_eq!(outcome, Some(Ok(msg_id.clone())));
+
+        // Acked on success
+        assert!(broker.get_message("test", &msg_id).await.unwrap().is_none());
+        assert!(broker
+            .receive_with_keepalive("test", |_| async { Ok::<_, ()>(()) })
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_publish_and_receive() {
+        let broker = create_test_broker();
+        broker.create_queue("test").await.unwrap();
+
+        // Publish
+        let msg_id = broker.publish_bytes("test", "Hello!").await.unwrap();
+
+        // Receive
+        let received = broker.receive("test").await.unwrap();
+        assert!(received.is_some());
+
+        let msg = received.unwrap();
+        assert_eq!(msg.id, msg_id);
+        assert_eq!(msg.body_as_str(), Some("Hello!"));
+
+        // Ack
+        broker.ack("test", &msg.id).await.unwrap();
+
+        // Queue should be empty
+        let stats = broker.get_queue_stats("test").await.unwrap();
+        assert_eq!(stats.message_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_publish_if_absent_with_supplied_id() {
+        let broker = create_test_broker();
+        broker.create_queue("test").await.unwrap();
+
+        let id = MessageId::new();
+        let mut message = Message::new("first");
+        message.id = id.clone();
+        let (published, created) = broker
+            .publish_if_absent("test", message, Durability::None)
+            .await
+            .unwrap();
+        assert_eq!(published, id);
+        assert!(created);
+
+        // Duplicate while pending
+        let mut duplicate = Message::new("second");
+        duplicate.id = id.clone();
+        let (published, created) = broker
+            .publish_if_absent("test", duplicate.clone(), Durability::None)
+            .await
+            .unwrap();
+        assert_eq!(published, id);
+        assert!(!created);
+
+        // Duplicate while in flight
+        broker.receive("test").await.unwrap().unwrap();
+        let (_, created) = broker
+            .publish_if_absent("test", duplicate, Durability::None)
+            .await
+            .unwrap();
+        assert!(!created);
+
+        let stats = broker.get_queue_stats("test").await.unwrap();
+        assert_eq!(stats.message_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_publish_to_missing_queue_errors_by_default() {
+        let broker = create_test_broker();
+
+        let result = broker.publish_bytes("missing", "hello").await;
+        assert!(matches!(result, Err(Error::QueueNotFound(_))));
+        assert!(broker.get_queue("missing").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_publish_auto_creates_queue() {
+        let broker = Broker::builder(Arc::new(MemoryStorage::new()))
+            .auto_create_queues(true)
+            .build();
+
+        broker.publish_bytes("auto", "hello").await.unwrap();
+        assert!(broker.get_queue("auto").await.unwrap().is_some());
+
+        let msg = broker.receive("auto").await.unwrap().unwrap();
+        assert_eq!(msg.body_as_str(), Some("hello"));
+    }
+
+    #[tokio::test]
+    async fn test_group_fanout_shares_body_and_attributes() {
+        let broker = create_test_broker();
+        broker.create_queue("events").await.unwrap();
+        let mut groups = Vec::new();
+        for i in 0..1000 {
+            let group = broker
+                .subscribe_group("events", &format!("g{}", i))
+                .await
+                .unwrap();
+            groups.push(group);
+        }
+
+        let body = bytes::Bytes::from(vec![7u8; 64 * 1024]);
+        let message = Message::new(body.clone()).with_attribute("kind", "bulk");
+        let attributes = Arc::clone(&message.attributes);
+        broker
+            .publish("events", message, Durability::None)
+            .await
+            .unwrap();
+
+        // Every copy points into the publisher's buffers, so memory grows with
+        // the number of groups only by per-copy metadata, not by body or
+        // attribute size
+        for group in &groups {
+            let received = broker.receive_group(group, 1).await.unwrap();
+            assert_eq!(received.len(), 1);
+            assert_eq!(received[0].body.as_ptr(), body.as_ptr());
+            assert!(Arc::ptr_eq(&received[0].attributes, &attributes));
+        }
+        let source = broker.receive("events").await.unwrap().unwrap();
+        assert_eq!(source.body.as_ptr(), body.as_ptr());
+        assert!(Arc::ptr_eq(&source.attributes, &attributes));
+    }
+
+    #[tokio::test]
+    async fn test_group_backing_queue_has_no_webhook() {
+        let broker = create_test_broker();
+        let config = QueueConfig {
+            delivery_webhook: Some("http://hooks.example/orders".to_string()),
+            ..Default::default()
+        };
+        broker
+            .create_queue_with_config("orders", config)
+            .await
+            .unwrap();
+
+        let group = broker.subscribe_group("orders", "audit").await.unwrap();
+        let backing = broker
+            .get_queue(&group.backing_queue())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(backing.config.delivery_webhook.is_none());
+        let source = broker.get_queue("orders").await.unwrap().unwrap();
+        assert!(source.config.delivery_webhook.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_consumer_groups_receive_independently() {
+        let broker = create_test_broker();
+        broker.create_queue("orders").await.unwrap();
+
+        let billing = broker.subscribe_group("orders", "billing").await.unwrap();
+        let shipping = broker.subscribe_group("orders", "shipping").await.unwrap();
+
+        for i in 0..3 {
+            broker
+                .publish_bytes("orders", format!("order {}", i))
+                .await
+                .unwrap();
+        }
+
+        for group in [&billing, &shipping] {
+            let messages = broker.receive_group(group, 10).await.unwrap();
+            let bodies: Vec<_> = messages.iter().map(|m| m.body_as_str().unwrap()).collect();
+            assert_eq!(bodies, vec!["order 0", "order 1", "order 2"]);
+
+            for msg in &messages {
+                broker.ack_group(group, &msg.id).await.unwrap();
+            }
+        }
+
+        // Acking in one group leaves the source queue untouched
+        let stats = broker.get_queue_stats("orders").await.unwrap();
+        assert_eq!(stats.pending_count, 3);
+
+        // Deleting the source queue removes the groups' backing queues
+        broker.delete_queue("orders").await.unwrap();
+        assert!(broker.list_queues().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_group_copy_keeps_publish() {
+        let broker = Broker::builder(Arc::new(MemoryStorage::new()))
+            .global_dedup(Duration::from_secs(60), 100)
+            .build();
+        broker.create_queue("orders").await.unwrap();
+        let billing = broker.subscribe_group("orders", "billing").await.unwrap();
+        let shipping = broker.subscribe_group("orders", "shipping").await.unwrap();
+
+        // Billing's backing queue disappears, so its copy is rejected
+        broker.delete_queue(&billing.backing_queue()).await.unwrap();
+
+        let message = Message::new("order").with_dedup_id("order-1");
+        let id = broker
+            .publish("orders", message.clone(), Durability::default())
+            .await
+            .unwrap();
+        assert_eq!(broker.receive_group(&shipping, 10).await.unwrap().len(), 1);
+
+        // The stored message stays recorded, so a retry is not duplicated
+        let retried = Message {
+            id: MessageId::new(),
+            ..message
+        };
+        let retry_id = broker
+            .publish("orders", retried, Durability::default())
+            .await
+            .unwrap();
+        assert_eq!(retry_id, id);
+        assert_eq!(
+            broker
+                .get_queue_stats("orders")
+                .await
+                .unwrap()
+                .pending_count,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_restore_round_trip() {
+        let broker = create_test_broker();
+        let config = QueueConfig {
+            max_retries: 2,
+            ..Default::default()
+        };
+        broker
+            .create_queue_with_config("orders", config)
+            .await
+            .unwrap();
+        let config = QueueConfig {
+            attribute_ttl_key: Some("x-ttl-secs".to_string()),
+            quarantine_after: 1,
+            ..Default::default()
+        };
+        broker
+            .create_queue_with_config("events", config)
+            .await
+            .unwrap();
+
+        broker.publish_bytes("orders", "first").await.unwrap();
+        broker.publish_bytes("orders", "second").await.unwrap();
+        let event_id = broker
+            .publish(
+                "events",
+                Message::new(vec![0xffu8, 0x00, 0xfe]).with_attribute("x-ttl-secs", "600"),
+                Durability::default(),
+            )
+            .await
+            .unwrap();
+        let event = broker
+            .get_message("events", &event_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Leave one message in-flight and quarantine another
+        broker.receive("orders").await.unwrap().unwrap();
+        let later_id = broker.publish_bytes("events", "later").await.unwrap();
+        let received = broker.receive("events").await.unwrap().unwrap();
+        assert_eq!(received.id, event_id);
+        broker.nack("events", &event_id).await.unwrap();
+        let later = broker
+            .get_message("events", &later_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut buf = Vec::new();
+        broker.snapshot(&mut buf).await.unwrap();
+
+        let restored = create_test_broker();
+        restored.restore(buf.as_slice()).await.unwrap();
+
+        let orders = restored.get_queue("orders").await.unwrap().unwrap();
+        assert_eq!(orders.config.max_retries, 2);
+
+        let stats = restored.get_queue_stats("orders").await.unwrap();
+        assert_eq!(stats.pending_count, 2);
+        assert_eq!(stats.in_flight_count, 0);
+
+        let bodies: Vec<_> = restored
+            .receive_batch("orders", 10)
+            .await
+            .unwrap()
+            .iter()
+            .map(|m| m.body_as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(bodies, vec!["first", "second"]);
+
+        // Stored fields come back as they were instead of being re-applied
+        let quarantined = restored.list_quarantined("events").await.unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(&quarantined[0].body[..], &[0xff, 0x00, 0xfe]);
+        assert_eq!(quarantined[0].expires_at, event.expires_at);
+        assert_eq!(quarantined[0].sequence, event.sequence);
+
+        let pending = restored.receive("events").await.unwrap().unwrap();
+        assert_eq!(pending.id, later.id);
+        assert_eq!(pending.sequence, later.sequence);
+
+        // Restoring into a non-empty broker is rejected
+        let result = restored.restore(buf.as_slice()).await;
+        assert!(matches!(result, Err(Error::Snapshot(_))));
+    }
+
+    #[tokio::test]
+    async fn test_restore_applies_queue_rules_and_dedup() {
+        let broker = create_test_broker();
+        for name in ["orders", "events", "audit"] {
+            broker.create_queue(name).await.unwrap();
+        }
+        let id = broker
+            .publish(
+                "orders",
+                Message::new("order").with_dedup_id("order-1"),
+                Durability::default(),
+            )
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        broker.snapshot(&mut buf).await.unwrap();
+
+        // Over the queue limit before anything is created
+        let limited = Broker::builder(Arc::new(MemoryStorage::new()))
+            .max_queues(2)
+            .build();
+        let result = limited.restore(buf.as_slice()).await;
+        assert!(matches!(result, Err(Error::QueueLimitReached(2))));
+        assert!(limited.list_queues().await.unwrap().is_empty());
+
+        let mut snapshot: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        snapshot["queues"][0]["queue"]["name"] = "bad name".into();
+        let result = create_test_broker()
+            .restore(snapshot.to_string().as_bytes())
+            .await;
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+
+        // Restored dedup IDs suppress republishing to any queue
+        let deduped = Broker::builder(Arc::new(MemoryStorage::new()))
+            .global_dedup(Duration::from_secs(60), 100)
+            .build();
+        deduped.restore(buf.as_slice()).await.unwrap();
+        let republished = deduped
+            .publish(
+                "events",
+                Message::new("order").with_dedup_id("order-1"),
+                Durability::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(republished, id);
+        let stats = deduped.get_queue_stats("events").await.unwrap();
+        assert_eq!(stats.pending_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_publish_fsync_flushes_before_returning() {
+        let storage = Arc::new(MemoryStorage::new());
+        let broker = Broker::with_storage(storage.clone());
+        broker.create_queue("test").await.unwrap();
+
+        broker
+            .publish("test", Message::new("fast"), Durability::None)
+            .await
+            .unwrap();
+        assert_eq!(storage.flush_count(), 0);
+
+        broker
+            .publish("test", Message::new("durable"), Durability::Fsync)
+            .await
+            .unwrap();
+        assert_eq!(storage.flush_count(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_op_timeout_fails_stalled_storage() {
+        let storage = Arc::new(MemoryStorage::new().with_flush_delay(Duration::from_secs(10)));
+        let broker = Broker::builder(storage.clone())
+            .op_timeout(Duration::from_millis(100))
+            .build();
+        broker.create_queue("test").await.unwrap();
+
+        let result = broker
+            .publish("test", Message::new("durable"), Durability::Fsync)
+            .await;
+        assert!(matches!(result, Err(Error::Timeout(op)) if op == "flush"));
+        assert_eq!(storage.flush_count(), 0);
+
+        // Calls finishing in time are unaffected
+        broker
+            .publish("test", Message::new("fast"), Durability::None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_only_broker() {
+        let storage: Arc<dyn StorageEngine> = Arc::new(MemoryStorage::new());
+        let writer = Broker::with_storage(Arc::clone(&storage));
+        writer.create_queue("orders").await.unwrap();
+        let id = writer.publish_bytes("orders", "hello").await.unwrap();
+
+        let reader = Broker::builder(storage).read_only(true).build();
+        assert!(reader.is_read_only());
+
+        // Reads see the shared backend
+        assert!(reader.get_queue("orders").await.unwrap().is_some());
+        assert_eq!(reader.list_queues().await.unwrap().len(), 1);
+        let stats = reader.get_queue_stats("orders").await.unwrap();
+        assert_eq!(stats.pending_count, 1);
+        assert_eq!(reader.peek("orders").await.unwrap().unwrap().id, id);
+
+        // Writes are rejected without touching it
+        let rejected = [
+            reader.publish_bytes("orders", "nope").await.err(),
+            reader.receive("orders").await.err(),
+            reader.ack("orders", &id).await.err(),
+            reader.nack("orders", &id).await.err(),
+            reader.create_queue("events").await.err(),
+            reader.delete_queue("orders").await.err(),
+        ];
+        for err in rejected {
+            assert!(matches!(err, Some(Error::ReadOnly(_))));
+        }
+        assert_eq!(reader.requeue_all_inflight().await.unwrap(), 0);
+
+        let stats = writer.get_queue_stats("orders").await.unwrap();
+        assert_eq!(stats.pending_count, 1);
+        assert_eq!(writer.list_queues().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reserved_attributes_rejected_on_publish() {
+        let broker = create_test_broker();
+        broker.create_queue("test").await.unwrap();
+
+        let rejected = [
+            Message::new("a").with_attribute("x-flowq-trace", "1"),
+            Message::new("b").with_attribute(flowq_types::DELIVERY_COUNT_ATTRIBUTE, "0"),
+            Message::new("c").with_typed_attribute("x-flowq-score", 1.0),
+            Message::new("d").with_attribute("k".repeat(257), "long"),
+        ];
+        for message in rejected {
+            let result = broker.publish("test", message, Durability::None).await;
+            assert!(matches!(result, Err(Error::InvalidMessage(_))));
+        }
+        let stats = broker.get_queue_stats("test").await.unwrap();
+        assert_eq!(stats.pending_count, 0);
+
+        // Other x- attributes are the producer's to use
+        let message = Message::new("e").with_attribute(flowq_types::DLQ_OVERRIDE_ATTRIBUTE, "dlq");
+        broker
+            .publish("test", message, Durability::None)
+            .await
+            .unwrap();
+
+        // The prefix is configurable
+        let broker = Broker::builder(Arc::new(MemoryStorage::new()))
+            .reserved_attribute_prefix("acme-")
+            .build();
+        broker.create_queue("test").await.unwrap();
+        let message = Message::new("f").with_attribute("x-flowq-trace", "1");
+        broker
+            .publish("test", message, Durability::None)
+            .await
+            .unwrap();
+        let message = Message::new("g").with_attribute("acme-internal", "1");
+        let result = broker.publish("test", message, Durability::None).await;
+        assert!(matches!(result, Err(Error::InvalidMessage(_))));
+    }
+
+    #[tokio::test]
+    async fn test_global_dedup_across_queues() {
+        let broker = Broker::builder(Arc::new(MemoryStorage::new()))
+            .global_dedup(Duration::from_secs(60), 100)
+            .build();
+        broker.create_queue("a").await.unwrap();
+        broker.create_queue("b").await.unwrap();
+
+        let first = Message::new("event").with_dedup_id("evt-1");
+        let first_id = broker.publish("a", first, Durability::None).await.unwrap();
+        let second = Message::new("event").with_dedup_id("evt-1");
+        let second_id = broker.publish("b", second, Durability::None).await.unwrap();
+
+        assert_eq!(second_id, first_id);
+        assert_eq!(broker.get_queue_stats("a").await.unwrap().pending_count, 1);
+        assert_eq!(broker.get_queue_stats("b").await.unwrap().pending_count, 0);
+
+        // Without global dedup the queues dedup independently
+        let broker = create_test_broker();
+        broker.create_queue("a").await.unwrap();
+        broker.create_queue("b").await.unwrap();
+        for queue in ["a", "b"] {
+            let message = Message::new("event").with_dedup_id("evt-1");
+            broker
+                .publish(queue, message, Durability::None)
+                .await
+                .unwrap();
+        }
+        assert_eq!(broker.get_queue_stats("b").await.unwrap().pending_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_processing_token_stable_across_redeliveries() {
+        let broker = Broker::builder(Arc::new(MemoryStorage::new()))
+            .track_processed(Duration::from_secs(60), 100)
+            .build();
+        broker.create_queue("test").await.unwrap();
+        broker.publish_bytes("test", "work").await.unwrap();
+
+        // Redelivered after a nack and after a shutdown requeue
+        let first = broker.receive("test").await.unwrap().unwrap();
+        broker.nack("test", &first.id).await.unwrap();
+        let second = broker.receive("test").await.unwrap().unwrap();
+        broker.requeue_all_inflight().await.unwrap();
+        let third = broker.receive("test").await.unwrap().unwrap();
+
+        assert_eq!(third.delivery_count, 3);
+        assert_eq!(second.processing_token(), first.processing_token());
+        assert_eq!(third.processing_token(), first.processing_token());
+
+        let token = third.processing_token();
+        assert!(!broker.was_processed(&token));
+        broker.ack("test", &third.id).await.unwrap();
+        assert!(broker.was_processed(&token));
+        assert!(!create_test_broker().was_processed(&token));
+    }
+
+    #[tokio::test]
+    async fn test_mark_processed_survives_failed_ack() {
+        let broker = Broker::builder(Arc::new(MemoryStorage::new()))
+            .track_processed(Duration::from_secs(60), 100)
+            .build();
+        let config = QueueConfig {
+            visibility_timeout_secs: 0,
+            ..Default::default()
+        };
+        broker
+            .create_queue_with_config("test", config)
+            .await
+            .unwrap();
+        broker.publish_bytes("test", "work").await.unwrap();
+
+        let first = broker.receive("test").await.unwrap().unwrap();
+        broker.mark_processed(&first.processing_token());
+
+        // The ack arrives after the visibility timeout requeued the message
+        broker.storage().requeue_timed_out().await.unwrap();
+        let redelivered = broker.receive("test").await.unwrap().unwrap();
+        assert_eq!(redelivered.delivery_count, 2);
+        assert!(broker.was_processed(&redelivered.processing_token()));
+    }
+
+    #[tokio::test]
+    async fn test_time_ordered_id_generator() {
+        let broker = Broker::builder(Arc::new(MemoryStorage::new()))
+            .id_generator(flowq_types::TimeOrderedIdGenerator::default())
+            .build();
+        broker.create_queue("test").await.unwrap();
+
+        let mut ids = Vec::new();
+        for i in 0..100 {
+            ids.push(
+                broker
+                    .publish_bytes("test", format!("{}", i))
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[tokio::test]
+    async fn test_receive_batch() {
+        let broker = create_test_broker();
+        broker.create_queue("test").await.unwrap();
 
         // Publish multiple messages
         for i in 0..5 {
@@ -207,6 +2816,38 @@ mod tests {
         assert_eq!(stats.in_flight_count, 3);
     }
 
+    #[tokio::test]
+    async fn test_get_message_pending_and_in_flight() {
+        let broker = create_test_broker();
+        broker.create_queue("test").await.unwrap();
+
+        let in_flight_id = broker.publish_bytes("test", "first").await.unwrap();
+        let pending_id = broker.publish_bytes("test", "second").await.unwrap();
+        broker.receive("test").await.unwrap();
+
+        let pending = broker
+            .get_message("test", &pending_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pending.status, MessageStatus::Pending);
+        assert_eq!(pending.body_as_str(), Some("second"));
+
+        let in_flight = broker
+            .get_message("test", &in_flight_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(in_flight.status, MessageStatus::Delivered);
+        assert_eq!(in_flight.body_as_str(), Some("first"));
+
+        assert!(broker
+            .get_message("test", &MessageId::new())
+            .await
+            .unwrap()
+            .is_none());
+    }
+
     #[tokio::test]
     async fn test_nack_returns_to_queue() {
         let broker = create_test_broker();
@@ -222,4 +2863,35 @@ mod tests {
         assert_eq!(stats.pending_count, 1);
         assert_eq!(stats.in_flight_count, 0);
     }
+
+    #[tokio::test]
+    async fn test_create_queue_rejects_inconsistent_config() {
+        let broker = create_test_broker();
+
+        let config = QueueConfig {
+            max_size_bytes: 100,
+            max_message_bytes: 1000,
+            ..Default::default()
+        };
+        let result = broker.create_queue_with_config("test", config).await;
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+        assert!(broker.get_queue("test").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stats_stream() {
+        let broker = create_test_broker();
+        broker.create_queue("test").await.unwrap();
+        broker.publish_bytes("test", "hello").await.unwrap();
+
+        let stream = broker.stats_stream(Duration::from_millis(10));
+        tokio::pin!(stream);
+
+        // Collect a couple of ticks worth of snapshots
+        for _ in 0..2 {
+            let (name, stats) = stream.next().await.unwrap();
+            assert_eq!(name, "test");
+            assert_eq!(stats.pending_count, 1);
+        }
+    }
 }
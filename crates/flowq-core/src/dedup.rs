@@ -0,0 +1,152 @@
+//! Broker-wide publish deduplication
+//!
+//! Remembers dedup IDs across all queues for a fixed window, so one upstream
+//! event published to several queues is only accepted once. The cache holds
+//! at most `capacity` IDs, forgetting the oldest first when full.
+//!
+//! The same cache, without message IDs, remembers processing tokens of acked
+//! messages for `Broker::was_processed`.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use flowq_types::MessageId;
+use tokio::time::Instant;
+
+/// Dedup IDs seen recently, oldest first, each with the value recorded for
+/// it (the first message published with it)
+pub(crate) struct DedupCache<V = MessageId> {
+    /// How long an ID suppresses later publishes
+    window: Duration,
+    /// Most IDs remembered at once
+    capacity: usize,
+    /// Value recorded for each dedup ID, and when
+    seen: HashMap<String, (V, Instant)>,
+    /// Dedup IDs in the order they were recorded
+    order: VecDeque<(String, Instant)>,
+}
+
+impl<V: Clone + PartialEq> DedupCache<V> {
+    /// Create a cache remembering up to `capacity` IDs for `window` each
+    pub(crate) fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity: capacity.max(1),
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Return the message already published with `dedup_id` inside the
+    /// window, or record `message_id` as its first publish
+    pub(crate) fn check_and_record(
+        &mut self,
+        dedup_id: &str,
+        message_id: &V,
+        now: Instant,
+    ) -> Option<V> {
+        self.evict(now);
+        if let Some((original, _)) = self.seen.get(dedup_id) {
+            return Some(original.clone());
+        }
+
+        self.insert(dedup_id, message_id.clone(), now);
+        None
+    }
+
+    /// Record `dedup_id` with `value`, restarting its window if it was
+    /// already recorded
+    pub(crate) fn record(&mut self, dedup_id: &str, value: V, now: Instant) {
+        self.evict(now);
+        self.insert(dedup_id, value, now);
+    }
+
+    fn insert(&mut self, dedup_id: &str, value: V, now: Instant) {
+        while self.order.len() >= self.capacity {
+            self.pop_oldest();
+        }
+        self.seen.insert(dedup_id.to_string(), (value, now));
+        self.order.push_back((dedup_id.to_string(), now));
+    }
+
+    /// Whether `dedup_id` was recorded within the window
+    pub(crate) fn contains(&mut self, dedup_id: &str, now: Instant) -> bool {
+        self.evict(now);
+        self.seen.contains_key(dedup_id)
+    }
+
+    /// Forget `dedup_id` if it was recorded for `message_id`, e.g. because
+    /// the publish failed
+    pub(crate) fn forget(&mut self, dedup_id: &str, message_id: &V) {
+        if self
+            .seen
+            .get(dedup_id)
+            .is_some_and(|(recorded, _)| recorded == message_id)
+        {
+            self.seen.remove(dedup_id);
+        }
+    }
+
+    /// Drop IDs whose window has passed
+    fn evict(&mut self, now: Instant) {
+        while self
+            .order
+            .front()
+            .is_some_and(|(_, seen_at)| now.duration_since(*seen_at) >= self.window)
+        {
+            self.pop_oldest();
+        }
+    }
+
+    fn pop_oldest(&mut self) {
+        let Some((dedup_id, seen_at)) = self.order.pop_front() else {
+            return;
+        };
+        // The ID may have been forgotten and recorded again since
+        if self
+            .seen
+            .get(&dedup_id)
+            .is_some_and(|(_, recorded_at)| *recorded_at == seen_at)
+        {
+            self.seen.remove(&dedup_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_and_capacity() {
+        let mut cache = DedupCache::new(Duration::from_secs(60), 2);
+        let start = Instant::now();
+        let (a, b, c) = (MessageId::new(), MessageId::new(), MessageId::new());
+
+        assert_eq!(cache.check_and_record("a", &a, start), None);
+        assert_eq!(cache.check_and_record("a", &b, start), Some(a.clone()));
+
+        // Past the window the ID is accepted again
+        let later = start + Duration::from_secs(60);
+        assert_eq!(cache.check_and_record("a", &b, later), None);
+
+        // When full, the oldest ID is forgotten first
+        assert_eq!(cache.check_and_record("b", &c, later), None);
+        assert_eq!(cache.check_and_record("c", &c, later), None);
+        assert_eq!(cache.check_and_record("a", &a, later), None);
+    }
+
+    #[test]
+    fn test_record_restarts_window() {
+        let mut cache = DedupCache::new(Duration::from_secs(60), 10);
+        let start = Instant::now();
+
+        cache.record("token", (), start);
+        assert!(cache.contains("token", start + Duration::from_secs(30)));
+
+        // Recording again counts the window from the new time
+        cache.record("token", (), start + Duration::from_secs(30));
+        assert!(cache.contains("token", start + Duration::from_secs(80)));
+        assert!(!cache.contains("token", start + Duration::from_secs(90)));
+    }
+}
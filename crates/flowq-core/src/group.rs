@@ -0,0 +1,54 @@
+//! Consumer groups
+//!
+//! A consumer group reads its own copy of every message published to a queue
+//! after it subscribed, independently of other groups and of plain consumers.
+
+/// Separator between the source queue name and the group ID in backing queue names
+const GROUP_QUEUE_SEPARATOR: &str = "::group::";
+
+/// A named group of consumers with its own progress through a queue
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConsumerGroup {
+    /// Source queue the group reads from
+    queue: String,
+    /// Group identifier, unique per source queue
+    group_id: String,
+}
+
+impl ConsumerGroup {
+    /// Create a handle for the given queue and group
+    pub fn new(queue: impl Into<String>, group_id: impl Into<String>) -> Self {
+        Self {
+            queue: queue.into(),
+            group_id: group_id.into(),
+        }
+    }
+
+    /// Source queue name
+    pub fn queue(&self) -> &str {
+        &self.queue
+    }
+
+    /// Group identifier
+    pub fn group_id(&self) -> &str {
+        &self.group_id
+    }
+
+    /// Name of the internal queue holding this group's copies of each message
+    pub fn backing_queue(&self) -> String {
+        format!("{}{}{}", self.queue, GROUP_QUEUE_SEPARATOR, self.group_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backing_queue_name() {
+        let group = ConsumerGroup::new("orders", "billing");
+        assert_eq!(group.queue(), "orders");
+        assert_eq!(group.group_id(), "billing");
+        assert_eq!(group.backing_queue(), "orders::group::billing");
+    }
+}
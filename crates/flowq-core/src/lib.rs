@@ -4,8 +4,13 @@
 //! - Broker: Main orchestrator
 //! - Queue management
 //! - Message handling
+//! - Consumer groups
 
 pub mod broker;
+mod dedup;
+pub mod group;
+mod timeout;
 
 // Re-exports
-pub use broker::Broker;
+pub use broker::{Broker, BrokerBuilder, MaintenanceHandle};
+pub use group::ConsumerGroup;
@@ -0,0 +1,384 @@
+//! Per-operation storage timeouts
+//!
+//! Wraps a storage backend so that no call can hang the broker: each one is
+//! abandoned with `Error::Timeout` once it runs past the configured limit.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use flowq_storage::StorageEngine;
+use flowq_types::{
+    Error, Message, MessageId, MessagePage, MessageStateFilter, PopFilter, Queue, QueueConfig,
+    QueueStats, Result, SearchCriteria,
+};
+use tracing::warn;
+
+/// Storage backend whose every call is bounded by a timeout
+pub(crate) struct TimeoutStorage {
+    inner: Arc<dyn StorageEngine>,
+    timeout: Duration,
+}
+
+impl TimeoutStorage {
+    /// Bound every call into `inner` by `timeout`
+    pub(crate) fn new(inner: Arc<dyn StorageEngine>, timeout: Duration) -> Self {
+        Self { inner, timeout }
+    }
+
+    /// Await `fut`, giving up with `Error::Timeout` once the limit passes
+    ///
+    /// The abandoned call is dropped; whether its effect still lands depends
+    /// on how far the backend got.
+    async fn bounded<T>(
+        &self,
+        op: &'static str,
+        fut: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        match tokio::time::timeout(self.timeout, fut).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!(
+                    "Storage operation {} timed out after {:?}",
+                    op, self.timeout
+                );
+                Err(Error::Timeout(op.to_string()))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl StorageEngine for TimeoutStorage {
+    fn backend_name(&self) -> &'static str {
+        self.inner.backend_name()
+    }
+
+    async fn create_queue(&self, queue: Queue) -> Result<Queue> {
+        self.bounded("create_queue", self.inner.create_queue(queue))
+            .await
+    }
+
+    async fn create_queue_with_dlq(&self, queue: Queue, dlq: Queue) -> Result<Queue> {
+        self.bounded(
+            "create_queue_with_dlq",
+            self.inner.create_queue_with_dlq(queue, dlq),
+        )
+        .await
+    }
+
+    async fn get_queue(&self, name: &str) -> Result<Option<Queue>> {
+        self.bounded("get_queue", self.inner.get_queue(name)).await
+    }
+
+    async fn list_queues(&self) -> Result<Vec<Queue>> {
+        self.bounded("list_queues", self.inner.list_queues()).await
+    }
+
+    async fn queue_count(&self) -> Result<usize> {
+        self.bounded("queue_count", self.inner.queue_count()).await
+    }
+
+    async fn delete_queue(&self, name: &str) -> Result<()> {
+        self.bounded("delete_queue", self.inner.delete_queue(name))
+            .await
+    }
+
+    async fn rename_queue(&self, name: &str, new_name: &str) -> Result<Queue> {
+        self.bounded("rename_queue", self.inner.rename_queue(name, new_name))
+            .await
+    }
+
+    async fn get_queue_stats(&self, name: &str) -> Result<QueueStats> {
+        self.bounded("get_queue_stats", self.inner.get_queue_stats(name))
+            .await
+    }
+
+    async fn list_queues_with_stats(&self) -> Result<Vec<(Queue, QueueStats)>> {
+        self.bounded(
+            "list_queues_with_stats",
+            self.inner.list_queues_with_stats(),
+        )
+        .await
+    }
+
+    async fn reset_metrics(&self, name: &str) -> Result<()> {
+        self.bounded("reset_metrics", self.inner.reset_metrics(name))
+            .await
+    }
+
+    async fn replace_queue_config(&self, name: &str, config: QueueConfig) -> Result<Queue> {
+        self.bounded(
+            "replace_queue_config",
+            self.inner.replace_queue_config(name, config),
+        )
+        .await
+    }
+
+    async fn push_message(&self, queue_name: &str, message: Message) -> Result<MessageId> {
+        self.bounded("push_message", self.inner.push_message(queue_name, message))
+            .await
+    }
+
+    async fn pop_message(&self, queue_name: &str) -> Result<Option<Message>> {
+        self.bounded("pop_message", self.inner.pop_message(queue_name))
+            .await
+    }
+
+    async fn pop_messages(&self, queue_name: &str, max: usize) -> Result<Vec<Message>> {
+        self.bounded("pop_messages", self.inner.pop_messages(queue_name, max))
+            .await
+    }
+
+    async fn pop_messages_filtered(
+        &self,
+        queue_name: &str,
+        max: usize,
+        filter: &PopFilter,
+    ) -> Result<Vec<Message>> {
+        self.bounded(
+            "pop_messages_filtered",
+            self.inner.pop_messages_filtered(queue_name, max, filter),
+        )
+        .await
+    }
+
+    async fn pop_messages_as(
+        &self,
+        queue_name: &str,
+        max: usize,
+        consumer: &str,
+        reclaim: bool,
+    ) -> Result<Vec<Message>> {
+        self.bounded(
+            "pop_messages_as",
+            self.inner
+                .pop_messages_as(queue_name, max, consumer, reclaim),
+        )
+        .await
+    }
+
+    async fn peek_message(&self, queue_name: &str) -> Result<Option<Message>> {
+        self.bounded("peek_message", self.inner.peek_message(queue_name))
+            .await
+    }
+
+    async fn peek_messages(&self, queue_name: &str, max: usize) -> Result<Vec<Message>> {
+        self.bounded("peek_messages", self.inner.peek_messages(queue_name, max))
+            .await
+    }
+
+    async fn peek_page(
+        &self,
+        queue_name: &str,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> Result<MessagePage> {
+        self.bounded("peek_page", self.inner.peek_page(queue_name, cursor, limit))
+            .await
+    }
+
+    async fn ack_message(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
+        self.bounded(
+            "ack_message",
+            self.inner.ack_message(queue_name, message_id),
+        )
+        .await
+    }
+
+    async fn nack_message(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
+        self.bounded(
+            "nack_message",
+            self.inner.nack_message(queue_name, message_id),
+        )
+        .await
+    }
+
+    async fn nack_with_priority(
+        &self,
+        queue_name: &str,
+        message_id: &MessageId,
+        priority: u8,
+    ) -> Result<()> {
+        self.bounded(
+            "nack_with_priority",
+            self.inner
+                .nack_with_priority(queue_name, message_id, priority),
+        )
+        .await
+    }
+
+    async fn reset_delivery_count(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
+        self.bounded(
+            "reset_delivery_count",
+            self.inner.reset_delivery_count(queue_name, message_id),
+        )
+        .await
+    }
+
+    async fn change_visibility(
+        &self,
+        queue_name: &str,
+        message_id: &MessageId,
+        timeout_secs: u64,
+    ) -> Result<DateTime<Utc>> {
+        self.bounded(
+            "change_visibility",
+            self.inner
+                .change_visibility(queue_name, message_id, timeout_secs),
+        )
+        .await
+    }
+
+    async fn move_to_dlq(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
+        self.bounded(
+            "move_to_dlq",
+            self.inner.move_to_dlq(queue_name, message_id),
+        )
+        .await
+    }
+
+    async fn list_quarantined(&self, queue_name: &str) -> Result<Vec<Message>> {
+        self.bounded("list_quarantined", self.inner.list_quarantined(queue_name))
+            .await
+    }
+
+    async fn list_acked(&self, queue_name: &str) -> Result<Vec<Message>> {
+        self.bounded("list_acked", self.inner.list_acked(queue_name))
+            .await
+    }
+
+    async fn release_quarantined(&self, queue_name: &str) -> Result<u64> {
+        self.bounded(
+            "release_quarantined",
+            self.inner.release_quarantined(queue_name),
+        )
+        .await
+    }
+
+    async fn get_message(
+        &self,
+        queue_name: &str,
+        message_id: &MessageId,
+    ) -> Result<Option<Message>> {
+        self.bounded(
+            "get_message",
+            self.inner.get_message(queue_name, message_id),
+        )
+        .await
+    }
+
+    async fn export_messages(&self, queue_name: &str) -> Result<Vec<Message>> {
+        self.bounded("export_messages", self.inner.export_messages(queue_name))
+            .await
+    }
+
+    async fn restore_message(&self, queue_name: &str, message: Message) -> Result<()> {
+        self.bounded(
+            "restore_message",
+            self.inner.restore_message(queue_name, message),
+        )
+        .await
+    }
+
+    async fn purge_queue(&self, queue_name: &str) -> Result<u64> {
+        self.bounded("purge_queue", self.inner.purge_queue(queue_name))
+            .await
+    }
+
+    async fn purge_older_than(&self, queue_name: &str, cutoff: DateTime<Utc>) -> Result<u64> {
+        self.bounded(
+            "purge_older_than",
+            self.inner.purge_older_than(queue_name, cutoff),
+        )
+        .await
+    }
+
+    async fn search_messages(
+        &self,
+        queue_name: &str,
+        criteria: &SearchCriteria,
+    ) -> Result<Vec<Message>> {
+        self.bounded(
+            "search_messages",
+            self.inner.search_messages(queue_name, criteria),
+        )
+        .await
+    }
+
+    async fn count_messages(&self, queue_name: &str, criteria: &SearchCriteria) -> Result<u64> {
+        self.bounded(
+            "count_messages",
+            self.inner.count_messages(queue_name, criteria),
+        )
+        .await
+    }
+
+    async fn list_message_ids(
+        &self,
+        queue_name: &str,
+        filter: MessageStateFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<MessageId>> {
+        self.bounded(
+            "list_message_ids",
+            self.inner
+                .list_message_ids(queue_name, filter, offset, limit),
+        )
+        .await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.bounded("flush", self.inner.flush()).await
+    }
+
+    async fn cleanup_expired(&self, limit: usize) -> Result<u64> {
+        self.bounded("cleanup_expired", self.inner.cleanup_expired(limit))
+            .await
+    }
+
+    async fn requeue_timed_out(&self) -> Result<u64> {
+        self.bounded("requeue_timed_out", self.inner.requeue_timed_out())
+            .await
+    }
+
+    async fn promote_retries(&self) -> Result<u64> {
+        self.bounded("promote_retries", self.inner.promote_retries())
+            .await
+    }
+
+    async fn requeue_all_inflight(&self) -> Result<u64> {
+        self.bounded("requeue_all_inflight", self.inner.requeue_all_inflight())
+            .await
+    }
+
+    async fn dead_letter_overdue(&self) -> Result<u64> {
+        self.bounded("dead_letter_overdue", self.inner.dead_letter_overdue())
+            .await
+    }
+
+    async fn idle_queues(&self) -> Result<Vec<String>> {
+        self.bounded("idle_queues", self.inner.idle_queues()).await
+    }
+
+    async fn delete_idle_queue(&self, name: &str) -> Result<bool> {
+        self.bounded("delete_idle_queue", self.inner.delete_idle_queue(name))
+            .await
+    }
+
+    async fn cleanup_dedup(&self) -> Result<u64> {
+        self.bounded("cleanup_dedup", self.inner.cleanup_dedup())
+            .await
+    }
+
+    async fn prune_acked(&self) -> Result<u64> {
+        self.bounded("prune_acked", self.inner.prune_acked()).await
+    }
+
+    async fn compact(&self) -> Result<u64> {
+        self.bounded("compact", self.inner.compact()).await
+    }
+}
@@ -0,0 +1,21 @@
+//! Records the build time for `GET /api/v1/info`
+//!
+//! Honors `SOURCE_DATE_EPOCH` so reproducible builds get a fixed timestamp.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let epoch_secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|secs| secs.parse::<i64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs() as i64)
+                .unwrap_or_default()
+        });
+
+    println!("cargo:rustc-env=FLOWQ_BUILD_EPOCH={}", epoch_secs);
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
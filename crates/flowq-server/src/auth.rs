@@ -0,0 +1,139 @@
+//! API key authentication
+//!
+//! When API keys are configured, `/api` and `/admin` routes require one in
+//! the `x-api-key` header or as an `Authorization: Bearer` token. Health and
+//! documentation routes stay open.
+//!
+//! Consumer keys are API keys that also name their holder. Requests made with
+//! one carry a `ConsumerIdentity`, which queues with `allowed_consumers` check
+//! before letting the request read or settle messages. Admin and
+//! queue-management routes, which span every queue or could lift a queue's
+//! allowlist, turn consumer keys away altogether.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use flowq_core::Broker;
+use flowq_types::Error;
+
+use crate::problem::ErrorDetails;
+use crate::{ApiErrorBody, AppError};
+
+/// Header carrying an API key
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Accepted API keys
+pub type ApiKeys = Arc<HashSet<String>>;
+
+/// Consumer keys, mapped to the identity each one names
+pub type ConsumerKeys = Arc<HashMap<String, String>>;
+
+/// Identity of the consumer making a request, from its consumer key
+#[derive(Debug, Clone)]
+pub struct ConsumerIdentity(pub String);
+
+/// Whether a request path needs a key
+fn is_protected(path: &str) -> bool {
+    path.starts_with("/api/") || path.starts_with("/admin/")
+}
+
+/// The key presented by a request, if any
+fn presented_key(headers: &HeaderMap) -> Option<&str> {
+    if let Some(key) = headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok()) {
+        return Some(key);
+    }
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+/// Middleware rejecting protected requests without a valid key
+pub async fn require_api_key(
+    State(keys): State<ApiKeys>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !is_protected(request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    match presented_key(request.headers()) {
+        Some(key) if keys.contains(key) => next.run(request).await,
+        _ => {
+            let details = ErrorDetails {
+                code: "UNAUTHORIZED",
+                detail: "Missing or invalid API key".to_string(),
+            };
+            let body = ApiErrorBody {
+                error: details.detail.clone(),
+                code: details.code.to_string(),
+            };
+            (StatusCode::UNAUTHORIZED, Extension(details), Json(body)).into_response()
+        }
+    }
+}
+
+/// Middleware attaching a `ConsumerIdentity` to requests made with a consumer key
+pub async fn identify_consumer(
+    State(keys): State<ConsumerKeys>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let identity = presented_key(request.headers())
+        .and_then(|key| keys.get(key))
+        .cloned();
+    if let Some(identity) = identity {
+        request.extensions_mut().insert(ConsumerIdentity(identity));
+    }
+    next.run(request).await
+}
+
+/// Middleware rejecting requests that read or settle a queue's messages
+/// from consumers not on its `allowed_consumers` list with 403
+///
+/// Missing queues are let through so the handler reports them.
+pub async fn restrict_consumers(
+    State(broker): State<Arc<Broker>>,
+    Path(params): Path<HashMap<String, String>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let Some(queue_name) = params.get("name") else {
+        return Ok(next.run(request).await);
+    };
+    if let Some(queue) = broker.get_queue(queue_name).await? {
+        let identity = request.extensions().get::<ConsumerIdentity>();
+        let identity = identity.map(|ConsumerIdentity(identity)| identity.as_str());
+        if !queue.config.allows_consumer(identity) {
+            return Err(Error::ConsumerNotAllowed(format!(
+                "{} may not consume from {}",
+                identity.unwrap_or("anonymous consumer"),
+                queue_name
+            ))
+            .into());
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Middleware rejecting requests made with a consumer key with 403
+///
+/// Guards admin routes, which reach every queue regardless of its
+/// `allowed_consumers` list, and the routes that reconfigure, empty, rename
+/// or delete a queue, which could otherwise clear that list.
+pub async fn reject_consumers(request: Request, next: Next) -> Result<Response, AppError> {
+    if let Some(ConsumerIdentity(identity)) = request.extensions().get::<ConsumerIdentity>() {
+        return Err(
+            Error::ConsumerNotAllowed(format!("{} may not use admin routes", identity)).into(),
+        );
+    }
+    Ok(next.run(request).await)
+}
@@ -0,0 +1,97 @@
+//! Storage backend selection
+//!
+//! The backend is picked at startup from the `[storage]` config section or
+//! `FLOWQ_STORAGE` (default `memory`), with `FLOWQ_STORAGE_URL` supplying the
+//! connection URL for database backends.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail};
+use flowq_storage::{MemoryStorage, StorageEngine};
+
+/// Environment variable selecting the storage backend
+pub const STORAGE_ENV: &str = "FLOWQ_STORAGE";
+
+/// Environment variable holding the backend's connection URL
+pub const STORAGE_URL_ENV: &str = "FLOWQ_STORAGE_URL";
+
+/// Supported storage backends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Memory,
+    Sqlite,
+    Postgres,
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "memory" => Ok(Backend::Memory),
+            "sqlite" => Ok(Backend::Sqlite),
+            "postgres" => Ok(Backend::Postgres),
+            other => bail!(
+                "unknown {} value '{}', expected memory, sqlite or postgres",
+                STORAGE_ENV,
+                other
+            ),
+        }
+    }
+}
+
+/// Build the storage engine for a backend selection
+///
+/// `kind` defaults to memory. Database backends require a connection URL.
+/// `max_total_in_flight` caps in-flight messages across all queues (0 = unlimited).
+pub fn build_storage(
+    kind: Option<&str>,
+    url: Option<&str>,
+    max_total_in_flight: usize,
+) -> anyhow::Result<Arc<dyn StorageEngine>> {
+    let backend = kind
+        .map(Backend::from_str)
+        .transpose()?
+        .unwrap_or(Backend::Memory);
+
+    match backend {
+        Backend::Memory => Ok(Arc::new(
+            MemoryStorage::new().with_max_total_in_flight(max_total_in_flight),
+        )),
+        Backend::Sqlite | Backend::Postgres => {
+            url.ok_or_else(|| anyhow!("{:?} storage requires {}", backend, STORAGE_URL_ENV))?;
+            bail!("{:?} storage is not available in this build", backend)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_backend() {
+        assert_eq!("memory".parse::<Backend>().unwrap(), Backend::Memory);
+        assert_eq!("SQLite".parse::<Backend>().unwrap(), Backend::Sqlite);
+        assert_eq!("postgres".parse::<Backend>().unwrap(), Backend::Postgres);
+
+        let err = "redis".parse::<Backend>().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("unknown FLOWQ_STORAGE value 'redis'"));
+    }
+
+    #[tokio::test]
+    async fn test_build_storage() {
+        let storage = build_storage(None, None, 0).unwrap();
+        assert!(storage.list_queues().await.unwrap().is_empty());
+        assert!(build_storage(Some("memory"), None, 100).is_ok());
+
+        let err = build_storage(Some("sqlite"), None, 0).err().unwrap();
+        assert!(err.to_string().contains(STORAGE_URL_ENV));
+        let url = Some("postgres://localhost/flowq");
+        assert!(build_storage(Some("postgres"), url, 0).is_err());
+        assert!(build_storage(Some("bogus"), None, 0).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! Request and response body logging
+//!
+//! A debugging aid, off by default since bodies are message payloads. When
+//! enabled, every request and its response are logged with their headers and
+//! bodies. Bodies are cut to a configured length, and headers carrying
+//! credentials are redacted.
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{HeaderMap, Method, StatusCode, Uri},
+    middleware::Next,
+    response::Response,
+};
+use tokio_stream::StreamExt;
+use tracing::info;
+
+use crate::auth::API_KEY_HEADER;
+
+/// Default number of body bytes logged
+pub const DEFAULT_MAX_BYTES: usize = 1024;
+
+/// Headers whose values are never logged
+const REDACTED_HEADERS: [&str; 5] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    API_KEY_HEADER,
+];
+
+/// Render headers for the log, hiding credentials
+fn redacted(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if REDACTED_HEADERS.contains(&name.as_str()) {
+                "[REDACTED]"
+            } else {
+                value.to_str().unwrap_or("[binary]")
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Render the first bytes of a body of `total` bytes for the log
+fn truncated(head: &[u8], total: usize) -> String {
+    if head.len() == total {
+        return String::from_utf8_lossy(head).into_owned();
+    }
+    format!(
+        "{}... ({} bytes total)",
+        String::from_utf8_lossy(head),
+        total
+    )
+}
+
+/// What a logged body belongs to
+enum Origin {
+    Request {
+        method: Method,
+        uri: Uri,
+        headers: String,
+    },
+    Response {
+        status: StatusCode,
+        headers: String,
+    },
+}
+
+/// The first `max_bytes` of a body passing through, logged once it is
+/// finished or dropped
+struct BodyLog {
+    origin: Origin,
+    head: Vec<u8>,
+    total: usize,
+    max_bytes: usize,
+}
+
+impl BodyLog {
+    fn new(origin: Origin, max_bytes: usize) -> Self {
+        Self {
+            origin,
+            head: Vec::new(),
+            total: 0,
+            max_bytes,
+        }
+    }
+
+    fn record(&mut self, chunk: &[u8]) {
+        let room = self.max_bytes.saturating_sub(self.head.len());
+        self.head.extend_from_slice(&chunk[..room.min(chunk.len())]);
+        self.total += chunk.len();
+    }
+}
+
+impl Drop for BodyLog {
+    fn drop(&mut self) {
+        let body = truncated(&self.head, self.total);
+        match &self.origin {
+            Origin::Request {
+                method,
+                uri,
+                headers,
+            } => info!(method = %method, uri = %uri, headers = %headers, body = %body, "Request"),
+            Origin::Response { status, headers } => {
+                info!(status = %status, headers = %headers, body = %body, "Response")
+            }
+        }
+    }
+}
+
+/// Pass a body through unchanged, copying its first bytes into `log`
+fn tee(body: Body, mut log: BodyLog) -> Body {
+    Body::from_stream(body.into_data_stream().map(move |chunk| {
+        if let Ok(bytes) = &chunk {
+            log.record(bytes);
+        }
+        chunk
+    }))
+}
+
+/// Middleware logging request and response bodies, each cut to `max_bytes`
+///
+/// Bodies are streamed through, not buffered: each is logged once it has been
+/// read, so body limits and streaming handlers work as without logging.
+pub async fn log_bodies(State(max_bytes): State<usize>, request: Request, next: Next) -> Response {
+    let (parts, body) = request.into_parts();
+    let origin = Origin::Request {
+        method: parts.method.clone(),
+        uri: parts.uri.clone(),
+        headers: redacted(&parts.headers),
+    };
+    let request = Request::from_parts(parts, tee(body, BodyLog::new(origin, max_bytes)));
+    let response = next.run(request).await;
+
+    let (parts, body) = response.into_parts();
+    let origin = Origin::Response {
+        status: parts.status,
+        headers: redacted(&parts.headers),
+    };
+    Response::from_parts(parts, tee(body, BodyLog::new(origin, max_bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use axum::{extract::DefaultBodyLimit, middleware, routing::post, Router};
+    use tower::Service;
+
+    /// Log output captured by the test subscriber
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Echo a request through a router, returning the response body and the log
+    async fn echo(log_bodies_up_to: Option<usize>) -> (String, String) {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let router = Router::new().route("/echo", post(|body: String| async move { body }));
+        let mut router = match log_bodies_up_to {
+            Some(max_bytes) => router.layer(middleware::from_fn_with_state(max_bytes, log_bodies)),
+            None => router,
+        };
+        let request = axum::http::Request::post("/echo")
+            .header(API_KEY_HEADER, "secret-key")
+            .body(Body::from("ping-payload-0123456789"))
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        let log = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        (String::from_utf8(body.to_vec()).unwrap(), log)
+    }
+
+    #[tokio::test]
+    async fn test_bodies_logged_only_when_enabled() {
+        let (body, log) = echo(Some(12)).await;
+        assert_eq!(body, "ping-payload-0123456789");
+        assert!(log.contains("ping-payload... (23 bytes total)"), "{}", log);
+        assert!(!log.contains("0123456789"), "{}", log);
+        assert!(log.contains("x-api-key: [REDACTED]"), "{}", log);
+        assert!(!log.contains("secret-key"), "{}", log);
+
+        let (body, log) = echo(None).await;
+        assert_eq!(body, "ping-payload-0123456789");
+        assert!(!log.contains("ping-payload"), "{}", log);
+    }
+
+    #[tokio::test]
+    async fn test_body_limit_still_applies() {
+        let mut router = Router::new()
+            .route("/echo", post(|body: String| async move { body }))
+            .layer(DefaultBodyLimit::max(8))
+            .layer(middleware::from_fn_with_state(4usize, log_bodies));
+        let request = axum::http::Request::post("/echo")
+            .body(Body::from("ping-payload-0123456789"))
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
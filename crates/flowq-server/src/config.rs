@@ -0,0 +1,375 @@
+//! Server configuration
+//!
+//! Settings are layered, each overriding the last: built-in defaults, a TOML
+//! file passed with `--config`, `FLOWQ_*` environment variables, and finally
+//! command-line flags. Fields missing from the file keep their defaults.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::backend::{STORAGE_ENV, STORAGE_URL_ENV};
+use crate::{bodylog, cors, webhook};
+use crate::{DEFAULT_MAX_RAW_BODY_BYTES, DEFAULT_MAX_RECEIVE_BATCH, DEFAULT_MAX_RESTORE_BYTES};
+
+/// Environment variable overriding the listen address
+pub const BIND_ENV: &str = "FLOWQ_BIND";
+
+/// Environment variable overriding the maintenance interval, in seconds
+pub const MAINTENANCE_INTERVAL_ENV: &str = "FLOWQ_MAINTENANCE_INTERVAL_SECS";
+
+/// Environment variable overriding the API keys (comma-separated)
+pub const API_KEYS_ENV: &str = "FLOWQ_API_KEYS";
+
+/// Environment variable overriding the allowed CORS origins (comma-separated)
+pub const CORS_ORIGINS_ENV: &str = "FLOWQ_CORS_ORIGINS";
+
+/// Complete server configuration
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address the HTTP server listens on
+    pub bind: String,
+    /// Create queues with default config when publishing to a missing queue
+    pub auto_create_queues: bool,
+    /// Generate time-ordered UUIDv7 message IDs instead of random UUIDv4
+    pub time_ordered_ids: bool,
+    /// Reject invalid publish fields with 400 instead of correcting them
+    pub strict_publish: bool,
+    /// Render errors as RFC 7807 `application/problem+json` documents
+    pub problem_json: bool,
+    /// Serve reads only, rejecting writes with 403 `READ_ONLY`
+    pub read_only: bool,
+    /// `[storage]` section
+    pub storage: StorageConfig,
+    /// `[maintenance]` section
+    pub maintenance: MaintenanceConfig,
+    /// `[auth]` section
+    pub auth: AuthConfig,
+    /// `[cors]` section
+    pub cors: CorsConfig,
+    /// `[limits]` section
+    pub limits: LimitsConfig,
+    /// `[body_log]` section
+    pub body_log: BodyLogConfig,
+}
+
+/// Storage backend selection
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// `memory`, `sqlite` or `postgres`
+    pub backend: String,
+    /// Connection URL for database backends
+    pub url: Option<String>,
+}
+
+/// Background maintenance settings
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaintenanceConfig {
+    /// Seconds between maintenance passes
+    pub interval_secs: u64,
+    /// Maximum expired messages removed per pass (0 = unlimited)
+    pub max_cleanup_per_tick: usize,
+}
+
+/// API key authentication
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Keys accepted on API routes; empty disables authentication
+    pub api_keys: Vec<String>,
+    /// Consumer identities and their keys; each key is accepted like an API
+    /// key and identifies its holder to queues' `allowed_consumers`
+    pub consumer_keys: BTreeMap<String, String>,
+}
+
+/// Cross-origin request settings
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Origins allowed to call the API (`*` for any); empty disables CORS
+    pub allowed_origins: Vec<String>,
+}
+
+/// Request and response body logging, for debugging
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BodyLogConfig {
+    /// Log request and response bodies; off by default as they hold payloads
+    pub enabled: bool,
+    /// Bytes of each body logged; the rest is cut off
+    pub max_bytes: usize,
+}
+
+/// Broker and request limits
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Maximum number of queues (0 = unlimited)
+    pub max_queues: usize,
+    /// Maximum concurrent receive requests across all clients (0 = unlimited)
+    pub max_concurrent_receives: usize,
+    /// Maximum concurrent receive requests per `x-tenant-id` (0 = unlimited)
+    pub max_concurrent_receives_per_tenant: usize,
+    /// Maximum in-flight messages across all queues (0 = unlimited)
+    pub max_total_in_flight: usize,
+    /// Maximum body size in bytes accepted by the raw publish endpoint
+    pub max_raw_body_bytes: usize,
+    /// Maximum snapshot size in bytes accepted by /admin/restore
+    pub max_restore_bytes: usize,
+    /// Most messages a single receive may return
+    pub max_receive_batch: usize,
+    /// Seconds clients are told to wait (Retry-After) when a queue is full
+    pub queue_full_retry_after: u64,
+    /// Most webhook deliveries in flight at once across all queues
+    pub webhook_pool_size: usize,
+    /// Hosts queue webhooks may be delivered to (empty = webhook delivery off)
+    pub webhook_allowed_hosts: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:3000".to_string(),
+            auto_create_queues: false,
+            time_ordered_ids: false,
+            strict_publish: false,
+            problem_json: false,
+            read_only: false,
+            storage: StorageConfig::default(),
+            maintenance: MaintenanceConfig::default(),
+            auth: AuthConfig::default(),
+            cors: CorsConfig::default(),
+            limits: LimitsConfig::default(),
+            body_log: BodyLogConfig::default(),
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: "memory".to_string(),
+            url: None,
+        }
+    }
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 60,
+            max_cleanup_per_tick: 0,
+        }
+    }
+}
+
+impl Default for BodyLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_bytes: bodylog::DEFAULT_MAX_BYTES,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_queues: 0,
+            max_concurrent_receives: 0,
+            max_concurrent_receives_per_tenant: 0,
+            max_total_in_flight: 0,
+            max_raw_body_bytes: DEFAULT_MAX_RAW_BODY_BYTES,
+            max_restore_bytes: DEFAULT_MAX_RESTORE_BYTES,
+            max_receive_batch: DEFAULT_MAX_RECEIVE_BATCH,
+            queue_full_retry_after: 1,
+            webhook_pool_size: webhook::DEFAULT_POOL_SIZE,
+            webhook_allowed_hosts: Vec::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Load the config file, if any, and apply environment overrides
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let mut config = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("failed to read config {}", path.display()))?;
+                Self::from_toml(&text)
+                    .with_context(|| format!("invalid config {}", path.display()))?
+            }
+            None => Self::default(),
+        };
+        config.apply_env(|key| std::env::var(key).ok())?;
+        Ok(config)
+    }
+
+    /// Reject settings that cannot work together
+    pub fn validate(&self) -> anyhow::Result<()> {
+        // Consumer keys are accepted like API keys, so on their own they would
+        // quietly turn on authentication for every route
+        if !self.auth.consumer_keys.is_empty() && self.auth.api_keys.is_empty() {
+            anyhow::bail!(
+                "auth.consumer_keys requires auth.api_keys: consumer keys are checked as API keys, \
+                 which would require a key on every route"
+            );
+        }
+        // A zero period would panic the maintenance timer at startup
+        if self.maintenance.interval_secs == 0 {
+            anyhow::bail!("maintenance.interval_secs must be at least 1");
+        }
+        cors::check_origins(&self.cors.allowed_origins)?;
+        Ok(())
+    }
+
+    /// Parse a TOML config document
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Override settings from environment variables looked up with `var`
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> anyhow::Result<()> {
+        if let Some(bind) = var(BIND_ENV) {
+            self.bind = bind;
+        }
+        if let Some(backend) = var(STORAGE_ENV) {
+            self.storage.backend = backend;
+        }
+        if let Some(url) = var(STORAGE_URL_ENV) {
+            self.storage.url = Some(url);
+        }
+        if let Some(secs) = var(MAINTENANCE_INTERVAL_ENV) {
+            self.maintenance.interval_secs = secs.trim().parse().with_context(|| {
+                format!("invalid {} value '{}'", MAINTENANCE_INTERVAL_ENV, secs)
+            })?;
+        }
+        if let Some(keys) = var(API_KEYS_ENV) {
+            self.auth.api_keys = split_list(&keys);
+        }
+        if let Some(origins) = var(CORS_ORIGINS_ENV) {
+            self.cors.allowed_origins = split_list(&origins);
+        }
+        Ok(())
+    }
+}
+
+/// Split a comma-separated list, dropping empty entries
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const SAMPLE: &str = r#"
+bind = "0.0.0.0:8080"
+auto_create_queues = true
+
+[storage]
+backend = "postgres"
+url = "postgres://localhost/flowq"
+
+[maintenance]
+interval_secs = 15
+
+[auth]
+api_keys = ["k1", "k2"]
+
+[cors]
+allowed_origins = ["https://app.example.com"]
+
+[limits]
+max_queues = 50
+max_receive_batch = 25
+"#;
+
+    #[test]
+    fn test_sample_config() {
+        let config = ServerConfig::from_toml(SAMPLE).unwrap();
+        assert_eq!(config.bind, "0.0.0.0:8080");
+        assert!(config.auto_create_queues);
+        assert_eq!(config.storage.backend, "postgres");
+        assert_eq!(
+            config.storage.url.as_deref(),
+            Some("postgres://localhost/flowq")
+        );
+        assert_eq!(config.maintenance.interval_secs, 15);
+        assert_eq!(config.auth.api_keys, ["k1", "k2"]);
+        assert_eq!(config.cors.allowed_origins, ["https://app.example.com"]);
+        assert_eq!(config.limits.max_queues, 50);
+        assert_eq!(config.limits.max_receive_batch, 25);
+
+        // Fields missing from a section keep their defaults
+        assert_eq!(config.maintenance.max_cleanup_per_tick, 0);
+        assert_eq!(config.limits.max_raw_body_bytes, DEFAULT_MAX_RAW_BODY_BYTES);
+        assert_eq!(config.limits.queue_full_retry_after, 1);
+        assert!(!config.strict_publish);
+    }
+
+    #[test]
+    fn test_empty_config_is_default() {
+        assert_eq!(
+            ServerConfig::from_toml("").unwrap(),
+            ServerConfig::default()
+        );
+        assert!(ServerConfig::from_toml("bogus = 1").is_err());
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let env: HashMap<&str, &str> = [
+            (BIND_ENV, "0.0.0.0:9000"),
+            (API_KEYS_ENV, "a, b,,"),
+            (MAINTENANCE_INTERVAL_ENV, "5"),
+        ]
+        .into();
+        let mut config = ServerConfig::from_toml(SAMPLE).unwrap();
+        config
+            .apply_env(|key| env.get(key).map(|v| v.to_string()))
+            .unwrap();
+
+        assert_eq!(config.bind, "0.0.0.0:9000");
+        assert_eq!(config.auth.api_keys, ["a", "b"]);
+        assert_eq!(config.maintenance.interval_secs, 5);
+        assert_eq!(config.storage.backend, "postgres");
+
+        let bad = |key: &str| (key == MAINTENANCE_INTERVAL_ENV).then(|| "soon".to_string());
+        assert!(config.apply_env(bad).is_err());
+    }
+
+    #[test]
+    fn test_consumer_keys_require_api_keys() {
+        let mut config = ServerConfig::from_toml(SAMPLE).unwrap();
+        config
+            .auth
+            .consumer_keys
+            .insert("billing".to_string(), "billing-secret".to_string());
+        config.validate().unwrap();
+
+        config.auth.api_keys.clear();
+        assert!(config.validate().is_err());
+
+        config.auth.consumer_keys.clear();
+        config.validate().unwrap();
+
+        config.cors.allowed_origins = vec!["https://app.example.com/".to_string()];
+        assert!(config.validate().is_err());
+        config.cors.allowed_origins.clear();
+
+        config.maintenance.interval_secs = 0;
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,88 @@
+//! Cross-origin access for browser clients
+//!
+//! Configured origins are checked at startup, so a typo fails loudly instead
+//! of leaving that origin quietly blocked.
+
+use anyhow::Context;
+use axum::http::HeaderValue;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+/// Check that every configured origin is `*` or a bare `http(s)` origin,
+/// as browsers send it in the `Origin` header
+pub fn check_origins(origins: &[String]) -> anyhow::Result<()> {
+    for origin in origins {
+        parse_origin(origin)?;
+    }
+    Ok(())
+}
+
+/// Parse one origin, rejecting paths, trailing slashes and other schemes
+fn parse_origin(origin: &str) -> anyhow::Result<Option<HeaderValue>> {
+    if origin == "*" {
+        return Ok(None);
+    }
+    let url =
+        reqwest::Url::parse(origin).with_context(|| format!("invalid CORS origin '{}'", origin))?;
+    let serialized = url.origin().ascii_serialization();
+    if !matches!(url.scheme(), "http" | "https") || serialized != origin {
+        anyhow::bail!(
+            "invalid CORS origin '{}': expected scheme, host and optional port only, like '{}'",
+            origin,
+            serialized
+        );
+    }
+    Ok(Some(HeaderValue::from_str(origin)?))
+}
+
+/// CORS layer for the allowed origins, or None if none are configured
+pub fn cors_layer(origins: &[String]) -> anyhow::Result<Option<CorsLayer>> {
+    if origins.is_empty() {
+        return Ok(None);
+    }
+    let mut allowed = Vec::with_capacity(origins.len());
+    for origin in origins {
+        match parse_origin(origin)? {
+            Some(origin) => allowed.push(origin),
+            None => {
+                allowed.clear();
+                break;
+            }
+        }
+    }
+    let allow_origin = if allowed.is_empty() {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(allowed)
+    };
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods(Any)
+            .allow_headers(Any),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_origins() {
+        let valid = ["https://app.example.com", "http://localhost:8080", "*"];
+        check_origins(&valid.map(String::from)).unwrap();
+
+        for invalid in [
+            "app.example.com",
+            "https://app.example.com/",
+            "https://app.example.com/path",
+            "ftp://files.example.com",
+            "not an origin",
+        ] {
+            assert!(
+                check_origins(&[invalid.to_string()]).is_err(),
+                "{} accepted",
+                invalid
+            );
+        }
+    }
+}
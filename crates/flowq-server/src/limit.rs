@@ -0,0 +1,162 @@
+//! Receive concurrency limiting
+//!
+//! Caps how many receive handlers run at once, globally and optionally per
+//! tenant, so a single client cannot tie up the server with concurrent receives.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::problem::ErrorDetails;
+use crate::ApiErrorBody;
+
+/// Header identifying the tenant for per-tenant limits
+pub const TENANT_HEADER: &str = "x-tenant-id";
+
+/// Permits held for the duration of a single receive request
+pub struct ReceivePermit {
+    _global: Option<OwnedSemaphorePermit>,
+    _tenant: Option<OwnedSemaphorePermit>,
+}
+
+/// Semaphore-based limit on concurrent receive handlers
+pub struct ReceiveLimiter {
+    /// Global limit (None = unlimited)
+    global: Option<Arc<Semaphore>>,
+    /// Limit per tenant (0 = unlimited)
+    per_tenant: usize,
+    /// Per-tenant semaphores, created on a tenant's first receive and
+    /// dropped once none of its receives are running
+    tenants: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl ReceiveLimiter {
+    /// Create a limiter; 0 disables the corresponding limit
+    pub fn new(max_global: usize, max_per_tenant: usize) -> Self {
+        Self {
+            global: (max_global > 0).then(|| Arc::new(Semaphore::new(max_global))),
+            per_tenant: max_per_tenant,
+            tenants: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Try to reserve a slot, returning `None` if a limit is saturated
+    pub fn try_acquire(&self, tenant: Option<&str>) -> Option<ReceivePermit> {
+        let global = match &self.global {
+            Some(semaphore) => Some(Arc::clone(semaphore).try_acquire_owned().ok()?),
+            None => None,
+        };
+
+        let tenant = match tenant {
+            Some(tenant) if self.per_tenant > 0 => {
+                Some(self.tenant_semaphore(tenant).try_acquire_owned().ok()?)
+            }
+            _ => None,
+        };
+
+        Some(ReceivePermit {
+            _global: global,
+            _tenant: tenant,
+        })
+    }
+
+    fn tenant_semaphore(&self, tenant: &str) -> Arc<Semaphore> {
+        let mut tenants = self.tenants.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(semaphore) = tenants.get(tenant) {
+            return Arc::clone(semaphore);
+        }
+
+        // Tenant IDs come from clients, so before adding one drop every
+        // tenant whose semaphore only the map still holds; permits keep
+        // their semaphore alive, so these have no receive running
+        tenants.retain(|_, semaphore| Arc::strong_count(semaphore) > 1);
+        let semaphore = Arc::new(Semaphore::new(self.per_tenant));
+        tenants.insert(tenant.to_string(), Arc::clone(&semaphore));
+        semaphore
+    }
+}
+
+/// Middleware rejecting receives with 429 once the limiter is saturated
+pub async fn limit_receives(
+    State(limiter): State<Arc<ReceiveLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let tenant = request
+        .headers()
+        .get(TENANT_HEADER)
+        .and_then(|v| v.to_str().ok());
+
+    match limiter.try_acquire(tenant) {
+        Some(_permit) => next.run(request).await,
+        None => too_many_requests(),
+    }
+}
+
+fn too_many_requests() -> Response {
+    let details = ErrorDetails {
+        code: "TOO_MANY_REQUESTS",
+        detail: "Too many concurrent receive requests".to_string(),
+    };
+    let body = ApiErrorBody {
+        error: details.detail.clone(),
+        code: details.code.to_string(),
+    };
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        Extension(details),
+        Json(body),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_global_limit_saturates() {
+        let limiter = ReceiveLimiter::new(1, 0);
+
+        let permit = limiter.try_acquire(None).unwrap();
+        assert!(limiter.try_acquire(None).is_none());
+
+        drop(permit);
+        assert!(limiter.try_acquire(None).is_some());
+    }
+
+    #[test]
+    fn test_per_tenant_limit() {
+        let limiter = ReceiveLimiter::new(0, 1);
+
+        let _a = limiter.try_acquire(Some("a")).unwrap();
+        assert!(limiter.try_acquire(Some("a")).is_none());
+        assert!(limiter.try_acquire(Some("b")).is_some());
+        assert!(limiter.try_acquire(None).is_some());
+    }
+
+    #[test]
+    fn test_idle_tenants_are_evicted() {
+        let limiter = ReceiveLimiter::new(0, 1);
+
+        let _busy = limiter.try_acquire(Some("busy")).unwrap();
+        for i in 0..100 {
+            limiter.try_acquire(Some(&format!("t{}", i))).unwrap();
+        }
+
+        // Only the tenant still receiving and the latest one are kept
+        let tenants = limiter.tenants.lock().unwrap();
+        assert_eq!(tenants.len(), 2);
+        assert!(tenants.contains_key("busy"));
+        drop(tenants);
+        assert!(limiter.try_acquire(Some("busy")).is_none());
+    }
+}
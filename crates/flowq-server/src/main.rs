@@ -2,24 +2,225 @@
 //!
 //! This is the main entry point for the FlowQ message broker.
 
+mod auth;
+mod backend;
+mod bodylog;
+mod config;
+mod cors;
+mod limit;
+mod ndjson;
+mod prefetch;
+mod pretty;
+mod problem;
+mod projection;
+mod validation;
+mod webhook;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
+use auth::{
+    identify_consumer, reject_consumers, require_api_key, restrict_consumers, ApiKeys, ConsumerKeys,
+};
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
-    Json, Router,
+    body::{Body, Bytes},
+    extract::{rejection::BytesRejection, DefaultBodyLimit, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Extension, Json, Router,
 };
+use bodylog::log_bodies;
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use config::ServerConfig;
 use flowq_core::Broker;
-use flowq_storage::MemoryStorage;
-use flowq_types::{Error, Message, Queue, QueueConfig, QueueStats};
+use flowq_types::{
+    AttributeValue, BodyEncoding, DeadLetterReason, DeliveryStrategy, Durability, Error, Message,
+    MessageId, MessageStateFilter, MessageStatus, PopFilter, QuarantineReason, Queue, QueueConfig,
+    QueueSelector, QueueStats, ReconcileSnapshot, RequeuePosition, SearchCriteria,
+    TimeOrderedIdGenerator,
+};
+use limit::{limit_receives, ReceiveLimiter};
+use ndjson::{Line, LineSplitter};
+use prefetch::{PrefetchTracker, CONSUMER_HEADER};
+use pretty::pretty_print;
+use problem::{render_problems, ErrorDetails, ProblemDetails};
+use projection::Projection;
 use serde::{Deserialize, Serialize};
+use tokio_stream::StreamExt;
+use tokio_util::io::ReaderStream;
 use tower_http::trace::TraceLayer;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use utoipa::{OpenApi, ToSchema};
 use utoipa_swagger_ui::SwaggerUi;
+use validation::{validate_publish, validation_failed, FieldError, ValidationErrorBody};
+
+// ==================== CLI ====================
+
+/// FlowQ message broker server
+///
+/// Flags override the config file and environment; see `config` for the layering.
+/// On/off flags take an optional value, so `--read-only=false` turns off a
+/// setting the file turns on.
+#[derive(Debug, Parser)]
+#[command(name = "flowq", version, about)]
+struct Cli {
+    /// TOML config file
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Address to listen on (default: 127.0.0.1:3000)
+    #[arg(long)]
+    bind: Option<String>,
+
+    /// Create queues with default config when publishing to a missing queue
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    auto_create_queues: Option<bool>,
+
+    /// Maximum number of queues (0 = unlimited)
+    #[arg(long)]
+    max_queues: Option<usize>,
+
+    /// Generate time-ordered UUIDv7 message IDs instead of random UUIDv4
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    time_ordered_ids: Option<bool>,
+
+    /// Maximum concurrent receive requests across all clients (0 = unlimited)
+    #[arg(long)]
+    max_concurrent_receives: Option<usize>,
+
+    /// Maximum concurrent receive requests per `x-tenant-id` header value (0 = unlimited)
+    #[arg(long)]
+    max_concurrent_receives_per_tenant: Option<usize>,
+
+    /// Maximum expired messages removed per maintenance pass (0 = unlimited)
+    #[arg(long)]
+    max_cleanup_per_tick: Option<usize>,
+
+    /// Maximum in-flight messages across all queues; receives return nothing
+    /// once reached (0 = unlimited)
+    #[arg(long)]
+    max_total_in_flight: Option<usize>,
+
+    /// Reject invalid publish fields with 400 instead of correcting them
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    strict_publish: Option<bool>,
+
+    /// Seconds clients are told to wait (Retry-After) when a queue is full (default: 1)
+    #[arg(long)]
+    queue_full_retry_after: Option<u64>,
+
+    /// Maximum body size in bytes accepted by the raw publish endpoint (default: 10 MiB)
+    #[arg(long)]
+    max_raw_body_bytes: Option<usize>,
+
+    /// Maximum snapshot size in bytes accepted by /admin/restore (default: 1 GiB)
+    #[arg(long)]
+    max_restore_bytes: Option<usize>,
+
+    /// Most messages a single receive may return; larger `max` values are clamped (default: 100)
+    #[arg(long)]
+    max_receive_batch: Option<usize>,
+
+    /// Render errors as RFC 7807 `application/problem+json` documents
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    problem_json: Option<bool>,
+
+    /// Serve reads only; publishes, receives, acks and queue changes get 403
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    read_only: Option<bool>,
+
+    /// Most webhook deliveries in flight at once across all queues (default: 16)
+    #[arg(long)]
+    webhook_pool_size: Option<usize>,
+
+    /// Log request and response bodies, with credentials redacted (for debugging)
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    log_bodies: Option<bool>,
+
+    /// Bytes of each body logged by `--log-bodies` (default: 1024)
+    #[arg(long)]
+    log_body_max_bytes: Option<usize>,
+}
+
+impl Cli {
+    /// Apply the flags that were given on top of `config`
+    fn apply(self, config: &mut ServerConfig) {
+        if let Some(bind) = self.bind {
+            config.bind = bind;
+        }
+        let switches = [
+            (&mut config.auto_create_queues, self.auto_create_queues),
+            (&mut config.time_ordered_ids, self.time_ordered_ids),
+            (&mut config.strict_publish, self.strict_publish),
+            (&mut config.problem_json, self.problem_json),
+            (&mut config.read_only, self.read_only),
+            (&mut config.body_log.enabled, self.log_bodies),
+        ];
+        for (setting, flag) in switches {
+            if let Some(value) = flag {
+                *setting = value;
+            }
+        }
+
+        let limits = &mut config.limits;
+        let overrides = [
+            (&mut limits.max_queues, self.max_queues),
+            (
+                &mut limits.max_concurrent_receives,
+                self.max_concurrent_receives,
+            ),
+            (
+                &mut limits.max_concurrent_receives_per_tenant,
+                self.max_concurrent_receives_per_tenant,
+            ),
+            (&mut limits.max_total_in_flight, self.max_total_in_flight),
+            (&mut limits.max_raw_body_bytes, self.max_raw_body_bytes),
+            (&mut limits.max_restore_bytes, self.max_restore_bytes),
+            (&mut limits.max_receive_batch, self.max_receive_batch),
+            (&mut limits.webhook_pool_size, self.webhook_pool_size),
+            (
+                &mut config.maintenance.max_cleanup_per_tick,
+                self.max_cleanup_per_tick,
+            ),
+            (&mut config.body_log.max_bytes, self.log_body_max_bytes),
+        ];
+        for (setting, flag) in overrides {
+            if let Some(value) = flag {
+                *setting = value;
+            }
+        }
+        if let Some(secs) = self.queue_full_retry_after {
+            limits.queue_full_retry_after = secs;
+        }
+    }
+}
+
+/// Default raw publish body limit (10 MiB)
+const DEFAULT_MAX_RAW_BODY_BYTES: usize = 10 * 1024 * 1024;
+
+/// Default snapshot size accepted by /admin/restore (1 GiB)
+const DEFAULT_MAX_RESTORE_BYTES: usize = 1024 * 1024 * 1024;
+
+/// Buffer between the snapshot writer task and the streamed response
+const SNAPSHOT_STREAM_BUFFER: usize = 64 * 1024;
+
+/// Default cap on messages returned by one receive
+const DEFAULT_MAX_RECEIVE_BATCH: usize = 100;
+
+/// Longest a receive may wait for messages to arrive
+const MAX_RECEIVE_WAIT_SECS: u64 = 20;
+
+/// Response header carrying the effective `max` when a receive was clamped
+const RECEIVE_CLAMPED_HEADER: &str = "x-receive-max-clamped";
+
+/// Response header carrying the highest `delivery_count` in a received batch
+const MAX_DELIVERY_COUNT_HEADER: &str = "x-max-delivery-count";
 
 // ==================== App State ====================
 
@@ -27,34 +228,163 @@ use utoipa_swagger_ui::SwaggerUi;
 #[derive(Clone)]
 struct AppState {
     broker: Arc<Broker>,
+    receive_limiter: Arc<ReceiveLimiter>,
+    /// Unacked deliveries per consumer, for prefetch limits
+    prefetch: Arc<PrefetchTracker>,
+    /// Retry-After hint, in seconds, for publishes rejected with QueueFull
+    queue_full_retry_after: u64,
+    /// Validate publish requests strictly unless overridden per request
+    strict_publish: bool,
+    /// Body limit for raw publishes, separate from the JSON routes' limit
+    max_raw_body_bytes: usize,
+    /// Body limit for /admin/restore, which takes a whole snapshot
+    max_restore_bytes: usize,
+    /// Cap on messages returned by one receive
+    max_receive_batch: usize,
+    /// Render errors as problem documents instead of `ApiErrorBody`
+    problem_json: bool,
+    /// Keys required on API routes; empty disables authentication
+    api_keys: ApiKeys,
+    /// Keys identifying consumers, for queues' `allowed_consumers`
+    consumer_keys: ConsumerKeys,
+    /// Origins allowed cross-origin access; empty disables CORS
+    cors_origins: Arc<[String]>,
+    /// Bytes of request and response bodies to log; `None` disables logging
+    log_bodies: Option<usize>,
 }
 
 // ==================== Request/Response Types ====================
 
+/// Rename queue request
+#[derive(Debug, Deserialize, ToSchema)]
+struct RenameQueueRequest {
+    /// New name for the queue
+    new_name: String,
+}
+
 /// Create queue request
 #[derive(Debug, Deserialize, ToSchema)]
 struct CreateQueueRequest {
     /// Name of the queue to create
     name: String,
-    /// Optional queue configuration
+    /// Registered config template to start from
+    #[serde(default)]
+    template: Option<String>,
+    /// Optional queue configuration; with `template`, only the fields given
+    /// here override the template's
+    #[serde(default)]
+    #[schema(value_type = Option<QueueConfig>)]
+    config: Option<serde_json::Value>,
+    /// Create the config's `dead_letter_queue`, with default config, if it
+    /// does not exist yet
+    #[serde(default)]
+    auto_create_dlq: bool,
+}
+
+/// Queue listing query parameters
+#[derive(Debug, Deserialize)]
+struct ListQueuesQuery {
+    /// Include each queue's statistics
+    #[serde(default)]
+    with_stats: bool,
+}
+
+/// A queue paired with its statistics
+#[derive(Debug, Serialize, ToSchema)]
+struct QueueWithStats {
+    /// Queue metadata
+    queue: Queue,
+    /// Queue statistics
+    stats: QueueStats,
+}
+
+/// Bulk delete request; set exactly one of `names` or `prefix`
+#[derive(Debug, Deserialize, ToSchema)]
+struct DeleteQueuesRequest {
+    /// Queue names to delete
     #[serde(default)]
-    config: Option<QueueConfig>,
+    names: Option<Vec<String>>,
+    /// Delete every queue whose name starts with this prefix
+    #[serde(default)]
+    prefix: Option<String>,
+}
+
+/// Outcome of deleting one queue in a bulk delete
+#[derive(Debug, Serialize, ToSchema)]
+struct QueueDeleteResult {
+    /// Queue name
+    name: String,
+    /// Whether the queue was deleted
+    deleted: bool,
+    /// Error code when the delete failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    /// Error message when the delete failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Bulk delete response
+#[derive(Debug, Serialize, ToSchema)]
+struct DeleteQueuesResponse {
+    /// Per-queue outcomes
+    results: Vec<QueueDeleteResult>,
 }
 
 /// Publish message request
 #[derive(Debug, Deserialize, ToSchema)]
 struct PublishRequest {
+    /// Client-supplied message ID (UUID); republishing an ID still in the queue is a no-op
+    #[serde(default)]
+    id: Option<String>,
     /// Message body content
     body: String,
     /// Content type (e.g., "application/json")
     #[serde(default)]
     content_type: Option<String>,
-    /// Message priority (1-10, higher = more important)
+    /// Message priority (1 to the queue's `max_priority`, default 10;
+    /// higher = more important)
     #[serde(default)]
     priority: Option<u8>,
     /// Custom message attributes
     #[serde(default)]
-    attributes: Option<std::collections::HashMap<String, String>>,
+    attributes: Option<HashMap<String, String>>,
+    /// Typed message attributes, e.g. `{"amount": {"type": "number", "value": 9.5}}`
+    #[serde(default)]
+    typed_attributes: Option<HashMap<String, AttributeValue>>,
+    /// CRC32 of the body as 8 hex digits; the publish is rejected if it does not match
+    #[serde(default)]
+    checksum: Option<String>,
+    /// Never redeliver; a nack sends the message straight to the dead letter queue
+    #[serde(default)]
+    no_retry: bool,
+    /// Latest time the message is worth processing; a message still queued
+    /// after it goes to the dead letter queue instead of being delivered
+    #[serde(default)]
+    deadline: Option<DateTime<Utc>>,
+    /// Durability guarantee (`none` or `fsync`, default `none`)
+    #[serde(default)]
+    durability: Durability,
+}
+
+/// Publish query parameters
+#[derive(Debug, Deserialize)]
+struct PublishQuery {
+    /// Reject invalid fields instead of correcting them (defaults to the server setting)
+    #[serde(default)]
+    strict: Option<bool>,
+}
+
+/// Raw publish query parameters
+#[derive(Debug, Deserialize)]
+struct RawPublishQuery {
+    /// Message priority (1 to the queue's `max_priority`, default 10;
+    /// higher = more important)
+    #[serde(default)]
+    priority: Option<u8>,
+    /// Durability guarantee (`none` or `fsync`, default `none`)
+    #[serde(default)]
+    durability: Durability,
 }
 
 /// Publish response
@@ -64,12 +394,52 @@ struct PublishResponse {
     message_id: String,
 }
 
+/// NDJSON import response
+#[derive(Debug, Default, Serialize, ToSchema)]
+struct ImportResponse {
+    /// Messages published
+    imported: u64,
+    /// Lines skipped because their client-supplied ID was already queued
+    skipped: u64,
+    /// Lines that could not be imported
+    failed: u64,
+    /// Errors for the first failed lines (at most 100)
+    errors: Vec<ImportLineError>,
+}
+
+/// A line that could not be imported
+#[derive(Debug, Serialize, ToSchema)]
+struct ImportLineError {
+    /// Line number, counting from 1
+    line: u64,
+    /// Error code
+    code: String,
+    /// Error message
+    error: String,
+}
+
 /// Receive query parameters
 #[derive(Debug, Deserialize, ToSchema)]
 struct ReceiveQuery {
     /// Maximum number of messages to receive (default: 1)
     #[serde(default = "default_max_messages")]
     max: usize,
+    /// Comma-separated fields to return, e.g. `id,priority,attr.region`
+    fields: Option<String>,
+    /// Path selecting part of a JSON body, e.g. `$.items`
+    body_path: Option<String>,
+    /// Maximum unacked messages the calling consumer may hold (needs `x-consumer-id`)
+    prefetch: Option<usize>,
+    /// Seconds to wait for a message when the queue is empty (capped at 20)
+    #[serde(default)]
+    wait_secs: u64,
+    /// Return `application/json` bodies parsed, in `body_json`
+    #[serde(default)]
+    parse: bool,
+    /// Only deliver messages with at least this priority
+    min_priority: Option<u8>,
+    /// Only deliver messages published before this time; `wait_secs` is ignored
+    published_before: Option<DateTime<Utc>>,
 }
 
 fn default_max_messages() -> usize {
@@ -81,41 +451,296 @@ fn default_max_messages() -> usize {
 struct MessageResponse {
     /// Unique message ID
     id: String,
-    /// Message body content
-    body: String,
+    /// Same on every redelivery of this message; store it to skip messages
+    /// already processed whose ack was lost
+    processing_token: String,
+    /// Message body content (base64-encoded when `body_encoding` is `base64`);
+    /// omitted when the body is returned parsed in `body_json`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    /// Parsed JSON body, with `parse=true` and an `application/json` content type
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    body_json: Option<serde_json::Value>,
+    /// Encoding of `body`
+    body_encoding: BodyEncoding,
     /// Content type
     content_type: Option<String>,
     /// Message priority
     priority: u8,
     /// Number of delivery attempts
     delivery_count: u32,
+    /// Arrival order in the queue, counting from 1; gaps mean missed messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequence: Option<u64>,
     /// Custom attributes
-    attributes: std::collections::HashMap<String, String>,
+    attributes: Arc<HashMap<String, String>>,
+    /// Typed attributes
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    typed_attributes: Arc<HashMap<String, AttributeValue>>,
+    /// CRC32 of the body as 8 hex digits, if computed or supplied on publish
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
     /// Creation timestamp
     created_at: String,
+    /// When the message will be redelivered unless acked (ISO-8601)
+    visible_until: Option<String>,
+    /// Processing deadline set on publish (ISO-8601)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deadline: Option<String>,
+    /// Why the message was quarantined, if it is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quarantine_reason: Option<QuarantineReason>,
+    /// Why the message was moved to this dead letter queue, if it was
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dead_letter_reason: Option<DeadLetterReason>,
 }
 
 impl From<Message> for MessageResponse {
     fn from(msg: Message) -> Self {
+        let (body, body_encoding) = msg.encoded_body();
         Self {
             id: msg.id.to_string(),
-            body: msg.body_as_str().unwrap_or("").to_string(),
+            processing_token: msg.processing_token(),
+            body: Some(body),
+            body_json: None,
+            body_encoding,
             content_type: msg.content_type,
             priority: msg.priority,
             delivery_count: msg.delivery_count,
+            sequence: msg.sequence,
             attributes: msg.attributes,
+            typed_attributes: msg.typed_attributes,
+            checksum: msg.checksum,
             created_at: msg.created_at.to_rfc3339(),
+            visible_until: msg.visible_until.map(|t| t.to_rfc3339()),
+            deadline: msg.deadline.map(|t| t.to_rfc3339()),
+            quarantine_reason: msg.quarantine_reason,
+            dead_letter_reason: msg.dead_letter_reason,
+        }
+    }
+}
+
+impl MessageResponse {
+    /// Move a JSON body into `body_json`
+    ///
+    /// Bodies with another content type, or that fail to parse, keep the
+    /// string or base64 `body`.
+    fn parse_body(mut self) -> Self {
+        let is_json = self.content_type.as_deref().is_some_and(|ct| {
+            let essence = ct.split(';').next().unwrap_or_default().trim();
+            essence.eq_ignore_ascii_case("application/json")
+        });
+        if !is_json || self.body_encoding != BodyEncoding::Utf8 {
+            return self;
+        }
+
+        let parsed = self
+            .body
+            .as_deref()
+            .and_then(|b| serde_json::from_str(b).ok());
+        if let Some(value) = parsed {
+            self.body_json = Some(value);
+            self.body = None;
+        }
+        self
+    }
+}
+
+/// Prefix for attribute keys in search and projection query parameters
+const SEARCH_ATTR_PREFIX: &str = "attr.";
+
+/// Build search criteria from raw query parameters
+///
+/// Supports `attr.<key>=<value>`, `body=<substring>`, `offset` and `limit`.
+fn parse_search_query(params: HashMap<String, String>) -> Result<SearchCriteria, Error> {
+    let mut criteria = SearchCriteria::default();
+
+    for (key, value) in params {
+        match key.as_str() {
+            "body" => criteria.body_contains = Some(value),
+            "offset" => {
+                criteria.offset = value
+                    .parse()
+                    .map_err(|_| Error::InvalidMessage("Invalid offset".to_string()))?
+            }
+            "limit" => {
+                criteria.limit = value
+                    .parse()
+                    .map_err(|_| Error::InvalidMessage("Invalid limit".to_string()))?
+            }
+            _ => {
+                if let Some(attr) = key.strip_prefix(SEARCH_ATTR_PREFIX) {
+                    criteria.attributes.insert(attr.to_string(), value);
+                }
+            }
+        }
+    }
+
+    Ok(criteria)
+}
+
+/// Default page size for message ID listings
+const DEFAULT_MESSAGE_ID_PAGE: usize = 100;
+
+/// Largest page a message ID listing may request
+const MAX_MESSAGE_ID_PAGE: usize = 1000;
+
+/// Dead letter queue peek query parameters
+#[derive(Debug, Deserialize)]
+struct DlqPeekQuery {
+    /// Maximum messages to return (default: 10)
+    #[serde(default = "default_dlq_peek_max")]
+    max: usize,
+}
+
+fn default_dlq_peek_max() -> usize {
+    10
+}
+
+/// Default page size for cursor-based peeks
+const DEFAULT_PEEK_PAGE: usize = 20;
+
+/// Largest page a cursor-based peek may request
+const MAX_PEEK_PAGE: usize = 100;
+
+/// Cursor-based peek query parameters
+#[derive(Debug, Deserialize)]
+struct PeekPageQuery {
+    /// `next_cursor` from the previous page; omitted for the first page
+    cursor: Option<u64>,
+    /// Maximum messages to return
+    limit: Option<usize>,
+}
+
+/// Page of pending messages
+#[derive(Debug, Serialize, ToSchema)]
+struct PeekPageResponse {
+    /// Pending messages in arrival order
+    messages: Vec<MessageResponse>,
+    /// Cursor for the next page; absent after the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<u64>,
+}
+
+/// Message ID listing query parameters
+#[derive(Debug, Deserialize)]
+struct MessageIdsQuery {
+    /// Which messages to list (default: all)
+    #[serde(default)]
+    status: MessageStateFilter,
+    /// Number of IDs to skip
+    #[serde(default)]
+    offset: usize,
+    /// Maximum IDs to return
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+/// Message ID listing response
+#[derive(Debug, Serialize, ToSchema)]
+struct MessageIdsResponse {
+    /// Message IDs in delivery order, pending before in-flight
+    ids: Vec<String>,
+}
+
+/// Default number of sample IDs per queue in a reconciliation
+const DEFAULT_RECONCILE_SAMPLES: usize = 10;
+
+/// Reconciliation query parameters
+#[derive(Debug, Deserialize)]
+struct ReconcileQuery {
+    /// Maximum sample IDs per queue (default: 10)
+    #[serde(default)]
+    samples: Option<usize>,
+}
+
+/// Counts and sample IDs from the main queue
+#[derive(Debug, Serialize, ToSchema)]
+struct ReconcileMain {
+    pending: u64,
+    in_flight: u64,
+    /// Pending IDs before in-flight ones
+    sample_ids: Vec<String>,
+}
+
+/// Counts and sample IDs from the dead letter queue
+#[derive(Debug, Serialize, ToSchema)]
+struct ReconcileDlq {
+    pending: u64,
+    sample_ids: Vec<String>,
+}
+
+/// Reconciliation snapshot of a queue and its dead letter queue
+#[derive(Debug, Serialize, ToSchema)]
+struct ReconcileResponse {
+    main: ReconcileMain,
+    dlq: ReconcileDlq,
+}
+
+impl From<ReconcileSnapshot> for ReconcileResponse {
+    fn from(snapshot: ReconcileSnapshot) -> Self {
+        let ids = |ids: Vec<MessageId>| ids.iter().map(ToString::to_string).collect();
+        Self {
+            main: ReconcileMain {
+                pending: snapshot.main.pending,
+                in_flight: snapshot.main.in_flight,
+                sample_ids: ids(snapshot.main.sample_ids),
+            },
+            dlq: ReconcileDlq {
+                pending: snapshot.dlq.pending,
+                sample_ids: ids(snapshot.dlq.sample_ids),
+            },
         }
     }
 }
 
-/// Ack/Nack request
+/// Ack request
 #[derive(Debug, Deserialize, ToSchema)]
 struct AckRequest {
     /// ID of the message to acknowledge
     message_id: String,
 }
 
+/// Combined ack-and-receive request
+#[derive(Debug, Deserialize, ToSchema)]
+struct AckReceiveRequest {
+    /// ID of the message to acknowledge
+    ack_id: String,
+    /// Maximum number of messages to receive (default: 1)
+    #[serde(default = "default_max_messages")]
+    max: usize,
+}
+
+/// Combined ack-and-receive response
+#[derive(Debug, Serialize, ToSchema)]
+struct AckReceiveResponse {
+    /// Whether the ack succeeded
+    acked: bool,
+    /// Why the ack failed, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ack_error: Option<ApiErrorBody>,
+    /// Next batch of messages
+    messages: Vec<MessageResponse>,
+}
+
+/// Nack request
+#[derive(Debug, Deserialize, ToSchema)]
+struct NackRequest {
+    /// ID of the message to return to the queue
+    message_id: String,
+    /// New priority for the requeued message (clamped to the queue's range)
+    #[serde(default)]
+    priority: Option<u8>,
+}
+
+/// Parse a message ID supplied by a client
+fn parse_message_id(id: &str) -> Result<MessageId, Error> {
+    id.parse()
+        .map(MessageId)
+        .map_err(|_| Error::InvalidMessage("Invalid message ID".to_string()))
+}
+
 /// API Error response
 #[derive(Debug, Serialize, ToSchema)]
 struct ApiErrorBody {
@@ -125,6 +750,14 @@ struct ApiErrorBody {
     code: String,
 }
 
+/// Purge query parameters
+#[derive(Debug, Deserialize)]
+struct PurgeQuery {
+    /// Only purge pending messages created before this time
+    #[serde(default)]
+    before: Option<DateTime<Utc>>,
+}
+
 /// Purge response
 #[derive(Debug, Serialize, ToSchema)]
 struct PurgeResponse {
@@ -132,6 +765,27 @@ struct PurgeResponse {
     purged: u64,
 }
 
+/// Message count response
+#[derive(Debug, Serialize, ToSchema)]
+struct CountResponse {
+    /// Number of matching pending and in-flight messages
+    count: u64,
+}
+
+/// Quarantine release response
+#[derive(Debug, Serialize, ToSchema)]
+struct ReleaseResponse {
+    /// Number of messages released back to the queue
+    released: u64,
+}
+
+/// Expired message cleanup response
+#[derive(Debug, Serialize, ToSchema)]
+struct CleanupResponse {
+    /// Number of expired messages removed
+    removed: u64,
+}
+
 /// Health check response
 #[derive(Debug, Serialize, ToSchema)]
 struct HealthResponse {
@@ -141,9 +795,25 @@ struct HealthResponse {
     version: String,
 }
 
+/// Build and runtime details of the running server
+#[derive(Debug, Serialize, ToSchema)]
+struct InfoResponse {
+    /// Server version
+    version: String,
+    /// When the server binary was built (RFC 3339)
+    build_timestamp: String,
+    /// Storage backend in use, e.g. `memory`
+    storage_backend: String,
+    /// Seconds since the broker started
+    uptime_secs: u64,
+    /// Optional broker and server features turned on
+    features: Vec<String>,
+}
+
 // ==================== Error Handling ====================
 
 /// Wrapper for FlowQ errors to implement IntoResponse
+#[derive(Debug)]
 struct AppError(Error);
 
 impl From<Error> for AppError {
@@ -152,25 +822,55 @@ impl From<Error> for AppError {
     }
 }
 
+/// HTTP status and API error code for an error
+fn error_status(err: &Error) -> (StatusCode, &'static str) {
+    match err {
+        Error::QueueNotFound(_) => (StatusCode::NOT_FOUND, "QUEUE_NOT_FOUND"),
+        Error::QueueDeleted(_) => (StatusCode::GONE, "QUEUE_DELETED"),
+        Error::QueueAlreadyExists(_) => (StatusCode::CONFLICT, "QUEUE_ALREADY_EXISTS"),
+        Error::QueueLimitReached(_) => (StatusCode::FORBIDDEN, "QUEUE_LIMIT_REACHED"),
+        Error::MessageNotFound(_) => (StatusCode::NOT_FOUND, "MESSAGE_NOT_FOUND"),
+        Error::QueueFull(_) => (StatusCode::SERVICE_UNAVAILABLE, "QUEUE_FULL"),
+        Error::QueueEmpty(_) => (StatusCode::NO_CONTENT, "QUEUE_EMPTY"),
+        Error::InvalidMessage(_) => (StatusCode::BAD_REQUEST, "INVALID_MESSAGE"),
+        Error::MessageTooLarge(_) => (StatusCode::PAYLOAD_TOO_LARGE, "MESSAGE_TOO_LARGE"),
+        Error::InvalidConfig(_) => (StatusCode::BAD_REQUEST, "INVALID_CONFIG"),
+        Error::Snapshot(_) => (StatusCode::BAD_REQUEST, "INVALID_SNAPSHOT"),
+        Error::ConsumerNotAllowed(_) => (StatusCode::FORBIDDEN, "CONSUMER_NOT_ALLOWED"),
+        Error::ReadOnly(_) => (StatusCode::FORBIDDEN, "READ_ONLY"),
+        Error::Timeout(_) => (StatusCode::GATEWAY_TIMEOUT, "OPERATION_TIMED_OUT"),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR"),
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-        let (status, code) = match &self.0 {
-            Error::QueueNotFound(_) => (StatusCode::NOT_FOUND, "QUEUE_NOT_FOUND"),
-            Error::QueueAlreadyExists(_) => (StatusCode::CONFLICT, "QUEUE_ALREADY_EXISTS"),
-            Error::MessageNotFound(_) => (StatusCode::NOT_FOUND, "MESSAGE_NOT_FOUND"),
-            Error::QueueFull(_) => (StatusCode::SERVICE_UNAVAILABLE, "QUEUE_FULL"),
-            Error::QueueEmpty(_) => (StatusCode::NO_CONTENT, "QUEUE_EMPTY"),
-            Error::InvalidMessage(_) => (StatusCode::BAD_REQUEST, "INVALID_MESSAGE"),
-            _ => (StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR"),
-        };
+        let (status, code) = error_status(&self.0);
 
         let body = Json(ApiErrorBody {
             error: self.0.to_string(),
             code: code.to_string(),
         });
 
-        (status, body).into_response()
+        // Kept on the response so `render_problems` can re-render it
+        let details = ErrorDetails {
+            code,
+            detail: self.0.to_string(),
+        };
+        (status, Extension(details), body).into_response()
+    }
+}
+
+/// Convert an error to a response, telling clients when to retry if the queue is full
+fn with_backpressure(err: Error, retry_after_secs: u64) -> Response {
+    let queue_full = matches!(err, Error::QueueFull(_));
+    let mut response = AppError(err).into_response();
+    if queue_full {
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(retry_after_secs));
     }
+    response
 }
 
 // ==================== OpenAPI Documentation ====================
@@ -189,37 +889,93 @@ impl IntoResponse for AppError {
     ),
     paths(
         health,
+        info,
         list_queues,
         create_queue,
+        put_template,
         get_queue,
+        get_queue_config,
+        put_queue_config,
         delete_queue,
+        delete_queues,
         get_queue_stats,
         purge_queue,
+        rename_queue,
+        reset_metrics,
         publish_message,
+        publish_raw_message,
+        import_messages,
         receive_messages,
         ack_message,
+        ack_and_receive,
         nack_message,
+        get_message,
+        reset_delivery_count,
+        move_to_dlq,
+        peek_page,
+        peek_dlq,
+        reconcile,
+        search_messages,
+        count_messages,
+        list_message_ids,
+        list_quarantined,
+        list_acked,
+        release_quarantined,
+        snapshot,
+        restore,
+        cleanup_expired,
     ),
     components(
         schemas(
             HealthResponse,
+            InfoResponse,
             Queue,
             QueueConfig,
+            RequeuePosition,
+            DeliveryStrategy,
             QueueStats,
             CreateQueueRequest,
+            RenameQueueRequest,
+            DeleteQueuesRequest,
+            QueueDeleteResult,
+            DeleteQueuesResponse,
             PublishRequest,
+            AttributeValue,
+            Durability,
             PublishResponse,
+            ImportResponse,
+            ImportLineError,
             MessageResponse,
+            BodyEncoding,
+            QuarantineReason,
+            DeadLetterReason,
             ReceiveQuery,
             AckRequest,
+            AckReceiveRequest,
+            AckReceiveResponse,
+            NackRequest,
             ApiErrorBody,
+            ProblemDetails,
+            FieldError,
+            ValidationErrorBody,
             PurgeResponse,
+            CountResponse,
+            QueueWithStats,
+            MessageStateFilter,
+            MessageIdsResponse,
+            ReconcileMain,
+            ReconcileDlq,
+            ReconcileResponse,
+            PeekPageResponse,
+            ReleaseResponse,
+            CleanupResponse,
         )
     ),
     tags(
         (name = "health", description = "Health check endpoints"),
         (name = "queues", description = "Queue management endpoints"),
-        (name = "messages", description = "Message operations endpoints")
+        (name = "messages", description = "Message operations endpoints"),
+        (name = "admin", description = "Broker administration endpoints")
     )
 )]
 struct ApiDoc;
@@ -242,18 +998,84 @@ async fn health() -> impl IntoResponse {
     })
 }
 
+/// Build and runtime info
+#[utoipa::path(
+    get,
+    path = "/api/v1/info",
+    tag = "health",
+    responses(
+        (status = 200, description = "Server build and runtime info", body = InfoResponse)
+    )
+)]
+async fn info(State(state): State<AppState>) -> Json<InfoResponse> {
+    let build_timestamp = env!("FLOWQ_BUILD_EPOCH")
+        .parse()
+        .ok()
+        .and_then(|secs| DateTime::<Utc>::from_timestamp(secs, 0))
+        .map(|built| built.to_rfc3339())
+        .unwrap_or_default();
+
+    let mut features: Vec<String> = state
+        .broker
+        .enabled_features()
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    let server_features = [
+        ("strict_publish", state.strict_publish),
+        ("problem_json", state.problem_json),
+        ("api_keys", !state.api_keys.is_empty()),
+        ("cors", !state.cors_origins.is_empty()),
+    ];
+    features.extend(
+        server_features
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name.to_string()),
+    );
+
+    Json(InfoResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        build_timestamp,
+        storage_backend: state.broker.storage().backend_name().to_string(),
+        uptime_secs: state.broker.uptime().as_secs(),
+        features,
+    })
+}
+
 /// List all queues
+///
+/// With `with_stats=true` each queue is returned alongside its statistics,
+/// saving dashboards a stats request per queue.
 #[utoipa::path(
     get,
     path = "/api/v1/queues",
     tag = "queues",
+    params(
+        ("with_stats" = Option<bool>, Query, description = "Include each queue's statistics")
+    ),
     responses(
-        (status = 200, description = "List of all queues", body = Vec<Queue>)
+        (status = 200, description = "List of all queues (QueueWithStats items with with_stats)",
+            body = Vec<Queue>)
     )
 )]
-async fn list_queues(State(state): State<AppState>) -> Result<Json<Vec<Queue>>, AppError> {
+async fn list_queues(
+    State(state): State<AppState>,
+    Query(query): Query<ListQueuesQuery>,
+) -> Result<Response, AppError> {
+    if query.with_stats {
+        let queues: Vec<QueueWithStats> = state
+            .broker
+            .list_queues_with_stats()
+            .await?
+            .into_iter()
+            .map(|(queue, stats)| QueueWithStats { queue, stats })
+            .collect();
+        return Ok(Json(queues).into_response());
+    }
+
     let queues = state.broker.list_queues().await?;
-    Ok(Json(queues))
+    Ok(Json(queues).into_response())
 }
 
 /// Create a new queue
@@ -264,6 +1086,8 @@ async fn list_queues(State(state): State<AppState>) -> Result<Json<Vec<Queue>>,
     request_body = CreateQueueRequest,
     responses(
         (status = 201, description = "Queue created successfully", body = Queue),
+        (status = 400, description = "Invalid queue configuration", body = ApiErrorBody),
+        (status = 403, description = "Queue limit reached", body = ApiErrorBody),
         (status = 409, description = "Queue already exists", body = ApiErrorBody)
     )
 )]
@@ -271,128 +1095,420 @@ async fn create_queue(
     State(state): State<AppState>,
     Json(req): Json<CreateQueueRequest>,
 ) -> Result<(StatusCode, Json<Queue>), AppError> {
-    let queue = match req.config {
-        Some(config) => {
-            state
-                .broker
-                .create_queue_with_config(req.name, config)
-                .await?
-        }
-        None => state.broker.create_queue(req.name).await?,
+    let config = match (req.template, req.config) {
+        (Some(template), overrides) => state
+            .broker
+            .template_config(&template, overrides.as_ref())?,
+        (None, Some(config)) => serde_json::from_value(config)
+            .map_err(|e| Error::InvalidConfig(format!("Invalid config: {}", e)))?,
+        (None, None) => QueueConfig::default(),
+    };
+    let queue = if req.auto_create_dlq {
+        state.broker.create_queue_with_dlq(req.name, config).await?
+    } else {
+        state
+            .broker
+            .create_queue_with_config(req.name, config)
+            .await?
     };
 
     Ok((StatusCode::CREATED, Json(queue)))
 }
 
+/// Register or replace a named queue config template
+#[utoipa::path(
+    put,
+    path = "/api/v1/templates/{name}",
+    tag = "queues",
+    params(
+        ("name" = String, Path, description = "Template name")
+    ),
+    request_body = QueueConfig,
+    responses(
+        (status = 204, description = "Template registered"),
+        (status = 400, description = "Invalid queue configuration", body = ApiErrorBody),
+        (status = 403, description = "Consumer keys not allowed", body = ApiErrorBody)
+    )
+)]
+async fn put_template(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(config): Json<QueueConfig>,
+) -> Result<StatusCode, AppError> {
+    state.broker.register_template(name, config)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Entity tag for a queue's metadata, changing whenever the queue is updated
+fn queue_etag(queue: &Queue) -> String {
+    format!("\"{:x}\"", queue.updated_at.timestamp_micros())
+}
+
+/// Whether an `If-None-Match` value matches an entity tag (weak comparison)
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
 /// Get queue details
+///
+/// Responses carry an `ETag`; send it back in `If-None-Match` to get a 304
+/// while the queue is unchanged.
 #[utoipa::path(
     get,
     path = "/api/v1/queues/{name}",
     tag = "queues",
     params(
-        ("name" = String, Path, description = "Queue name")
+        ("name" = String, Path, description = "Queue name"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag from a previous response")
     ),
     responses(
-        (status = 200, description = "Queue details", body = Queue),
+        (status = 200, description = "Queue details", body = Queue,
+            headers(("ETag" = String, description = "Queue metadata version"))),
+        (status = 304, description = "Queue unchanged since the given ETag"),
         (status = 404, description = "Queue not found", body = ApiErrorBody)
     )
 )]
 async fn get_queue(
     State(state): State<AppState>,
     Path(name): Path<String>,
-) -> Result<Json<Queue>, AppError> {
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
     let queue = state
         .broker
         .get_queue(&name)
         .await?
-        .ok_or_else(|| Error::QueueNotFound(name))?;
+        .ok_or(Error::QueueNotFound(name))?;
 
-    Ok(Json(queue))
+    let etag = queue_etag(&queue);
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| etag_matches(v, &etag));
+
+    if not_modified {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+    Ok(([(header::ETAG, etag)], Json(queue)).into_response())
 }
 
-/// Delete a queue
+/// Get just a queue's configuration
 #[utoipa::path(
-    delete,
-    path = "/api/v1/queues/{name}",
+    get,
+    path = "/api/v1/queues/{name}/config",
     tag = "queues",
     params(
         ("name" = String, Path, description = "Queue name")
     ),
     responses(
-        (status = 204, description = "Queue deleted successfully"),
+        (status = 200, description = "Queue configuration", body = QueueConfig),
         (status = 404, description = "Queue not found", body = ApiErrorBody)
     )
 )]
-async fn delete_queue(
+async fn get_queue_config(
     State(state): State<AppState>,
     Path(name): Path<String>,
-) -> Result<StatusCode, AppError> {
-    state.broker.delete_queue(&name).await?;
-    Ok(StatusCode::NO_CONTENT)
+) -> Result<Json<QueueConfig>, AppError> {
+    let queue = state
+        .broker
+        .get_queue(&name)
+        .await?
+        .ok_or(Error::QueueNotFound(name))?;
+
+    Ok(Json(queue.config))
 }
 
-/// Get queue statistics
+/// Replace a queue's configuration
+///
+/// The new config is validated as a whole, including dead letter cycles, and
+/// applied only if valid; otherwise the current config is left untouched.
 #[utoipa::path(
-    get,
-    path = "/api/v1/queues/{name}/stats",
+    put,
+    path = "/api/v1/queues/{name}/config",
     tag = "queues",
     params(
         ("name" = String, Path, description = "Queue name")
     ),
+    request_body = QueueConfig,
     responses(
-        (status = 200, description = "Queue statistics", body = QueueStats),
+        (status = 200, description = "Config replaced", body = Queue),
+        (status = 400, description = "Invalid config", body = ApiErrorBody),
+        (status = 403, description = "Consumer keys not allowed", body = ApiErrorBody),
         (status = 404, description = "Queue not found", body = ApiErrorBody)
     )
 )]
-async fn get_queue_stats(
+async fn put_queue_config(
     State(state): State<AppState>,
     Path(name): Path<String>,
-) -> Result<Json<QueueStats>, AppError> {
-    let stats = state.broker.get_queue_stats(&name).await?;
-    Ok(Json(stats))
-}
+    Json(config): Json<QueueConfig>,
+) -> Result<Json<Queue>, AppError> {
+    let queue = state.broker.replace_queue_config(&name, config).await?;
+    Ok(Json(queue))
+}
+
+/// Delete a queue
+#[utoipa::path(
+    delete,
+    path = "/api/v1/queues/{name}",
+    tag = "queues",
+    params(
+        ("name" = String, Path, description = "Queue name")
+    ),
+    responses(
+        (status = 204, description = "Queue deleted successfully"),
+        (status = 403, description = "Consumer keys not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody)
+    )
+)]
+async fn delete_queue(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    state.broker.delete_queue(&name).await?;
+    state.prefetch.release_queue(&name);
+    Ok(StatusCode::NO_CONTENT)
+}
 
-/// Purge all messages from a queue
+/// Delete several queues by name or prefix
 #[utoipa::path(
     post,
-    path = "/api/v1/queues/{name}/purge",
+    path = "/api/v1/queues/delete",
+    tag = "queues",
+    request_body = DeleteQueuesRequest,
+    responses(
+        (status = 200, description = "Per-queue delete results", body = DeleteQueuesResponse),
+        (status = 400, description = "Not exactly one of names or prefix", body = ApiErrorBody),
+        (status = 403, description = "Consumer keys not allowed", body = ApiErrorBody)
+    )
+)]
+async fn delete_queues(
+    State(state): State<AppState>,
+    Json(req): Json<DeleteQueuesRequest>,
+) -> Result<Json<DeleteQueuesResponse>, AppError> {
+    let selector = match (req.names, req.prefix) {
+        (Some(names), None) => QueueSelector::Names(names),
+        (None, Some(prefix)) => QueueSelector::Prefix(prefix),
+        _ => {
+            return Err(
+                Error::InvalidMessage("Provide exactly one of names or prefix".to_string()).into(),
+            )
+        }
+    };
+
+    let results = state
+        .broker
+        .delete_queues(&selector)
+        .await?
+        .into_iter()
+        .map(|(name, result)| match result {
+            Ok(()) => {
+                state.prefetch.release_queue(&name);
+                QueueDeleteResult {
+                    name,
+                    deleted: true,
+                    code: None,
+                    error: None,
+                }
+            }
+            Err(e) => QueueDeleteResult {
+                name,
+                deleted: false,
+                code: Some(error_status(&e).1.to_string()),
+                error: Some(e.to_string()),
+            },
+        })
+        .collect();
+
+    Ok(Json(DeleteQueuesResponse { results }))
+}
+
+/// Get queue statistics
+#[utoipa::path(
+    get,
+    path = "/api/v1/queues/{name}/stats",
     tag = "queues",
     params(
         ("name" = String, Path, description = "Queue name")
     ),
+    responses(
+        (status = 200, description = "Queue statistics", body = QueueStats),
+        (status = 404, description = "Queue not found", body = ApiErrorBody)
+    )
+)]
+async fn get_queue_stats(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<QueueStats>, AppError> {
+    let stats = state.broker.get_queue_stats(&name).await?;
+    Ok(Json(stats))
+}
+
+/// Purge messages from a queue, optionally only those created before a cutoff
+#[utoipa::path(
+    post,
+    path = "/api/v1/queues/{name}/purge",
+    tag = "queues",
+    params(
+        ("name" = String, Path, description = "Queue name"),
+        ("before" = Option<String>, Query, description = "Only purge messages created before this ISO-8601 time")
+    ),
     responses(
         (status = 200, description = "Queue purged", body = PurgeResponse),
+        (status = 400, description = "Invalid cutoff", body = ApiErrorBody),
+        (status = 403, description = "Consumer keys not allowed", body = ApiErrorBody),
         (status = 404, description = "Queue not found", body = ApiErrorBody)
     )
 )]
 async fn purge_queue(
     State(state): State<AppState>,
     Path(name): Path<String>,
+    Query(query): Query<PurgeQuery>,
 ) -> Result<Json<PurgeResponse>, AppError> {
-    let count = state.broker.purge_queue(&name).await?;
+    let count = match query.before {
+        Some(cutoff) => state.broker.purge_older_than(&name, cutoff).await?,
+        None => {
+            let count = state.broker.purge_queue(&name).await?;
+            state.prefetch.release_queue(&name);
+            count
+        }
+    };
     Ok(Json(PurgeResponse { purged: count }))
 }
 
+/// Rename a queue, keeping its messages
+///
+/// Other queues' dead letter, expiry and retry settings that name this queue
+/// are not rewritten.
+#[utoipa::path(
+    post,
+    path = "/api/v1/queues/{name}/rename",
+    tag = "queues",
+    params(
+        ("name" = String, Path, description = "Current queue name")
+    ),
+    request_body = RenameQueueRequest,
+    responses(
+        (status = 200, description = "Queue renamed", body = Queue),
+        (status = 400, description = "Invalid new name", body = ApiErrorBody),
+        (status = 403, description = "Consumer keys not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody),
+        (status = 409, description = "New name already taken", body = ApiErrorBody)
+    )
+)]
+async fn rename_queue(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<RenameQueueRequest>,
+) -> Result<Json<Queue>, AppError> {
+    let queue = state.broker.rename_queue(&name, &req.new_name).await?;
+    Ok(Json(queue))
+}
+
+/// Reset a queue's rate counters without touching its messages
+#[utoipa::path(
+    post,
+    path = "/api/v1/queues/{name}/metrics/reset",
+    tag = "queues",
+    params(
+        ("name" = String, Path, description = "Queue name")
+    ),
+    responses(
+        (status = 204, description = "Metrics reset"),
+        (status = 403, description = "Consumer keys not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody)
+    )
+)]
+async fn reset_metrics(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    state.broker.reset_metrics(&name).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 /// Publish a message to a queue
 #[utoipa::path(
     post,
     path = "/api/v1/queues/{name}/messages",
     tag = "messages",
     params(
-        ("name" = String, Path, description = "Queue name")
+        ("name" = String, Path, description = "Queue name"),
+        ("strict" = Option<bool>, Query, description = "Reject invalid fields instead of fixing them")
     ),
     request_body = PublishRequest,
     responses(
+        (status = 200, description = "Message with this ID already queued", body = PublishResponse),
         (status = 201, description = "Message published", body = PublishResponse),
-        (status = 404, description = "Queue not found", body = ApiErrorBody)
+        (status = 400, description = "Invalid message ID or, in strict mode, invalid fields",
+            body = ValidationErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody),
+        (status = 413, description = "Message exceeds the queue's size limit", body = ApiErrorBody),
+        (status = 503, description = "Queue is full, see Retry-After", body = ApiErrorBody,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
     )
 )]
 async fn publish_message(
     State(state): State<AppState>,
     Path(queue_name): Path<String>,
+    Query(query): Query<PublishQuery>,
     Json(req): Json<PublishRequest>,
-) -> Result<(StatusCode, Json<PublishResponse>), AppError> {
-    let mut message = Message::new(req.body);
+) -> Result<(StatusCode, Json<PublishResponse>), Response> {
+    if query.strict.unwrap_or(state.strict_publish) {
+        // Queues that do not exist yet (auto-create) get the default range
+        let max_priority = match state.broker.get_queue(&queue_name).await {
+            Ok(Some(queue)) => queue.config.max_priority,
+            _ => QueueConfig::default().max_priority,
+        };
+        let errors = validate_publish(&req, max_priority);
+        if !errors.is_empty() {
+            return Err(validation_failed(errors));
+        }
+    }
+
+    let client_id = req.id.is_some();
+    let durability = req.durability;
+    let message =
+        message_from_request(&state.broker, req).map_err(|e| AppError(e).into_response())?;
+
+    // Only client-supplied IDs can collide, so skip the lookup otherwise
+    let published = if client_id {
+        state
+            .broker
+            .publish_if_absent(&queue_name, message, durability)
+            .await
+    } else {
+        state
+            .broker
+            .publish(&queue_name, message, durability)
+            .await
+            .map(|id| (id, true))
+    };
+    let (message_id, created) =
+        published.map_err(|err| with_backpressure(err, state.queue_full_retry_after))?;
+
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+
+    Ok((
+        status,
+        Json(PublishResponse {
+            message_id: message_id.to_string(),
+        }),
+    ))
+}
+
+/// Build a message from a publish request; `durability` is left to the caller
+fn message_from_request(broker: &Broker, req: PublishRequest) -> Result<Message, Error> {
+    let mut message = broker.new_message(req.body);
+
+    if let Some(id) = req.id {
+        message.id = parse_message_id(&id)?;
+    }
 
     if let Some(ct) = req.content_type {
         message = message.with_content_type(ct);
@@ -408,7 +1524,87 @@ async fn publish_message(
         }
     }
 
-    let message_id = state.broker.publish(&queue_name, message).await?;
+    if let Some(attrs) = req.typed_attributes {
+        for (k, v) in attrs {
+            message = message.with_typed_attribute(k, v);
+        }
+    }
+
+    if let Some(checksum) = req.checksum {
+        message = message.with_checksum(checksum);
+    }
+
+    if req.no_retry {
+        message = message.with_no_retry();
+    }
+
+    if let Some(deadline) = req.deadline {
+        message = message.with_deadline(deadline);
+    }
+
+    Ok(message)
+}
+
+/// Publish a raw request body as a message
+///
+/// The body is stored as-is (binary bodies are returned base64-encoded on
+/// receive) and the `Content-Type` header becomes the message content type.
+#[utoipa::path(
+    post,
+    path = "/api/v1/queues/{name}/messages/raw",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name"),
+        ("priority" = Option<u8>, Query,
+            description = "Message priority (1 to the queue's max_priority)"),
+        ("durability" = Option<Durability>, Query, description = "none (default) or fsync")
+    ),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 201, description = "Message published", body = PublishResponse),
+        (status = 404, description = "Queue not found", body = ApiErrorBody),
+        (status = 413, description = "Body exceeds a size limit", body = ApiErrorBody),
+        (status = 503, description = "Queue is full, see Retry-After", body = ApiErrorBody,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+    )
+)]
+async fn publish_raw_message(
+    State(state): State<AppState>,
+    Path(queue_name): Path<String>,
+    Query(query): Query<RawPublishQuery>,
+    headers: HeaderMap,
+    body: Result<Bytes, BytesRejection>,
+) -> Result<(StatusCode, Json<PublishResponse>), Response> {
+    let body = body.map_err(|rejection| {
+        if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            AppError(Error::MessageTooLarge(format!(
+                "raw body exceeds {} bytes",
+                state.max_raw_body_bytes
+            )))
+            .into_response()
+        } else {
+            rejection.into_response()
+        }
+    })?;
+
+    let mut message = state.broker.new_message(body);
+
+    if let Some(ct) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+    {
+        message = message.with_content_type(ct);
+    }
+
+    if let Some(p) = query.priority {
+        message = message.with_priority(p);
+    }
+
+    let message_id = state
+        .broker
+        .publish(&queue_name, message, query.durability)
+        .await
+        .map_err(|err| with_backpressure(err, state.queue_full_retry_after))?;
 
     Ok((
         StatusCode::CREATED,
@@ -418,6 +1614,183 @@ async fn publish_message(
     ))
 }
 
+/// Most per-line errors an import reports; later failures are only counted
+const MAX_IMPORT_ERRORS: usize = 100;
+
+/// Import messages from an NDJSON body, one publish request per line
+///
+/// The body is parsed and published line by line as it arrives, so only one
+/// line is held in memory and a slow queue slows the upload down. Lines may be
+/// up to the raw body limit. Failed lines are reported at the end; a missing
+/// or deleted queue, a read-only broker or a storage timeout stops the import.
+#[utoipa::path(
+    post,
+    path = "/api/v1/queues/{name}/import",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name")
+    ),
+    request_body(content = String, content_type = "application/x-ndjson",
+        description = "One PublishRequest JSON object per line"),
+    responses(
+        (status = 200, description = "Import finished", body = ImportResponse),
+        (status = 400, description = "Body could not be read", body = ApiErrorBody),
+        (status = 403, description = "Consumer keys not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody),
+        (status = 504, description = "Storage operation timed out", body = ApiErrorBody)
+    )
+)]
+async fn import_messages(
+    State(state): State<AppState>,
+    Path(queue_name): Path<String>,
+    body: Body,
+) -> Result<Json<ImportResponse>, AppError> {
+    let mut splitter = LineSplitter::new(state.max_raw_body_bytes);
+    let mut report = ImportResponse::default();
+    let mut lines = Vec::new();
+    let mut line_number = 0u64;
+
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk
+            .map_err(|e| Error::InvalidMessage(format!("Failed to read import body: {}", e)))?;
+        splitter.push(&chunk, &mut lines);
+        for line in lines.drain(..) {
+            line_number += 1;
+            import_line(&state, &queue_name, line_number, line, &mut report).await?;
+        }
+    }
+    if let Some(line) = splitter.finish() {
+        line_number += 1;
+        import_line(&state, &queue_name, line_number, line, &mut report).await?;
+    }
+
+    info!(
+        queue = %queue_name,
+        imported = report.imported,
+        failed = report.failed,
+        "NDJSON import finished"
+    );
+    Ok(Json(report))
+}
+
+/// Publish one import line, recording a failure in `report`
+///
+/// Only errors that would fail every remaining line are returned.
+async fn import_line(
+    state: &AppState,
+    queue_name: &str,
+    number: u64,
+    line: Line,
+    report: &mut ImportResponse,
+) -> Result<(), Error> {
+    let result = match line {
+        Line::Complete(bytes) if bytes.iter().all(u8::is_ascii_whitespace) => return Ok(()),
+        Line::Complete(bytes) => publish_import_line(state, queue_name, &bytes).await,
+        Line::TooLong => Err(Error::MessageTooLarge(format!(
+            "line exceeds {} bytes",
+            state.max_raw_body_bytes
+        ))),
+    };
+
+    match result {
+        Ok(true) => report.imported += 1,
+        Ok(false) => report.skipped += 1,
+        Err(
+            e @ (Error::QueueNotFound(_)
+            | Error::QueueDeleted(_)
+            | Error::ReadOnly(_)
+            | Error::Timeout(_)),
+        ) => return Err(e),
+        Err(e) => {
+            report.failed += 1;
+            if report.errors.len() < MAX_IMPORT_ERRORS {
+                report.errors.push(ImportLineError {
+                    line: number,
+                    code: error_status(&e).1.to_string(),
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse and publish one import line, returning whether a message was stored
+async fn publish_import_line(
+    state: &AppState,
+    queue_name: &str,
+    line: &[u8],
+) -> Result<bool, Error> {
+    let req: PublishRequest = serde_json::from_slice(line)
+        .map_err(|e| Error::InvalidMessage(format!("Invalid JSON: {}", e)))?;
+    let client_id = req.id.is_some();
+    let durability = req.durability;
+    let message = message_from_request(&state.broker, req)?;
+
+    if client_id {
+        let (_, created) = state
+            .broker
+            .publish_if_absent(queue_name, message, durability)
+            .await?;
+        Ok(created)
+    } else {
+        state
+            .broker
+            .publish(queue_name, message, durability)
+            .await?;
+        Ok(true)
+    }
+}
+
+/// Receive a batch, long-polling for up to `wait` when it is non-zero
+///
+/// Messages published later never qualify for a `published_before` receive,
+/// so it does not wait.
+async fn receive_or_wait(
+    broker: &Broker,
+    queue_name: &str,
+    max: usize,
+    filter: &PopFilter,
+    wait: Duration,
+) -> Result<Vec<Message>, Error> {
+    if wait.is_zero() || filter.published_before.is_some() {
+        broker.receive_filtered(queue_name, max, filter).await
+    } else {
+        broker
+            .receive_wait_filtered(queue_name, max, filter, wait)
+            .await
+    }
+}
+
+/// Free a consumer's prefetch slots for deliveries no longer in flight
+///
+/// A message can leave its in-flight state without an ack or nack through
+/// this API: its visibility timeout lapses, it expires or is dead-lettered, or
+/// it is settled by another path. Checking the held deliveries against
+/// storage on each prefetch receive keeps those from holding slots forever.
+async fn reclaim_lapsed(state: &AppState, consumer: &str) {
+    for (queue, id, delivery_count) in state.prefetch.held(consumer) {
+        let in_flight = match state.broker.get_message(&queue, &id).await {
+            Ok(Some(message)) => {
+                let lapsed = match message.visible_until {
+                    Some(until) => until <= Utc::now(),
+                    None => false,
+                };
+                message.status == MessageStatus::Delivered
+                    && message.delivery_count == delivery_count
+                    && !lapsed
+            }
+            Ok(None) | Err(Error::QueueNotFound(_)) => false,
+            // Keep the slot if storage could not tell
+            Err(_) => true,
+        };
+        if !in_flight {
+            state.prefetch.release_delivery(&queue, &id, delivery_count);
+        }
+    }
+}
+
 /// Receive messages from a queue
 #[utoipa::path(
     get,
@@ -425,21 +1798,104 @@ async fn publish_message(
     tag = "messages",
     params(
         ("name" = String, Path, description = "Queue name"),
-        ("max" = Option<usize>, Query, description = "Maximum messages to receive")
+        ("max" = Option<usize>, Query, description = "Maximum messages (server-capped)"),
+        ("fields" = Option<String>, Query, description = "Fields to return, e.g. id,attr.region"),
+        ("body_path" = Option<String>, Query, description = "Part of a JSON body to return, e.g. $.items"),
+        ("prefetch" = Option<usize>, Query, description = "Max unacked messages per consumer"),
+        ("wait_secs" = Option<u64>, Query, description = "Long-poll seconds if empty (max 20)"),
+        ("parse" = Option<bool>, Query, description = "Return JSON bodies parsed in body_json"),
+        ("min_priority" = Option<u8>, Query, description = "Skip messages below this priority"),
+        ("published_before" = Option<String>, Query,
+            description = "Only messages published before this RFC 3339 time"),
+        ("x-consumer-id" = Option<String>, Header, description = "Consumer ID, required with prefetch")
     ),
     responses(
-        (status = 200, description = "Messages received", body = Vec<MessageResponse>),
-        (status = 404, description = "Queue not found", body = ApiErrorBody)
+        (status = 200, description = "Messages received", body = Vec<MessageResponse>,
+            headers(
+                ("x-receive-max-clamped" = usize, description = "Effective max, if clamped"),
+                ("x-max-delivery-count" = u32,
+                    description = "Highest delivery count in the batch, if not empty")
+            )),
+        (status = 400, description = "Invalid projection", body = ApiErrorBody),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody),
+        (status = 410, description = "Queue deleted while waiting", body = ApiErrorBody),
+        (status = 429, description = "Too many concurrent receives", body = ApiErrorBody)
     )
 )]
 async fn receive_messages(
     State(state): State<AppState>,
     Path(queue_name): Path<String>,
     Query(query): Query<ReceiveQuery>,
-) -> Result<Json<Vec<MessageResponse>>, AppError> {
-    let messages = state.broker.receive_batch(&queue_name, query.max).await?;
-    let responses: Vec<MessageResponse> = messages.into_iter().map(Into::into).collect();
-    Ok(Json(responses))
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    // Validate the projection before consuming anything
+    let projection = Projection::parse(query.fields.as_deref(), query.body_path.as_deref())?;
+
+    let max = query.max.min(state.max_receive_batch);
+    let clamped = max < query.max;
+    let wait = Duration::from_secs(query.wait_secs.min(MAX_RECEIVE_WAIT_SECS));
+    let filter = PopFilter {
+        min_priority: query.min_priority.unwrap_or(0),
+        published_before: query.published_before,
+    };
+
+    let messages = match query.prefetch {
+        Some(prefetch) => {
+            let consumer = headers
+                .get(CONSUMER_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| {
+                    let msg = format!("prefetch requires the {} header", CONSUMER_HEADER);
+                    Error::InvalidMessage(msg)
+                })?;
+
+            reclaim_lapsed(&state, consumer).await;
+            // Returns its slots if this handler is dropped mid long poll
+            let reservation = state.prefetch.reserve(consumer, prefetch, max);
+            let received = match reservation.granted() {
+                0 => Ok(Vec::new()),
+                granted => {
+                    receive_or_wait(&state.broker, &queue_name, granted, &filter, wait).await
+                }
+            };
+
+            let delivered = received.as_deref().unwrap_or_default();
+            reservation.commit(&queue_name, delivered);
+            received?
+        }
+        None => receive_or_wait(&state.broker, &queue_name, max, &filter, wait).await?,
+    };
+    // Lets consumers notice a redelivery storm and back off
+    let max_delivery_count = messages.iter().map(|m| m.delivery_count).max();
+
+    let responses = messages.into_iter().map(|message| {
+        let response = MessageResponse::from(message);
+        if query.parse {
+            response.parse_body()
+        } else {
+            response
+        }
+    });
+
+    let mut response = match projection {
+        Some(projection) => {
+            let projected: Vec<_> = responses.map(|r| projection.apply(r)).collect();
+            Json(projected).into_response()
+        }
+        None => Json(responses.collect::<Vec<_>>()).into_response(),
+    };
+    if clamped {
+        response
+            .headers_mut()
+            .insert(RECEIVE_CLAMPED_HEADER, HeaderValue::from(max));
+    }
+    if let Some(count) = max_delivery_count {
+        response
+            .headers_mut()
+            .insert(MAX_DELIVERY_COUNT_HEADER, HeaderValue::from(count));
+    }
+    Ok(response)
 }
 
 /// Acknowledge a message
@@ -453,6 +1909,7 @@ async fn receive_messages(
     request_body = AckRequest,
     responses(
         (status = 204, description = "Message acknowledged"),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
         (status = 404, description = "Message not found", body = ApiErrorBody)
     )
 )]
@@ -461,16 +1918,59 @@ async fn ack_message(
     Path(queue_name): Path<String>,
     Json(req): Json<AckRequest>,
 ) -> Result<StatusCode, AppError> {
-    let message_id = flowq_types::MessageId(
-        req.message_id
-            .parse()
-            .map_err(|_| Error::InvalidMessage("Invalid message ID".to_string()))?,
-    );
+    let message_id = parse_message_id(&req.message_id)?;
 
     state.broker.ack(&queue_name, &message_id).await?;
+    state.prefetch.release(&queue_name, &message_id);
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// Acknowledge a message and receive the next batch in one call
+///
+/// The receive happens even if the ack fails (e.g. a stale ID); the ack
+/// error is reported in the response.
+#[utoipa::path(
+    post,
+    path = "/api/v1/queues/{name}/messages/ack-receive",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name")
+    ),
+    request_body = AckReceiveRequest,
+    responses(
+        (status = 200, description = "Ack outcome and next messages", body = AckReceiveResponse),
+        (status = 400, description = "Invalid message ID", body = ApiErrorBody),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody),
+        (status = 429, description = "Too many concurrent receives", body = ApiErrorBody)
+    )
+)]
+async fn ack_and_receive(
+    State(state): State<AppState>,
+    Path(queue_name): Path<String>,
+    Json(req): Json<AckReceiveRequest>,
+) -> Result<Json<AckReceiveResponse>, AppError> {
+    let ack_id = parse_message_id(&req.ack_id)?;
+    let max = req.max.min(state.max_receive_batch);
+
+    let (acked, messages) = state
+        .broker
+        .ack_and_receive(&queue_name, &ack_id, max)
+        .await?;
+    if acked.is_ok() {
+        state.prefetch.release(&queue_name, &ack_id);
+    }
+
+    Ok(Json(AckReceiveResponse {
+        acked: acked.is_ok(),
+        ack_error: acked.err().map(|e| ApiErrorBody {
+            code: error_status(&e).1.to_string(),
+            error: e.to_string(),
+        }),
+        messages: messages.into_iter().map(MessageResponse::from).collect(),
+    }))
+}
+
 /// Negative acknowledge a message (return to queue)
 #[utoipa::path(
     post,
@@ -479,90 +1979,2133 @@ async fn ack_message(
     params(
         ("name" = String, Path, description = "Queue name")
     ),
-    request_body = AckRequest,
+    request_body = NackRequest,
     responses(
         (status = 204, description = "Message returned to queue"),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
         (status = 404, description = "Message not found", body = ApiErrorBody)
     )
 )]
 async fn nack_message(
     State(state): State<AppState>,
     Path(queue_name): Path<String>,
-    Json(req): Json<AckRequest>,
+    Json(req): Json<NackRequest>,
 ) -> Result<StatusCode, AppError> {
-    let message_id = flowq_types::MessageId(
-        req.message_id
-            .parse()
-            .map_err(|_| Error::InvalidMessage("Invalid message ID".to_string()))?,
-    );
+    let message_id = parse_message_id(&req.message_id)?;
 
-    state.broker.nack(&queue_name, &message_id).await?;
+    match req.priority {
+        Some(priority) => {
+            state
+                .broker
+                .nack_with_priority(&queue_name, &message_id, priority)
+                .await?
+        }
+        None => state.broker.nack(&queue_name, &message_id).await?,
+    }
+    state.prefetch.release(&queue_name, &message_id);
     Ok(StatusCode::NO_CONTENT)
 }
 
-// ==================== Router ====================
+/// Fetch a pending or in-flight message by ID without consuming it
+#[utoipa::path(
+    get,
+    path = "/api/v1/queues/{name}/messages/{id}",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name"),
+        ("id" = String, Path, description = "Message ID")
+    ),
+    responses(
+        (status = 200, description = "Message details", body = MessageResponse),
+        (status = 400, description = "Invalid message ID", body = ApiErrorBody),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
+        (status = 404, description = "Message not found", body = ApiErrorBody)
+    )
+)]
+async fn get_message(
+    State(state): State<AppState>,
+    Path((queue_name, id)): Path<(String, String)>,
+) -> Result<Json<MessageResponse>, AppError> {
+    let message_id = parse_message_id(&id)?;
+    let message = state
+        .broker
+        .get_message(&queue_name, &message_id)
+        .await?
+        .ok_or(Error::MessageNotFound(id))?;
 
-fn create_router(state: AppState) -> Router {
-    Router::new()
-        // Swagger UI
-        .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()))
-        // Health
-        .route("/health", get(health))
-        // Queues
-        .route("/api/v1/queues", get(list_queues).post(create_queue))
-        .route(
+    Ok(Json(message.into()))
+}
+
+/// Reset a message's delivery count so it gets fresh retries
+#[utoipa::path(
+    post,
+    path = "/api/v1/queues/{name}/messages/{id}/reset-attempts",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name"),
+        ("id" = String, Path, description = "Message ID")
+    ),
+    responses(
+        (status = 204, description = "Delivery count reset"),
+        (status = 400, description = "Invalid message ID", body = ApiErrorBody),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
+        (status = 404, description = "Message not found", body = ApiErrorBody)
+    )
+)]
+async fn reset_delivery_count(
+    State(state): State<AppState>,
+    Path((queue_name, id)): Path<(String, String)>,
+) -> Result<StatusCode, AppError> {
+    let message_id = parse_message_id(&id)?;
+    state
+        .broker
+        .reset_delivery_count(&queue_name, &message_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Move an in-flight message straight to the queue's dead letter queue
+#[utoipa::path(
+    post,
+    path = "/api/v1/queues/{name}/messages/{id}/dlq",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name"),
+        ("id" = String, Path, description = "Message ID")
+    ),
+    responses(
+        (status = 204, description = "Message moved to the dead letter queue"),
+        (status = 400, description = "Bad message ID or no DLQ configured", body = ApiErrorBody),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
+        (status = 404, description = "Message not in flight", body = ApiErrorBody)
+    )
+)]
+async fn move_to_dlq(
+    State(state): State<AppState>,
+    Path((queue_name, id)): Path<(String, String)>,
+) -> Result<StatusCode, AppError> {
+    let message_id = parse_message_id(&id)?;
+
+    state.broker.move_to_dlq(&queue_name, &message_id).await?;
+    state.prefetch.release(&queue_name, &message_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Page through pending messages without consuming them
+#[utoipa::path(
+    get,
+    path = "/api/v1/queues/{name}/peek",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name"),
+        ("cursor" = Option<u64>, Query, description = "next_cursor from the previous page"),
+        ("limit" = Option<usize>, Query, description = "Maximum messages (default 20, max 100)")
+    ),
+    responses(
+        (status = 200, description = "Page of pending messages", body = PeekPageResponse),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody)
+    )
+)]
+async fn peek_page(
+    State(state): State<AppState>,
+    Path(queue_name): Path<String>,
+    Query(query): Query<PeekPageQuery>,
+) -> Result<Json<PeekPageResponse>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PEEK_PAGE)
+        .clamp(1, MAX_PEEK_PAGE);
+    let page = state
+        .broker
+        .peek_page(&queue_name, query.cursor, limit)
+        .await?;
+    Ok(Json(PeekPageResponse {
+        messages: page
+            .messages
+            .into_iter()
+            .map(MessageResponse::from)
+            .collect(),
+        next_cursor: page.next_cursor,
+    }))
+}
+
+/// Peek at the queue's dead letter queue without consuming or redriving anything
+#[utoipa::path(
+    get,
+    path = "/api/v1/queues/{name}/dlq/peek",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name"),
+        ("max" = Option<usize>, Query, description = "Maximum messages (default 10)")
+    ),
+    responses(
+        (status = 200, description = "Dead-lettered messages", body = Vec<MessageResponse>),
+        (status = 400, description = "No dead letter queue configured", body = ApiErrorBody),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody)
+    )
+)]
+async fn peek_dlq(
+    State(state): State<AppState>,
+    Path(queue_name): Path<String>,
+    Query(query): Query<DlqPeekQuery>,
+) -> Result<Json<Vec<MessageResponse>>, AppError> {
+    let max = query.max.min(state.max_receive_batch);
+    let messages = state.broker.peek_dlq(&queue_name, max).await?;
+    Ok(Json(
+        messages.into_iter().map(MessageResponse::from).collect(),
+    ))
+}
+
+/// Counts and sample IDs from a queue and its dead letter queue, for auditing
+#[utoipa::path(
+    get,
+    path = "/api/v1/queues/{name}/reconcile",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name"),
+        ("samples" = Option<usize>, Query, description = "Sample IDs per queue (default 10)")
+    ),
+    responses(
+        (status = 200, description = "Reconciliation snapshot", body = ReconcileResponse),
+        (status = 400, description = "No dead letter queue configured", body = ApiErrorBody),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody)
+    )
+)]
+async fn reconcile(
+    State(state): State<AppState>,
+    Path(queue_name): Path<String>,
+    Query(query): Query<ReconcileQuery>,
+) -> Result<Json<ReconcileResponse>, AppError> {
+    let samples = query
+        .samples
+        .unwrap_or(DEFAULT_RECONCILE_SAMPLES)
+        .min(MAX_MESSAGE_ID_PAGE);
+    let snapshot = state.broker.reconcile(&queue_name, samples).await?;
+    Ok(Json(snapshot.into()))
+}
+
+/// List message IDs without bodies, for reconciliation
+#[utoipa::path(
+    get,
+    path = "/api/v1/queues/{name}/message-ids",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name"),
+        ("status" = Option<MessageStateFilter>, Query, description = "pending, in_flight or all"),
+        ("offset" = Option<usize>, Query, description = "Number of IDs to skip"),
+        ("limit" = Option<usize>, Query, description = "Maximum IDs (default 100, max 1000)")
+    ),
+    responses(
+        (status = 200, description = "Message IDs", body = MessageIdsResponse),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody)
+    )
+)]
+async fn list_message_ids(
+    State(state): State<AppState>,
+    Path(queue_name): Path<String>,
+    Query(query): Query<MessageIdsQuery>,
+) -> Result<Json<MessageIdsResponse>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_MESSAGE_ID_PAGE)
+        .min(MAX_MESSAGE_ID_PAGE);
+
+    let ids = state
+        .broker
+        .list_message_ids(&queue_name, query.status, query.offset, limit)
+        .await?
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+
+    Ok(Json(MessageIdsResponse { ids }))
+}
+
+/// Search pending and in-flight messages without consuming them
+#[utoipa::path(
+    get,
+    path = "/api/v1/queues/{name}/search",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name"),
+        ("body" = Option<String>, Query, description = "Substring the message body must contain"),
+        ("attr.<key>" = Option<String>, Query, description = "Attribute that must match exactly, e.g. attr.orderId=123"),
+        ("offset" = Option<usize>, Query, description = "Number of matches to skip"),
+        ("limit" = Option<usize>, Query, description = "Maximum matches to return (capped at 100)")
+    ),
+    responses(
+        (status = 200, description = "Matching messages", body = Vec<MessageResponse>),
+        (status = 400, description = "Invalid search parameters", body = ApiErrorBody),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody)
+    )
+)]
+async fn search_messages(
+    State(state): State<AppState>,
+    Path(queue_name): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<MessageResponse>>, AppError> {
+    let criteria = parse_search_query(params)?;
+    let messages = state.broker.search(&queue_name, &criteria).await?;
+    let responses: Vec<MessageResponse> = messages.into_iter().map(Into::into).collect();
+    Ok(Json(responses))
+}
+
+/// Count pending and in-flight messages matching a filter without returning them
+#[utoipa::path(
+    get,
+    path = "/api/v1/queues/{name}/count",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name"),
+        ("body" = Option<String>, Query, description = "Substring the message body must contain"),
+        ("attr.<key>" = Option<String>, Query, description = "Attribute that must match exactly, e.g. attr.status=retry")
+    ),
+    responses(
+        (status = 200, description = "Number of matching messages", body = CountResponse),
+        (status = 400, description = "Invalid filter parameters", body = ApiErrorBody),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody)
+    )
+)]
+async fn count_messages(
+    State(state): State<AppState>,
+    Path(queue_name): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<CountResponse>, AppError> {
+    let criteria = parse_search_query(params)?;
+    let count = state.broker.count_messages(&queue_name, &criteria).await?;
+    Ok(Json(CountResponse { count }))
+}
+
+/// List messages quarantined after repeated failures
+#[utoipa::path(
+    get,
+    path = "/api/v1/queues/{name}/quarantine",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name")
+    ),
+    responses(
+        (status = 200, description = "Quarantined messages", body = Vec<MessageResponse>),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody)
+    )
+)]
+async fn list_quarantined(
+    State(state): State<AppState>,
+    Path(queue_name): Path<String>,
+) -> Result<Json<Vec<MessageResponse>>, AppError> {
+    let messages = state.broker.list_quarantined(&queue_name).await?;
+    let responses: Vec<MessageResponse> = messages.into_iter().map(Into::into).collect();
+    Ok(Json(responses))
+}
+
+/// List acked messages still inside the queue's retention window
+#[utoipa::path(
+    get,
+    path = "/api/v1/queues/{name}/messages/acked",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name")
+    ),
+    responses(
+        (status = 200, description = "Retained acked messages", body = Vec<MessageResponse>),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody)
+    )
+)]
+async fn list_acked(
+    State(state): State<AppState>,
+    Path(queue_name): Path<String>,
+) -> Result<Json<Vec<MessageResponse>>, AppError> {
+    let messages = state.broker.list_acked(&queue_name).await?;
+    let responses: Vec<MessageResponse> = messages.into_iter().map(Into::into).collect();
+    Ok(Json(responses))
+}
+
+/// Release quarantined messages back to the queue
+#[utoipa::path(
+    delete,
+    path = "/api/v1/queues/{name}/quarantine",
+    tag = "messages",
+    params(
+        ("name" = String, Path, description = "Queue name")
+    ),
+    responses(
+        (status = 200, description = "Quarantined messages released", body = ReleaseResponse),
+        (status = 403, description = "Consumer not allowed", body = ApiErrorBody),
+        (status = 404, description = "Queue not found", body = ApiErrorBody)
+    )
+)]
+async fn release_quarantined(
+    State(state): State<AppState>,
+    Path(queue_name): Path<String>,
+) -> Result<Json<ReleaseResponse>, AppError> {
+    let released = state.broker.release_quarantined(&queue_name).await?;
+    Ok(Json(ReleaseResponse { released }))
+}
+
+/// Download a snapshot of every queue and its messages
+///
+/// The snapshot is streamed as it is written, one queue at a time. A failure
+/// part way through can only cut the response short, which restore rejects
+/// as invalid JSON.
+#[utoipa::path(
+    post,
+    path = "/admin/snapshot",
+    tag = "admin",
+    responses(
+        (
+            status = 200,
+            description = "Versioned broker snapshot",
+            body = String,
+            content_type = "application/json"
+        ),
+        (status = 403, description = "Consumer keys not allowed", body = ApiErrorBody)
+    )
+)]
+async fn snapshot(State(state): State<AppState>) -> impl IntoResponse {
+    let (writer, reader) = tokio::io::duplex(SNAPSHOT_STREAM_BUFFER);
+    tokio::spawn(async move {
+        if let Err(e) = state.broker.snapshot(writer).await {
+            warn!(error = %e, "Snapshot stream aborted");
+        }
+    });
+
+    (
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"flowq-snapshot.json\"",
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(reader)),
+    )
+}
+
+/// Restore queues and messages from a snapshot into an empty broker
+#[utoipa::path(
+    post,
+    path = "/admin/restore",
+    tag = "admin",
+    request_body(
+        content = String,
+        description = "Snapshot produced by /admin/snapshot",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = 204, description = "Broker restored"),
+        (status = 400, description = "Invalid snapshot or broker not empty", body = ApiErrorBody),
+        (status = 403, description = "Consumer keys not allowed", body = ApiErrorBody)
+    )
+)]
+async fn restore(State(state): State<AppState>, body: Bytes) -> Result<StatusCode, AppError> {
+    state.broker.restore(&body[..]).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Remove expired messages from every queue now, without waiting for maintenance
+#[utoipa::path(
+    post,
+    path = "/admin/cleanup",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Expired messages removed", body = CleanupResponse),
+        (status = 403, description = "Consumer keys not allowed", body = ApiErrorBody)
+    )
+)]
+async fn cleanup_expired(State(state): State<AppState>) -> Result<Json<CleanupResponse>, AppError> {
+    let removed = state.broker.cleanup_expired().await?;
+    Ok(Json(CleanupResponse { removed }))
+}
+
+// ==================== Router ====================
+
+fn create_router(state: AppState) -> Router {
+    let receive_limit =
+        middleware::from_fn_with_state(Arc::clone(&state.receive_limiter), limit_receives);
+    let consumer_check =
+        middleware::from_fn_with_state(Arc::clone(&state.broker), restrict_consumers);
+    let admin_only = middleware::from_fn(reject_consumers);
+
+    let problem_json = state.problem_json;
+    let api_keys = Arc::clone(&state.api_keys);
+    let consumer_keys = Arc::clone(&state.consumer_keys);
+    let cors = cors::cors_layer(&state.cors_origins)
+        .expect("CORS origins are checked by ServerConfig::validate");
+    let body_log = state.log_bodies;
+
+    let router = Router::new()
+        // Swagger UI
+        .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()))
+        // Health
+        .route("/health", get(health))
+        .route("/api/v1/info", get(info))
+        // Queues
+        .route("/api/v1/queues", get(list_queues).post(create_queue))
+        .route(
+            "/api/v1/queues/delete",
+            post(delete_queues).route_layer(admin_only.clone()),
+        )
+        .route(
+            "/api/v1/templates/:name",
+            put(put_template).route_layer(admin_only.clone()),
+        )
+        .route(
             "/api/v1/queues/:name",
-            get(get_queue).delete(delete_queue),
+            delete(delete_queue)
+                .route_layer(admin_only.clone())
+                .get(get_queue),
+        )
+        .route(
+            "/api/v1/queues/:name/config",
+            put(put_queue_config)
+                .route_layer(admin_only.clone())
+                .get(get_queue_config),
+        )
+        .route("/api/v1/queues/:name/stats", get(get_queue_stats))
+        .route(
+            "/api/v1/queues/:name/purge",
+            post(purge_queue).route_layer(admin_only.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/rename",
+            post(rename_queue).route_layer(admin_only.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/metrics/reset",
+            post(reset_metrics).route_layer(admin_only.clone()),
+        )
+        // Messages
+        .route(
+            "/api/v1/queues/:name/messages",
+            get(receive_messages)
+                .route_layer(receive_limit.clone())
+                .route_layer(consumer_check.clone())
+                .post(publish_message),
+        )
+        .route(
+            "/api/v1/queues/:name/messages/raw",
+            post(publish_raw_message).layer(DefaultBodyLimit::max(state.max_raw_body_bytes)),
+        )
+        .route(
+            "/api/v1/queues/:name/messages/ack",
+            post(ack_message).route_layer(consumer_check.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/messages/ack-receive",
+            post(ack_and_receive)
+                .route_layer(receive_limit)
+                .route_layer(consumer_check.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/messages/nack",
+            post(nack_message).route_layer(consumer_check.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/messages/acked",
+            get(list_acked).route_layer(consumer_check.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/messages/:id",
+            get(get_message).route_layer(consumer_check.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/messages/:id/reset-attempts",
+            post(reset_delivery_count).route_layer(consumer_check.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/messages/:id/dlq",
+            post(move_to_dlq).route_layer(consumer_check.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/peek",
+            get(peek_page).route_layer(consumer_check.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/dlq/peek",
+            get(peek_dlq).route_layer(consumer_check.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/reconcile",
+            get(reconcile).route_layer(consumer_check.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/search",
+            get(search_messages).route_layer(consumer_check.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/count",
+            get(count_messages).route_layer(consumer_check.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/import",
+            post(import_messages).route_layer(admin_only.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/message-ids",
+            get(list_message_ids).route_layer(consumer_check.clone()),
+        )
+        .route(
+            "/api/v1/queues/:name/quarantine",
+            get(list_quarantined)
+                .delete(release_quarantined)
+                .route_layer(consumer_check),
+        )
+        // Admin
+        .route(
+            "/admin/snapshot",
+            post(snapshot).route_layer(admin_only.clone()),
+        )
+        .route(
+            "/admin/restore",
+            post(restore)
+                .layer(DefaultBodyLimit::max(state.max_restore_bytes))
+                .route_layer(admin_only.clone()),
+        )
+        .route(
+            "/admin/cleanup",
+            post(cleanup_expired).route_layer(admin_only),
+        )
+        // Middleware
+        .layer(TraceLayer::new_for_http())
+        .with_state(state);
+
+    // Inside `require_api_key`, so only accepted keys identify a consumer
+    let router = if consumer_keys.is_empty() {
+        router
+    } else {
+        router.layer(middleware::from_fn_with_state(
+            consumer_keys,
+            identify_consumer,
+        ))
+    };
+    let router = if api_keys.is_empty() {
+        router
+    } else {
+        router.layer(middleware::from_fn_with_state(api_keys, require_api_key))
+    };
+    let router = if problem_json {
+        router.layer(middleware::from_fn(render_problems))
+    } else {
+        router
+    };
+    // Outside `render_problems`, so problem documents are pretty-printed too
+    let router = router.layer(middleware::from_fn(pretty_print));
+    // Outside the rest, so bodies are logged as the client sends and sees them
+    let router = match body_log {
+        Some(max_bytes) => router.layer(middleware::from_fn_with_state(max_bytes, log_bodies)),
+        None => router,
+    };
+    // Outermost, so preflight requests are answered without an API key
+    match cors {
+        Some(cors) => router.layer(cors),
+        None => router,
+    }
+}
+
+// ==================== Main ====================
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let mut config = ServerConfig::load(cli.config.as_deref())?;
+    cli.apply(&mut config);
+    config.validate()?;
+
+    // Initialize logging
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "flowq=debug,tower_http=debug".into()),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Create broker with the configured storage backend
+    let storage = backend::build_storage(
+        Some(&config.storage.backend),
+        config.storage.url.as_deref(),
+        config.limits.max_total_in_flight,
+    )?;
+    let mut builder = Broker::builder(storage)
+        .auto_create_queues(config.auto_create_queues)
+        .max_queues(config.limits.max_queues)
+        .max_cleanup_per_tick(config.maintenance.max_cleanup_per_tick)
+        .maintenance_interval(Duration::from_secs(config.maintenance.interval_secs))
+        .read_only(config.read_only);
+    if config.time_ordered_ids {
+        builder = builder.id_generator(TimeOrderedIdGenerator::default());
+    }
+    let broker = Arc::new(builder.build());
+
+    // Start maintenance tasks
+    let maintenance = broker.start_maintenance().await;
+    if !broker.is_read_only() {
+        if config.limits.webhook_allowed_hosts.is_empty() {
+            info!("No webhook_allowed_hosts configured, webhook delivery disabled");
+        } else {
+            webhook::spawn_dispatcher(
+                Arc::clone(&broker),
+                config.limits.webhook_pool_size,
+                config.limits.webhook_allowed_hosts.clone(),
+            );
+        }
+    }
+
+    // Create app state
+    let receive_limiter = Arc::new(ReceiveLimiter::new(
+        config.limits.max_concurrent_receives,
+        config.limits.max_concurrent_receives_per_tenant,
+    ));
+    let state = AppState {
+        broker: Arc::clone(&broker),
+        receive_limiter,
+        prefetch: Arc::new(PrefetchTracker::new()),
+        queue_full_retry_after: config.limits.queue_full_retry_after,
+        strict_publish: config.strict_publish,
+        max_raw_body_bytes: config.limits.max_raw_body_bytes,
+        max_restore_bytes: config.limits.max_restore_bytes,
+        max_receive_batch: config.limits.max_receive_batch,
+        problem_json: config.problem_json,
+        api_keys: Arc::new(
+            config
+                .auth
+                .api_keys
+                .iter()
+                .chain(config.auth.consumer_keys.values())
+                .cloned()
+                .collect(),
+        ),
+        consumer_keys: Arc::new(
+            config
+                .auth
+                .consumer_keys
+                .iter()
+                .map(|(identity, key)| (key.clone(), identity.clone()))
+                .collect(),
+        ),
+        cors_origins: Arc::from(config.cors.allowed_origins.as_slice()),
+        log_bodies: config.body_log.enabled.then_some(config.body_log.max_bytes),
+    };
+
+    // Create router
+    let app = create_router(state);
+
+    // Start server
+    let listener = tokio::net::TcpListener::bind(&config.bind).await?;
+    let addr = listener.local_addr()?;
+
+    info!("FlowQ server listening on {}", addr);
+    info!("Swagger UI: http://{}/swagger-ui/", addr);
+    info!("Health check: http://{}/health", addr);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+    maintenance.stop().await;
+
+    // Hand unfinished deliveries back so they are not stuck until their timeouts
+    let requeued = broker.requeue_all_inflight().await?;
+    info!(requeued, "FlowQ server stopped");
+
+    Ok(())
+}
+
+/// Resolve on Ctrl-C or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl-C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    info!("Shutdown signal received, draining connections");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowq_storage::MemoryStorage;
+    use flowq_types::{DEAD_LETTERED_FROM_ATTRIBUTE, DLQ_REASON_ATTRIBUTE};
+
+    fn test_state() -> AppState {
+        AppState {
+            broker: Arc::new(Broker::new(MemoryStorage::new())),
+            receive_limiter: Arc::new(ReceiveLimiter::new(0, 0)),
+            prefetch: Arc::new(PrefetchTracker::new()),
+            queue_full_retry_after: 1,
+            strict_publish: false,
+            max_raw_body_bytes: DEFAULT_MAX_RAW_BODY_BYTES,
+            max_restore_bytes: DEFAULT_MAX_RESTORE_BYTES,
+            max_receive_batch: DEFAULT_MAX_RECEIVE_BATCH,
+            problem_json: false,
+            api_keys: ApiKeys::default(),
+            consumer_keys: ConsumerKeys::default(),
+            cors_origins: Arc::from([]),
+            log_bodies: None,
+        }
+    }
+
+    fn out_of_range_priority() -> PublishRequest {
+        PublishRequest {
+            id: None,
+            body: "hello".to_string(),
+            content_type: None,
+            priority: Some(42),
+            attributes: None,
+            typed_attributes: None,
+            checksum: None,
+            no_retry: false,
+            deadline: None,
+            durability: Durability::None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lenient_publish_clamps_priority() {
+        let state = test_state();
+        state.broker.create_queue("orders").await.unwrap();
+
+        let query = PublishQuery { strict: None };
+        let result = publish_message(
+            State(state.clone()),
+            Path("orders".to_string()),
+            Query(query),
+            Json(out_of_range_priority()),
         )
-        .route("/api/v1/queues/:name/stats", get(get_queue_stats))
-        .route("/api/v1/queues/:name/purge", post(purge_queue))
-        // Messages
-        .route(
-            "/api/v1/queues/:name/messages",
-            post(publish_message).get(receive_messages),
+        .await;
+        let Ok((status, _)) = result else {
+            panic!("lenient publish was rejected");
+        };
+        assert_eq!(status, StatusCode::CREATED);
+
+        let message = state.broker.receive("orders").await.unwrap().unwrap();
+        assert_eq!(message.priority, 10);
+    }
+
+    #[tokio::test]
+    async fn test_strict_publish_rejects_invalid_fields() {
+        let state = test_state();
+        state.broker.create_queue("orders").await.unwrap();
+
+        let query = PublishQuery { strict: Some(true) };
+        let rejected = publish_message(
+            State(state.clone()),
+            Path("orders".to_string()),
+            Query(query),
+            Json(out_of_range_priority()),
         )
-        .route("/api/v1/queues/:name/messages/ack", post(ack_message))
-        .route("/api/v1/queues/:name/messages/nack", post(nack_message))
-        // Middleware
-        .layer(TraceLayer::new_for_http())
-        .with_state(state)
-}
+        .await
+        .unwrap_err();
+        assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
 
-// ==================== Main ====================
+        let stats = state.broker.get_queue_stats("orders").await.unwrap();
+        assert_eq!(stats.message_count, 0);
+    }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    // Initialize logging
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "flowq=debug,tower_http=debug".into()),
+    #[tokio::test]
+    async fn test_strict_publish_uses_queue_max_priority() {
+        let state = test_state();
+        let config = QueueConfig {
+            max_priority: 100,
+            ..Default::default()
+        };
+        state
+            .broker
+            .create_queue_with_config("fine", config)
+            .await
+            .unwrap();
+
+        let publish = |priority| {
+            let req = PublishRequest {
+                priority: Some(priority),
+                ..out_of_range_priority()
+            };
+            publish_message(
+                State(state.clone()),
+                Path("fine".to_string()),
+                Query(PublishQuery { strict: Some(true) }),
+                Json(req),
+            )
+        };
+        assert!(publish(42).await.is_ok());
+        let rejected = publish(101).await.unwrap_err();
+        assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
+
+        let message = state.broker.receive("fine").await.unwrap().unwrap();
+        assert_eq!(message.priority, 42);
+    }
+
+    #[tokio::test]
+    async fn test_api_keys_guard_api_routes() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::Service;
+
+        let state = AppState {
+            api_keys: Arc::new(["secret".to_string()].into()),
+            ..test_state()
+        };
+        let mut app = create_router(state);
+        let mut status = |uri: &str, key: Option<&str>| {
+            let mut request = Request::get(uri);
+            if let Some(key) = key {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", key));
+            }
+            let response = app.call(request.body(Body::empty()).unwrap());
+            async move { response.await.unwrap().status() }
+        };
+
+        assert_eq!(
+            status("/api/v1/queues", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status("/api/v1/queues", Some("wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status("/api/v1/queues", Some("secret")).await,
+            StatusCode::OK
+        );
+        assert_eq!(status("/health", None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_allowed_consumers_restrict_receives() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::Service;
+
+        let state = AppState {
+            api_keys: Arc::new(
+                [
+                    "k-admin".to_string(),
+                    "k-billing".to_string(),
+                    "k-reporting".to_string(),
+                ]
+                .into(),
+            ),
+            consumer_keys: Arc::new(
+                [
+                    ("k-billing".to_string(), "billing".to_string()),
+                    ("k-reporting".to_string(), "reporting".to_string()),
+                ]
+                .into(),
+            ),
+            ..test_state()
+        };
+        let config = QueueConfig {
+            allowed_consumers: vec!["billing".to_string()],
+            ..Default::default()
+        };
+        state
+            .broker
+            .create_queue_with_config("invoices", config)
+            .await
+            .unwrap();
+        state.broker.create_queue("events").await.unwrap();
+        let broker = Arc::clone(&state.broker);
+
+        let mut app = create_router(state);
+        let mut status = |uri: &str, key: &str| {
+            let request = Request::get(uri)
+                .header(header::AUTHORIZATION, format!("Bearer {}", key))
+                .body(Body::empty())
+                .unwrap();
+            let response = app.call(request);
+            async move { response.await.unwrap().status() }
+        };
+
+        let invoices = "/api/v1/queues/invoices/messages";
+        assert_eq!(status(invoices, "k-billing").await, StatusCode::OK);
+        assert_eq!(status(invoices, "k-reporting").await, StatusCode::FORBIDDEN);
+        // Queues without a list stay open to every consumer
+        assert_eq!(
+            status("/api/v1/queues/events/messages", "k-reporting").await,
+            StatusCode::OK
+        );
+
+        // Reading messages without consuming them is restricted too
+        let id = MessageId::new();
+        let reads = [
+            "/api/v1/queues/invoices/peek".to_string(),
+            "/api/v1/queues/invoices/search".to_string(),
+            "/api/v1/queues/invoices/message-ids".to_string(),
+            "/api/v1/queues/invoices/count".to_string(),
+            "/api/v1/queues/invoices/messages/acked".to_string(),
+            format!("/api/v1/queues/invoices/messages/{}", id),
+        ];
+        for uri in &reads {
+            assert_eq!(
+                status(uri, "k-reporting").await,
+                StatusCode::FORBIDDEN,
+                "{}",
+                uri
+            );
+            assert_ne!(
+                status(uri, "k-billing").await,
+                StatusCode::FORBIDDEN,
+                "{}",
+                uri
+            );
+        }
+
+        // And so is settling them
+        let ack = Request::post("/api/v1/queues/invoices/messages/ack")
+            .header(header::AUTHORIZATION, "Bearer k-reporting")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(format!(r#"{{"message_id":"{}"}}"#, id)))
+            .unwrap();
+        let response = app.call(ack).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let mut post = |uri: &str, key: &str| {
+            let request = Request::post(uri)
+                .header(header::AUTHORIZATION, format!("Bearer {}", key))
+                .body(Body::empty())
+                .unwrap();
+            let response = app.call(request);
+            async move { response.await.unwrap().status() }
+        };
+        let reset = format!("/api/v1/queues/invoices/messages/{}/reset-attempts", id);
+        assert_eq!(post(&reset, "k-reporting").await, StatusCode::FORBIDDEN);
+        assert_ne!(post(&reset, "k-billing").await, StatusCode::FORBIDDEN);
+
+        // Admin routes span every queue, so no consumer key may use them
+        for uri in ["/admin/snapshot", "/admin/restore", "/admin/cleanup"] {
+            assert_eq!(
+                post(uri, "k-reporting").await,
+                StatusCode::FORBIDDEN,
+                "{}",
+                uri
+            );
+            assert_eq!(
+                post(uri, "k-billing").await,
+                StatusCode::FORBIDDEN,
+                "{}",
+                uri
+            );
+        }
+        assert_eq!(post("/admin/cleanup", "k-admin").await, StatusCode::OK);
+
+        // Nor may they manage a queue, which could clear its list
+        let manage = [
+            (
+                "PUT",
+                "/api/v1/queues/invoices/config",
+                r#"{"allowed_consumers":[]}"#,
+            ),
+            ("DELETE", "/api/v1/queues/invoices", ""),
+            ("POST", "/api/v1/queues/invoices/purge", ""),
+        ];
+        for (method, uri, body) in manage {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::AUTHORIZATION, "Bearer k-reporting")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            let response = app.call(request).await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::FORBIDDEN,
+                "{} {}",
+                method,
+                uri
+            );
+        }
+        let queue = broker.get_queue("invoices").await.unwrap().unwrap();
+        assert_eq!(queue.config.allowed_consumers, vec!["billing".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_problem_json_errors() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::Service;
+
+        async fn get_missing(problem_json: bool) -> (StatusCode, String, serde_json::Value) {
+            let state = AppState {
+                problem_json,
+                ..test_state()
+            };
+            let request = Request::get("/api/v1/queues/missing")
+                .body(Body::empty())
+                .unwrap();
+            let response = create_router(state).call(request).await.unwrap();
+
+            let status = response.status();
+            let content_type = response.headers()[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, content_type, serde_json::from_slice(&body).unwrap())
+        }
+
+        let (status, content_type, body) = get_missing(false).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, "application/json");
+        assert_eq!(body["code"], "QUEUE_NOT_FOUND");
+        assert!(body["error"].as_str().unwrap().contains("missing"));
+
+        let (status, content_type, body) = get_missing(true).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, problem::PROBLEM_CONTENT_TYPE);
+        assert_eq!(body["type"], "about:blank");
+        assert_eq!(body["title"], "Not Found");
+        assert_eq!(body["status"], 404);
+        assert_eq!(body["code"], "QUEUE_NOT_FOUND");
+        assert!(body["detail"].as_str().unwrap().contains("missing"));
+        assert!(body.get("error").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pretty_json_responses() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::Service;
+
+        let state = test_state();
+        state.broker.create_queue("orders").await.unwrap();
+        let get = |uri: &str| {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            create_router(state.clone()).call(request)
+        };
+
+        for (uri, pretty) in [
+            ("/api/v1/queues", false),
+            ("/api/v1/queues?pretty=true", true),
+            ("/api/v1/queues/missing?pretty=true", true),
+        ] {
+            let response = get(uri).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let text = std::str::from_utf8(&body).unwrap();
+            assert_eq!(text.contains('\n'), pretty, "{}", uri);
+            serde_json::from_str::<serde_json::Value>(text).unwrap();
+        }
+
+        // The streamed snapshot passes through without being buffered
+        let request = Request::post("/admin/snapshot?pretty=true")
+            .body(Body::empty())
+            .unwrap();
+        let response = create_router(state.clone()).call(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(!body.contains(&b'\n'));
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_raw_publish_body_limit() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::Service;
+
+        let state = AppState {
+            max_raw_body_bytes: 16,
+            ..test_state()
+        };
+        state.broker.create_queue("orders").await.unwrap();
+        let mut app = create_router(state.clone());
+
+        let raw = |len: usize| {
+            Request::post("/api/v1/queues/orders/messages/raw")
+                .header(header::CONTENT_TYPE, "application/octet-stream")
+                .body(Body::from(vec![0xffu8; len]))
+                .unwrap()
+        };
+
+        let response = app.call(raw(64)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "MESSAGE_TOO_LARGE");
+
+        let response = app.call(raw(16)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let message = state.broker.receive("orders").await.unwrap().unwrap();
+        assert_eq!(message.body.len(), 16);
+        assert_eq!(
+            message.content_type.as_deref(),
+            Some("application/octet-stream")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_streams_and_restore_is_bounded() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::Service;
+
+        // Bigger than axum's default 2 MB body limit
+        let body = "x".repeat(3 * 1024 * 1024);
+        let source = test_state();
+        source.broker.create_queue("orders").await.unwrap();
+        source
+            .broker
+            .publish_bytes("orders", body.clone())
+            .await
+            .unwrap();
+
+        let response = create_router(source)
+            .call(
+                Request::post("/admin/snapshot")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let snapshot = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        let restore = |state: AppState| {
+            let request = Request::post("/admin/restore")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(snapshot.clone()))
+                .unwrap();
+            create_router(state).call(request)
+        };
+
+        let small = AppState {
+            max_restore_bytes: snapshot.len() - 1,
+            ..test_state()
+        };
+        let response = restore(small.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(small.broker.list_queues().await.unwrap().is_empty());
+
+        let large = AppState {
+            max_restore_bytes: snapshot.len(),
+            ..test_state()
+        };
+        let response = restore(large.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let message = large.broker.receive("orders").await.unwrap().unwrap();
+        assert_eq!(message.body_as_str(), Some(body.as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_receive_max_is_clamped() {
+        let state = AppState {
+            max_receive_batch: 2,
+            ..test_state()
+        };
+        state.broker.create_queue("orders").await.unwrap();
+        for i in 0..5 {
+            state
+                .broker
+                .publish_bytes("orders", format!("m{}", i))
+                .await
+                .unwrap();
+        }
+
+        let receive = |max: usize| {
+            let query = ReceiveQuery {
+                max,
+                fields: None,
+                body_path: None,
+                prefetch: None,
+                wait_secs: 0,
+                parse: false,
+                min_priority: None,
+                published_before: None,
+            };
+            receive_messages(
+                State(state.clone()),
+                Path("orders".to_string()),
+                Query(query),
+                HeaderMap::new(),
+            )
+        };
+
+        let response = receive(1_000_000).await.unwrap();
+        assert_eq!(response.headers()[RECEIVE_CLAMPED_HEADER], "2");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(messages.len(), 2);
+
+        let response = receive(1).await.unwrap();
+        assert!(response.headers().get(RECEIVE_CLAMPED_HEADER).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_receive_reports_max_delivery_count() {
+        let state = test_state();
+        let config = QueueConfig {
+            nack_requeue_position: RequeuePosition::Front,
+            ..Default::default()
+        };
+        state
+            .broker
+            .create_queue_with_config("orders", config)
+            .await
+            .unwrap();
+        let retried = state
+            .broker
+            .publish_bytes("orders", "retried")
+            .await
+            .unwrap();
+        state.broker.publish_bytes("orders", "fresh").await.unwrap();
+
+        // Deliver the first message three times
+        for _ in 0..2 {
+            let message = state.broker.receive("orders").await.unwrap().unwrap();
+            assert_eq!(message.id, retried);
+            state.broker.nack("orders", &message.id).await.unwrap();
+        }
+
+        let receive = || {
+            let query = ReceiveQuery {
+                max: 10,
+                fields: None,
+                body_path: None,
+                prefetch: None,
+                wait_secs: 0,
+                parse: false,
+                min_priority: None,
+                published_before: None,
+            };
+            receive_messages(
+                State(state.clone()),
+                Path("orders".to_string()),
+                Query(query),
+                HeaderMap::new(),
+            )
+        };
+
+        let response = receive().await.unwrap();
+        assert_eq!(response.headers()[MAX_DELIVERY_COUNT_HEADER], "3");
+
+        // Nothing delivered, nothing to report
+        let response = receive().await.unwrap();
+        assert!(response.headers().get(MAX_DELIVERY_COUNT_HEADER).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_slot_freed_when_visibility_lapses() {
+        let state = test_state();
+        let config = QueueConfig {
+            visibility_timeout_secs: 0,
+            ..Default::default()
+        };
+        state
+            .broker
+            .create_queue_with_config("orders", config)
+            .await
+            .unwrap();
+        state.broker.publish_bytes("orders", "a").await.unwrap();
+
+        let receive = || {
+            let query = ReceiveQuery {
+                max: 10,
+                fields: None,
+                body_path: None,
+                prefetch: Some(1),
+                wait_secs: 0,
+                parse: false,
+                min_priority: None,
+                published_before: None,
+            };
+            let mut headers = HeaderMap::new();
+            headers.insert(CONSUMER_HEADER, "worker-1".parse().unwrap());
+            let state = state.clone();
+            async move {
+                let response = receive_messages(
+                    State(state),
+                    Path("orders".to_string()),
+                    Query(query),
+                    headers,
+                )
+                .await
+                .unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<Vec<serde_json::Value>>(&body).unwrap()
+            }
+        };
+        assert_eq!(receive().await.len(), 1);
+
+        // Never acked: the visibility timeout lapses and the message is redelivered
+        state.broker.storage().requeue_timed_out().await.unwrap();
+        let redelivered = receive().await;
+        assert_eq!(redelivered.len(), 1);
+        assert_eq!(redelivered[0]["delivery_count"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_long_poll_returns_prefetch_slots() {
+        let state = test_state();
+        state.broker.create_queue("orders").await.unwrap();
+
+        let receive = |wait_secs: u64| {
+            let query = ReceiveQuery {
+                max: 1,
+                fields: None,
+                body_path: None,
+                prefetch: Some(1),
+                wait_secs,
+                parse: false,
+                min_priority: None,
+                published_before: None,
+            };
+            let mut headers = HeaderMap::new();
+            headers.insert(CONSUMER_HEADER, "worker-1".parse().unwrap());
+            receive_messages(
+                State(state.clone()),
+                Path("orders".to_string()),
+                Query(query),
+                headers,
+            )
+        };
+
+        // The client disconnects mid long poll, dropping the handler
+        let abandoned = tokio::time::timeout(Duration::from_millis(50), receive(10)).await;
+        assert!(abandoned.is_err());
+
+        state.broker.publish_bytes("orders", "a").await.unwrap();
+        let response = receive(0).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_receive_parses_json_bodies() {
+        let state = test_state();
+        state.broker.create_queue("orders").await.unwrap();
+        let publish = |body: &str, content_type: &str| {
+            let message = Message::new(body.to_string()).with_content_type(content_type);
+            state.broker.publish("orders", message, Durability::None)
+        };
+        publish(
+            r#"{"id":7,"items":["a"]}"#,
+            "application/json; charset=utf-8",
         )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+        .await
+        .unwrap();
+        publish("not json", "application/json").await.unwrap();
+        publish(r#"{"id":8}"#, "text/plain").await.unwrap();
 
-    // Create broker with in-memory storage
-    let storage = MemoryStorage::new();
-    let broker = Arc::new(Broker::new(storage));
+        let query = ReceiveQuery {
+            max: 10,
+            fields: None,
+            body_path: None,
+            prefetch: None,
+            wait_secs: 0,
+            parse: true,
+            min_priority: None,
+            published_before: None,
+        };
+        let response = receive_messages(
+            State(state.clone()),
+            Path("orders".to_string()),
+            Query(query),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
 
-    // Start maintenance tasks
-    broker.start_maintenance().await;
+        assert_eq!(
+            messages[0]["body_json"],
+            serde_json::json!({"id": 7, "items": ["a"]})
+        );
+        assert!(messages[0].get("body").is_none());
 
-    // Create app state
-    let state = AppState { broker };
+        // Unparseable and non-JSON bodies fall back to the string body
+        for (message, body) in messages[1..].iter().zip(["not json", r#"{"id":8}"#]) {
+            assert_eq!(message["body"], body);
+            assert!(message.get("body_json").is_none());
+        }
+    }
 
-    // Create router
-    let app = create_router(state);
+    #[tokio::test]
+    async fn test_receive_min_priority() {
+        let state = test_state();
+        state.broker.create_queue("orders").await.unwrap();
+        for priority in [3, 9, 5, 7, 1] {
+            let message = Message::new(format!("p{}", priority)).with_priority(priority);
+            state
+                .broker
+                .publish("orders", message, Durability::None)
+                .await
+                .unwrap();
+        }
 
-    // Start server
-    let addr = "127.0.0.1:3000";
-    let listener = tokio::net::TcpListener::bind(addr).await?;
+        let query = ReceiveQuery {
+            max: 10,
+            fields: None,
+            body_path: None,
+            prefetch: None,
+            wait_secs: 0,
+            parse: false,
+            min_priority: Some(7),
+            published_before: None,
+        };
+        let response = receive_messages(
+            State(state.clone()),
+            Path("orders".to_string()),
+            Query(query),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        let priorities: Vec<_> = messages
+            .iter()
+            .map(|m| m["priority"].as_u64().unwrap())
+            .collect();
+        assert_eq!(priorities, [9, 7]);
 
-    info!("FlowQ server listening on {}", addr);
-    info!("Swagger UI: http://localhost:3000/swagger-ui/");
-    info!("Health check: http://localhost:3000/health");
+        // Lower-priority messages are left in place, in priority order
+        let rest = state.broker.receive_batch("orders", 10).await.unwrap();
+        let priorities: Vec<_> = rest.iter().map(|m| m.priority).collect();
+        assert_eq!(priorities, [5, 3, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_receive_published_before() {
+        let state = test_state();
+        state.broker.create_queue("orders").await.unwrap();
+        let cutoff = Utc::now() - chrono::Duration::minutes(30);
+        for (body, age_mins) in [("old-1", 60), ("new-1", 10), ("old-2", 45), ("new-2", 0)] {
+            let mut message = Message::new(body);
+            message.created_at = Utc::now() - chrono::Duration::minutes(age_mins);
+            state
+                .broker
+                .publish("orders", message, Durability::None)
+                .await
+                .unwrap();
+        }
 
-    axum::serve(listener, app).await?;
+        let query = ReceiveQuery {
+            max: 10,
+            fields: None,
+            body_path: None,
+            prefetch: None,
+            wait_secs: 0,
+            parse: false,
+            min_priority: None,
+            published_before: Some(cutoff),
+        };
+        let response = receive_messages(
+            State(state.clone()),
+            Path("orders".to_string()),
+            Query(query),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        let bodies: Vec<_> = messages
+            .iter()
+            .map(|m| m["body"].as_str().unwrap())
+            .collect();
+        assert_eq!(bodies, ["old-1", "old-2"]);
 
-    Ok(())
+        // Newer messages stay pending
+        let rest = state.broker.receive_batch("orders", 10).await.unwrap();
+        let bodies: Vec<_> = rest.iter().filter_map(|m| m.body_as_str()).collect();
+        assert_eq!(bodies, ["new-1", "new-2"]);
+    }
+
+    #[tokio::test]
+    async fn test_ack_and_receive() {
+        let state = test_state();
+        state.broker.create_queue("orders").await.unwrap();
+        for body in ["a", "b"] {
+            state.broker.publish_bytes("orders", body).await.unwrap();
+        }
+        let first = state.broker.receive("orders").await.unwrap().unwrap();
+
+        let ack_receive = |ack_id: &MessageId| {
+            let req = AckReceiveRequest {
+                ack_id: ack_id.to_string(),
+                max: 10,
+            };
+            ack_and_receive(State(state.clone()), Path("orders".to_string()), Json(req))
+        };
+
+        let Json(response) = ack_receive(&first.id).await.unwrap();
+        assert!(response.acked);
+        assert!(response.ack_error.is_none());
+        assert_eq!(response.messages.len(), 1);
+        assert_eq!(response.messages[0].body.as_deref(), Some("b"));
+
+        // Acking the same ID again fails, but the (empty) receive still succeeds
+        let Json(response) = ack_receive(&first.id).await.unwrap();
+        assert!(!response.acked);
+        assert_eq!(response.ack_error.unwrap().code, "MESSAGE_NOT_FOUND");
+        assert!(response.messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_queue_ends_long_poll() {
+        let state = test_state();
+        state.broker.create_queue("orders").await.unwrap();
+
+        let query = ReceiveQuery {
+            max: 1,
+            fields: None,
+            body_path: None,
+            prefetch: None,
+            wait_secs: 10,
+            parse: false,
+            min_priority: None,
+            published_before: None,
+        };
+        let poll = tokio::spawn(receive_messages(
+            State(state.clone()),
+            Path("orders".to_string()),
+            Query(query),
+            HeaderMap::new(),
+        ));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        state.broker.delete_queue("orders").await.unwrap();
+
+        let err = poll.await.unwrap().unwrap_err();
+        assert!(matches!(err.0, Error::QueueDeleted(_)));
+        assert_eq!(error_status(&err.0).0, StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_receive_limit_returns_429() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::Service;
+
+        let state = AppState {
+            receive_limiter: Arc::new(ReceiveLimiter::new(0, 1)),
+            problem_json: true,
+            ..test_state()
+        };
+        state.broker.create_queue("orders").await.unwrap();
+        let app = create_router(state);
+        let receive = |tenant: &str| {
+            let request = Request::get("/api/v1/queues/orders/messages?wait_secs=1")
+                .header(limit::TENANT_HEADER, tenant)
+                .body(Body::empty())
+                .unwrap();
+            app.clone().call(request)
+        };
+
+        // The first long poll holds the tenant's only slot
+        let poll = tokio::spawn(receive("acme"));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let response = receive("acme").await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            problem::PROBLEM_CONTENT_TYPE
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "TOO_MANY_REQUESTS");
+        assert_eq!(body["status"], 429);
+
+        let response = receive("globex").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(poll.await.unwrap().unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_admin_cleanup_removes_expired() {
+        let state = test_state();
+        state.broker.create_queue("orders").await.unwrap();
+        let expired = Message::new("stale").with_expiry(Utc::now() - chrono::Duration::seconds(1));
+        state
+            .broker
+            .publish("orders", expired, Durability::None)
+            .await
+            .unwrap();
+        state.broker.publish_bytes("orders", "fresh").await.unwrap();
+
+        let Json(response) = cleanup_expired(State(state.clone())).await.unwrap();
+        assert_eq!(response.removed, 1);
+        let stats = state.broker.get_queue_stats("orders").await.unwrap();
+        assert_eq!(stats.pending_count, 1);
+
+        let Json(response) = cleanup_expired(State(state.clone())).await.unwrap();
+        assert_eq!(response.removed, 0);
+    }
+
+    #[tokio::test]
+    async fn test_info_reports_build_and_runtime() {
+        let broker = Broker::builder(Arc::new(MemoryStorage::new()))
+            .auto_create_queues(true)
+            .build();
+        let state = AppState {
+            broker: Arc::new(broker),
+            strict_publish: true,
+            ..test_state()
+        };
+
+        let Json(info) = info(State(state)).await;
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(DateTime::parse_from_rfc3339(&info.build_timestamp).is_ok());
+        assert_eq!(info.storage_backend, "memory");
+        assert!(info.uptime_secs < 60);
+        assert_eq!(info.features, vec!["auto_create_queues", "strict_publish"]);
+
+        let json = serde_json::to_value(&info).unwrap();
+        for field in [
+            "version",
+            "build_timestamp",
+            "storage_backend",
+            "uptime_secs",
+            "features",
+        ] {
+            assert!(json.get(field).is_some(), "missing {}", field);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_queues_with_stats() {
+        let state = test_state();
+        for name in ["orders", "events"] {
+            state.broker.create_queue(name).await.unwrap();
+        }
+        state.broker.publish_bytes("orders", "a").await.unwrap();
+        state.broker.publish_bytes("orders", "b").await.unwrap();
+        state.broker.receive("orders").await.unwrap();
+
+        let query = ListQueuesQuery { with_stats: true };
+        let response = list_queues(State(state.clone()), Query(query))
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let listed: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(listed.len(), 2);
+
+        for entry in listed {
+            let name = entry["queue"]["name"].as_str().unwrap();
+            let stats = state.broker.get_queue_stats(name).await.unwrap();
+            for field in [
+                "message_count",
+                "pending_count",
+                "in_flight_count",
+                "size_bytes",
+            ] {
+                let expected = serde_json::to_value(&stats).unwrap()[field].clone();
+                assert_eq!(entry["stats"][field], expected, "{} {}", name, field);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_queue_etag() {
+        let state = test_state();
+        state.broker.create_queue("orders").await.unwrap();
+
+        let get = |if_none_match: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(tag) = if_none_match {
+                headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(tag).unwrap());
+            }
+            get_queue(State(state.clone()), Path("orders".to_string()), headers)
+        };
+
+        let first = get(None).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()[header::ETAG].to_str().unwrap().to_string();
+
+        // Stable while the queue is unchanged
+        let again = get(None).await.unwrap();
+        assert_eq!(again.headers()[header::ETAG], etag.as_str());
+
+        let cached = get(Some(&etag)).await.unwrap();
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(cached.headers()[header::ETAG], etag.as_str());
+
+        let weak = format!("W/{}", etag);
+        let cached = get(Some(&weak)).await.unwrap();
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+
+        // Recreating the queue changes its metadata
+        state.broker.delete_queue("orders").await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        state.broker.create_queue("orders").await.unwrap();
+
+        let changed = get(Some(&etag)).await.unwrap();
+        assert_eq!(changed.status(), StatusCode::OK);
+        assert_ne!(changed.headers()[header::ETAG], etag.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_create_queue_from_template() {
+        let state = test_state();
+        let template = QueueConfig {
+            max_retries: 8,
+            visibility_timeout_secs: 45,
+            ..Default::default()
+        };
+        put_template(
+            State(state.clone()),
+            Path("standard".to_string()),
+            Json(template),
+        )
+        .await
+        .unwrap();
+
+        let create = |name: &str, config: Option<serde_json::Value>| {
+            let req = CreateQueueRequest {
+                name: name.to_string(),
+                template: Some("standard".to_string()),
+                config,
+                auto_create_dlq: false,
+            };
+            create_queue(State(state.clone()), Json(req))
+        };
+
+        let (_, Json(plain)) = create("orders", None).await.unwrap();
+        assert_eq!(plain.config.max_retries, 8);
+
+        let overrides = serde_json::json!({"visibility_timeout_secs": 5});
+        let (_, Json(custom)) = create("payments", Some(overrides)).await.unwrap();
+        assert_eq!(custom.config.max_retries, 8);
+        assert_eq!(custom.config.visibility_timeout_secs, 5);
+
+        let req = CreateQueueRequest {
+            name: "events".to_string(),
+            template: Some("missing".to_string()),
+            config: None,
+            auto_create_dlq: false,
+        };
+        let err = create_queue(State(state), Json(req)).await.unwrap_err();
+        assert_eq!(error_status(&err.0).0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_create_queue_auto_creates_dlq() {
+        let state = test_state();
+        let req: CreateQueueRequest = serde_json::from_value(serde_json::json!({
+            "name": "orders",
+            "config": {"dead_letter_queue": "orders-dlq"},
+            "auto_create_dlq": true
+        }))
+        .unwrap();
+        let (status, Json(queue)) = create_queue(State(state.clone()), Json(req)).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(
+            queue.config.dead_letter_queue.as_deref(),
+            Some("orders-dlq")
+        );
+
+        assert!(state.broker.get_queue("orders").await.unwrap().is_some());
+        assert!(state
+            .broker
+            .get_queue("orders-dlq")
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_get_queue_config_returns_only_config() {
+        let state = test_state();
+        let config = QueueConfig {
+            max_retries: 9,
+            visibility_timeout_secs: 12,
+            ..Default::default()
+        };
+        state
+            .broker
+            .create_queue_with_config("orders", config.clone())
+            .await
+            .unwrap();
+
+        let Json(returned) = get_queue_config(State(state.clone()), Path("orders".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&returned).unwrap(),
+            serde_json::to_value(&config).unwrap()
+        );
+
+        let missing = get_queue_config(State(state), Path("missing".to_string())).await;
+        assert!(matches!(missing, Err(AppError(Error::QueueNotFound(_)))));
+    }
+
+    #[test]
+    fn test_openapi_documents_queue_config_defaults() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let properties = &spec["components"]["schemas"]["QueueConfig"]["properties"];
+
+        assert_eq!(properties["visibility_timeout_secs"]["default"], 30);
+        assert_eq!(properties["max_retries"]["default"], 5);
+        assert_eq!(properties["dedup_window_secs"]["default"], 300);
+    }
+
+    #[tokio::test]
+    async fn test_import_streams_ndjson() {
+        const COUNT: usize = 20_000;
+        let state = test_state();
+        state.broker.create_queue("imports").await.unwrap();
+
+        let lines = (0..COUNT)
+            .map(|i| {
+                format!(
+                    "{{\"body\":\"message {}\",\"priority\":{}}}\n",
+                    i,
+                    i % 10 + 1
+                )
+            })
+            .chain(["\n".to_string(), "not json\n".to_string()]);
+        // Split every line in two so lines straddle chunk boundaries; the
+        // body is generated lazily as the handler reads it
+        let chunks = lines.flat_map(|line| {
+            let (head, tail) = line.split_at(line.len() / 2);
+            [head.as_bytes().to_vec(), tail.as_bytes().to_vec()]
+        });
+        let body = Body::from_stream(tokio_stream::iter(chunks.map(Ok::<_, std::io::Error>)));
+
+        let Json(report) = import_messages(State(state.clone()), Path("imports".to_string()), body)
+            .await
+            .unwrap();
+        assert_eq!(report.imported, COUNT as u64);
+        assert_eq!(report.failed, 1);
+        assert_eq!(report.errors[0].line, COUNT as u64 + 2);
+        assert_eq!(report.errors[0].code, "INVALID_MESSAGE");
+
+        let stats = state.broker.get_queue_stats("imports").await.unwrap();
+        assert_eq!(stats.message_count, COUNT as u64);
+        let first = state.broker.receive("imports").await.unwrap().unwrap();
+        assert_eq!(first.priority, 10);
+
+        let body = Body::from("{\"body\":\"x\"}\n");
+        let missing = import_messages(State(state), Path("missing".to_string()), body).await;
+        assert!(matches!(missing, Err(AppError(Error::QueueNotFound(_)))));
+    }
+
+    #[tokio::test]
+    async fn test_import_into_read_only_broker_fails_fast() {
+        let storage = Arc::new(MemoryStorage::new());
+        let writer = Broker::with_storage(storage.clone());
+        writer.create_queue("imports").await.unwrap();
+        let state = AppState {
+            broker: Arc::new(Broker::builder(storage).read_only(true).build()),
+            ..test_state()
+        };
+
+        let body = Body::from("{\"body\":\"a\"}\n{\"body\":\"b\"}\n");
+        let result = import_messages(State(state), Path("imports".to_string()), body).await;
+        let Err(AppError(err)) = result else {
+            panic!("import into a read-only broker succeeded");
+        };
+        assert!(matches!(err, Error::ReadOnly(_)));
+        assert_eq!(error_status(&err).0, StatusCode::FORBIDDEN);
+        let stats = writer.get_queue_stats("imports").await.unwrap();
+        assert_eq!(stats.message_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_peek_dlq() {
+        let state = test_state();
+        state.broker.create_queue("orders-dlq").await.unwrap();
+        let config = QueueConfig {
+            max_retries: 1,
+            dead_letter_queue: Some("orders-dlq".to_string()),
+            ..Default::default()
+        };
+        state
+            .broker
+            .create_queue_with_config("orders", config)
+            .await
+            .unwrap();
+        state.broker.create_queue("plain").await.unwrap();
+
+        let msg_id = state
+            .broker
+            .publish_bytes("orders", "poison")
+            .await
+            .unwrap();
+        state.broker.receive("orders").await.unwrap().unwrap();
+        state.broker.nack("orders", &msg_id).await.unwrap();
+
+        let peek = || {
+            peek_dlq(
+                State(state.clone()),
+                Path("orders".to_string()),
+                Query(DlqPeekQuery { max: 10 }),
+            )
+        };
+        let Json(peeked) = peek().await.unwrap();
+        assert_eq!(peeked.len(), 1);
+        assert_eq!(peeked[0].id, msg_id.to_string());
+        assert_eq!(
+            peeked[0].dead_letter_reason,
+            Some(DeadLetterReason::MaxRetries)
+        );
+        assert_eq!(peeked[0].attributes[DEAD_LETTERED_FROM_ATTRIBUTE], "orders");
+        assert_eq!(peeked[0].attributes[DLQ_REASON_ATTRIBUTE], "max_retries");
+
+        // Peeking does not consume
+        assert_eq!(peek().await.unwrap().0.len(), 1);
+
+        let err = peek_dlq(
+            State(state),
+            Path("plain".to_string()),
+            Query(DlqPeekQuery { max: 10 }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error_status(&err.0).0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_reconcile() {
+        let state = test_state();
+        state.broker.create_queue("orders-dlq").await.unwrap();
+        let config = QueueConfig {
+            max_retries: 1,
+            dead_letter_queue: Some("orders-dlq".to_string()),
+            ..Default::default()
+        };
+        state
+            .broker
+            .create_queue_with_config("orders", config)
+            .await
+            .unwrap();
+
+        let poison = state
+            .broker
+            .publish_bytes("orders", "poison")
+            .await
+            .unwrap();
+        state.broker.receive("orders").await.unwrap().unwrap();
+        state.broker.nack("orders", &poison).await.unwrap();
+        let working = state.broker.publish_bytes("orders", "a").await.unwrap();
+        let waiting = state.broker.publish_bytes("orders", "b").await.unwrap();
+        state.broker.receive("orders").await.unwrap().unwrap();
+
+        let Json(snapshot) = reconcile(
+            State(state.clone()),
+            Path("orders".to_string()),
+            Query(ReconcileQuery { samples: None }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(snapshot.main.pending, 1);
+        assert_eq!(snapshot.main.in_flight, 1);
+        assert_eq!(
+            snapshot.main.sample_ids,
+            vec![waiting.to_string(), working.to_string()]
+        );
+        assert_eq!(snapshot.dlq.pending, 1);
+        assert_eq!(snapshot.dlq.sample_ids, vec![poison.to_string()]);
+
+        let Json(snapshot) = reconcile(
+            State(state.clone()),
+            Path("orders".to_string()),
+            Query(ReconcileQuery { samples: Some(1) }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(snapshot.main.sample_ids.len(), 1);
+
+        state.broker.create_queue("plain").await.unwrap();
+        let err = reconcile(
+            State(state),
+            Path("plain".to_string()),
+            Query(ReconcileQuery { samples: None }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error_status(&err.0).0, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_queue_full_sets_retry_after() {
+        let response = with_backpressure(Error::QueueFull("orders".to_string()), 5);
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "5");
+    }
+
+    #[test]
+    fn test_invalid_message_id_is_bad_request() {
+        let err = parse_message_id("not-a-uuid").unwrap_err();
+        assert_eq!(error_status(&err).0, StatusCode::BAD_REQUEST);
+
+        let id = MessageId::new();
+        assert_eq!(parse_message_id(&id.to_string()).unwrap(), id);
+    }
+
+    #[test]
+    fn test_other_errors_have_no_retry_after() {
+        let response = with_backpressure(Error::QueueNotFound("orders".to_string()), 5);
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+    }
+
+    #[test]
+    fn test_cli_switches_override_config() {
+        let mut config = ServerConfig {
+            read_only: true,
+            ..Default::default()
+        };
+        Cli::parse_from(["flowq", "--read-only=false", "--problem-json"]).apply(&mut config);
+        assert!(!config.read_only);
+        assert!(config.problem_json);
+
+        // Switches left off the command line keep the file's setting
+        config.strict_publish = true;
+        Cli::parse_from(["flowq"]).apply(&mut config);
+        assert!(config.strict_publish);
+        assert!(config.problem_json);
+    }
 }
@@ -0,0 +1,124 @@
+//! Incremental NDJSON line splitting
+//!
+//! Splits a body arriving in arbitrary chunks into lines without holding more
+//! than one line in memory. Lines longer than the cap are reported as too long
+//! and their bytes discarded as they arrive.
+
+/// A line split out of the stream
+#[derive(Debug, PartialEq)]
+pub enum Line {
+    /// A complete line, without its terminator
+    Complete(Vec<u8>),
+    /// A line exceeding the size cap
+    TooLong,
+}
+
+/// Splits chunks into `\n`-terminated lines
+pub struct LineSplitter {
+    /// Bytes of the current, unterminated line
+    buf: Vec<u8>,
+    /// Longest line accepted, in bytes
+    max_line: usize,
+    /// Whether the current line already exceeded `max_line`
+    overflowed: bool,
+}
+
+impl LineSplitter {
+    /// Create a splitter accepting lines up to `max_line` bytes
+    pub fn new(max_line: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            max_line,
+            overflowed: false,
+        }
+    }
+
+    /// Feed a chunk, appending the lines it completes to `out`
+    pub fn push(&mut self, chunk: &[u8], out: &mut Vec<Line>) {
+        let mut pieces = chunk.split(|&b| b == b'\n');
+        // `split` always yields at least one piece; the last is unterminated
+        let last = pieces.next_back().unwrap_or_default();
+
+        for piece in pieces {
+            self.append(piece);
+            out.push(self.take_line());
+        }
+        self.append(last);
+    }
+
+    /// End of input: the final line, if it was not terminated
+    pub fn finish(mut self) -> Option<Line> {
+        if self.buf.is_empty() && !self.overflowed {
+            return None;
+        }
+        Some(self.take_line())
+    }
+
+    fn append(&mut self, bytes: &[u8]) {
+        if self.overflowed {
+            return;
+        }
+        if self.buf.len() + bytes.len() > self.max_line {
+            self.buf = Vec::new();
+            self.overflowed = true;
+        } else {
+            self.buf.extend_from_slice(bytes);
+        }
+    }
+
+    fn take_line(&mut self) -> Line {
+        if std::mem::take(&mut self.overflowed) {
+            return Line::TooLong;
+        }
+        let mut line = std::mem::take(&mut self.buf);
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Line::Complete(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(chunks: &[&str], max_line: usize) -> Vec<Line> {
+        let mut splitter = LineSplitter::new(max_line);
+        let mut lines = Vec::new();
+        for chunk in chunks {
+            splitter.push(chunk.as_bytes(), &mut lines);
+            assert!(splitter.buf.len() <= max_line);
+        }
+        lines.extend(splitter.finish());
+        lines
+    }
+
+    fn complete(line: &str) -> Line {
+        Line::Complete(line.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_lines_across_chunks() {
+        let lines = split(&["{\"a\"", ":1}\r\n{\"b\":2}\n", "\nlast"], 64);
+        assert_eq!(
+            lines,
+            vec![
+                complete("{\"a\":1}"),
+                complete("{\"b\":2}"),
+                complete(""),
+                complete("last")
+            ]
+        );
+        assert_eq!(split(&["one\n"], 64), vec![complete("one")]);
+    }
+
+    #[test]
+    fn test_long_lines_are_discarded() {
+        let lines = split(&["short\n0123", "456789", "abc\nok"], 8);
+        assert_eq!(
+            lines,
+            vec![complete("short"), Line::TooLong, complete("ok")]
+        );
+        assert_eq!(split(&["0123456789"], 8), vec![Line::TooLong]);
+    }
+}
@@ -0,0 +1,314 @@
+//! Per-consumer prefetch limits
+//!
+//! Tracks which delivered messages each consumer (identified by the
+//! `x-consumer-id` header) still holds, so a receive can be capped at the
+//! consumer's remaining prefetch capacity until it acks or nacks, or the
+//! message leaves its in-flight state some other way.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use flowq_types::{Message, MessageId};
+
+/// Header identifying the consumer for prefetch limits
+pub const CONSUMER_HEADER: &str = "x-consumer-id";
+
+/// A delivered message: (queue name, message ID)
+type Delivery = (String, MessageId);
+
+#[derive(Default)]
+struct ConsumerState {
+    /// Delivered messages not yet acked or nacked, with their delivery count
+    held: HashMap<Delivery, u32>,
+    /// Slots reserved by receives still in progress
+    reserved: usize,
+}
+
+impl ConsumerState {
+    fn is_idle(&self) -> bool {
+        self.held.is_empty() && self.reserved == 0
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    consumers: HashMap<String, ConsumerState>,
+    /// Which consumer holds each delivery
+    owners: HashMap<Delivery, String>,
+}
+
+impl Inner {
+    /// Drop a consumer's entry once it holds and reserves nothing
+    fn evict_if_idle(&mut self, consumer: &str) {
+        if self
+            .consumers
+            .get(consumer)
+            .is_some_and(ConsumerState::is_idle)
+        {
+            self.consumers.remove(consumer);
+        }
+    }
+}
+
+/// Unacked deliveries per consumer
+#[derive(Default)]
+pub struct PrefetchTracker {
+    inner: Mutex<Inner>,
+}
+
+impl PrefetchTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserve up to `want` slots within `prefetch`
+    ///
+    /// The reservation holds its slots until [`Reservation::commit`] turns
+    /// them into held deliveries, or until it is dropped, e.g. because the
+    /// client went away during a long poll.
+    pub fn reserve(&self, consumer: &str, prefetch: usize, want: usize) -> Reservation<'_> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let state = inner.consumers.entry(consumer.to_string()).or_default();
+
+        let available = prefetch.saturating_sub(state.held.len() + state.reserved);
+        let granted = want.min(available);
+        state.reserved += granted;
+        inner.evict_if_idle(consumer);
+        Reservation {
+            tracker: self,
+            consumer: consumer.to_string(),
+            granted,
+        }
+    }
+
+    /// Give back `reserved` slots without recording any delivery
+    fn unreserve(&self, consumer: &str, reserved: usize) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(state) = inner.consumers.get_mut(consumer) {
+            state.reserved = state.reserved.saturating_sub(reserved);
+        }
+        inner.evict_if_idle(consumer);
+    }
+
+    /// Give back `reserved` slots and record the delivered messages as held
+    fn commit(&self, consumer: &str, reserved: usize, queue: &str, messages: &[Message]) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let inner = &mut *inner;
+        let state = inner.consumers.entry(consumer.to_string()).or_default();
+        state.reserved = state.reserved.saturating_sub(reserved);
+
+        for message in messages {
+            let delivery = (queue.to_string(), message.id.clone());
+            // Redelivered after its visibility timeout lapsed on another consumer
+            if let Some(previous) = inner.owners.insert(delivery.clone(), consumer.to_string()) {
+                if previous != consumer {
+                    if let Some(state) = inner.consumers.get_mut(&previous) {
+                        state.held.remove(&delivery);
+                    }
+                    inner.evict_if_idle(&previous);
+                }
+            }
+            let state = inner.consumers.entry(consumer.to_string()).or_default();
+            state.held.insert(delivery, message.delivery_count);
+        }
+        inner.evict_if_idle(consumer);
+    }
+
+    /// Deliveries a consumer holds, with the delivery count each was received at
+    ///
+    /// Used to check them against storage before a receive, see
+    /// [`release_delivery`](Self::release_delivery).
+    pub fn held(&self, consumer: &str) -> Vec<(String, MessageId, u32)> {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner
+            .consumers
+            .get(consumer)
+            .map(|state| {
+                state
+                    .held
+                    .iter()
+                    .map(|((queue, id), count)| (queue.clone(), id.clone(), *count))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Free the slot held for a message once it is acked or nacked
+    pub fn release(&self, queue: &str, id: &MessageId) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let delivery = (queue.to_string(), id.clone());
+
+        if let Some(consumer) = inner.owners.remove(&delivery) {
+            if let Some(state) = inner.consumers.get_mut(&consumer) {
+                state.held.remove(&delivery);
+            }
+            inner.evict_if_idle(&consumer);
+        }
+    }
+
+    /// Free the slot held for one delivery of a message that is no longer in flight
+    ///
+    /// Does nothing if the message has since been delivered again, so a
+    /// stale check cannot free the slot of the newer delivery.
+    pub fn release_delivery(&self, queue: &str, id: &MessageId, delivery_count: u32) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let delivery = (queue.to_string(), id.clone());
+
+        let Some(consumer) = inner.owners.get(&delivery).cloned() else {
+            return;
+        };
+        let Some(state) = inner.consumers.get_mut(&consumer) else {
+            return;
+        };
+        if state.held.get(&delivery) == Some(&delivery_count) {
+            state.held.remove(&delivery);
+            inner.owners.remove(&delivery);
+            inner.evict_if_idle(&consumer);
+        }
+    }
+
+    /// Free every slot held for a queue, e.g. after it is purged or deleted
+    pub fn release_queue(&self, queue: &str) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+
+        inner.owners.retain(|(q, _), _| q != queue);
+        inner.consumers.retain(|_, state| {
+            state.held.retain(|(q, _), _| q != queue);
+            !state.is_idle()
+        });
+    }
+
+    /// Number of consumers currently tracked
+    #[cfg(test)]
+    fn consumer_count(&self) -> usize {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.consumers.len()
+    }
+}
+
+/// Prefetch slots reserved for a receive in progress
+///
+/// Dropping it without committing returns the slots.
+pub struct Reservation<'a> {
+    tracker: &'a PrefetchTracker,
+    consumer: String,
+    granted: usize,
+}
+
+impl Reservation<'_> {
+    /// Number of slots granted
+    pub fn granted(&self) -> usize {
+        self.granted
+    }
+
+    /// Record the delivered messages as held, returning unused slots
+    pub fn commit(mut self, queue: &str, messages: &[Message]) {
+        let granted = std::mem::take(&mut self.granted);
+        self.tracker
+            .commit(&self.consumer, granted, queue, messages);
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if self.granted > 0 {
+            self.tracker.unreserve(&self.consumer, self.granted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deliver(tracker: &PrefetchTracker, consumer: &str, want: usize) -> Vec<MessageId> {
+        let reservation = tracker.reserve(consumer, 2, want);
+        let messages: Vec<Message> = (0..reservation.granted())
+            .map(|_| Message::new("m"))
+            .collect();
+        reservation.commit("orders", &messages);
+        messages.into_iter().map(|m| m.id).collect()
+    }
+
+    #[test]
+    fn test_prefetch_caps_until_release() {
+        let tracker = PrefetchTracker::new();
+
+        let first = deliver(&tracker, "c1", 5);
+        assert_eq!(first.len(), 2);
+        assert!(deliver(&tracker, "c1", 5).is_empty());
+
+        // Other consumers have their own budget
+        assert_eq!(deliver(&tracker, "c2", 1).len(), 1);
+
+        tracker.release("orders", &first[0]);
+        assert_eq!(deliver(&tracker, "c1", 5).len(), 1);
+        assert!(deliver(&tracker, "c1", 5).is_empty());
+    }
+
+    #[test]
+    fn test_unused_reservation_is_returned() {
+        let tracker = PrefetchTracker::new();
+
+        // Queue had fewer messages than reserved
+        let reservation = tracker.reserve("c1", 3, 3);
+        reservation.commit("orders", &[Message::new("m")]);
+
+        assert_eq!(tracker.reserve("c1", 3, 3).granted(), 2);
+    }
+
+    #[test]
+    fn test_dropped_reservation_is_returned() {
+        let tracker = PrefetchTracker::new();
+
+        // The receive was abandoned before it could commit
+        let reservation = tracker.reserve("c1", 3, 3);
+        assert_eq!(reservation.granted(), 3);
+        drop(reservation);
+
+        assert_eq!(tracker.consumer_count(), 0);
+        assert_eq!(tracker.reserve("c1", 3, 3).granted(), 3);
+    }
+
+    #[test]
+    fn test_release_queue() {
+        let tracker = PrefetchTracker::new();
+        deliver(&tracker, "c1", 2);
+
+        tracker.release_queue("orders");
+        assert_eq!(deliver(&tracker, "c1", 2).len(), 2);
+    }
+
+    #[test]
+    fn test_release_delivery_skips_newer_delivery() {
+        let tracker = PrefetchTracker::new();
+        let ids = deliver(&tracker, "c1", 1);
+
+        // Held at delivery count 0; a check against count 1 is stale
+        tracker.release_delivery("orders", &ids[0], 1);
+        assert_eq!(deliver(&tracker, "c1", 2).len(), 1);
+
+        tracker.release_delivery("orders", &ids[0], 0);
+        assert_eq!(tracker.held("c1").len(), 1);
+    }
+
+    #[test]
+    fn test_idle_consumers_are_evicted() {
+        let tracker = PrefetchTracker::new();
+        let ids = deliver(&tracker, "c1", 2);
+        deliver(&tracker, "c2", 1);
+        assert_eq!(tracker.consumer_count(), 2);
+
+        for id in &ids {
+            tracker.release("orders", id);
+        }
+        assert_eq!(tracker.consumer_count(), 1);
+
+        tracker.release_queue("orders");
+        assert_eq!(tracker.consumer_count(), 0);
+
+        // A receive that got nothing leaves no entry behind
+        tracker.reserve("c3", 2, 2).commit("orders", &[]);
+        assert_eq!(tracker.consumer_count(), 0);
+    }
+}
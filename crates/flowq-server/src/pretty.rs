@@ -0,0 +1,62 @@
+//! Pretty-printed JSON responses
+//!
+//! Adding `?pretty=true` to any request re-renders a JSON response body with
+//! indentation, for reading the API by hand. Responses stay compact otherwise.
+
+use axum::{
+    body::{Body, HttpBody},
+    extract::Request,
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+
+/// Whether the query string asks for pretty output
+fn wants_pretty(query: Option<&str>) -> bool {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .any(|pair| matches!(pair, "pretty" | "pretty=true" | "pretty=1"))
+}
+
+/// Whether the response body is JSON, including `application/problem+json`
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|mime| mime == "application/json" || mime.ends_with("+json"))
+}
+
+/// Middleware re-rendering JSON responses with `serde_json::to_vec_pretty`
+///
+/// Status and headers are kept, and object keys come out sorted. Bodies that
+/// fail to parse are passed through unchanged, as are streamed bodies of no
+/// known length, such as a snapshot download, which are never buffered.
+pub async fn pretty_print(request: Request, next: Next) -> Response {
+    let pretty = wants_pretty(request.uri().query());
+    let response = next.run(request).await;
+    if !pretty || !is_json(response.headers()) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Some(len) = body.size_hint().exact() else {
+        return Response::from_parts(parts, body);
+    };
+    let bytes = match axum::body::to_bytes(body, len as usize).await {
+        Ok(bytes) => bytes,
+        Err(_) => return Response::from_parts(parts, Body::empty()),
+    };
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(value) => match serde_json::to_vec_pretty(&value) {
+            Ok(pretty) => {
+                parts.headers.remove(header::CONTENT_LENGTH);
+                Body::from(pretty)
+            }
+            Err(_) => Body::from(bytes),
+        },
+        Err(_) => Body::from(bytes),
+    };
+    Response::from_parts(parts, body)
+}
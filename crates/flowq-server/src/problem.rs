@@ -0,0 +1,77 @@
+//! RFC 7807 problem documents
+//!
+//! With `--problem-json`, error responses are re-rendered as
+//! `application/problem+json` instead of the default `ApiErrorBody`.
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Content type of problem documents
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Error details attached to error responses for re-rendering
+#[derive(Debug, Clone)]
+pub struct ErrorDetails {
+    /// Machine-readable error code, e.g. `QUEUE_NOT_FOUND`
+    pub code: &'static str,
+    /// Human-readable error message
+    pub detail: String,
+}
+
+/// RFC 7807 problem document
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProblemDetails {
+    /// Problem type URI (`about:blank`, so `title` is the HTTP status phrase)
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Short summary of the problem
+    pub title: String,
+    /// HTTP status code
+    pub status: u16,
+    /// Explanation specific to this occurrence
+    pub detail: String,
+    /// Machine-readable error code, as in `ApiErrorBody`
+    pub code: String,
+}
+
+impl ProblemDetails {
+    /// Build a problem document for an error response
+    pub fn new(status: StatusCode, details: &ErrorDetails) -> Self {
+        Self {
+            problem_type: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail: details.detail.clone(),
+            code: details.code.to_string(),
+        }
+    }
+}
+
+/// Middleware rendering error responses as problem documents
+///
+/// Only responses carrying `ErrorDetails` are rewritten; status and other
+/// headers (e.g. `Retry-After`) are kept.
+pub async fn render_problems(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let Some(details) = response.extensions().get::<ErrorDetails>().cloned() else {
+        return response;
+    };
+
+    let (mut parts, _) = response.into_parts();
+    let problem = ProblemDetails::new(parts.status, &details);
+    let body = serde_json::to_vec(&problem).expect("problem document serializes");
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(PROBLEM_CONTENT_TYPE),
+    );
+    Response::from_parts(parts, Body::from(body))
+}
@@ -0,0 +1,245 @@
+//! Receive projections
+//!
+//! Trims receive responses down to selected fields and, for JSON bodies, to a
+//! sub-document selected by a small JSONPath-like expression (`$.items[0].id`).
+
+use flowq_types::{BodyEncoding, Error};
+use serde_json::Value;
+
+use crate::{MessageResponse, SEARCH_ATTR_PREFIX};
+
+/// Top-level `MessageResponse` fields that can be selected
+const FIELDS: &[&str] = &[
+    "id",
+    "processing_token",
+    "body",
+    "body_json",
+    "body_encoding",
+    "content_type",
+    "priority",
+    "delivery_count",
+    "sequence",
+    "attributes",
+    "typed_attributes",
+    "checksum",
+    "created_at",
+    "visible_until",
+    "deadline",
+    "quarantine_reason",
+    "dead_letter_reason",
+];
+
+/// One step of a body path
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Parsed `fields` / `body_path` receive options
+#[derive(Debug)]
+pub struct Projection {
+    /// Selected top-level fields (None = all)
+    fields: Option<Vec<String>>,
+    /// Attribute keys selected via `attr.<key>`
+    attributes: Vec<String>,
+    /// Path into a JSON body
+    body_path: Option<Vec<Segment>>,
+}
+
+impl Projection {
+    /// Parse receive options; returns None when no projection was requested
+    pub fn parse(fields: Option<&str>, body_path: Option<&str>) -> Result<Option<Self>, Error> {
+        if fields.is_none() && body_path.is_none() {
+            return Ok(None);
+        }
+
+        let mut projection = Projection {
+            fields: None,
+            attributes: Vec::new(),
+            body_path: None,
+        };
+
+        if let Some(fields) = fields {
+            let mut selected = Vec::new();
+            for field in fields.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+                if let Some(key) = field.strip_prefix(SEARCH_ATTR_PREFIX) {
+                    projection.attributes.push(key.to_string());
+                } else if FIELDS.contains(&field) {
+                    selected.push(field.to_string());
+                } else {
+                    return Err(Error::InvalidMessage(format!("Unknown field: {}", field)));
+                }
+            }
+            projection.fields = Some(selected);
+        }
+
+        if let Some(path) = body_path {
+            projection.body_path = Some(parse_path(path)?);
+        }
+
+        Ok(Some(projection))
+    }
+
+    /// Apply the projection to a message response
+    ///
+    /// With a body path the body is returned as the selected JSON value, or
+    /// null if the body is not JSON or the path does not resolve.
+    pub fn apply(&self, response: MessageResponse) -> Value {
+        let json_body = match (&response.body_json, response.body_encoding) {
+            (Some(parsed), _) => Some(parsed.clone()),
+            (None, BodyEncoding::Utf8) => response
+                .body
+                .as_deref()
+                .and_then(|body| serde_json::from_str::<Value>(body).ok()),
+            (None, BodyEncoding::Base64) => None,
+        };
+
+        let mut value = serde_json::to_value(&response).unwrap_or(Value::Null);
+        let Value::Object(object) = &mut value else {
+            return value;
+        };
+
+        let attributes = object.remove("attributes");
+        if let Some(fields) = &self.fields {
+            object.retain(|key, _| fields.contains(key));
+        }
+
+        let all_attributes = match &self.fields {
+            Some(fields) => fields.iter().any(|f| f == "attributes"),
+            None => true,
+        };
+        if let Some(Value::Object(mut attrs)) = attributes {
+            if !all_attributes {
+                attrs.retain(|key, _| self.attributes.contains(key));
+            }
+            if all_attributes || !self.attributes.is_empty() {
+                object.insert("attributes".to_string(), Value::Object(attrs));
+            }
+        }
+
+        let body = match object.get_mut("body_json") {
+            Some(parsed) => Some(parsed),
+            None => object.get_mut("body"),
+        };
+        if let (Some(path), Some(body)) = (&self.body_path, body) {
+            *body = json_body
+                .as_ref()
+                .and_then(|doc| select(doc, path))
+                .cloned()
+                .unwrap_or(Value::Null);
+        }
+
+        value
+    }
+}
+
+/// Parse a path such as `$.items[0].name`
+fn parse_path(path: &str) -> Result<Vec<Segment>, Error> {
+    let invalid = || Error::InvalidMessage(format!("Invalid body_path: {}", path));
+
+    let rest = path.strip_prefix('$').ok_or_else(invalid)?;
+    let mut chars = rest.chars().peekable();
+    let mut segments = Vec::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '.' => {
+                let mut key = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c == '.' || c == '[' {
+                        break;
+                    }
+                    key.push(c);
+                    chars.next();
+                }
+                if key.is_empty() {
+                    return Err(invalid());
+                }
+                segments.push(Segment::Key(key));
+            }
+            '[' => {
+                let mut index = String::new();
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some(c) => index.push(c),
+                        None => return Err(invalid()),
+                    }
+                }
+                segments.push(Segment::Index(index.parse().map_err(|_| invalid())?));
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Walk a JSON document along a parsed path
+fn select<'a>(doc: &'a Value, path: &[Segment]) -> Option<&'a Value> {
+    path.iter().try_fold(doc, |value, segment| match segment {
+        Segment::Key(key) => value.get(key),
+        Segment::Index(index) => value.get(index),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowq_types::Message;
+    use serde_json::json;
+
+    fn response(body: &str) -> MessageResponse {
+        Message::new(body.to_string())
+            .with_priority(7)
+            .with_attribute("region", "eu")
+            .with_attribute("tenant", "acme")
+            .into()
+    }
+
+    #[test]
+    fn test_attribute_projection() {
+        let projection = Projection::parse(Some("id,priority,attr.region"), None)
+            .unwrap()
+            .unwrap();
+
+        let value = projection.apply(response("hello"));
+        let object = value.as_object().unwrap();
+
+        assert_eq!(object.len(), 3);
+        assert!(object.contains_key("id"));
+        assert_eq!(object["priority"], json!(7));
+        assert_eq!(object["attributes"], json!({ "region": "eu" }));
+    }
+
+    #[test]
+    fn test_body_path_extraction() {
+        let projection = Projection::parse(None, Some("$.order.items[1]"))
+            .unwrap()
+            .unwrap();
+
+        let value = projection.apply(response(r#"{"order":{"items":["a",{"sku":"b"}]}}"#));
+        assert_eq!(value["body"], json!({ "sku": "b" }));
+        assert_eq!(value["attributes"]["tenant"], json!("acme"));
+
+        let value = projection.apply(response("not json"));
+        assert_eq!(value["body"], Value::Null);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_input() {
+        assert!(Projection::parse(None, None).unwrap().is_none());
+        assert!(Projection::parse(Some("id,bogus"), None).is_err());
+        assert!(Projection::parse(None, Some("items")).is_err());
+        assert!(Projection::parse(None, Some("$.items[x]")).is_err());
+        assert_eq!(
+            parse_path("$.items[0].id").unwrap(),
+            vec![
+                Segment::Key("items".to_string()),
+                Segment::Index(0),
+                Segment::Key("id".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Strict publish validation
+//!
+//! By default the publish handler corrects what it can (priority is clamped to
+//! the queue's range) and accepts any content type. In strict mode those fields are checked
+//! up front and every problem is reported back at once.
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::PublishRequest;
+
+/// A single invalid request field
+#[derive(Debug, Serialize, ToSchema, PartialEq)]
+pub struct FieldError {
+    /// Name of the offending field
+    pub field: String,
+    /// What is wrong with it
+    pub message: String,
+}
+
+/// Error response listing every invalid field
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ValidationErrorBody {
+    /// Error message
+    pub error: String,
+    /// Error code
+    pub code: String,
+    /// Field-level problems
+    pub fields: Vec<FieldError>,
+}
+
+/// Check a publish request against a queue accepting priorities up to
+/// `max_priority`, returning one entry per invalid field
+pub fn validate_publish(req: &PublishRequest, max_priority: u8) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    if let Some(priority) = req.priority {
+        if !(1..=max_priority).contains(&priority) {
+            errors.push(FieldError {
+                field: "priority".to_string(),
+                message: format!("must be between 1 and {}, got {}", max_priority, priority),
+            });
+        }
+    }
+
+    if let Some(content_type) = &req.content_type {
+        if !is_valid_media_type(content_type) {
+            errors.push(FieldError {
+                field: "content_type".to_string(),
+                message: format!("'{}' is not a valid media type", content_type),
+            });
+        }
+    }
+
+    errors
+}
+
+/// 400 response for a request that failed strict validation
+pub fn validation_failed(fields: Vec<FieldError>) -> Response {
+    let body = Json(ValidationErrorBody {
+        error: "Request validation failed".to_string(),
+        code: "VALIDATION_FAILED".to_string(),
+        fields,
+    });
+    (StatusCode::BAD_REQUEST, body).into_response()
+}
+
+/// Whether a value looks like `type/subtype`, optionally followed by `; parameters`
+fn is_valid_media_type(value: &str) -> bool {
+    let essence = value.split(';').next().unwrap_or_default().trim();
+    match essence.split_once('/') {
+        Some((kind, subtype)) => is_token(kind) && is_token(subtype),
+        None => false,
+    }
+}
+
+/// RFC 7230 token characters
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(priority: Option<u8>, content_type: Option<&str>) -> PublishRequest {
+        PublishRequest {
+            id: None,
+            body: "hello".to_string(),
+            content_type: content_type.map(str::to_string),
+            priority,
+            attributes: None,
+            typed_attributes: None,
+            checksum: None,
+            no_retry: false,
+            deadline: None,
+            durability: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_valid_request_passes() {
+        let req = request(Some(10), Some("application/json; charset=utf-8"));
+        assert!(validate_publish(&req, 10).is_empty());
+        assert!(validate_publish(&request(None, None), 10).is_empty());
+    }
+
+    #[test]
+    fn test_reports_every_invalid_field() {
+        let errors = validate_publish(&request(Some(0), Some("json")), 10);
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec!["priority", "content_type"]);
+
+        assert_eq!(validate_publish(&request(Some(11), None), 10).len(), 1);
+        assert_eq!(validate_publish(&request(None, Some("text/")), 10).len(), 1);
+    }
+
+    #[test]
+    fn test_priority_checked_against_queue_max() {
+        assert!(validate_publish(&request(Some(100), None), 100).is_empty());
+
+        let errors = validate_publish(&request(Some(101), None), 100);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "must be between 1 and 100, got 101");
+    }
+}
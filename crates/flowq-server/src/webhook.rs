@@ -0,0 +1,478 @@
+//! Push delivery to per-queue webhooks
+//!
+//! Queues with `delivery_webhook` set have their messages received and POSTed,
+//! each as a `MessageResponse`, to the webhook. A 2xx response acks the
+//! message; anything else, including a timeout, nacks it, so the queue's
+//! `max_retries` and dead letter queue bound redelivery.
+//!
+//! All webhook queues share one pool of delivery slots. Free slots go to the
+//! queues in weighted round-robin order, so a busy queue cannot starve the
+//! others: while several queues have messages waiting, each gets deliveries
+//! in proportion to its `delivery_webhook_weight`.
+//!
+//! Queue configs come from API clients, so the server only POSTs to hosts on
+//! its own allowlist and never follows redirects.
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use flowq_core::Broker;
+use flowq_types::{Error, Message};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use crate::MessageResponse;
+
+/// Default number of webhook requests in flight across all queues
+pub const DEFAULT_POOL_SIZE: usize = 16;
+
+/// How often queue configs are checked for added or removed webhooks
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long an empty queue is skipped before it is polled again
+const IDLE_POLL: Duration = Duration::from_millis(50);
+
+/// Longest time allowed for a webhook to respond
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause after a failed delivery, so a down endpoint is not hammered
+const FAILURE_BACKOFF: Duration = Duration::from_secs(1);
+
+/// A webhook queue and its scheduling state
+struct Target {
+    url: String,
+    concurrency: usize,
+    weight: u32,
+    /// Time allowed for the webhook to respond
+    timeout: Duration,
+    /// Deliveries currently running for this queue
+    in_flight: usize,
+    /// Smooth weighted round-robin credit
+    credit: i64,
+    /// Skip the queue until then, after finding it empty or failing
+    paused_until: Option<Instant>,
+}
+
+/// Webhook settings read from a queue's config
+struct Settings {
+    url: String,
+    concurrency: usize,
+    weight: u32,
+    timeout: Duration,
+}
+
+impl Target {
+    fn new(settings: Settings) -> Self {
+        Self {
+            url: settings.url,
+            concurrency: settings.concurrency,
+            weight: settings.weight,
+            timeout: settings.timeout,
+            in_flight: 0,
+            credit: 0,
+            paused_until: None,
+        }
+    }
+
+    /// Whether the queue may be given a free pool slot
+    fn is_ready(&self, now: Instant) -> bool {
+        self.in_flight < self.concurrency
+            && !matches!(self.paused_until, Some(until) if until > now)
+    }
+
+    /// Skip the queue for `pause`, dropping credit it built up while busy
+    fn pause(&mut self, now: Instant, pause: Duration) {
+        self.paused_until = Some(now + pause);
+        self.credit = 0;
+    }
+}
+
+/// Time allowed for a webhook to respond on a queue with this visibility
+/// timeout
+///
+/// Kept below the visibility timeout, so a slow webhook cannot still be
+/// handling a message once it has been redelivered elsewhere.
+fn request_timeout(visibility_timeout_secs: u64) -> Duration {
+    REQUEST_TIMEOUT.min(Duration::from_secs(visibility_timeout_secs).mul_f64(0.9))
+}
+
+/// Whether `url` points at a host on the allowlist
+///
+/// Hosts are compared case-insensitively and without the port.
+fn host_allowed(allowed_hosts: &[String], url: &str) -> bool {
+    let Ok(url) = reqwest::Url::parse(url) else {
+        return false;
+    };
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    allowed_hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+}
+
+/// Choose the queue the next free slot goes to
+///
+/// Smooth weighted round-robin: every ready queue gains its weight in credit,
+/// and the one with the most credit is chosen and pays back the total weight
+/// of the ready queues. Queues are picked in proportion to their weights, with
+/// picks spread out rather than bunched.
+fn pick(targets: &mut BTreeMap<String, Target>, now: Instant) -> Option<String> {
+    let mut total = 0;
+    let mut best: Option<(&String, &mut Target)> = None;
+    for (queue, target) in targets.iter_mut() {
+        if !target.is_ready(now) {
+            continue;
+        }
+        target.credit += i64::from(target.weight);
+        total += i64::from(target.weight);
+        let leads = match &best {
+            Some((_, leader)) => target.credit > leader.credit,
+            None => true,
+        };
+        if leads {
+            best = Some((queue, target));
+        }
+    }
+    let (queue, target) = best?;
+    target.credit -= total;
+    Some(queue.clone())
+}
+
+/// Start the background task delivering messages to webhooks
+///
+/// At most `pool_size` requests run at once across all queues. Queues are
+/// picked up, updated and dropped as they are created, change their webhook
+/// or are deleted. Webhooks whose host is not in `allowed_hosts` are skipped.
+pub fn spawn_dispatcher(
+    broker: Arc<Broker>,
+    pool_size: usize,
+    allowed_hosts: Vec<String>,
+) -> JoinHandle<()> {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("webhook HTTP client");
+    let pool_size = pool_size.max(1);
+    tokio::spawn(async move {
+        let mut targets: BTreeMap<String, Target> = BTreeMap::new();
+        // Disallowed webhook URLs already warned about
+        let mut blocked: HashSet<String> = HashSet::new();
+        let mut deliveries = JoinSet::new();
+        let mut scan = tokio::time::interval(SCAN_INTERVAL);
+        loop {
+            // Hand out free slots, one message at a time
+            while deliveries.len() < pool_size {
+                let now = Instant::now();
+                let Some(queue) = pick(&mut targets, now) else {
+                    break;
+                };
+                match broker.receive(&queue).await {
+                    Ok(Some(message)) => {
+                        let Some(target) = targets.get_mut(&queue) else {
+                            continue;
+                        };
+                        target.in_flight += 1;
+                        let client = client.clone();
+                        let url = target.url.clone();
+                        let timeout = target.timeout;
+                        deliveries.spawn(async move {
+                            let delivered = post(&client, &url, timeout, &message).await;
+                            (queue, message, delivered)
+                        });
+                    }
+                    Ok(None) => pause(&mut targets, &queue, now, IDLE_POLL),
+                    Err(Error::QueueNotFound(_)) | Err(Error::QueueDeleted(_)) => {
+                        targets.remove(&queue);
+                        debug!(queue = %queue, "Webhook delivery stopped");
+                    }
+                    Err(e) => {
+                        warn!(queue = %queue, error = %e, "Webhook receive failed");
+                        pause(&mut targets, &queue, now, FAILURE_BACKOFF);
+                    }
+                }
+            }
+
+            tokio::select! {
+                Some(joined) = deliveries.join_next() => {
+                    if let Ok((queue, message, delivered)) = joined {
+                        settle(&broker, &mut targets, &queue, &message, delivered).await;
+                    }
+                }
+                _ = scan.tick() => match broker.list_queues().await {
+                    Ok(queues) => {
+                        let wanted = queues
+                            .into_iter()
+                            .filter_map(|queue| {
+                                let url = queue.config.delivery_webhook?;
+                                if !host_allowed(&allowed_hosts, &url) {
+                                    if blocked.insert(url.clone()) {
+                                        warn!(
+                                            queue = %queue.name,
+                                            url = %url,
+                                            "Webhook host not in webhook_allowed_hosts, not delivering"
+                                        );
+                                    }
+                                    return None;
+                                }
+                                let settings = Settings {
+                                    url,
+                                    concurrency: queue.config.delivery_webhook_concurrency.max(1)
+                                        as usize,
+                                    weight: queue.config.delivery_webhook_weight.max(1),
+                                    timeout: request_timeout(queue.config.visibility_timeout_secs),
+                                };
+                                Some((queue.name, settings))
+                            })
+                            .collect();
+                        reconcile(&mut targets, wanted);
+                    }
+                    Err(e) => warn!(error = %e, "Failed to list queues for webhooks"),
+                },
+                _ = tokio::time::sleep(IDLE_POLL) => {}
+            }
+        }
+    })
+}
+
+/// Pause a queue that is still being delivered to
+fn pause(targets: &mut BTreeMap<String, Target>, queue: &str, now: Instant, pause: Duration) {
+    if let Some(target) = targets.get_mut(queue) {
+        target.pause(now, pause);
+    }
+}
+
+/// Bring the delivery targets in line with the configured webhooks
+///
+/// Deliveries already running for a removed or changed queue finish against
+/// the webhook they started with.
+fn reconcile(targets: &mut BTreeMap<String, Target>, mut wanted: BTreeMap<String, Settings>) {
+    targets.retain(|queue, target| match wanted.remove(queue) {
+        Some(settings) => {
+            target.url = settings.url;
+            target.concurrency = settings.concurrency;
+            target.weight = settings.weight;
+            target.timeout = settings.timeout;
+            true
+        }
+        None => {
+            debug!(queue = %queue, "Webhook delivery stopped");
+            false
+        }
+    });
+
+    for (queue, settings) in wanted {
+        info!(queue = %queue, url = %settings.url, "Starting webhook delivery");
+        targets.insert(queue, Target::new(settings));
+    }
+}
+
+/// Ack or nack a finished delivery and free its slot
+async fn settle(
+    broker: &Broker,
+    targets: &mut BTreeMap<String, Target>,
+    queue: &str,
+    message: &Message,
+    delivered: bool,
+) {
+    let settled = if delivered {
+        broker.ack(queue, &message.id).await
+    } else {
+        broker.nack(queue, &message.id).await
+    };
+    if let Err(e) = settled {
+        warn!(
+            queue = %queue,
+            message_id = %message.id,
+            error = %e,
+            "Failed to settle webhook delivery"
+        );
+    }
+
+    if let Some(target) = targets.get_mut(queue) {
+        target.in_flight = target.in_flight.saturating_sub(1);
+        if !delivered {
+            target.pause(Instant::now(), FAILURE_BACKOFF);
+        }
+    }
+}
+
+/// POST a message to a webhook, returning whether it answered with a 2xx
+async fn post(client: &reqwest::Client, url: &str, timeout: Duration, message: &Message) -> bool {
+    let body = MessageResponse::from(message.clone());
+    match client.post(url).json(&body).timeout(timeout).send().await {
+        Ok(response) if response.status().is_success() => true,
+        Ok(response) => {
+            debug!(
+                message_id = %message.id,
+                status = %response.status(),
+                "Webhook rejected message"
+            );
+            false
+        }
+        Err(e) => {
+            debug!(message_id = %message.id, error = %e, "Webhook request failed");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+    use flowq_storage::MemoryStorage;
+    use flowq_types::QueueConfig;
+    use tokio::sync::mpsc;
+
+    /// Allowlist holding the mock webhook's host
+    fn local() -> Vec<String> {
+        vec!["127.0.0.1".to_string()]
+    }
+
+    /// Serve a webhook on a random local port, forwarding received bodies
+    async fn mock_webhook() -> (String, mpsc::UnboundedReceiver<serde_json::Value>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(
+                    |State(tx): State<mpsc::UnboundedSender<serde_json::Value>>,
+                     Json(body): Json<serde_json::Value>| async move {
+                        tx.send(body).unwrap();
+                        StatusCode::OK
+                    },
+                ),
+            )
+            .with_state(tx);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}/hook", addr), rx)
+    }
+
+    #[tokio::test]
+    async fn test_webhook_delivery_acks_on_success() {
+        let (url, mut received) = mock_webhook().await;
+        let broker = Arc::new(Broker::new(MemoryStorage::new()));
+        let config = QueueConfig {
+            delivery_webhook: Some(url),
+            ..Default::default()
+        };
+        broker
+            .create_queue_with_config("orders", config)
+            .await
+            .unwrap();
+        broker.publish_bytes("orders", "hello").await.unwrap();
+
+        let dispatcher = spawn_dispatcher(Arc::clone(&broker), DEFAULT_POOL_SIZE, local());
+
+        let body = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .expect("webhook should be called")
+            .unwrap();
+        assert_eq!(body["body"], "hello");
+
+        // The 200 response acks the message
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        loop {
+            let stats = broker.get_queue_stats("orders").await.unwrap();
+            if stats.message_count == 0 {
+                break;
+            }
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "message was not acked"
+            );
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        dispatcher.abort();
+    }
+
+    #[tokio::test]
+    async fn test_webhook_pool_shared_by_weight() {
+        let (url, mut received) = mock_webhook().await;
+        let broker = Arc::new(Broker::new(MemoryStorage::new()));
+        for (queue, weight) in [("bulk", 3), ("alerts", 1)] {
+            let config = QueueConfig {
+                delivery_webhook: Some(url.clone()),
+                delivery_webhook_weight: weight,
+                ..Default::default()
+            };
+            broker
+                .create_queue_with_config(queue, config)
+                .await
+                .unwrap();
+            for _ in 0..60 {
+                broker.publish_bytes(queue, queue).await.unwrap();
+            }
+        }
+
+        // One slot, so the queues compete for every delivery
+        let dispatcher = spawn_dispatcher(Arc::clone(&broker), 1, local());
+
+        let mut bulk = 0;
+        for _ in 0..40 {
+            let body = tokio::time::timeout(Duration::from_secs(5), received.recv())
+                .await
+                .expect("webhook should be called")
+                .unwrap();
+            if body["body"] == "bulk" {
+                bulk += 1;
+            }
+        }
+        dispatcher.abort();
+
+        // Both queues stay backlogged, so deliveries follow the 3:1 weights
+        assert!(
+            (26..=34).contains(&bulk),
+            "bulk got {} of 40 deliveries",
+            bulk
+        );
+    }
+
+    #[tokio::test]
+    async fn test_webhook_host_not_allowed() {
+        let (url, mut received) = mock_webhook().await;
+        let broker = Arc::new(Broker::new(MemoryStorage::new()));
+        let config = QueueConfig {
+            delivery_webhook: Some(url),
+            ..Default::default()
+        };
+        broker
+            .create_queue_with_config("orders", config)
+            .await
+            .unwrap();
+        broker.publish_bytes("orders", "hello").await.unwrap();
+
+        let allowed = vec!["hooks.example.com".to_string()];
+        let dispatcher = spawn_dispatcher(Arc::clone(&broker), DEFAULT_POOL_SIZE, allowed);
+        let call = tokio::time::timeout(Duration::from_millis(1500), received.recv()).await;
+        dispatcher.abort();
+
+        assert!(call.is_err(), "webhook on a disallowed host was called");
+        let stats = broker.get_queue_stats("orders").await.unwrap();
+        assert_eq!(stats.pending_count, 1);
+    }
+
+    #[test]
+    fn test_host_allowed_and_timeout() {
+        let allowed = vec!["Hooks.Example.com".to_string()];
+        assert!(host_allowed(
+            &allowed,
+            "https://hooks.example.com:8443/orders"
+        ));
+        assert!(!host_allowed(&allowed, "http://169.254.169.254/latest"));
+        assert!(!host_allowed(
+            &allowed,
+            "https://hooks.example.com.evil.test/"
+        ));
+        assert!(!host_allowed(&allowed, "not a url"));
+
+        assert_eq!(request_timeout(30), REQUEST_TIMEOUT);
+        assert_eq!(request_timeout(5), Duration::from_millis(4500));
+    }
+}
@@ -3,40 +3,712 @@
 //! Fast, non-persistent storage for development and testing.
 //! All data is lost when the process exits.
 
-use std::collections::VecDeque;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
-use chrono::Utc;
-use dashmap::DashMap;
-use flowq_types::{Error, Message, MessageId, MessageStatus, Queue, QueueStats, Result};
-use tracing::{debug, info};
+use chrono::{DateTime, Utc};
+use dashmap::{mapref::entry::Entry, DashMap};
+use flowq_types::{
+    DeadLetterReason, DeliveryStrategy, Error, Message, MessageId, MessagePage, MessageStateFilter,
+    MessageStatus, PopFilter, QuarantineReason, Queue, QueueConfig, QueueStats, RequeuePosition,
+    Result, SearchCriteria, DEAD_LETTERED_FROM_ATTRIBUTE, DELIVERY_COUNT_ATTRIBUTE,
+    DLQ_REASON_ATTRIBUTE, EXPIRED_FROM_ATTRIBUTE, FIRST_DELIVERED_AT_ATTRIBUTE,
+    RETRY_FROM_ATTRIBUTE,
+};
+use rand::Rng;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
 
 use crate::traits::StorageEngine;
 
+/// Secondary index from attribute values to message IDs for configured keys
+///
+/// Covers pending and in-flight messages, the same set that search looks at.
+struct AttributeIndex {
+    /// Attribute key -> attribute value -> message IDs
+    entries: HashMap<String, HashMap<String, HashSet<MessageId>>>,
+}
+
+impl AttributeIndex {
+    fn new(keys: &[String]) -> Self {
+        Self {
+            entries: keys.iter().map(|k| (k.clone(), HashMap::new())).collect(),
+        }
+    }
+
+    fn insert(&mut self, message: &Message) {
+        for (key, values) in self.entries.iter_mut() {
+            if let Some(value) = message.attributes.get(key) {
+                values
+                    .entry(value.clone())
+                    .or_default()
+                    .insert(message.id.clone());
+            }
+        }
+    }
+
+    fn remove(&mut self, message: &Message) {
+        for (key, values) in self.entries.iter_mut() {
+            if let Some(value) = message.attributes.get(key) {
+                if let Some(ids) = values.get_mut(value) {
+                    ids.remove(&message.id);
+                    if ids.is_empty() {
+                        values.remove(value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        for values in self.entries.values_mut() {
+            values.clear();
+        }
+    }
+
+    /// IDs matching every indexed attribute in `attributes`
+    ///
+    /// Returns `None` when none of the attributes are indexed.
+    fn lookup(&self, attributes: &HashMap<String, String>) -> Option<HashSet<MessageId>> {
+        let mut result: Option<HashSet<MessageId>> = None;
+
+        for (key, value) in attributes {
+            let Some(values) = self.entries.get(key) else {
+                continue;
+            };
+            let ids = values.get(value).cloned().unwrap_or_default();
+            result = Some(match result {
+                Some(acc) => acc.intersection(&ids).cloned().collect(),
+                None => ids,
+            });
+        }
+
+        result
+    }
+}
+
+/// Seconds of history averaged into publish and consume rates
+const RATE_WINDOW_SECS: usize = 60;
+
+/// Ring buffer of per-second event counts
+struct RateCounter {
+    /// Event counts, one bucket per second modulo the window
+    buckets: [u64; RATE_WINDOW_SECS],
+    /// Unix second each bucket currently counts
+    seconds: [i64; RATE_WINDOW_SECS],
+}
+
+impl RateCounter {
+    fn new() -> Self {
+        Self {
+            buckets: [0; RATE_WINDOW_SECS],
+            seconds: [i64::MIN; RATE_WINDOW_SECS],
+        }
+    }
+
+    fn record(&mut self, now: DateTime<Utc>) {
+        let second = now.timestamp();
+        let slot = second.rem_euclid(RATE_WINDOW_SECS as i64) as usize;
+        if self.seconds[slot] != second {
+            self.seconds[slot] = second;
+            self.buckets[slot] = 0;
+        }
+        self.buckets[slot] += 1;
+    }
+
+    /// Average events per second over the window
+    fn rate(&self, now: DateTime<Utc>) -> f64 {
+        let second = now.timestamp();
+        let oldest = second - RATE_WINDOW_SECS as i64;
+        let total: u64 = self
+            .buckets
+            .iter()
+            .zip(&self.seconds)
+            .filter(|&(_, &s)| s > oldest && s <= second)
+            .map(|(&count, _)| count)
+            .sum();
+        total as f64 / RATE_WINDOW_SECS as f64
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// Containers smaller than this are never worth shrinking
+const COMPACT_MIN_CAPACITY: usize = 64;
+
+/// Shrink once capacity exceeds this many times the live entries
+const COMPACT_RATIO: usize = 4;
+
+/// Initial sequence number; back pushes count up and front requeues count down from here
+const SEQ_START: u64 = 1 << 63;
+
+/// Pending message with its position in delivery order
+struct StoredMessage {
+    /// Per-queue sequence number, the definitive tie-breaker within a priority
+    seq: u64,
+    message: Message,
+}
+
 /// Internal queue data structure
 struct QueueData {
     /// Queue metadata
     queue: Queue,
     /// Messages in the queue (pending)
-    messages: VecDeque<Message>,
+    messages: VecDeque<StoredMessage>,
+    /// Sequence number for the next message added at the back
+    back_seq: u64,
+    /// Sequence number for the next message requeued at the front
+    front_seq: u64,
+    /// `Message::sequence` for the next message to arrive; requeues keep theirs
+    next_sequence: u64,
     /// Messages currently being processed (delivered but not acked)
-    in_flight: DashMap<MessageId, Message>,
+    in_flight: InFlight,
+    /// Messages paused from delivery after repeated failures
+    quarantine: HashMap<MessageId, Message>,
+    /// Index over configured attribute keys
+    attribute_index: AttributeIndex,
+    /// Recent nack times per message, for poison detection
+    nack_history: HashMap<MessageId, VecDeque<DateTime<Utc>>>,
+    /// Recent publishes, for `publish_rate`
+    publish_rate: RateCounter,
+    /// Recent deliveries, for `consume_rate`
+    consume_rate: RateCounter,
+    /// Dedup IDs seen within the window: first message ID and when it was published
+    dedup: HashMap<String, (MessageId, DateTime<Utc>)>,
+    /// Acked messages kept for `acked_retention_secs`, with their ack time, oldest first
+    acked: VecDeque<(DateTime<Utc>, Message)>,
+    /// Held for a whole pop when the queue requires strict ordering
+    pop_lock: Arc<Mutex<()>>,
+    /// Last publish or receive, for `auto_delete_after_idle_secs`
+    ///
+    /// Monotonic, so a wall-clock jump cannot make a queue look idle.
+    last_activity_at: Instant,
+}
+
+/// A queue's in-flight messages, counted towards the storage-wide total
+///
+/// Reads go through `Deref`; inserts and removals must use the methods here
+/// so the shared counter stays in step.
+struct InFlight {
+    messages: DashMap<MessageId, Message>,
+    total: Arc<AtomicUsize>,
+}
+
+impl InFlight {
+    fn new(total: Arc<AtomicUsize>) -> Self {
+        Self {
+            messages: DashMap::new(),
+            total,
+        }
+    }
+
+    fn insert(&self, id: MessageId, message: Message) -> Option<Message> {
+        let replaced = self.messages.insert(id, message);
+        if replaced.is_none() {
+            self.total.fetch_add(1, Ordering::AcqRel);
+        }
+        replaced
+    }
+
+    fn remove(&self, id: &MessageId) -> Option<(MessageId, Message)> {
+        let removed = self.messages.remove(id);
+        if removed.is_some() {
+            self.total.fetch_sub(1, Ordering::AcqRel);
+        }
+        removed
+    }
+
+    fn clear(&self) {
+        let count = self.messages.len();
+        self.messages.clear();
+        self.total.fetch_sub(count, Ordering::AcqRel);
+    }
+}
+
+impl Deref for InFlight {
+    type Target = DashMap<MessageId, Message>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.messages
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        // Deleted queues give back their slots
+        self.total.fetch_sub(self.messages.len(), Ordering::AcqRel);
+    }
 }
 
 impl QueueData {
-    fn new(queue: Queue) -> Self {
+    fn new(queue: Queue, in_flight_total: Arc<AtomicUsize>) -> Self {
+        let attribute_index = AttributeIndex::new(&queue.config.indexed_attributes);
         Self {
             queue,
             messages: VecDeque::new(),
-            in_flight: DashMap::new(),
+            back_seq: SEQ_START,
+            front_seq: SEQ_START - 1,
+            next_sequence: 1,
+            in_flight: InFlight::new(in_flight_total),
+            quarantine: HashMap::new(),
+            attribute_index,
+            nack_history: HashMap::new(),
+            publish_rate: RateCounter::new(),
+            consume_rate: RateCounter::new(),
+            dedup: HashMap::new(),
+            acked: VecDeque::new(),
+            pop_lock: Arc::new(Mutex::new(())),
+            last_activity_at: Instant::now(),
+        }
+    }
+
+    /// Current statistics for the queue
+    fn stats(&self, now: DateTime<Utc>) -> QueueStats {
+        let pending_count = self.messages.len() as u64;
+        let in_flight_count = self.in_flight.len() as u64;
+
+        let threshold = self.queue.config.high_attempt_threshold;
+        let struggling = |m: &Message| threshold > 0 && m.delivery_count >= threshold;
+
+        let (size_bytes, pending_high) = self.pending().fold((0u64, 0u64), |(size, high), m| {
+            (size + m.body.len() as u64, high + struggling(m) as u64)
+        });
+        let in_flight_high = if threshold > 0 {
+            self.in_flight
+                .iter()
+                .filter(|entry| struggling(entry.value()))
+                .count() as u64
+        } else {
+            0
+        };
+
+        QueueStats {
+            message_count: pending_count + in_flight_count,
+            pending_count,
+            in_flight_count,
+            quarantined_count: self.quarantine.len() as u64,
+            high_attempt_count: pending_high + in_flight_high,
+            size_bytes,
+            consumer_count: 0, // TODO: Track consumers
+            publish_rate: self.publish_rate.rate(now),
+            consume_rate: self.consume_rate.rate(now),
+            // Filled in by the caller, which can see other queues
+            dlq_pending: None,
+        }
+    }
+
+    /// Return a message to the pending queue at the configured requeue position
+    fn requeue(&mut self, mut message: Message) {
+        message.status = MessageStatus::Pending;
+        message.visible_until = None;
+        message.delivered_at = None;
+        message.consumer_token = None;
+
+        let jitter_secs = self.queue.config.requeue_jitter_secs;
+        if jitter_secs > 0 {
+            let jitter_ms = rand::thread_rng().gen_range(0..=jitter_secs * 1000);
+            let jitter = chrono::Duration::milliseconds(jitter_ms as i64);
+            message.available_at = Some(Utc::now() + jitter);
+        }
+        match self.queue.config.nack_requeue_position {
+            RequeuePosition::Front => self.enqueue_front(message),
+            RequeuePosition::Back => self.enqueue_back(message),
+        }
+    }
+
+    /// Requeue in-flight messages whose visibility deadline has passed
+    fn requeue_timed_out(&mut self, now: DateTime<Utc>) -> usize {
+        let timed_out: Vec<MessageId> = self
+            .in_flight
+            .iter()
+            .filter(|entry| entry.visible_until.is_some_and(|deadline| deadline <= now))
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        let mut requeued = 0;
+        for id in timed_out {
+            if let Some((_, message)) = self.in_flight.remove(&id) {
+                self.requeue(message);
+                requeued += 1;
+            }
+        }
+        requeued
+    }
+
+    /// Return every in-flight message to the head of the queue
+    ///
+    /// They were delivered ahead of everything still pending, so they go back
+    /// in front of it, keeping their original order.
+    fn requeue_all_inflight(&mut self) -> usize {
+        let mut held: Vec<Message> = self.in_flight.iter().map(|e| e.value().clone()).collect();
+        self.in_flight.clear();
+        held.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+
+        let requeued = held.len();
+        for mut message in held.into_iter().rev() {
+            message.status = MessageStatus::Pending;
+            message.visible_until = None;
+            message.delivered_at = None;
+            message.consumer_token = None;
+            self.enqueue_front(message);
+        }
+        requeued
+    }
+
+    /// Remove in-flight messages held past `max_inflight_duration_secs`
+    ///
+    /// Measured from the first delivery, so redeliveries do not restart it.
+    fn take_overdue(&mut self, now: DateTime<Utc>) -> Vec<Message> {
+        let ceiling_secs = self.queue.config.max_inflight_duration_secs;
+        if ceiling_secs == 0 {
+            return Vec::new();
+        }
+
+        let ceiling = chrono::Duration::seconds(ceiling_secs as i64);
+        let overdue: Vec<MessageId> = self
+            .in_flight
+            .iter()
+            .filter(|entry| {
+                entry
+                    .first_delivered_at
+                    .is_some_and(|at| now - at >= ceiling)
+            })
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        let mut taken = Vec::with_capacity(overdue.len());
+        for id in overdue {
+            if let Some((_, message)) = self.in_flight.remove(&id) {
+                self.attribute_index.remove(&message);
+                self.nack_history.remove(&id);
+                taken.push(message);
+            }
+        }
+        taken
+    }
+
+    /// Message ID already published with this dedup ID inside the window, if any
+    fn find_duplicate(&self, dedup_id: &str, now: DateTime<Utc>) -> Option<MessageId> {
+        let window = chrono::Duration::seconds(self.queue.config.dedup_window_secs as i64);
+        self.dedup
+            .get(dedup_id)
+            .filter(|(_, seen_at)| now - *seen_at < window)
+            .map(|(id, _)| id.clone())
+    }
+
+    /// Drop dedup IDs that have aged out of the window, returning how many
+    fn evict_dedup(&mut self, now: DateTime<Utc>) -> usize {
+        let window = chrono::Duration::seconds(self.queue.config.dedup_window_secs as i64);
+        let before = self.dedup.len();
+        self.dedup.retain(|_, (_, seen_at)| now - *seen_at < window);
+        before - self.dedup.len()
+    }
+
+    /// Whether an entry acked at `acked_at` is still inside the retention window
+    fn is_retained(&self, acked_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        now - acked_at < chrono::Duration::seconds(self.queue.config.acked_retention_secs as i64)
+    }
+
+    /// Drop acked messages that have aged out of the retention window, returning how many
+    fn prune_acked(&mut self, now: DateTime<Utc>) -> usize {
+        let before = self.acked.len();
+        while let Some((acked_at, _)) = self.acked.front() {
+            if self.is_retained(*acked_at, now) {
+                break;
+            }
+            self.acked.pop_front();
+        }
+        before - self.acked.len()
+    }
+
+    /// Whether the queue is empty and has been idle past its auto-delete window
+    fn is_idle(&self, now: Instant) -> bool {
+        let idle_secs = self.queue.config.auto_delete_after_idle_secs;
+        idle_secs > 0
+            && self.messages.is_empty()
+            && self.in_flight.is_empty()
+            && self.quarantine.is_empty()
+            && now.duration_since(self.last_activity_at) >= Duration::from_secs(idle_secs)
+    }
+
+    /// Add a pending message behind everything already queued
+    fn enqueue_back(&mut self, message: Message) {
+        let seq = self.back_seq;
+        self.back_seq += 1;
+        self.messages.push_back(StoredMessage { seq, message });
+    }
+
+    /// Add a pending message ahead of everything already queued
+    fn enqueue_front(&mut self, message: Message) {
+        let seq = self.front_seq;
+        self.front_seq -= 1;
+        self.messages.push_front(StoredMessage { seq, message });
+    }
+
+    /// Pending messages in queue order
+    fn pending(&self) -> impl Iterator<Item = &Message> {
+        self.messages.iter().map(|stored| &stored.message)
+    }
+
+    /// Remove up to `limit` expired pending messages, dropping them from the index
+    fn take_expired(&mut self, now: DateTime<Utc>, limit: usize) -> Vec<Message> {
+        self.take_pending(limit, |message| message.is_expired_at(now))
+    }
+
+    /// Whether a pending message's deadline has passed
+    ///
+    /// Deadlines only apply to queues with a dead letter queue to take the
+    /// late messages; elsewhere they are delivered as usual.
+    fn missed_deadline(&self, message: &Message, now: DateTime<Utc>) -> bool {
+        self.queue.config.dead_letter_queue.is_some() && message.deadline.is_some_and(|at| now > at)
+    }
+
+    /// Remove pending messages whose processing deadline has passed
+    fn take_missed_deadlines(&mut self, now: DateTime<Utc>) -> Vec<Message> {
+        if self.queue.config.dead_letter_queue.is_none() {
+            return Vec::new();
+        }
+        self.take_pending(usize::MAX, |message| {
+            message.deadline.is_some_and(|at| now > at)
+        })
+    }
+
+    /// Remove expired and deadline-missed messages from the head of the queue,
+    /// returning `(expired, missed)`
+    ///
+    /// Only looks as far as the first deliverable message; the rest are
+    /// skipped by `next_index` and removed by the maintenance sweep.
+    fn take_dead_head(&mut self, now: DateTime<Utc>) -> (Vec<Message>, Vec<Message>) {
+        let mut expired = Vec::new();
+        let mut missed = Vec::new();
+        while let Some(front) = self.messages.front() {
+            let is_expired = front.message.is_expired_at(now);
+            if !is_expired && !self.missed_deadline(&front.message, now) {
+                break;
+            }
+            let Some(stored) = self.messages.pop_front() else {
+                break;
+            };
+            self.attribute_index.remove(&stored.message);
+            self.nack_history.remove(&stored.message.id);
+            if is_expired {
+                expired.push(stored.message);
+            } else {
+                missed.push(stored.message);
+            }
+        }
+        (expired, missed)
+    }
+
+    /// Remove up to `limit` pending messages matching `matches`, in queue order
+    ///
+    /// Stops looking once `limit` are found and only rewrites the queue up to
+    /// the last one taken, so the work is bounded by how far in they sit
+    /// rather than by the queue's length. Keeps the queue's allocation.
+    fn take_pending(&mut self, limit: usize, matches: impl Fn(&Message) -> bool) -> Vec<Message> {
+        let mut hits = Vec::new();
+        for (i, stored) in self.messages.iter().enumerate() {
+            if hits.len() == limit {
+                break;
+            }
+            if matches(&stored.message) {
+                hits.push(i);
+            }
+        }
+        let Some(&last) = hits.last() else {
+            return Vec::new();
+        };
+
+        let mut taken = Vec::with_capacity(hits.len());
+        let mut kept = Vec::with_capacity(last + 1 - hits.len());
+        let mut hits = hits.into_iter().peekable();
+        for (i, stored) in self.messages.drain(..=last).enumerate() {
+            if hits.next_if_eq(&i).is_some() {
+                taken.push(stored.message);
+            } else {
+                kept.push(stored);
+            }
+        }
+        for stored in kept.into_iter().rev() {
+            self.messages.push_front(stored);
+        }
+
+        for message in &taken {
+            self.attribute_index.remove(message);
+            self.nack_history.remove(&message.id);
+        }
+        taken
+    }
+
+    /// Remove held retries whose delay has passed, with the queue each returns to
+    fn take_due_retries(&mut self, now: DateTime<Utc>) -> Vec<(String, Message)> {
+        let is_due = |message: &Message| {
+            message.attributes.contains_key(RETRY_FROM_ATTRIBUTE)
+                && message.available_at.is_some_and(|at| at <= now)
+        };
+        if !self.pending().any(is_due) {
+            return Vec::new();
+        }
+
+        let mut due = Vec::new();
+        let mut kept = VecDeque::with_capacity(self.messages.len());
+        for stored in self.messages.drain(..) {
+            if is_due(&stored.message) {
+                due.push(stored.message);
+            } else {
+                kept.push_back(stored);
+            }
+        }
+        self.messages = kept;
+
+        due.into_iter()
+            .map(|mut message| {
+                self.attribute_index.remove(&message);
+                self.nack_history.remove(&message.id);
+                let source = message
+                    .attributes_mut()
+                    .remove(RETRY_FROM_ATTRIBUTE)
+                    .unwrap_or_default();
+                message.available_at = None;
+                (source, message)
+            })
+            .collect()
+    }
+
+    /// Shrink emptied containers if they are mostly spare capacity
+    ///
+    /// Returns whether anything was shrunk.
+    fn compact(&mut self) -> bool {
+        let mostly_empty = |len: usize, capacity: usize| {
+            capacity >= COMPACT_MIN_CAPACITY && len * COMPACT_RATIO < capacity
+        };
+
+        let mut compacted = false;
+        if mostly_empty(self.in_flight.len(), self.in_flight.capacity()) {
+            self.in_flight.shrink_to_fit();
+            compacted = true;
+        }
+        if mostly_empty(self.messages.len(), self.messages.capacity()) {
+            self.messages.shrink_to_fit();
+            compacted = true;
+        }
+        if mostly_empty(self.nack_history.len(), self.nack_history.capacity()) {
+            self.nack_history.shrink_to_fit();
+            compacted = true;
+        }
+        compacted
+    }
+
+    /// Record a nack and report whether the message now counts as poison
+    fn record_nack(&mut self, message_id: &MessageId, now: DateTime<Utc>) -> bool {
+        let threshold = self.queue.config.poison_threshold;
+        if threshold == 0 {
+            return false;
+        }
+
+        let window = chrono::Duration::seconds(self.queue.config.poison_window_secs as i64);
+        let nacks = self.nack_history.entry(message_id.clone()).or_default();
+        while nacks.front().is_some_and(|&at| now - at > window) {
+            nacks.pop_front();
+        }
+        nacks.push_back(now);
+        nacks.len() >= threshold as usize
+    }
+
+    /// Position of the next pending message to deliver that matches `filter`
+    ///
+    /// Highest effective priority wins, the lowest sequence number breaks ties.
+    /// With `DeliveryStrategy::EarliestExpiry` the earliest `expires_at` comes
+    /// first and priority only breaks ties.
+    fn next_index(&self, now: DateTime<Utc>, filter: &PopFilter) -> Option<usize> {
+        let aging_secs = self.queue.config.priority_aging_secs;
+        let max_priority = self.queue.config.max_priority;
+        let by_priority = |a: &StoredMessage, b: &StoredMessage| {
+            a.message
+                .effective_priority(aging_secs, max_priority, now)
+                .cmp(&b.message.effective_priority(aging_secs, max_priority, now))
+                .then(b.seq.cmp(&a.seq))
+        };
+
+        let available = self
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(_, stored)| match stored.message.available_at {
+                Some(at) => at <= now,
+                None => true,
+            })
+            .filter(|(_, stored)| {
+                !stored.message.is_expired_at(now) && !self.missed_deadline(&stored.message, now)
+            })
+            .filter(|(_, stored)| filter.matches(&stored.message));
+        match self.queue.config.delivery_strategy {
+            DeliveryStrategy::Priority => available.max_by(|(_, a), (_, b)| by_priority(a, b)),
+            // Sooner expiry ranks higher and no expiry lowest, as None < Some
+            DeliveryStrategy::EarliestExpiry => available.max_by(|(_, a), (_, b)| {
+                a.message
+                    .expires_at
+                    .map(Reverse)
+                    .cmp(&b.message.expires_at.map(Reverse))
+                    .then_with(|| by_priority(a, b))
+            }),
         }
+        .map(|(i, _)| i)
     }
 }
 
+/// Where a nacked message goes once the queue lock is released
+enum NackRoute {
+    /// Hold in the retry queue until its delay passes
+    Retry(String, Message),
+    /// Must not be retried, move to the dead letter queue, or to the fallback
+    /// (the queue's own dead letter queue) if a per-message override names a
+    /// queue that does not exist
+    DeadLetter(String, Option<String>, Message, DeadLetterReason),
+}
+
+/// Prepare a message for its source queue's dead letter queue
+fn dead_lettered(mut message: Message, source: &str, reason: DeadLetterReason) -> Message {
+    message.status = MessageStatus::Pending;
+    message.visible_until = None;
+    message.delivered_at = None;
+    message.consumer_token = None;
+    message.deadline = None;
+    message.dead_letter_reason = Some(reason);
+    message
+        .attributes_mut()
+        .insert(DEAD_LETTERED_FROM_ATTRIBUTE.to_string(), source.to_string());
+    message.attributes_mut().insert(
+        DLQ_REASON_ATTRIBUTE.to_string(),
+        reason.as_str().to_string(),
+    );
+    message
+}
+
 /// In-memory storage implementation
 pub struct MemoryStorage {
     /// Queues stored by name
     queues: DashMap<String, QueueData>,
+    /// In-flight messages across all queues
+    in_flight_total: Arc<AtomicUsize>,
+    /// Most in-flight messages across all queues (0 = unlimited)
+    max_total_in_flight: usize,
+    /// Held by operations that check or change several queue names together
+    catalog: parking_lot::Mutex<()>,
+    /// Last queue a capped expiry cleanup reached; the next one starts after it
+    cleanup_cursor: parking_lot::Mutex<Option<String>>,
+    /// Flushes completed so far
+    flushes: AtomicU64,
+    /// How long each flush takes, standing in for a durable backend's sync
+    flush_delay: Duration,
 }
 
 impl MemoryStorage {
@@ -45,335 +717,3657 @@ impl MemoryStorage {
         info!("Initializing in-memory storage");
         Self {
             queues: DashMap::new(),
+            in_flight_total: Arc::new(AtomicUsize::new(0)),
+            max_total_in_flight: 0,
+            catalog: parking_lot::Mutex::new(()),
+            cleanup_cursor: parking_lot::Mutex::new(None),
+            flushes: AtomicU64::new(0),
+            flush_delay: Duration::ZERO,
         }
     }
-}
 
-impl Default for MemoryStorage {
-    fn default() -> Self {
-        Self::new()
+    /// Cap in-flight messages across all queues (0 = unlimited)
+    ///
+    /// Once the cap is reached every pop returns nothing until an ack, nack
+    /// or requeue frees a slot.
+    pub fn with_max_total_in_flight(mut self, max: usize) -> Self {
+        self.max_total_in_flight = max;
+        self
+    }
+
+    /// Make every flush take `delay`, to try out durable publishes against
+    /// the latency of a real sync
+    pub fn with_flush_delay(mut self, delay: Duration) -> Self {
+        self.flush_delay = delay;
+        self
+    }
+
+    /// Number of flushes completed
+    pub fn flush_count(&self) -> u64 {
+        self.flushes.load(Ordering::Acquire)
+    }
+
+    /// Add a queue under a name not yet taken
+    fn insert_new_queue(&self, queue: Queue) -> Result<Queue> {
+        match self.queues.entry(queue.name.clone()) {
+            Entry::Occupied(_) => Err(Error::QueueAlreadyExists(queue.name)),
+            Entry::Vacant(slot) => {
+                info!(queue = %queue.name, "Queue created");
+                slot.insert(QueueData::new(
+                    queue.clone(),
+                    Arc::clone(&self.in_flight_total),
+                ));
+                Ok(queue)
+            }
+        }
+    }
+
+    /// Number of in-flight messages across all queues
+    pub fn total_in_flight(&self) -> usize {
+        self.in_flight_total.load(Ordering::Acquire)
+    }
+
+    /// Claim a slot under `max_total_in_flight` for a pop
+    ///
+    /// Returns false if the cap is reached. A granted slot must be handed
+    /// back with `release_in_flight_slot` once the pop is done, since moving
+    /// the message in flight counts it separately.
+    fn reserve_in_flight_slot(&self) -> bool {
+        if self.max_total_in_flight == 0 {
+            return true;
+        }
+        self.in_flight_total
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |total| {
+                (total < self.max_total_in_flight).then_some(total + 1)
+            })
+            .is_ok()
+    }
+
+    fn release_in_flight_slot(&self) {
+        if self.max_total_in_flight > 0 {
+            self.in_flight_total.fetch_sub(1, Ordering::AcqRel);
+        }
     }
 }
 
-#[async_trait]
-impl StorageEngine for MemoryStorage {
-    // ==================== Queue Operations ====================
+impl MemoryStorage {
+    /// Move expired messages into an expiry queue, tagged with their source queue
+    ///
+    /// Must not be called while holding a lock on any queue.
+    async fn route_expired(&self, source: &str, target: &str, expired: Vec<Message>) {
+        for mut message in expired {
+            message.expires_at = None;
+            message.status = MessageStatus::Pending;
+            message
+                .attributes_mut()
+                .insert(EXPIRED_FROM_ATTRIBUTE.to_string(), source.to_string());
 
-    async fn create_queue(&self, queue: Queue) -> Result<Queue> {
-        let name = queue.name.clone();
+            let message_id = message.id.clone();
+            if let Err(e) = self.insert_message(target, message, false) {
+                warn!(
+                    queue = %source,
+                    expiry_queue = %target,
+                    message_id = %message_id,
+                    error = %e,
+                    "Failed to route expired message, dropping it"
+                );
+            }
+        }
+    }
+
+    /// Return an in-flight message to the queue (or quarantine it), optionally
+    /// changing its priority first
+    fn nack(&self, queue_name: &str, message_id: &MessageId, priority: Option<u8>) -> Result<()> {
+        let route = {
+            let mut queue_data = self
+                .queues
+                .get_mut(queue_name)
+                .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+            let (_, mut message) = queue_data
+                .in_flight
+                .remove(message_id)
+                .ok_or_else(|| Error::MessageNotFound(message_id.to_string()))?;
+
+            if let Some(priority) = priority {
+                message.priority = priority.clamp(1, queue_data.queue.config.max_priority);
+            }
+
+            let now = Utc::now();
+            let quarantine_after = queue_data.queue.config.quarantine_after;
+            let poisoned = queue_data.record_nack(message_id, now);
+
+            let retries_exhausted = message.delivery_count >= queue_data.queue.config.max_retries;
+
+            let quarantine_reason = if message.no_retry {
+                // Dead-lettered below without waiting for more nacks
+                None
+            } else if poisoned {
+                Some(QuarantineReason::Poison)
+            } else if quarantine_after > 0 && message.delivery_count >= quarantine_after {
+                Some(QuarantineReason::Retries)
+            } else {
+                None
+            };
+
+            // Quarantine takes precedence so the message can be inspected
+            if let Some(reason) = quarantine_reason {
+                message.status = MessageStatus::Quarantined;
+                message.quarantine_reason = Some(reason);
+                queue_data.attribute_index.remove(&message);
+                queue_data.nack_history.remove(message_id);
+                queue_data.quarantine.insert(message.id.clone(), message);
+                debug!(
+                    queue = %queue_name,
+                    message_id = %message_id,
+                    reason = ?reason,
+                    "Message quarantined"
+                );
+                None
+            } else if message.no_retry || retries_exhausted {
+                let reason = if message.no_retry {
+                    DeadLetterReason::NoRetry
+                } else {
+                    DeadLetterReason::MaxRetries
+                };
+                queue_data.attribute_index.remove(&message);
+                queue_data.nack_history.remove(message_id);
+                // A per-message override wins over the queue's dead letter queue
+                let configured = queue_data.queue.config.dead_letter_queue.clone();
+                let route = match message.dead_letter_override() {
+                    Some(target) => Some((target.to_string(), configured)),
+                    None => configured.map(|dlq| (dlq, None)),
+                };
+                match route {
+                    Some((dlq, fallback)) => {
+                        Some(NackRoute::DeadLetter(dlq, fallback, message, reason))
+                    }
+                    None => {
+                        message.status = MessageStatus::Failed;
+                        debug!(
+                            queue = %queue_name,
+                            message_id = %message_id,
+                            reason = ?reason,
+                            "Message not retried, marking as failed"
+                        );
+                        None
+                    }
+                }
+            } else if let Some(retry_queue) = queue_data.queue.config.retry_queue.clone() {
+                // Hold the message in the retry queue until its delay has passed
+                let delay = queue_data.queue.config.retry_delay_secs;
+                queue_data.attribute_index.remove(&message);
+                queue_data.nack_history.remove(message_id);
+
+                message.status = MessageStatus::Pending;
+                message.visible_until = None;
+                message.delivered_at = None;
+                message.available_at = Some(now + chrono::Duration::seconds(delay as i64));
+                message
+                    .attributes_mut()
+                    .insert(RETRY_FROM_ATTRIBUTE.to_string(), queue_name.to_string());
+                Some(NackRoute::Retry(retry_queue, message))
+            } else {
+                // Return to queue
+                let position = queue_data.queue.config.nack_requeue_position;
+                queue_data.requeue(message);
+                debug!(
+                    queue = %queue_name,
+                    message_id = %message_id,
+                    position = ?position,
+                    "Message returned to queue"
+                );
+                None
+            }
+        };
+
+        // Route outside the queue lock, the target may live in the same shard
+        match route {
+            Some(NackRoute::Retry(retry_queue, message)) => {
+                self.route_retry(queue_name, &retry_queue, message)
+            }
+            Some(NackRoute::DeadLetter(dlq, fallback, message, reason)) => {
+                let dlq = match fallback {
+                    Some(fallback) if !self.queues.contains_key(&dlq) => {
+                        warn!(
+                            queue = %queue_name,
+                            dead_letter_queue = %dlq,
+                            message_id = %message_id,
+                            "Dead letter override names a missing queue, using the queue's own"
+                        );
+                        fallback
+                    }
+                    _ => dlq,
+                };
+                self.route_dead_letter(queue_name, &dlq, message, reason)
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Move a message into the dead letter queue without operator involvement,
+    /// requeueing it in place if the dead letter queue cannot take it
+    ///
+    /// Must not be called while holding a lock on any queue.
+    fn route_dead_letter(
+        &self,
+        source: &str,
+        dlq: &str,
+        message: Message,
+        reason: DeadLetterReason,
+    ) {
+        let message_id = message.id.clone();
+        match self.insert_message(dlq, dead_lettered(message.clone(), source, reason), false) {
+            Ok(_) => debug!(
+                queue = %source,
+                dead_letter_queue = %dlq,
+                message_id = %message_id,
+                reason = ?reason,
+                "Message moved to dead letter queue"
+            ),
+            Err(e) => {
+                warn!(
+                    queue = %source,
+                    dead_letter_queue = %dlq,
+                    message_id = %message_id,
+                    error = %e,
+                    "Failed to route message to dead letter queue, requeueing it"
+                );
+                if let Some(mut queue_data) = self.queues.get_mut(source) {
+                    queue_data.attribute_index.insert(&message);
+                    queue_data.requeue(message);
+                }
+            }
+        }
+    }
+
+    /// Move a nacked message into its retry queue, requeueing it in place if
+    /// the retry queue cannot take it
+    ///
+    /// Must not be called while holding a lock on any queue.
+    fn route_retry(&self, source: &str, retry_queue: &str, message: Message) {
+        let message_id = message.id.clone();
+        match self.insert_message(retry_queue, message.clone(), false) {
+            Ok(_) => debug!(
+                queue = %source,
+                retry_queue = %retry_queue,
+                message_id = %message_id,
+                "Message moved to retry queue"
+            ),
+            Err(e) => {
+                warn!(
+                    queue = %source,
+                    retry_queue = %retry_queue,
+                    message_id = %message_id,
+                    error = %e,
+                    "Failed to route nacked message to retry queue, requeueing it"
+                );
+                if let Some(mut queue_data) = self.queues.get_mut(source) {
+                    let mut message = message;
+                    message.available_at = None;
+                    message.attributes_mut().remove(RETRY_FROM_ATTRIBUTE);
+                    queue_data.attribute_index.insert(&message);
+                    queue_data.requeue(message);
+                }
+            }
+        }
+    }
+
+    /// Take the queue's pop lock if it requires strict ordering
+    ///
+    /// Hold the guard for the whole pop so concurrent batches cannot interleave.
+    async fn ordering_guard(&self, queue_name: &str) -> Result<Option<OwnedMutexGuard<()>>> {
+        let pop_lock = {
+            let queue_data = self
+                .queues
+                .get(queue_name)
+                .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+            if !queue_data.queue.config.strict_ordering {
+                return Ok(None);
+            }
+            Arc::clone(&queue_data.pop_lock)
+        };
+        Ok(Some(pop_lock.lock_owned().await))
+    }
+
+    /// Pop the next message matching `filter`, recording which consumer
+    /// token it was delivered to
+    async fn pop_as(
+        &self,
+        queue_name: &str,
+        consumer: Option<&str>,
+        filter: &PopFilter,
+    ) -> Result<Option<Message>> {
+        let (popped, expired, expiry_queue, missed, dead_letter_queue) = {
+            let mut queue_data = self
+                .queues
+                .get_mut(queue_name)
+                .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+            // Skip expired and deadline-missed messages at the head
+            let now = Utc::now();
+            queue_data.last_activity_at = Instant::now();
+            let (expired, missed) = queue_data.take_dead_head(now);
+            for message in &expired {
+                debug!(
+                    queue = %queue_name,
+                    message_id = %message.id,
+                    "Skipping expired message"
+                );
+            }
+
+            let visibility_timeout =
+                chrono::Duration::seconds(queue_data.queue.config.visibility_timeout_secs as i64);
+
+            let reserved = self.reserve_in_flight_slot();
+            let next = match reserved {
+                true => queue_data
+                    .next_index(now, filter)
+                    .and_then(|i| queue_data.messages.remove(i))
+                    .map(|stored| stored.message),
+                false => None,
+            };
+
+            let popped = match next {
+                Some(mut message) => {
+                    // Update message status
+                    message.status = MessageStatus::Delivered;
+                    message.delivery_count += 1;
+                    message.visible_until = Some(now + visibility_timeout);
+                    message.delivered_at = Some(now);
+                    let first_delivered_at = *message.first_delivered_at.get_or_insert(now);
+                    message.consumer_token = consumer.map(str::to_string);
+                    queue_data.consume_rate.record(now);
+
+                    if queue_data.queue.config.inject_delivery_attributes {
+                        // Overwrite on every delivery so the values are always current
+                        let delivery_count = message.delivery_count.to_string();
+                        let attributes = message.attributes_mut();
+                        attributes.insert(DELIVERY_COUNT_ATTRIBUTE.to_string(), delivery_count);
+                        attributes.insert(
+                            FIRST_DELIVERED_AT_ATTRIBUTE.to_string(),
+                            first_delivered_at.to_rfc3339(),
+                        );
+                    }
+
+                    // Move to in-flight
+                    let message_clone = message.clone();
+                    queue_data.in_flight.insert(message.id.clone(), message);
+
+                    debug!(
+                        queue = %queue_name,
+                        message_id = %message_clone.id,
+                        delivery_count = message_clone.delivery_count,
+                        "Message popped"
+                    );
+
+                    Some(message_clone)
+                }
+                None => None,
+            };
+            if reserved {
+                self.release_in_flight_slot();
+            }
+
+            let config = &queue_data.queue.config;
+            (
+                popped,
+                expired,
+                config.expiry_queue.clone(),
+                missed,
+                config.dead_letter_queue.clone(),
+            )
+        };
+
+        // Route outside the queue lock, the target queues may live in the same shard
+        if let Some(target) = expiry_queue {
+            self.route_expired(queue_name, &target, expired).await;
+        }
+        // Deadlines are only missed on queues with a dead letter queue
+        if let Some(dlq) = dead_letter_queue {
+            for message in missed {
+                self.route_dead_letter(queue_name, &dlq, message, DeadLetterReason::DeadlineMissed);
+            }
+        }
+
+        Ok(popped)
+    }
+
+    /// Set a new message's expiry from the queue's `attribute_ttl_key`
+    /// attribute, if the queue has one and the message carries it
+    fn apply_attribute_ttl(&self, queue_name: &str, message: &mut Message) -> Result<()> {
+        let key = match self.queues.get(queue_name) {
+            Some(queue_data) => queue_data.queue.config.attribute_ttl_key.clone(),
+            None => return Err(Error::QueueNotFound(queue_name.to_string())),
+        };
+        let Some(value) = key.as_ref().and_then(|key| message.attributes.get(key)) else {
+            return Ok(());
+        };
+
+        // u32 seconds covers over a century and cannot overflow a timestamp
+        let ttl_secs: u32 = value.trim().parse().map_err(|_| {
+            Error::InvalidMessage(format!(
+                "Attribute {} must be a whole number of seconds, got {:?}",
+                key.as_deref().unwrap_or_default(),
+                value
+            ))
+        })?;
+        message.expires_at = match ttl_secs {
+            0 => None,
+            secs => Some(Utc::now() + chrono::Duration::seconds(i64::from(secs))),
+        };
+        Ok(())
+    }
+
+    /// Add a message to the back of a queue, enforcing the queue's limits
+    ///
+    /// `dedup` is off when moving an already-published message between queues,
+    /// since its dedup ID was recorded when it was first published.
+    fn insert_message(
+        &self,
+        queue_name: &str,
+        mut message: Message,
+        dedup: bool,
+    ) -> Result<MessageId> {
+        let mut queue_data = self
+            .queues
+            .get_mut(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        // Check queue limits
+        if queue_data.queue.config.max_messages > 0
+            && queue_data.messages.len() as u64 >= queue_data.queue.config.max_messages
+        {
+            return Err(Error::QueueFull(queue_name.to_string()));
+        }
+
+        // The last `high_priority_reserved` slots only take high-priority messages
+        let config = &queue_data.queue.config;
+        message.priority = message.priority.clamp(1, config.max_priority);
+        if config.high_priority_reserved > 0 && message.priority < config.high_priority_threshold {
+            let free = config
+                .max_messages
+                .saturating_sub(queue_data.messages.len() as u64);
+            if free <= config.high_priority_reserved {
+                debug!(
+                    queue = %queue_name,
+                    priority = message.priority,
+                    "Remaining capacity reserved for high-priority messages"
+                );
+                return Err(Error::QueueFull(queue_name.to_string()));
+            }
+        }
+
+        message.check_body_size(queue_data.queue.config.max_message_bytes)?;
+
+        if queue_data.queue.config.require_utf8 && message.body_as_str().is_none() {
+            return Err(Error::InvalidMessage(format!(
+                "Queue {} only accepts UTF-8 bodies",
+                queue_name
+            )));
+        }
+
+        if !message.verify_checksum() {
+            return Err(Error::InvalidMessage(format!(
+                "Checksum mismatch for message {}",
+                message.id
+            )));
+        }
+        if queue_data.queue.config.compute_checksum && message.checksum.is_none() {
+            message.checksum = Some(message.compute_checksum());
+        }
+
+        let now = Utc::now();
+        if dedup && queue_data.queue.config.dedup_enabled {
+            if let Some(dedup_id) = &message.dedup_id {
+                if let Some(original) = queue_data.find_duplicate(dedup_id, now) {
+                    debug!(
+                        queue = %queue_name,
+                        dedup_id = %dedup_id,
+                        message_id = %original,
+                        "Duplicate message dropped"
+                    );
+                    return Ok(original);
+                }
+                queue_data
+                    .dedup
+                    .insert(dedup_id.clone(), (message.id.clone(), now));
+            }
+        }
+
+        message.sequence = Some(queue_data.next_sequence);
+        queue_data.next_sequence += 1;
+
+        let message_id = message.id.clone();
+        queue_data.attribute_index.insert(&message);
+        queue_data.enqueue_back(message);
+        queue_data.publish_rate.record(now);
+        queue_data.last_activity_at = Instant::now();
+
+        debug!(
+            queue = %queue_name,
+            message_id = %message_id,
+            "Message pushed"
+        );
+
+        Ok(message_id)
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl StorageEngine for MemoryStorage {
+    fn backend_name(&self) -> &'static str {
+        "memory"
+    }
+
+    // ==================== Queue Operations ====================
+
+    async fn create_queue(&self, queue: Queue) -> Result<Queue> {
+        let _catalog = self.catalog.lock();
+        self.insert_new_queue(queue)
+    }
+
+    async fn create_queue_with_dlq(&self, queue: Queue, dlq: Queue) -> Result<Queue> {
+        let _catalog = self.catalog.lock();
+        if self.queues.contains_key(&queue.name) {
+            return Err(Error::QueueAlreadyExists(queue.name));
+        }
+        if !self.queues.contains_key(&dlq.name) {
+            self.insert_new_queue(dlq)?;
+        }
+        self.insert_new_queue(queue)
+    }
+
+    async fn get_queue(&self, name: &str) -> Result<Option<Queue>> {
+        Ok(self.queues.get(name).map(|q| q.queue.clone()))
+    }
+
+    async fn list_queues(&self) -> Result<Vec<Queue>> {
+        Ok(self.queues.iter().map(|q| q.queue.clone()).collect())
+    }
+
+    async fn queue_count(&self) -> Result<usize> {
+        Ok(self.queues.len())
+    }
+
+    async fn delete_queue(&self, name: &str) -> Result<()> {
+        let _catalog = self.catalog.lock();
+        match self.queues.remove(name) {
+            Some(_) => {
+                info!(queue = %name, "Queue deleted");
+                Ok(())
+            }
+            None => Err(Error::QueueNotFound(name.to_string())),
+        }
+    }
+
+    async fn rename_queue(&self, name: &str, new_name: &str) -> Result<Queue> {
+        let _catalog = self.catalog.lock();
+        if !self.queues.contains_key(name) {
+            return Err(Error::QueueNotFound(name.to_string()));
+        }
+        if self.queues.contains_key(new_name) {
+            return Err(Error::QueueAlreadyExists(new_name.to_string()));
+        }
+
+        // The two names may share a shard, so the old entry is removed before
+        // the new one is locked; the queue is briefly absent in between.
+        let (_, mut queue_data) = self
+            .queues
+            .remove(name)
+            .ok_or_else(|| Error::QueueNotFound(name.to_string()))?;
+        match self.queues.entry(new_name.to_string()) {
+            Entry::Occupied(_) => {
+                // Lost a race with a concurrent create: put the queue back
+                self.queues.insert(name.to_string(), queue_data);
+                Err(Error::QueueAlreadyExists(new_name.to_string()))
+            }
+            Entry::Vacant(slot) => {
+                queue_data.queue.name = new_name.to_string();
+                queue_data.queue.updated_at = Utc::now();
+                let queue = queue_data.queue.clone();
+                slot.insert(queue_data);
+                info!(queue = %name, new_name = %new_name, "Queue renamed");
+                Ok(queue)
+            }
+        }
+    }
+
+    async fn get_queue_stats(&self, name: &str) -> Result<QueueStats> {
+        let queue_data = self
+            .queues
+            .get(name)
+            .ok_or_else(|| Error::QueueNotFound(name.to_string()))?;
+        let mut stats = queue_data.stats(Utc::now());
+        let dead_letter_queue = queue_data.queue.config.dead_letter_queue.clone();
+        drop(queue_data);
+
+        // Look the DLQ up only after releasing the source, they may share a shard
+        if let Some(dlq) = dead_letter_queue {
+            stats.dlq_pending = self.queues.get(&dlq).map(|dlq| dlq.messages.len() as u64);
+        }
+        Ok(stats)
+    }
+
+    async fn list_queues_with_stats(&self) -> Result<Vec<(Queue, QueueStats)>> {
+        let now = Utc::now();
+        let mut queues: Vec<(Queue, QueueStats)> = self
+            .queues
+            .iter()
+            .map(|entry| (entry.queue.clone(), entry.stats(now)))
+            .collect();
+
+        let pending: HashMap<String, u64> = queues
+            .iter()
+            .map(|(queue, stats)| (queue.name.clone(), stats.pending_count))
+            .collect();
+        for (queue, stats) in &mut queues {
+            if let Some(dlq) = &queue.config.dead_letter_queue {
+                stats.dlq_pending = pending.get(dlq).copied();
+            }
+        }
+        Ok(queues)
+    }
+
+    async fn replace_queue_config(&self, name: &str, config: QueueConfig) -> Result<Queue> {
+        let mut queue_data = self
+            .queues
+            .get_mut(name)
+            .ok_or_else(|| Error::QueueNotFound(name.to_string()))?;
+
+        if config.indexed_attributes != queue_data.queue.config.indexed_attributes {
+            let mut index = AttributeIndex::new(&config.indexed_attributes);
+            for message in queue_data.pending() {
+                index.insert(message);
+            }
+            for entry in queue_data.in_flight.iter() {
+                index.insert(entry.value());
+            }
+            queue_data.attribute_index = index;
+        }
+
+        queue_data.queue.config = config;
+        queue_data.queue.updated_at = Utc::now();
+
+        info!(queue = %name, "Queue config replaced");
+        Ok(queue_data.queue.clone())
+    }
+
+    async fn reset_metrics(&self, name: &str) -> Result<()> {
+        let mut queue_data = self
+            .queues
+            .get_mut(name)
+            .ok_or_else(|| Error::QueueNotFound(name.to_string()))?;
+
+        queue_data.publish_rate.reset();
+        queue_data.consume_rate.reset();
+
+        info!(queue = %name, "Queue metrics reset");
+        Ok(())
+    }
+
+    // ==================== Message Operations ====================
+
+    async fn push_message(&self, queue_name: &str, mut message: Message) -> Result<MessageId> {
+        self.apply_attribute_ttl(queue_name, &mut message)?;
+        self.insert_message(queue_name, message, true)
+    }
+
+    async fn pop_message(&self, queue_name: &str) -> Result<Option<Message>> {
+        let _ordered = self.ordering_guard(queue_name).await?;
+        self.pop_as(queue_name, None, &PopFilter::default()).await
+    }
+
+    async fn pop_messages(&self, queue_name: &str, max: usize) -> Result<Vec<Message>> {
+        self.pop_messages_filtered(queue_name, max, &PopFilter::default())
+            .await
+    }
+
+    async fn pop_messages_filtered(
+        &self,
+        queue_name: &str,
+        max: usize,
+        filter: &PopFilter,
+    ) -> Result<Vec<Message>> {
+        let _ordered = self.ordering_guard(queue_name).await?;
+        let mut messages = Vec::with_capacity(max);
+
+        for _ in 0..max {
+            match self.pop_as(queue_name, None, filter).await? {
+                Some(msg) => messages.push(msg),
+                None => break,
+            }
+        }
+
+        Ok(messages)
+    }
+
+    async fn pop_messages_as(
+        &self,
+        queue_name: &str,
+        max: usize,
+        consumer: &str,
+        reclaim: bool,
+    ) -> Result<Vec<Message>> {
+        let _ordered = self.ordering_guard(queue_name).await?;
+        let mut messages = Vec::with_capacity(max);
+
+        if reclaim {
+            let queue_data = self
+                .queues
+                .get(queue_name)
+                .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+            let mut owned: Vec<(DateTime<Utc>, MessageId)> = queue_data
+                .in_flight
+                .iter()
+                .filter(|entry| entry.consumer_token.as_deref() == Some(consumer))
+                .map(|entry| (entry.created_at, entry.key().clone()))
+                .collect();
+            owned.sort();
+
+            let visibility_timeout =
+                chrono::Duration::seconds(queue_data.queue.config.visibility_timeout_secs as i64);
+            let deadline = Utc::now() + visibility_timeout;
+            for (_, id) in owned.into_iter().take(max) {
+                if let Some(mut message) = queue_data.in_flight.get_mut(&id) {
+                    message.visible_until = Some(deadline);
+                    messages.push(message.clone());
+                }
+            }
+
+            if !messages.is_empty() {
+                debug!(
+                    queue = %queue_name,
+                    consumer = %consumer,
+                    count = messages.len(),
+                    "Reclaimed in-flight messages"
+                );
+            }
+        }
+
+        while messages.len() < max {
+            match self
+                .pop_as(queue_name, Some(consumer), &PopFilter::default())
+                .await?
+            {
+                Some(msg) => messages.push(msg),
+                None => break,
+            }
+        }
+
+        Ok(messages)
+    }
+
+    async fn peek_message(&self, queue_name: &str) -> Result<Option<Message>> {
+        let queue_data = self
+            .queues
+            .get(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        Ok(queue_data
+            .next_index(Utc::now(), &PopFilter::default())
+            .and_then(|i| queue_data.messages.get(i))
+            .map(|stored| stored.message.clone()))
+    }
+
+    async fn peek_messages(&self, queue_name: &str, max: usize) -> Result<Vec<Message>> {
+        let queue_data = self
+            .queues
+            .get(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        Ok(queue_data.pending().take(max).cloned().collect())
+    }
+
+    async fn peek_page(
+        &self,
+        queue_name: &str,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> Result<MessagePage> {
+        let queue_data = self
+            .queues
+            .get(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        // Requeues and priorities reorder the deque, sequence numbers do not change
+        let mut after: Vec<&Message> = queue_data
+            .pending()
+            .filter(|m| match cursor {
+                Some(cursor) => m.sequence.unwrap_or(0) > cursor,
+                None => true,
+            })
+            .collect();
+        after.sort_by_key(|m| m.sequence);
+
+        let more = after.len() > limit;
+        after.truncate(limit);
+        let next_cursor = match after.last() {
+            Some(last) if more => last.sequence,
+            _ => None,
+        };
+        Ok(MessagePage {
+            messages: after.into_iter().cloned().collect(),
+            next_cursor,
+        })
+    }
+
+    async fn ack_message(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
+        let mut queue_data = self
+            .queues
+            .get_mut(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        match queue_data.in_flight.remove(message_id) {
+            Some((_, mut message)) => {
+                queue_data.attribute_index.remove(&message);
+                queue_data.nack_history.remove(message_id);
+                if queue_data.queue.config.acked_retention_secs > 0 {
+                    message.status = MessageStatus::Acked;
+                    message.visible_until = None;
+                    queue_data.acked.push_back((Utc::now(), message));
+                }
+                debug!(
+                    queue = %queue_name,
+                    message_id = %message_id,
+                    "Message acknowledged"
+                );
+                Ok(())
+            }
+            None => Err(Error::MessageNotFound(message_id.to_string())),
+        }
+    }
+
+    async fn nack_message(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
+        self.nack(queue_name, message_id, None)
+    }
+
+    async fn nack_with_priority(
+        &self,
+        queue_name: &str,
+        message_id: &MessageId,
+        priority: u8,
+    ) -> Result<()> {
+        self.nack(queue_name, message_id, Some(priority))
+    }
+
+    async fn move_to_dlq(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
+        let (dlq, original) = {
+            let mut queue_data = self
+                .queues
+                .get_mut(queue_name)
+                .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+            let dlq = queue_data
+                .queue
+                .config
+                .dead_letter_queue
+                .clone()
+                .ok_or_else(|| {
+                    Error::InvalidConfig(format!("Queue {} has no dead letter queue", queue_name))
+                })?;
+
+            let (_, message) = queue_data
+                .in_flight
+                .remove(message_id)
+                .ok_or_else(|| Error::MessageNotFound(message_id.to_string()))?;
+            queue_data.attribute_index.remove(&message);
+            queue_data.nack_history.remove(message_id);
+            (dlq, message)
+        };
+
+        let message = dead_lettered(original.clone(), queue_name, DeadLetterReason::Manual);
+
+        // Push outside the source lock, the DLQ may live in the same shard
+        if let Err(e) = self.insert_message(&dlq, message, false) {
+            // Keep the message in flight so the consumer can still ack or nack it
+            if let Some(mut queue_data) = self.queues.get_mut(queue_name) {
+                queue_data.attribute_index.insert(&original);
+                queue_data.in_flight.insert(original.id.clone(), original);
+            }
+            return Err(e);
+        }
+
+        debug!(
+            queue = %queue_name,
+            dead_letter_queue = %dlq,
+            message_id = %message_id,
+            "Message moved to dead letter queue"
+        );
+        Ok(())
+    }
+
+    async fn list_quarantined(&self, queue_name: &str) -> Result<Vec<Message>> {
+        let queue_data = self
+            .queues
+            .get(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        let mut messages: Vec<Message> = queue_data.quarantine.values().cloned().collect();
+        messages.sort_by_key(|m| m.created_at);
+        Ok(messages)
+    }
+
+    async fn list_acked(&self, queue_name: &str) -> Result<Vec<Message>> {
+        let queue_data = self
+            .queues
+            .get(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        // Entries past the window may linger until the next prune, hide them
+        let now = Utc::now();
+        Ok(queue_data
+            .acked
+            .iter()
+            .filter(|(acked_at, _)| queue_data.is_retained(*acked_at, now))
+            .map(|(_, message)| message.clone())
+            .collect())
+    }
+
+    async fn release_quarantined(&self, queue_name: &str) -> Result<u64> {
+        let mut queue_data = self
+            .queues
+            .get_mut(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        let mut released: Vec<Message> = queue_data.quarantine.drain().map(|(_, m)| m).collect();
+        released.sort_by_key(|m| m.created_at);
+
+        let count = released.len() as u64;
+        for mut message in released {
+            message.status = MessageStatus::Pending;
+            message.delivery_count = 0;
+            message.visible_until = None;
+            message.delivered_at = None;
+            message.quarantine_reason = None;
+            queue_data.attribute_index.insert(&message);
+            queue_data.enqueue_back(message);
+        }
+
+        info!(queue = %queue_name, count = count, "Quarantined messages released");
+        Ok(count)
+    }
+
+    async fn reset_delivery_count(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
+        let mut queue_data = self
+            .queues
+            .get_mut(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        let reset_in_flight = match queue_data.in_flight.get_mut(message_id) {
+            Some(mut message) => {
+                message.delivery_count = 0;
+                true
+            }
+            None => false,
+        };
+
+        if !reset_in_flight {
+            let stored = queue_data
+                .messages
+                .iter_mut()
+                .find(|stored| &stored.message.id == message_id)
+                .ok_or_else(|| Error::MessageNotFound(message_id.to_string()))?;
+            stored.message.delivery_count = 0;
+        }
+
+        debug!(
+            queue = %queue_name,
+            message_id = %message_id,
+            "Message delivery count reset"
+        );
+        Ok(())
+    }
+
+    async fn change_visibility(
+        &self,
+        queue_name: &str,
+        message_id: &MessageId,
+        timeout_secs: u64,
+    ) -> Result<DateTime<Utc>> {
+        let queue_data = self
+            .queues
+            .get(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        let mut message = queue_data
+            .in_flight
+            .get_mut(message_id)
+            .ok_or_else(|| Error::MessageNotFound(message_id.to_string()))?;
+
+        let deadline = Utc::now() + chrono::Duration::seconds(timeout_secs as i64);
+        message.visible_until = Some(deadline);
+
+        debug!(
+            queue = %queue_name,
+            message_id = %message_id,
+            visible_until = %deadline,
+            "Message visibility changed"
+        );
+        Ok(deadline)
+    }
+
+    async fn get_message(
+        &self,
+        queue_name: &str,
+        message_id: &MessageId,
+    ) -> Result<Option<Message>> {
+        let queue_data = self
+            .queues
+            .get(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        // Check in-flight first
+        if let Some(msg) = queue_data.in_flight.get(message_id) {
+            return Ok(Some(msg.clone()));
+        }
+
+        // Check pending messages
+        let found = queue_data.pending().find(|m| &m.id == message_id).cloned();
+        Ok(found)
+    }
+
+    async fn export_messages(&self, queue_name: &str) -> Result<Vec<Message>> {
+        let queue_data = self
+            .queues
+            .get(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        // In-flight messages were delivered before anything still pending
+        let mut messages: Vec<Message> = queue_data
+            .in_flight
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        messages.sort_by_key(|m| m.created_at);
+        messages.extend(queue_data.pending().cloned());
+
+        Ok(messages)
+    }
+
+    async fn restore_message(&self, queue_name: &str, mut message: Message) -> Result<()> {
+        let mut queue_data = self
+            .queues
+            .get_mut(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        if let Some(sequence) = message.sequence {
+            queue_data.next_sequence = queue_data.next_sequence.max(sequence + 1);
+        }
+        if message.status == MessageStatus::Quarantined {
+            queue_data.quarantine.insert(message.id.clone(), message);
+            return Ok(());
+        }
+
+        message.status = MessageStatus::Pending;
+        message.visible_until = None;
+        message.delivered_at = None;
+        message.consumer_token = None;
+        queue_data.attribute_index.insert(&message);
+        queue_data.enqueue_back(message);
+        Ok(())
+    }
+
+    async fn purge_queue(&self, queue_name: &str) -> Result<u64> {
+        let mut queue_data = self
+            .queues
+            .get_mut(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        let count = queue_data.messages.len() as u64;
+        queue_data.messages.clear();
+        queue_data.in_flight.clear();
+        queue_data.quarantine.clear();
+        queue_data.attribute_index.clear();
+        queue_data.nack_history.clear();
+
+        info!(queue = %queue_name, count = count, "Queue purged");
+        Ok(count)
+    }
+
+    async fn purge_older_than(&self, queue_name: &str, cutoff: DateTime<Utc>) -> Result<u64> {
+        let mut queue_data = self
+            .queues
+            .get_mut(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        let mut purged = Vec::new();
+        let mut kept = VecDeque::with_capacity(queue_data.messages.len());
+        for stored in queue_data.messages.drain(..) {
+            if stored.message.created_at < cutoff {
+                purged.push(stored.message);
+            } else {
+                kept.push_back(stored);
+            }
+        }
+        queue_data.messages = kept;
+
+        for message in &purged {
+            queue_data.attribute_index.remove(message);
+            queue_data.nack_history.remove(&message.id);
+        }
+
+        let count = purged.len() as u64;
+        info!(queue = %queue_name, count = count, cutoff = %cutoff, "Queue purged by age");
+        Ok(count)
+    }
+
+    async fn search_messages(
+        &self,
+        queue_name: &str,
+        criteria: &SearchCriteria,
+    ) -> Result<Vec<Message>> {
+        let queue_data = self
+            .queues
+            .get(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        // Narrow down candidates through the attribute index when possible
+        let candidates = queue_data.attribute_index.lookup(&criteria.attributes);
+        if candidates.as_ref().is_some_and(|ids| ids.is_empty()) {
+            return Ok(Vec::new());
+        }
+        let is_candidate = |m: &Message| match &candidates {
+            Some(ids) => ids.contains(&m.id),
+            None => true,
+        };
+
+        // In-flight messages have no inherent order, sort them for stable paging
+        let mut in_flight: Vec<Message> = match &candidates {
+            Some(ids) => ids
+                .iter()
+                .filter_map(|id| queue_data.in_flight.get(id).map(|m| m.value().clone()))
+                .collect(),
+            None => queue_data
+                .in_flight
+                .iter()
+                .map(|entry| entry.value().clone())
+                .collect(),
+        };
+        in_flight.sort_by_key(|m| m.created_at);
+
+        Ok(queue_data
+            .pending()
+            .filter(|m| is_candidate(m))
+            .cloned()
+            .chain(in_flight)
+            .filter(|m| criteria.matches(m))
+            .skip(criteria.offset)
+            .take(criteria.effective_limit())
+            .collect())
+    }
+
+    async fn count_messages(&self, queue_name: &str, criteria: &SearchCriteria) -> Result<u64> {
+        let queue_data = self
+            .queues
+            .get(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        // Narrow down candidates through the attribute index when possible
+        let candidates = queue_data.attribute_index.lookup(&criteria.attributes);
+        let is_candidate = |m: &Message| match &candidates {
+            Some(ids) => ids.contains(&m.id),
+            None => true,
+        };
+
+        let pending = queue_data
+            .pending()
+            .filter(|m| is_candidate(m) && criteria.matches(m))
+            .count();
+        let in_flight = match &candidates {
+            Some(ids) => ids
+                .iter()
+                .filter_map(|id| queue_data.in_flight.get(id))
+                .filter(|m| criteria.matches(m))
+                .count(),
+            None => queue_data
+                .in_flight
+                .iter()
+                .filter(|entry| criteria.matches(entry.value()))
+                .count(),
+        };
+
+        Ok((pending + in_flight) as u64)
+    }
+
+    async fn list_message_ids(
+        &self,
+        queue_name: &str,
+        filter: MessageStateFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<MessageId>> {
+        let queue_data = self
+            .queues
+            .get(queue_name)
+            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+
+        let pending: Vec<MessageId> = match filter {
+            MessageStateFilter::Pending | MessageStateFilter::All => {
+                queue_data.pending().map(|m| m.id.clone()).collect()
+            }
+            MessageStateFilter::InFlight => Vec::new(),
+        };
+
+        // In-flight messages have no inherent order, sort them for stable paging
+        let in_flight = match filter {
+            MessageStateFilter::InFlight | MessageStateFilter::All => {
+                let mut in_flight: Vec<(DateTime<Utc>, MessageId)> = queue_data
+                    .in_flight
+                    .iter()
+                    .map(|entry| (entry.created_at, entry.key().clone()))
+                    .collect();
+                in_flight.sort();
+                in_flight
+            }
+            MessageStateFilter::Pending => Vec::new(),
+        };
+
+        Ok(pending
+            .into_iter()
+            .chain(in_flight.into_iter().map(|(_, id)| id))
+            .skip(offset)
+            .take(limit)
+            .collect())
+    }
+
+    // ==================== Durability ====================
+
+    async fn flush(&self) -> Result<()> {
+        // Nothing is buffered; only the configured delay is observed
+        if !self.flush_delay.is_zero() {
+            tokio::time::sleep(self.flush_delay).await;
+        }
+        self.flushes.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    // ==================== Maintenance ====================
+
+    async fn cleanup_expired(&self, limit: usize) -> Result<u64> {
+        let mut total_cleaned = 0u64;
+        let now = Utc::now();
+        let mut remaining = if limit == 0 { usize::MAX } else { limit };
+
+        // Visit queues in name order, starting after the one the last capped
+        // pass stopped at, so no queue waits behind others with more to clean
+        let mut names: Vec<String> = self.queues.iter().map(|e| e.key().clone()).collect();
+        names.sort_unstable();
+        if let Some(cursor) = self.cleanup_cursor.lock().take() {
+            let start = names.partition_point(|name| *name <= cursor);
+            names.rotate_left(start);
+        }
+
+        for name in names {
+            // Lock one queue at a time, releasing it before routing
+            let (expired, target) = match self.queues.get_mut(&name) {
+                Some(mut queue_data) => {
+                    let expired = queue_data.take_expired(now, remaining);
+                    (expired, queue_data.queue.config.expiry_queue.clone())
+                }
+                None => continue,
+            };
+            remaining -= expired.len();
+            total_cleaned += expired.len() as u64;
+
+            if let Some(target) = target {
+                if !expired.is_empty() {
+                    self.route_expired(&name, &target, expired).await;
+                }
+            }
+            if remaining == 0 {
+                *self.cleanup_cursor.lock() = Some(name);
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+
+        if total_cleaned > 0 {
+            debug!(count = total_cleaned, "Cleaned up expired messages");
+        }
+
+        Ok(total_cleaned)
+    }
+
+    async fn requeue_timed_out(&self) -> Result<u64> {
+        let now = Utc::now();
+        let requeued: usize = self
+            .queues
+            .iter_mut()
+            .map(|mut entry| entry.requeue_timed_out(now))
+            .sum();
+
+        if requeued > 0 {
+            debug!(
+                count = requeued,
+                "Requeued messages past their visibility timeout"
+            );
+        }
+
+        Ok(requeued as u64)
+    }
+
+    async fn requeue_all_inflight(&self) -> Result<u64> {
+        let requeued: usize = self
+            .queues
+            .iter_mut()
+            .map(|mut entry| entry.requeue_all_inflight())
+            .sum();
+
+        if requeued > 0 {
+            info!(count = requeued, "Requeued in-flight messages");
+        }
+
+        Ok(requeued as u64)
+    }
+
+    async fn dead_letter_overdue(&self) -> Result<u64> {
+        let now = Utc::now();
+        let mut overdue = Vec::new();
+        for mut entry in self.queues.iter_mut() {
+            let missed = entry.take_missed_deadlines(now);
+            if let Some(dlq) = entry.queue.config.dead_letter_queue.clone() {
+                for message in missed {
+                    overdue.push((
+                        entry.key().clone(),
+                        dlq.clone(),
+                        message,
+                        DeadLetterReason::DeadlineMissed,
+                    ));
+                }
+            }
+
+            let taken = entry.take_overdue(now);
+            if taken.is_empty() {
+                continue;
+            }
+            // Validation requires a DLQ whenever the ceiling is set, but a
+            // config written before that check may lack one
+            match entry.queue.config.dead_letter_queue.clone() {
+                Some(dlq) => {
+                    for message in taken {
+                        overdue.push((
+                            entry.key().clone(),
+                            dlq.clone(),
+                            message,
+                            DeadLetterReason::InflightTimeout,
+                        ));
+                    }
+                }
+                None => {
+                    warn!(
+                        queue = %entry.key(),
+                        count = taken.len(),
+                        "Overdue in-flight messages without a dead letter queue, requeueing them"
+                    );
+                    for message in taken {
+                        entry.attribute_index.insert(&message);
+                        entry.requeue(message);
+                    }
+                }
+            }
+        }
+
+        // Route outside the queue locks, the DLQ may live in the same shard
+        let moved = overdue.len() as u64;
+        for (source, dlq, message, reason) in overdue {
+            self.route_dead_letter(&source, &dlq, message, reason);
+        }
+
+        if moved > 0 {
+            debug!(count = moved, "Dead-lettered overdue messages");
+        }
+
+        Ok(moved)
+    }
+
+    async fn promote_retries(&self) -> Result<u64> {
+        let now = Utc::now();
+        let mut due = Vec::new();
+        for mut entry in self.queues.iter_mut() {
+            let retries = entry.take_due_retries(now);
+            if !retries.is_empty() {
+                due.push((entry.key().clone(), retries));
+            }
+        }
+
+        // Route after the iterator has released its shard locks
+        let mut promoted = 0u64;
+        for (retry_queue, retries) in due {
+            for (source, message) in retries {
+                let message_id = message.id.clone();
+                match self.insert_message(&source, message.clone(), false) {
+                    Ok(_) => promoted += 1,
+                    Err(e) => {
+                        warn!(
+                            queue = %source,
+                            retry_queue = %retry_queue,
+                            message_id = %message_id,
+                            error = %e,
+                            "Failed to return retried message, keeping it for the next pass"
+                        );
+                        if let Some(mut queue_data) = self.queues.get_mut(&retry_queue) {
+                            let mut message = message;
+                            message.available_at = Some(now);
+                            message
+                                .attributes_mut()
+                                .insert(RETRY_FROM_ATTRIBUTE.to_string(), source);
+                            queue_data.attribute_index.insert(&message);
+                            queue_data.enqueue_back(message);
+                        }
+                    }
+                }
+            }
+        }
+
+        if promoted > 0 {
+            debug!(
+                count = promoted,
+                "Returned retried messages to their queues"
+            );
+        }
+
+        Ok(promoted)
+    }
+
+    async fn cleanup_dedup(&self) -> Result<u64> {
+        let now = Utc::now();
+        let evicted: usize = self
+            .queues
+            .iter_mut()
+            .map(|mut entry| entry.evict_dedup(now))
+            .sum();
+
+        if evicted > 0 {
+            debug!(count = evicted, "Evicted expired dedup IDs");
+        }
+
+        Ok(evicted as u64)
+    }
+
+    async fn idle_queues(&self) -> Result<Vec<String>> {
+        let now = Instant::now();
+        Ok(self
+            .queues
+            .iter()
+            .filter(|entry| entry.is_idle(now))
+            .map(|entry| entry.key().clone())
+            .collect())
+    }
+
+    async fn delete_idle_queue(&self, name: &str) -> Result<bool> {
+        let _catalog = self.catalog.lock();
+        let now = Instant::now();
+        let deleted = self
+            .queues
+            .remove_if(name, |_, queue_data| queue_data.is_idle(now))
+            .is_some();
+        if deleted {
+            info!(queue = %name, "Idle queue deleted");
+        }
+        Ok(deleted)
+    }
+
+    async fn prune_acked(&self) -> Result<u64> {
+        let now = Utc::now();
+        let pruned: usize = self
+            .queues
+            .iter_mut()
+            .map(|mut entry| entry.prune_acked(now))
+            .sum();
+
+        if pruned > 0 {
+            debug!(count = pruned, "Pruned retained acked messages");
+        }
+
+        Ok(pruned as u64)
+    }
+
+    async fn compact(&self) -> Result<u64> {
+        let mut compacted = 0u64;
+        for mut entry in self.queues.iter_mut() {
+            if entry.compact() {
+                compacted += 1;
+            }
+        }
+
+        if compacted > 0 {
+            debug!(queues = compacted, "Compacted queue storage");
+        }
+
+        Ok(compacted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowq_types::DLQ_OVERRIDE_ATTRIBUTE;
+
+    #[tokio::test]
+    async fn test_create_and_get_queue() {
+        let storage = MemoryStorage::new();
+        let queue = Queue::new("test-queue");
+
+        storage.create_queue(queue.clone()).await.unwrap();
+
+        let retrieved = storage.get_queue("test-queue").await.unwrap();
+        assert!(retrieved.is_some());
+        assert_eq!(retrieved.unwrap().name, "test-queue");
+    }
+
+    #[tokio::test]
+    async fn test_push_and_pop_message() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+
+        let msg = Message::new("Hello, World!");
+        let msg_id = storage.push_message("test", msg).await.unwrap();
+
+        let received = storage.pop_message("test").await.unwrap();
+        assert!(received.is_some());
+
+        let received = received.unwrap();
+        assert_eq!(received.id, msg_id);
+        assert_eq!(received.body_as_str(), Some("Hello, World!"));
+        assert_eq!(received.delivery_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_pop_sets_visible_until() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            visibility_timeout_secs: 45,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+        storage
+            .push_message("test", Message::new("test"))
+            .await
+            .unwrap();
+
+        let before = Utc::now();
+        let msg = storage.pop_message("test").await.unwrap().unwrap();
+        let visible_until = msg.visible_until.unwrap();
+
+        let expected = before + chrono::Duration::seconds(45);
+        assert!((visible_until - expected).num_seconds().abs() <= 1);
+
+        // Requeued messages are visible again
+        storage.nack_message("test", &msg.id).await.unwrap();
+        let pending = storage.get_message("test", &msg.id).await.unwrap().unwrap();
+        assert!(pending.visible_until.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reset_metrics_zeroes_rates() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+
+        for i in 0..10 {
+            storage
+                .push_message("test", Message::new(format!("{}", i)))
+                .await
+                .unwrap();
+        }
+        storage.pop_message("test").await.unwrap();
+
+        let stats = storage.get_queue_stats("test").await.unwrap();
+        assert!(stats.publish_rate > 0.0);
+        assert!(stats.consume_rate > 0.0);
+
+        storage.reset_metrics("test").await.unwrap();
+
+        let stats = storage.get_queue_stats("test").await.unwrap();
+        assert_eq!(stats.publish_rate, 0.0);
+        assert_eq!(stats.consume_rate, 0.0);
+        assert_eq!(stats.message_count, 10);
+    }
+
+    #[tokio::test]
+    async fn test_purge_older_than() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+
+        let cutoff = Utc::now();
+        for (body, age_secs) in [("old", 120), ("older", 300), ("new", -5)] {
+            let mut message = Message::new(body);
+            message.created_at = cutoff - chrono::Duration::seconds(age_secs);
+            storage.push_message("test", message).await.unwrap();
+        }
+
+        assert_eq!(storage.purge_older_than("test", cutoff).await.unwrap(), 2);
+
+        let remaining = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(remaining.body_as_str(), Some("new"));
+        assert!(storage.pop_message("test").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_compact_reclaims_capacity_after_churn() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+
+        for i in 0..2000 {
+            storage
+                .push_message("test", Message::new(format!("{}", i)))
+                .await
+                .unwrap();
+        }
+        for msg in storage.pop_messages("test", 2000).await.unwrap() {
+            storage.ack_message("test", &msg.id).await.unwrap();
+        }
+
+        let capacity = |storage: &MemoryStorage| {
+            let queue_data = storage.queues.get("test").unwrap();
+            (
+                queue_data.in_flight.capacity(),
+                queue_data.messages.capacity(),
+            )
+        };
+        let (in_flight_before, pending_before) = capacity(&storage);
+        assert!(in_flight_before >= 2000);
+        assert!(pending_before >= 2000);
+
+        assert_eq!(storage.compact().await.unwrap(), 1);
+
+        let (in_flight_after, pending_after) = capacity(&storage);
+        assert!(in_flight_after < in_flight_before);
+        assert!(pending_after < pending_before);
+
+        // Nothing left to reclaim
+        assert_eq!(storage.compact().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_list_message_ids_by_status() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+
+        let mut ids = Vec::new();
+        for i in 0..5 {
+            ids.push(
+                storage
+                    .push_message("test", Message::new(format!("{}", i)))
+                    .await
+                    .unwrap(),
+            );
+        }
+        storage.pop_messages("test", 2).await.unwrap();
+
+        let list = |filter| storage.list_message_ids("test", filter, 0, 100);
+        assert_eq!(
+            list(MessageStateFilter::Pending).await.unwrap(),
+            ids[2..].to_vec()
+        );
+        assert_eq!(list(MessageStateFilter::InFlight).await.unwrap().len(), 2);
+
+        let all = list(MessageStateFilter::All).await.unwrap();
+        assert_eq!(all.len(), 5);
+        assert_eq!(&all[..3], &ids[2..]);
+    }
+
+    #[tokio::test]
+    async fn test_list_message_ids_pagination() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+
+        let mut ids = Vec::new();
+        for i in 0..7 {
+            ids.push(
+                storage
+                    .push_message("test", Message::new(format!("{}", i)))
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let mut pages = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page = storage
+                .list_message_ids("test", MessageStateFilter::All, offset, 3)
+                .await
+                .unwrap();
+            if page.is_empty() {
+                break;
+            }
+            offset += page.len();
+            pages.push(page);
+        }
+
+        assert_eq!(
+            pages.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![3, 3, 1]
+        );
+        assert_eq!(pages.concat(), ids);
+    }
+
+    #[tokio::test]
+    async fn test_ack_message() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+
+        let msg = Message::new("test");
+        storage.push_message("test", msg).await.unwrap();
+
+        let received = storage.pop_message("test").await.unwrap().unwrap();
+        storage.ack_message("test", &received.id).await.unwrap();
+
+        // Message should be gone
+        let stats = storage.get_queue_stats("test").await.unwrap();
+        assert_eq!(stats.message_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_nack_message() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+
+        let msg = Message::new("test");
+        storage.push_message("test", msg).await.unwrap();
+
+        let received = storage.pop_message("test").await.unwrap().unwrap();
+        storage.nack_message("test", &received.id).await.unwrap();
+
+        // Message should be back in queue
+        let stats = storage.get_queue_stats("test").await.unwrap();
+        assert_eq!(stats.pending_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_push_rejects_oversized_message() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            max_message_bytes: 4,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        storage
+            .push_message("test", Message::new("ok"))
+            .await
+            .unwrap();
+
+        let result = storage.push_message("test", Message::new("too long")).await;
+        assert!(matches!(result, Err(Error::MessageTooLarge(_))));
+    }
+
+    #[tokio::test]
+    async fn test_stats_high_attempt_count() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            high_attempt_threshold: 2,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        for i in 0..3 {
+            storage
+                .push_message("test", Message::new(format!("msg-{}", i)))
+                .await
+                .unwrap();
+        }
+
+        // Every message delivered once
+        for _ in 0..3 {
+            let msg = storage.pop_message("test").await.unwrap().unwrap();
+            storage.nack_message("test", &msg.id).await.unwrap();
+        }
+        let stats = storage.get_queue_stats("test").await.unwrap();
+        assert_eq!(stats.high_attempt_count, 0);
+
+        // Second deliveries: one nacked back to pending, one held in flight
+        let first = storage.pop_message("test").await.unwrap().unwrap();
+        storage.nack_message("test", &first.id).await.unwrap();
+        let second = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(first.delivery_count, 2);
+        assert_eq!(second.delivery_count, 2);
+
+        let stats = storage.get_queue_stats("test").await.unwrap();
+        assert_eq!(stats.high_attempt_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_nack_quarantines_after_threshold() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            quarantine_after: 2,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        let msg_id = storage
+            .push_message("test", Message::new("poison"))
+            .await
+            .unwrap();
+
+        // First failure is requeued
+        let received = storage.pop_message("test").await.unwrap().unwrap();
+        storage.nack_message("test", &received.id).await.unwrap();
+        assert!(storage.list_quarantined("test").await.unwrap().is_empty());
+
+        // Second failure hits the threshold
+        let received = storage.pop_message("test").await.unwrap().unwrap();
+        storage.nack_message("test", &received.id).await.unwrap();
+
+        let quarantined = storage.list_quarantined("test").await.unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].id, msg_id);
+        assert_eq!(quarantined[0].status, MessageStatus::Quarantined);
+        assert_eq!(
+            quarantined[0].quarantine_reason,
+            Some(QuarantineReason::Retries)
+        );
+
+        // Quarantined messages are not delivered
+        assert!(storage.pop_message("test").await.unwrap().is_none());
+        let stats = storage.get_queue_stats("test").await.unwrap();
+        assert_eq!(stats.quarantined_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_rapid_nacks_quarantine_as_poison() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            max_retries: 10,
+            poison_threshold: 3,
+            poison_window_secs: 60,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        let msg_id = storage
+            .push_message("test", Message::new("crash"))
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            let received = storage.pop_message("test").await.unwrap().unwrap();
+            storage.nack_message("test", &received.id).await.unwrap();
+        }
+        assert!(storage.list_quarantined("test").await.unwrap().is_empty());
+
+        // Third nack inside the window trips the detector, well before max_retries
+        let received = storage.pop_message("test").await.unwrap().unwrap();
+        storage.nack_message("test", &received.id).await.unwrap();
+
+        let quarantined = storage.list_quarantined("test").await.unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].id, msg_id);
+        assert_eq!(
+            quarantined[0].quarantine_reason,
+            Some(QuarantineReason::Poison)
+        );
+        assert!(storage.pop_message("test").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_release_quarantined() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            quarantine_after: 1,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        storage
+            .push_message("test", Message::new("poison"))
+            .await
+            .unwrap();
+        let received = storage.pop_message("test").await.unwrap().unwrap();
+        storage.nack_message("test", &received.id).await.unwrap();
+
+        let released = storage.release_quarantined("test").await.unwrap();
+        assert_eq!(released, 1);
+        assert!(storage.list_quarantined("test").await.unwrap().is_empty());
+
+        let msg = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(msg.id, received.id);
+        assert_eq!(msg.delivery_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_move_to_dlq() {
+        let storage = MemoryStorage::new();
+        let dlq_config = QueueConfig {
+            dedup_enabled: true,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("dlq", dlq_config))
+            .await
+            .unwrap();
+        let config = QueueConfig {
+            dead_letter_queue: Some("dlq".to_string()),
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        // The DLQ already saw this dedup ID; the move must not drop the message
+        storage
+            .push_message("dlq", Message::new("older").with_dedup_id("order-1"))
+            .await
+            .unwrap();
+        storage.pop_message("dlq").await.unwrap().unwrap();
+
+        let msg_id = storage
+            .push_message("test", Message::new("bad").with_dedup_id("order-1"))
+            .await
+            .unwrap();
+
+        // Only in-flight messages can be moved
+        let err = storage.move_to_dlq("test", &msg_id).await.unwrap_err();
+        assert!(matches!(err, Error::MessageNotFound(_)));
+
+        storage.pop_message("test").await.unwrap().unwrap();
+        storage.move_to_dlq("test", &msg_id).await.unwrap();
+        assert!(storage.ack_message("test", &msg_id).await.is_err());
+
+        let moved = storage.pop_message("dlq").await.unwrap().unwrap();
+        assert_eq!(moved.id, msg_id);
+        assert_eq!(moved.dead_letter_reason, Some(DeadLetterReason::Manual));
+        assert_eq!(
+            moved.attributes.get(DEAD_LETTERED_FROM_ATTRIBUTE),
+            Some(&"test".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inflight_ceiling_moves_to_dlq() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("dlq")).await.unwrap();
+        let config = QueueConfig {
+            max_inflight_duration_secs: 60,
+            dead_letter_queue: Some("dlq".to_string()),
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        let held_id = storage
+            .push_message("test", Message::new("held"))
+            .await
+            .unwrap();
+        let fresh_id = storage
+            .push_message("test", Message::new("fresh"))
+            .await
+            .unwrap();
+        storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(storage.dead_letter_overdue().await.unwrap(), 0);
+
+        // Started long ago; extending visibility does not reset the ceiling
+        storage
+            .queues
+            .get("test")
+            .unwrap()
+            .in_flight
+            .get_mut(&held_id)
+            .unwrap()
+            .first_delivered_at = Some(Utc::now() - chrono::Duration::seconds(120));
+        storage
+            .change_visibility("test", &held_id, 600)
+            .await
+            .unwrap();
+        storage.pop_message("test").await.unwrap().unwrap();
+
+        assert_eq!(storage.dead_letter_overdue().await.unwrap(), 1);
+        assert!(storage.ack_message("test", &held_id).await.is_err());
+        storage.ack_message("test", &fresh_id).await.unwrap();
+
+        let moved = storage.pop_message("dlq").await.unwrap().unwrap();
+        assert_eq!(moved.id, held_id);
+        assert_eq!(
+            moved.dead_letter_reason,
+            Some(DeadLetterReason::InflightTimeout)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inflight_ceiling_spans_redeliveries() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("dlq")).await.unwrap();
+        let config = QueueConfig {
+            max_inflight_duration_secs: 60,
+            dead_letter_queue: Some("dlq".to_string()),
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        let id = storage
+            .push_message("test", Message::new("slow"))
+            .await
+            .unwrap();
+        storage.pop_message("test").await.unwrap().unwrap();
+        storage
+            .queues
+            .get("test")
+            .unwrap()
+            .in_flight
+            .get_mut(&id)
+            .unwrap()
+            .first_delivered_at = Some(Utc::now() - chrono::Duration::seconds(120));
+
+        // A fresh delivery does not restart the ceiling
+        storage.nack_message("test", &id).await.unwrap();
+        let redelivered = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(redelivered.delivery_count, 2);
+        assert!(Utc::now() - redelivered.delivered_at.unwrap() < chrono::Duration::seconds(60));
+
+        assert_eq!(storage.dead_letter_overdue().await.unwrap(), 1);
+        let moved = storage.pop_message("dlq").await.unwrap().unwrap();
+        assert_eq!(moved.id, id);
+        assert_eq!(
+            moved.dead_letter_reason,
+            Some(DeadLetterReason::InflightTimeout)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inflight_ceiling_without_dlq_requeues() {
+        let storage = MemoryStorage::new();
+        // Stored before validation required a DLQ alongside the ceiling
+        let config = QueueConfig {
+            max_inflight_duration_secs: 60,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        let held_id = storage
+            .push_message("test", Message::new("held"))
+            .await
+            .unwrap();
+        storage.pop_message("test").await.unwrap().unwrap();
+        storage
+            .queues
+            .get("test")
+            .unwrap()
+            .in_flight
+            .get_mut(&held_id)
+            .unwrap()
+            .first_delivered_at = Some(Utc::now() - chrono::Duration::seconds(120));
+
+        assert_eq!(storage.dead_letter_overdue().await.unwrap(), 0);
+        let redelivered = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(redelivered.id, held_id);
+        assert_eq!(redelivered.delivery_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_move_to_dlq_without_dlq() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+
+        storage
+            .push_message("test", Message::new("bad"))
+            .await
+            .unwrap();
+        let received = storage.pop_message("test").await.unwrap().unwrap();
+
+        let err = storage.move_to_dlq("test", &received.id).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig(_)));
+
+        // The message stays in flight
+        storage.ack_message("test", &received.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_retries_moves_to_dlq() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("dlq")).await.unwrap();
+        let config = QueueConfig {
+            max_retries: 2,
+            dead_letter_queue: Some("dlq".to_string()),
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        let msg_id = storage
+            .push_message("test", Message::new("bad"))
+            .await
+            .unwrap();
+        storage
+            .push_message("dlq", Message::new("older"))
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            storage.pop_message("test").await.unwrap().unwrap();
+            storage.nack_message("test", &msg_id).await.unwrap();
+        }
+        assert!(storage.peek_message("test").await.unwrap().is_none());
+
+        // Peeking leaves the DLQ untouched
+        let peeked = storage.peek_messages("dlq", 10).await.unwrap();
+        assert_eq!(peeked.len(), 2);
+        assert_eq!(peeked[1].id, msg_id);
+        assert_eq!(
+            peeked[1].dead_letter_reason,
+            Some(DeadLetterReason::MaxRetries)
+        );
+        assert_eq!(peeked[1].delivery_count, 2);
+        assert_eq!(
+            peeked[1].attributes.get(DEAD_LETTERED_FROM_ATTRIBUTE),
+            Some(&"test".to_string())
+        );
+        assert_eq!(peeked[1].attributes[DLQ_REASON_ATTRIBUTE], "max_retries");
+        assert_eq!(storage.peek_messages("dlq", 1).await.unwrap().len(), 1);
+        assert_eq!(
+            storage.get_queue_stats("dlq").await.unwrap().pending_count,
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_retries_requeues_when_dlq_rejects() {
+        let storage = MemoryStorage::new();
+        // The dead letter queue was deleted after the config was set
+        let config = QueueConfig {
+            max_retries: 1,
+            dead_letter_queue: Some("gone".to_string()),
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        let msg_id = storage
+            .push_message("test", Message::new("bad"))
+            .await
+            .unwrap();
+        storage.pop_message("test").await.unwrap().unwrap();
+        storage.nack_message("test", &msg_id).await.unwrap();
+
+        let requeued = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(requeued.id, msg_id);
+        assert_eq!(requeued.dead_letter_reason, None);
+        assert!(!requeued.attributes.contains_key(DLQ_REASON_ATTRIBUTE));
+    }
+
+    #[tokio::test]
+    async fn test_peek_page_survives_consumed_head() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+        for i in 0..10 {
+            storage
+                .push_message("test", Message::new(format!("m{}", i)))
+                .await
+                .unwrap();
+        }
+
+        let page = storage.peek_page("test", None, 4).await.unwrap();
+        let mut seen: Vec<u64> = page.messages.iter().map(|m| m.sequence.unwrap()).collect();
+        assert_eq!(seen, [1, 2, 3, 4]);
+
+        // Consuming the head shifts offsets but not the cursor
+        for _ in 0..3 {
+            let message = storage.pop_message("test").await.unwrap().unwrap();
+            storage.ack_message("test", &message.id).await.unwrap();
+        }
+
+        let mut cursor = page.next_cursor;
+        while let Some(after) = cursor {
+            let page = storage.peek_page("test", Some(after), 4).await.unwrap();
+            seen.extend(page.messages.iter().map(|m| m.sequence.unwrap()));
+            cursor = page.next_cursor;
+        }
+        assert_eq!(seen, (1..=10).collect::<Vec<_>>());
+
+        // Paging past the end yields an empty last page
+        let page = storage.peek_page("test", Some(10), 4).await.unwrap();
+        assert!(page.messages.is_empty());
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_max_priority_widens_range() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            max_priority: 100,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("fine", config))
+            .await
+            .unwrap();
+        storage.create_queue(Queue::new("coarse")).await.unwrap();
+
+        for priority in [10, 99, 11, 200, 50] {
+            let message = Message::new(format!("p{}", priority)).with_priority(priority);
+            storage.push_message("fine", message.clone()).await.unwrap();
+            storage.push_message("coarse", message).await.unwrap();
+        }
+
+        // Above the max is clamped to it, and ties keep arrival order
+        let fine = storage.pop_messages("fine", 5).await.unwrap();
+        let priorities: Vec<u8> = fine.iter().map(|m| m.priority).collect();
+        assert_eq!(priorities, vec![100, 99, 50, 11, 10]);
+        assert_eq!(fine[0].body_as_str(), Some("p200"));
+
+        let coarse = storage.pop_messages("coarse", 5).await.unwrap();
+        let bodies: Vec<_> = coarse.iter().filter_map(|m| m.body_as_str()).collect();
+        assert_eq!(bodies, vec!["p10", "p99", "p11", "p200", "p50"]);
+        assert!(coarse.iter().all(|m| m.priority == 10));
+    }
+
+    #[tokio::test]
+    async fn test_high_priority_reservation() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            max_messages: 4,
+            high_priority_reserved: 1,
+            high_priority_threshold: 8,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        let push = |priority: u8| {
+            let message = Message::new(format!("p{}", priority)).with_priority(priority);
+            storage.push_message("test", message)
+        };
+        for _ in 0..3 {
+            push(2).await.unwrap();
+        }
+
+        // The last slot is reserved
+        assert!(matches!(push(7).await, Err(Error::QueueFull(_))));
+        push(8).await.unwrap();
+
+        // The hard limit still applies to high-priority messages
+        assert!(matches!(push(10).await, Err(Error::QueueFull(_))));
+    }
+
+    #[tokio::test]
+    async fn test_dlq_override_attribute() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("dlq")).await.unwrap();
+        storage
+            .create_queue(Queue::new("billing-dlq"))
+            .await
+            .unwrap();
+        let config = QueueConfig {
+            max_retries: 1,
+            dead_letter_queue: Some("dlq".to_string()),
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        let plain_id = storage
+            .push_message("test", Message::new("plain"))
+            .await
+            .unwrap();
+        let billing = Message::new("billing").with_attribute(DLQ_OVERRIDE_ATTRIBUTE, "billing-dlq");
+        let billing_id = storage.push_message("test", billing).await.unwrap();
+        // An override naming a missing queue falls back to the queue's own
+        let typo = Message::new("typo").with_attribute(DLQ_OVERRIDE_ATTRIBUTE, "biling-dlq");
+        let typo_id = storage.push_message("test", typo).await.unwrap();
+
+        for id in [&plain_id, &billing_id, &typo_id] {
+            storage.pop_message("test").await.unwrap().unwrap();
+            storage.nack_message("test", id).await.unwrap();
+        }
+
+        let dlq = storage.peek_messages("dlq", 10).await.unwrap();
+        assert_eq!(dlq.len(), 2);
+        assert_eq!(dlq[0].id, plain_id);
+        assert_eq!(dlq[1].id, typo_id);
+        let billing_dlq = storage.peek_messages("billing-dlq", 10).await.unwrap();
+        assert_eq!(billing_dlq.len(), 1);
+        assert_eq!(billing_dlq[0].id, billing_id);
+        assert_eq!(
+            billing_dlq[0].dead_letter_reason,
+            Some(DeadLetterReason::MaxRetries)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_attribute_ttl_override() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            attribute_ttl_key: Some("x-ttl-secs".to_string()),
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        let before = Utc::now();
+        let short = Message::new("short").with_attribute("x-ttl-secs", "60");
+        let short_id = storage.push_message("test", short).await.unwrap();
+        let plain_id = storage
+            .push_message("test", Message::new("plain"))
+            .await
+            .unwrap();
+
+        let short = storage
+            .get_message("test", &short_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let expires_at = short.expires_at.unwrap();
+        assert!(expires_at >= before + chrono::Duration::seconds(60));
+        assert!(expires_at <= Utc::now() + chrono::Duration::seconds(60));
+        let plain = storage
+            .get_message("test", &plain_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(plain.expires_at.is_none());
+
+        // Queues without the key ignore the attribute
+        storage.create_queue(Queue::new("other")).await.unwrap();
+        let other = Message::new("other").with_attribute("x-ttl-secs", "60");
+        let other_id = storage.push_message("other", other).await.unwrap();
+        let other = storage
+            .get_message("other", &other_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(other.expires_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_attribute_ttl_rejects_malformed_value() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            attribute_ttl_key: Some("x-ttl-secs".to_string()),
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        for value in ["soon", "-5", "1.5", ""] {
+            let message = Message::new("bad").with_attribute("x-ttl-secs", value);
+            let result = storage.push_message("test", message).await;
+            assert!(
+                matches!(result, Err(Error::InvalidMessage(_))),
+                "{:?}",
+                value
+            );
+        }
+        let stats = storage.get_queue_stats("test").await.unwrap();
+        assert_eq!(stats.pending_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_stats_report_dlq_pending() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            max_retries: 1,
+            dead_letter_queue: Some("dlq".to_string()),
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        // A configured DLQ that does not exist yet reports nothing
+        let stats = storage.get_queue_stats("test").await.unwrap();
+        assert_eq!(stats.dlq_pending, None);
+
+        storage.create_queue(Queue::new("dlq")).await.unwrap();
+        let stats = storage.get_queue_stats("test").await.unwrap();
+        assert_eq!(stats.dlq_pending, Some(0));
+
+        for body in ["a", "b"] {
+            let msg_id = storage
+                .push_message("test", Message::new(body))
+                .await
+                .unwrap();
+            storage.pop_message("test").await.unwrap().unwrap();
+            storage.nack_message("test", &msg_id).await.unwrap();
+        }
+
+        let stats = storage.get_queue_stats("test").await.unwrap();
+        assert_eq!(stats.dlq_pending, Some(2));
+        assert_eq!(stats.message_count, 0);
+
+        let listed = storage.list_queues_with_stats().await.unwrap();
+        let (_, listed_stats) = listed.iter().find(|(q, _)| q.name == "test").unwrap();
+        assert_eq!(listed_stats.dlq_pending, Some(2));
+        assert_eq!(
+            storage.get_queue_stats("dlq").await.unwrap().dlq_pending,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_dead_letters_on_first_nack() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("dlq")).await.unwrap();
+        let config = QueueConfig {
+            max_retries: 5,
+            dead_letter_queue: Some("dlq".to_string()),
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        let message = Message::new("done elsewhere").with_no_retry();
+        let msg_id = storage.push_message("test", message).await.unwrap();
+        storage.pop_message("test").await.unwrap().unwrap();
+        storage.nack_message("test", &msg_id).await.unwrap();
+
+        assert!(storage.peek_message("test").await.unwrap().is_none());
+        let dead = storage.peek_message("dlq").await.unwrap().unwrap();
+        assert_eq!(dead.id, msg_id);
+        assert_eq!(dead.delivery_count, 1);
+        assert_eq!(dead.dead_letter_reason, Some(DeadLetterReason::NoRetry));
+    }
+
+    async fn create_with_expiry_queue(storage: &MemoryStorage) {
+        storage.create_queue(Queue::new("expired")).await.unwrap();
+        let config = QueueConfig {
+            expiry_queue: Some("expired".to_string()),
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("main", config))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_routes_to_expiry_queue() {
+        let storage = MemoryStorage::new();
+        create_with_expiry_queue(&storage).await;
+
+        let past = Utc::now() - chrono::Duration::seconds(10);
+        let msg_id = storage
+            .push_message("main", Message::new("stale").with_expiry(past))
+            .await
+            .unwrap();
+        storage
+            .push_message("main", Message::new("fresh"))
+            .await
+            .unwrap();
+
+        assert_eq!(storage.cleanup_expired(0).await.unwrap(), 1);
+
+        let routed = storage.pop_message("expired").await.unwrap().unwrap();
+        assert_eq!(routed.id, msg_id);
+        assert_eq!(
+            routed.attributes.get(EXPIRED_FROM_ATTRIBUTE),
+            Some(&"main".to_string())
+        );
+        assert!(routed.expires_at.is_none());
+
+        let stats = storage.get_queue_stats("main").await.unwrap();
+        assert_eq!(stats.pending_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_respects_limit_per_tick() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+
+        let past = Utc::now() - chrono::Duration::seconds(10);
+        for i in 0..1000 {
+            let message = Message::new(format!("{}", i)).with_expiry(past);
+            storage.push_message("test", message).await.unwrap();
+        }
+        storage
+            .push_message("test", Message::new("fresh"))
+            .await
+            .unwrap();
+
+        let mut ticks = Vec::new();
+        loop {
+            let cleaned = storage.cleanup_expired(300).await.unwrap();
+            if cleaned == 0 {
+                break;
+            }
+            ticks.push(cleaned);
+        }
+
+        assert_eq!(ticks, vec![300, 300, 300, 100]);
+        let stats = storage.get_queue_stats("test").await.unwrap();
+        assert_eq!(stats.pending_count, 1);
+    }
+
+    #[test]
+    fn test_take_pending_stops_scanning_at_limit() {
+        let mut queue_data = QueueData::new(Queue::new("test"), Arc::default());
+        for i in 0..10_000 {
+            queue_data.enqueue_back(Message::new(format!("{}", i)));
+        }
+
+        let examined = std::cell::Cell::new(0);
+        let taken = queue_data.take_pending(5, |message| {
+            examined.set(examined.get() + 1);
+            message.body_as_str().is_some_and(|body| body.len() == 1)
+        });
+
+        assert_eq!(taken.len(), 5);
+        assert_eq!(examined.get(), 5);
+        assert_eq!(queue_data.messages.len(), 9_995);
+        assert_eq!(
+            queue_data.pending().next().unwrap().body_as_str(),
+            Some("5")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_capped_cleanup_resumes_at_next_queue() {
+        let storage = MemoryStorage::new();
+        let past = Utc::now() - chrono::Duration::seconds(10);
+        for name in ["a", "b", "c"] {
+            storage.create_queue(Queue::new(name)).await.unwrap();
+            for _ in 0..4 {
+                let message = Message::new("stale").with_expiry(past);
+                storage.push_message(name, message).await.unwrap();
+            }
+        }
+
+        // Each capped pass moves on to the next queue instead of starting
+        // over at the first, which still has expired messages left
+        async fn pending(storage: &MemoryStorage) -> Vec<u64> {
+            let mut counts = Vec::new();
+            for name in ["a", "b", "c"] {
+                counts.push(storage.get_queue_stats(name).await.unwrap().pending_count);
+            }
+            counts
+        }
+        assert_eq!(storage.cleanup_expired(2).await.unwrap(), 2);
+        assert_eq!(pending(&storage).await, vec![2, 4, 4]);
+        assert_eq!(storage.cleanup_expired(2).await.unwrap(), 2);
+        assert_eq!(pending(&storage).await, vec![2, 2, 4]);
+        assert_eq!(storage.cleanup_expired(2).await.unwrap(), 2);
+        assert_eq!(pending(&storage).await, vec![2, 2, 2]);
+        assert_eq!(storage.cleanup_expired(2).await.unwrap(), 2);
+        assert_eq!(pending(&storage).await, vec![0, 2, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_pop_routes_expired_to_expiry_queue() {
+        let storage = MemoryStorage::new();
+        create_with_expiry_queue(&storage).await;
+
+        let past = Utc::now() - chrono::Duration::seconds(10);
+        storage
+            .push_message("main", Message::new("stale").with_expiry(past))
+            .await
+            .unwrap();
+
+        assert!(storage.pop_message("main").await.unwrap().is_none());
+
+        let routed = storage.pop_message("expired").await.unwrap().unwrap();
+        assert_eq!(routed.body_as_str(), Some("stale"));
+    }
+
+    #[tokio::test]
+    async fn test_pop_prefers_higher_priority() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+
+        storage
+            .push_message("test", Message::new("low").with_priority(2))
+            .await
+            .unwrap();
+        storage
+            .push_message("test", Message::new("high").with_priority(9))
+            .await
+            .unwrap();
+
+        let msg = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(msg.body_as_str(), Some("high"));
+    }
+
+    #[tokio::test]
+    async fn test_sequence_counts_arrivals_per_queue() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("a")).await.unwrap();
+        storage.create_queue(Queue::new("b")).await.unwrap();
+
+        for i in 0..3 {
+            storage
+                .push_message("a", Message::new(format!("a{}", i)))
+                .await
+                .unwrap();
+        }
+        storage.push_message("b", Message::new("b0")).await.unwrap();
+
+        // A nacked message keeps its number and does not consume a new one
+        let first = storage.pop_message("a").await.unwrap().unwrap();
+        storage.nack_message("a", &first.id).await.unwrap();
+        storage.push_message("a", Message::new("a3")).await.unwrap();
+
+        let sequences: Vec<u64> = storage
+            .pop_messages("a", 10)
+            .await
+            .unwrap()
+            .iter()
+            .map(|m| m.sequence.unwrap())
+            .collect();
+        assert_eq!(sequences, [2, 3, 1, 4]);
+
+        let other = storage.pop_message("b").await.unwrap().unwrap();
+        assert_eq!(other.sequence, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_same_priority_is_exact_fifo() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+
+        let created_at = Utc::now();
+        let mut ids = Vec::new();
+        for i in 0..200 {
+            let mut message = Message::new(format!("{}", i)).with_priority(5);
+            message.created_at = created_at;
+            ids.push(storage.push_message("test", message).await.unwrap());
+        }
+        storage
+            .push_message("test", Message::new("urgent").with_priority(9))
+            .await
+            .unwrap();
+
+        let urgent = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(urgent.body_as_str(), Some("urgent"));
+
+        let received: Vec<MessageId> = storage
+            .pop_messages("test", 200)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|m| m.id)
+            .collect();
+        assert_eq!(received, ids);
+    }
+
+    #[tokio::test]
+    async fn test_earliest_expiry_delivered_first() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            delivery_strategy: DeliveryStrategy::EarliestExpiry,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        let now = Utc::now();
+        let messages = [
+            Message::new("no expiry, urgent").with_priority(10),
+            Message::new("in 1h").with_expiry(now + chrono::Duration::hours(1)),
+            Message::new("no expiry"),
+            Message::new("in 1m").with_expiry(now + chrono::Duration::minutes(1)),
+            Message::new("in 10m").with_expiry(now + chrono::Duration::minutes(10)),
+        ];
+        for message in messages {
+            storage.push_message("test", message).await.unwrap();
+        }
+
+        let order: Vec<String> = storage
+            .pop_messages("test", 5)
+            .await
+            .unwrap()
+            .iter()
+            .map(|m| m.body_as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            order,
+            ["in 1m", "in 10m", "in 1h", "no expiry, urgent", "no expiry"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_priority_aging_prevents_starvation() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            priority_aging_secs: 10,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("aging", config))
+            .await
+            .unwrap();
+        storage.create_queue(Queue::new("plain")).await.unwrap();
+
+        for queue in ["aging", "plain"] {
+            let mut old = Message::new("old low").with_priority(1);
+            old.created_at = Utc::now() - chrono::Duration::seconds(60);
+            storage.push_message(queue, old).await.unwrap();
+            storage
+                .push_message(queue, Message::new("new mid").with_priority(5))
+                .await
+                .unwrap();
+        }
+
+        // Aged to 1 + 60/10 = 7, outranking the newer priority-5 message
+        let msg = storage.pop_message("aging").await.unwrap().unwrap();
+        assert_eq!(msg.body_as_str(), Some("old low"));
+
+        // Without aging the higher priority wins
+        let msg = storage.pop_message("plain").await.unwrap().unwrap();
+        assert_eq!(msg.body_as_str(), Some("new mid"));
+    }
+
+    #[tokio::test]
+    async fn test_reset_delivery_count() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+
+        let msg_id = storage
+            .push_message("test", Message::new("test"))
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            let received = storage.pop_message("test").await.unwrap().unwrap();
+            storage.nack_message("test", &received.id).await.unwrap();
+        }
+
+        // Reset while pending
+        storage.reset_delivery_count("test", &msg_id).await.unwrap();
+        let msg = storage.get_message("test", &msg_id).await.unwrap().unwrap();
+        assert_eq!(msg.delivery_count, 0);
+
+        // Reset while in-flight
+        let received = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(received.delivery_count, 1);
+        storage.reset_delivery_count("test", &msg_id).await.unwrap();
+        let msg = storage.get_message("test", &msg_id).await.unwrap().unwrap();
+        assert_eq!(msg.delivery_count, 0);
+    }
+
+    async fn nack_first_of_two(position: RequeuePosition) -> Vec<String> {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            nack_requeue_position: position,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        storage
+            .push_message("test", Message::new("first"))
+            .await
+            .unwrap();
+        storage
+            .push_message("test", Message::new("second"))
+            .await
+            .unwrap();
+
+        let received = storage.pop_message("test").await.unwrap().unwrap();
+        storage.nack_message("test", &received.id).await.unwrap();
+
+        storage
+            .pop_messages("test", 2)
+            .await
+            .unwrap()
+            .iter()
+            .map(|m| m.body_as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_nack_requeues_to_back() {
+        let order = nack_first_of_two(RequeuePosition::Back).await;
+        assert_eq!(order, vec!["second", "first"]);
+    }
+
+    #[tokio::test]
+    async fn test_nack_requeues_to_front() {
+        let order = nack_first_of_two(RequeuePosition::Front).await;
+        assert_eq!(order, vec!["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn test_require_utf8_rejects_binary_bodies() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            require_utf8: true,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("text", config))
+            .await
+            .unwrap();
 
-        if self.queues.contains_key(&name) {
-            return Err(Error::QueueAlreadyExists(name));
-        }
+        storage
+            .push_message("text", Message::new("héllo"))
+            .await
+            .unwrap();
 
-        let queue_clone = queue.clone();
-        self.queues.insert(name.clone(), QueueData::new(queue));
-        info!(queue = %name, "Queue created");
+        let binary = Message::new(vec![0xff, 0xfe, 0x00]);
+        let err = storage.push_message("text", binary).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidMessage(_)));
 
-        Ok(queue_clone)
+        let stats = storage.get_queue_stats("text").await.unwrap();
+        assert_eq!(stats.pending_count, 1);
     }
 
-    async fn get_queue(&self, name: &str) -> Result<Option<Queue>> {
-        Ok(self.queues.get(name).map(|q| q.queue.clone()))
-    }
+    #[tokio::test]
+    async fn test_checksums() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("plain")).await.unwrap();
+        let config = QueueConfig {
+            compute_checksum: true,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("checked", config))
+            .await
+            .unwrap();
 
-    async fn list_queues(&self) -> Result<Vec<Queue>> {
-        Ok(self.queues.iter().map(|q| q.queue.clone()).collect())
+        storage
+            .push_message("checked", Message::new("123456789"))
+            .await
+            .unwrap();
+        let received = storage.pop_message("checked").await.unwrap().unwrap();
+        assert_eq!(received.checksum.as_deref(), Some("cbf43926"));
+        assert!(received.verify_checksum());
+
+        storage
+            .push_message("plain", Message::new("body"))
+            .await
+            .unwrap();
+        let received = storage.pop_message("plain").await.unwrap().unwrap();
+        assert!(received.checksum.is_none());
+
+        // Client-supplied checksums are verified on every queue
+        let good = Message::new("123456789").with_checksum("cbf43926");
+        storage.push_message("plain", good).await.unwrap();
+        let bad = Message::new("tampered").with_checksum("cbf43926");
+        let err = storage.push_message("plain", bad).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidMessage(_)));
+        assert_eq!(
+            storage
+                .get_queue_stats("plain")
+                .await
+                .unwrap()
+                .pending_count,
+            1
+        );
     }
 
-    async fn delete_queue(&self, name: &str) -> Result<()> {
-        match self.queues.remove(name) {
-            Some(_) => {
-                info!(queue = %name, "Queue deleted");
-                Ok(())
-            }
-            None => Err(Error::QueueNotFound(name.to_string())),
+    #[tokio::test]
+    async fn test_reconnect_reclaims_in_flight_messages() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+        for body in ["a", "b", "c", "d"] {
+            storage
+                .push_message("test", Message::new(body))
+                .await
+                .unwrap();
         }
-    }
 
-    async fn get_queue_stats(&self, name: &str) -> Result<QueueStats> {
-        let queue_data = self
-            .queues
-            .get(name)
-            .ok_or_else(|| Error::QueueNotFound(name.to_string()))?;
+        let held = storage
+            .pop_messages_as("test", 2, "worker-1", false)
+            .await
+            .unwrap();
+        let other = storage
+            .pop_messages_as("test", 1, "worker-2", true)
+            .await
+            .unwrap();
+        assert_eq!(other.len(), 1);
 
-        let pending_count = queue_data.messages.len() as u64;
-        let in_flight_count = queue_data.in_flight.len() as u64;
-        let size_bytes: u64 = queue_data
-            .messages
-            .iter()
-            .map(|m| m.body.len() as u64)
-            .sum();
+        // worker-1 reconnects and gets its unacked messages back before new ones
+        let reclaimed = storage
+            .pop_messages_as("test", 3, "worker-1", true)
+            .await
+            .unwrap();
+        let mut ids: Vec<&MessageId> = reclaimed[..2].iter().map(|m| &m.id).collect();
+        let mut held_ids: Vec<&MessageId> = held.iter().map(|m| &m.id).collect();
+        ids.sort();
+        held_ids.sort();
+        assert_eq!(ids, held_ids);
+        assert_eq!(reclaimed[0].delivery_count, 1);
+        assert!(reclaimed[0].visible_until >= held[1].visible_until);
+        assert_eq!(reclaimed[2].body_as_str(), Some("d"));
+        assert_eq!(reclaimed[2].consumer_token.as_deref(), Some("worker-1"));
 
-        Ok(QueueStats {
-            message_count: pending_count + in_flight_count,
-            pending_count,
-            in_flight_count,
-            size_bytes,
-            consumer_count: 0, // TODO: Track consumers
-            publish_rate: 0.0, // TODO: Calculate rate
-            consume_rate: 0.0,
-        })
+        // Without reclaim only new messages are delivered
+        storage
+            .push_message("test", Message::new("e"))
+            .await
+            .unwrap();
+        let fresh = storage
+            .pop_messages_as("test", 5, "worker-1", false)
+            .await
+            .unwrap();
+        assert_eq!(fresh.len(), 1);
+        assert_eq!(fresh[0].body_as_str(), Some("e"));
+
+        // Requeued messages are no longer owned
+        storage.nack_message("test", &held[0].id).await.unwrap();
+        let reclaimed = storage
+            .pop_messages_as("test", 10, "worker-1", true)
+            .await
+            .unwrap();
+        assert_eq!(reclaimed.len(), 4);
+        assert_eq!(reclaimed.last().unwrap().id, held[0].id);
+        assert_eq!(reclaimed.last().unwrap().delivery_count, 2);
     }
 
-    // ==================== Message Operations ====================
+    #[tokio::test]
+    async fn test_retry_queue_round_trip() {
+        let storage = MemoryStorage::new();
+        storage
+            .create_queue(Queue::new("work-retry"))
+            .await
+            .unwrap();
+        let config = QueueConfig {
+            retry_queue: Some("work-retry".to_string()),
+            retry_delay_secs: 30,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("work", config))
+            .await
+            .unwrap();
 
-    async fn push_message(&self, queue_name: &str, message: Message) -> Result<MessageId> {
-        let mut queue_data = self
-            .queues
-            .get_mut(queue_name)
-            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+        let msg_id = storage
+            .push_message("work", Message::new("job"))
+            .await
+            .unwrap();
+        storage.pop_message("work").await.unwrap().unwrap();
+        let nacked_at = Utc::now();
+        storage.nack_message("work", &msg_id).await.unwrap();
 
-        // Check queue limits
-        if queue_data.queue.config.max_messages > 0
-            && queue_data.messages.len() as u64 >= queue_data.queue.config.max_messages
+        // Held in the retry queue, not yet deliverable anywhere
+        assert_eq!(
+            storage.get_queue_stats("work").await.unwrap().pending_count,
+            0
+        );
+        let held = storage
+            .get_message("work-retry", &msg_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            held.attributes.get(RETRY_FROM_ATTRIBUTE),
+            Some(&"work".to_string())
+        );
+        let delay = held.available_at.unwrap() - nacked_at;
+        assert!((delay.num_seconds() - 30).abs() <= 1);
+        assert!(storage.pop_message("work-retry").await.unwrap().is_none());
+        assert_eq!(storage.promote_retries().await.unwrap(), 0);
+
+        // Once the delay has passed, maintenance returns it to the source queue
         {
-            return Err(Error::QueueFull(queue_name.to_string()));
+            let mut retry_queue = storage.queues.get_mut("work-retry").unwrap();
+            let past = Utc::now() - chrono::Duration::seconds(1);
+            retry_queue.messages[0].message.available_at = Some(past);
         }
-
-        let message_id = message.id.clone();
-        queue_data.messages.push_back(message);
-
-        debug!(
-            queue = %queue_name,
-            message_id = %message_id,
-            "Message pushed"
+        assert_eq!(storage.promote_retries().await.unwrap(), 1);
+        assert_eq!(
+            storage
+                .get_queue_stats("work-retry")
+                .await
+                .unwrap()
+                .pending_count,
+            0
         );
 
-        Ok(message_id)
+        let retried = storage.pop_message("work").await.unwrap().unwrap();
+        assert_eq!(retried.id, msg_id);
+        assert_eq!(retried.delivery_count, 2);
+        assert!(retried.available_at.is_none());
+        assert!(!retried.attributes.contains_key(RETRY_FROM_ATTRIBUTE));
     }
 
-    async fn pop_message(&self, queue_name: &str) -> Result<Option<Message>> {
-        let mut queue_data = self
-            .queues
-            .get_mut(queue_name)
-            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
-
-        // Find first non-expired message
-        while let Some(mut message) = queue_data.messages.pop_front() {
-            // Skip expired messages
-            if message.is_expired() {
-                debug!(
-                    queue = %queue_name,
-                    message_id = %message.id,
-                    "Skipping expired message"
-                );
-                continue;
-            }
-
-            // Update message status
-            message.status = MessageStatus::Delivered;
-            message.delivery_count += 1;
+    #[tokio::test]
+    async fn test_requeue_timed_out() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            visibility_timeout_secs: 0,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
 
-            // Move to in-flight
-            let message_clone = message.clone();
-            queue_data.in_flight.insert(message.id.clone(), message);
+        storage
+            .push_message("test", Message::new("a"))
+            .await
+            .unwrap();
+        storage
+            .push_message("test", Message::new("b"))
+            .await
+            .unwrap();
+        let timed_out = storage.pop_message("test").await.unwrap().unwrap();
+        let extended = storage.pop_message("test").await.unwrap().unwrap();
 
-            debug!(
-                queue = %queue_name,
-                message_id = %message_clone.id,
-                delivery_count = message_clone.delivery_count,
-                "Message popped"
-            );
+        // Extending visibility keeps a message in flight
+        storage
+            .change_visibility("test", &extended.id, 60)
+            .await
+            .unwrap();
+        assert_eq!(storage.requeue_timed_out().await.unwrap(), 1);
 
-            return Ok(Some(message_clone));
-        }
+        let redelivered = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(redelivered.id, timed_out.id);
+        assert_eq!(redelivered.delivery_count, 2);
 
-        Ok(None)
+        storage.ack_message("test", &extended.id).await.unwrap();
+        let err = storage
+            .change_visibility("test", &extended.id, 60)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::MessageNotFound(_)));
     }
 
-    async fn pop_messages(&self, queue_name: &str, max: usize) -> Result<Vec<Message>> {
-        let mut messages = Vec::with_capacity(max);
+    #[tokio::test]
+    async fn test_requeue_jitter_spreads_redeliveries() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            visibility_timeout_secs: 0,
+            requeue_jitter_secs: 30,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
 
-        for _ in 0..max {
-            match self.pop_message(queue_name).await? {
-                Some(msg) => messages.push(msg),
-                None => break,
-            }
+        for i in 0..50 {
+            storage
+                .push_message("test", Message::new(format!("m{}", i)))
+                .await
+                .unwrap();
         }
+        assert_eq!(storage.pop_messages("test", 50).await.unwrap().len(), 50);
 
-        Ok(messages)
-    }
+        let start = Utc::now();
+        assert_eq!(storage.requeue_timed_out().await.unwrap(), 50);
 
-    async fn peek_message(&self, queue_name: &str) -> Result<Option<Message>> {
-        let queue_data = self
+        let available: HashSet<DateTime<Utc>> = storage
             .queues
-            .get(queue_name)
-            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+            .get("test")
+            .unwrap()
+            .pending()
+            .map(|m| m.available_at.unwrap())
+            .collect();
+        assert!(available.len() > 1);
+        assert!(available
+            .iter()
+            .all(|at| *at >= start && *at <= Utc::now() + chrono::Duration::seconds(30)));
 
-        Ok(queue_data.messages.front().cloned())
+        // Only the messages whose jitter already passed are redelivered now
+        assert!(storage.pop_messages("test", 50).await.unwrap().len() < 50);
     }
 
-    async fn ack_message(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
-        let queue_data = self
-            .queues
-            .get(queue_name)
-            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
-
-        match queue_data.in_flight.remove(message_id) {
-            Some(_) => {
-                debug!(
-                    queue = %queue_name,
-                    message_id = %message_id,
-                    "Message acknowledged"
-                );
-                Ok(())
-            }
-            None => Err(Error::MessageNotFound(message_id.to_string())),
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_strict_ordering_keeps_batches_contiguous() {
+        let storage = Arc::new(MemoryStorage::new());
+        let config = QueueConfig {
+            strict_ordering: true,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+        for i in 0..400 {
+            storage
+                .push_message("test", Message::new(format!("{}", i)))
+                .await
+                .unwrap();
         }
-    }
 
-    async fn nack_message(&self, queue_name: &str, message_id: &MessageId) -> Result<()> {
-        let mut queue_data = self
-            .queues
-            .get_mut(queue_name)
-            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+        let consumers: Vec<_> = (0..8)
+            .map(|_| {
+                let storage = Arc::clone(&storage);
+                tokio::spawn(async move {
+                    let mut batches = Vec::new();
+                    loop {
+                        let batch = storage.pop_messages("test", 5).await.unwrap();
+                        if batch.is_empty() {
+                            return batches;
+                        }
+                        let positions: Vec<usize> = batch
+                            .iter()
+                            .map(|m| m.body_as_str().unwrap().parse().unwrap())
+                            .collect();
+                        batches.push(positions);
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
 
-        match queue_data.in_flight.remove(message_id) {
-            Some((_, mut message)) => {
-                // Check retry limit
-                if message.delivery_count >= queue_data.queue.config.max_retries {
-                    // TODO: Move to DLQ
-                    message.status = MessageStatus::Failed;
-                    debug!(
-                        queue = %queue_name,
-                        message_id = %message_id,
-                        "Message exceeded max retries, marking as failed"
-                    );
-                } else {
-                    // Return to queue
-                    message.status = MessageStatus::Pending;
-                    queue_data.messages.push_front(message);
-                    debug!(
-                        queue = %queue_name,
-                        message_id = %message_id,
-                        "Message returned to queue"
-                    );
-                }
-                Ok(())
+        let mut seen = Vec::new();
+        for consumer in consumers {
+            for batch in consumer.await.unwrap() {
+                assert_eq!(batch.len(), 5);
+                assert!(batch.windows(2).all(|w| w[1] == w[0] + 1), "{:?}", batch);
+                seen.extend(batch);
             }
-            None => Err(Error::MessageNotFound(message_id.to_string())),
         }
+        seen.sort_unstable();
+        assert_eq!(seen, (0..400).collect::<Vec<_>>());
     }
 
-    async fn get_message(&self, queue_name: &str, message_id: &MessageId) -> Result<Option<Message>> {
-        let queue_data = self
-            .queues
-            .get(queue_name)
-            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+    #[tokio::test]
+    async fn test_dedup_window_eviction() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            dedup_enabled: true,
+            dedup_window_secs: 60,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
 
-        // Check in-flight first
-        if let Some(msg) = queue_data.in_flight.get(message_id) {
-            return Ok(Some(msg.clone()));
-        }
+        let dedup = || Message::new("order").with_dedup_id("order-1");
+        let first = storage.push_message("test", dedup()).await.unwrap();
+        assert_eq!(storage.push_message("test", dedup()).await.unwrap(), first);
+        assert_eq!(
+            storage.get_queue_stats("test").await.unwrap().pending_count,
+            1
+        );
 
-        // Check pending messages
-        Ok(queue_data
-            .messages
-            .iter()
-            .find(|m| &m.id == message_id)
-            .cloned())
-    }
+        // Nothing to evict inside the window
+        assert_eq!(storage.cleanup_dedup().await.unwrap(), 0);
 
-    async fn purge_queue(&self, queue_name: &str) -> Result<u64> {
-        let mut queue_data = self
+        // Age the entry past the window
+        storage
             .queues
-            .get_mut(queue_name)
-            .ok_or_else(|| Error::QueueNotFound(queue_name.to_string()))?;
+            .get_mut("test")
+            .unwrap()
+            .dedup
+            .get_mut("order-1")
+            .unwrap()
+            .1 -= chrono::Duration::seconds(120);
 
-        let count = queue_data.messages.len() as u64;
-        queue_data.messages.clear();
-        queue_data.in_flight.clear();
+        assert_eq!(storage.cleanup_dedup().await.unwrap(), 1);
+        assert!(storage.queues.get("test").unwrap().dedup.is_empty());
 
-        info!(queue = %queue_name, count = count, "Queue purged");
-        Ok(count)
+        let second = storage.push_message("test", dedup()).await.unwrap();
+        assert_ne!(second, first);
+        assert_eq!(
+            storage.get_queue_stats("test").await.unwrap().pending_count,
+            2
+        );
     }
 
-    // ==================== Maintenance ====================
+    #[tokio::test]
+    async fn test_acked_retention() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("plain")).await.unwrap();
+        let config = QueueConfig {
+            acked_retention_secs: 60,
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
 
-    async fn cleanup_expired(&self) -> Result<u64> {
-        let mut total_cleaned = 0u64;
-        let now = Utc::now();
+        // Without retention acked messages are dropped as before
+        let id = storage
+            .push_message("plain", Message::new("a"))
+            .await
+            .unwrap();
+        storage.pop_message("plain").await.unwrap().unwrap();
+        storage.ack_message("plain", &id).await.unwrap();
+        assert!(storage.list_acked("plain").await.unwrap().is_empty());
 
-        for mut queue_data in self.queues.iter_mut() {
-            let before_count = queue_data.messages.len();
-            queue_data.messages.retain(|m| {
-                m.expires_at.map(|exp| now <= exp).unwrap_or(true)
-            });
-            let removed = before_count - queue_data.messages.len();
-            total_cleaned += removed as u64;
+        let id = storage
+            .push_message("test", Message::new("b"))
+            .await
+            .unwrap();
+        storage.pop_message("test").await.unwrap().unwrap();
+        storage.ack_message("test", &id).await.unwrap();
+
+        let acked = storage.list_acked("test").await.unwrap();
+        assert_eq!(acked.len(), 1);
+        assert_eq!(acked[0].id, id);
+        assert_eq!(acked[0].status, MessageStatus::Acked);
+        assert_eq!(
+            storage.get_queue_stats("test").await.unwrap().message_count,
+            0
+        );
+        assert_eq!(storage.prune_acked().await.unwrap(), 0);
+
+        // Age the entry past the window
+        storage.queues.get_mut("test").unwrap().acked[0].0 -= chrono::Duration::seconds(120);
+
+        assert!(storage.list_acked("test").await.unwrap().is_empty());
+        assert_eq!(storage.prune_acked().await.unwrap(), 1);
+        assert!(storage.queues.get("test").unwrap().acked.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_queues_are_deleted() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            auto_delete_after_idle_secs: 60,
+            ..Default::default()
+        };
+        for name in ["idle", "active", "backlog"] {
+            storage
+                .create_queue(Queue::with_config(name, config.clone()))
+                .await
+                .unwrap();
         }
+        storage.create_queue(Queue::new("default")).await.unwrap();
+        storage
+            .push_message("backlog", Message::new("waiting"))
+            .await
+            .unwrap();
 
-        if total_cleaned > 0 {
-            debug!(count = total_cleaned, "Cleaned up expired messages");
+        tokio::time::advance(Duration::from_secs(120)).await;
+        // A receive, even an empty one, counts as activity
+        assert!(storage.pop_message("active").await.unwrap().is_none());
+
+        assert_eq!(
+            storage.idle_queues().await.unwrap(),
+            vec!["idle".to_string()]
+        );
+        // Used again after being listed, so no longer idle
+        storage
+            .push_message("idle", Message::new("late"))
+            .await
+            .unwrap();
+        assert!(!storage.delete_idle_queue("idle").await.unwrap());
+        let late = storage.pop_message("idle").await.unwrap().unwrap();
+        storage.ack_message("idle", &late.id).await.unwrap();
+        tokio::time::advance(Duration::from_secs(120)).await;
+        assert!(storage.delete_idle_queue("idle").await.unwrap());
+        assert!(storage.get_queue("idle").await.unwrap().is_none());
+        for name in ["active", "backlog", "default"] {
+            assert!(storage.get_queue(name).await.unwrap().is_some(), "{}", name);
         }
+    }
 
-        Ok(total_cleaned)
+    #[tokio::test]
+    async fn test_missed_deadline_dead_letters() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            dead_letter_queue: Some("test-dlq".to_string()),
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+        storage.create_queue(Queue::new("test-dlq")).await.unwrap();
+
+        let past = Utc::now() - chrono::Duration::seconds(1);
+        let future = Utc::now() + chrono::Duration::hours(1);
+        let late = Message::new("late").with_deadline(past);
+        let late_id = storage.push_message("test", late).await.unwrap();
+        let on_time = Message::new("on time").with_deadline(future);
+        storage.push_message("test", on_time).await.unwrap();
+
+        let received = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(received.body_as_str().unwrap(), "on time");
+
+        let dead = storage.pop_message("test-dlq").await.unwrap().unwrap();
+        assert_eq!(dead.id, late_id);
+        assert_eq!(
+            dead.dead_letter_reason,
+            Some(DeadLetterReason::DeadlineMissed)
+        );
+        assert!(dead.deadline.is_none());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[tokio::test]
+    async fn test_missed_deadline_behind_head_is_swept() {
+        let storage = MemoryStorage::new();
+        let config = QueueConfig {
+            dead_letter_queue: Some("test-dlq".to_string()),
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+        storage.create_queue(Queue::new("test-dlq")).await.unwrap();
+        storage.create_queue(Queue::new("plain")).await.unwrap();
+
+        let past = Utc::now() - chrono::Duration::seconds(1);
+        storage
+            .push_message("test", Message::new("low").with_priority(1))
+            .await
+            .unwrap();
+        let late = Message::new("late").with_deadline(past).with_priority(9);
+        let late_id = storage.push_message("test", late).await.unwrap();
+
+        // Not at the head, so the pop skips it without removing it
+        let filter = PopFilter {
+            min_priority: 5,
+            ..Default::default()
+        };
+        let popped = storage
+            .pop_messages_filtered("test", 10, &filter)
+            .await
+            .unwrap();
+        assert!(popped.is_empty());
+        assert_eq!(
+            storage.get_queue_stats("test").await.unwrap().pending_count,
+            2
+        );
+
+        assert_eq!(storage.dead_letter_overdue().await.unwrap(), 1);
+        let dead = storage.pop_message("test-dlq").await.unwrap().unwrap();
+        assert_eq!(dead.id, late_id);
+        assert_eq!(
+            dead.dead_letter_reason,
+            Some(DeadLetterReason::DeadlineMissed)
+        );
+
+        // Without a dead letter queue the deadline is not enforced
+        let late = Message::new("late").with_deadline(past);
+        let kept_id = storage.push_message("plain", late).await.unwrap();
+        assert_eq!(storage.dead_letter_overdue().await.unwrap(), 0);
+        let received = storage.pop_message("plain").await.unwrap().unwrap();
+        assert_eq!(received.id, kept_id);
+    }
 
     #[tokio::test]
-    async fn test_create_and_get_queue() {
+    async fn test_injected_delivery_attributes() {
+        let config = QueueConfig {
+            inject_delivery_attributes: true,
+            visibility_timeout_secs: 0,
+            ..Default::default()
+        };
         let storage = MemoryStorage::new();
-        let queue = Queue::new("test-queue");
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+        storage
+            .push_message("test", Message::new("a"))
+            .await
+            .unwrap();
 
-        storage.create_queue(queue.clone()).await.unwrap();
+        let first = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(first.attributes[DELIVERY_COUNT_ATTRIBUTE], "1");
+        let first_delivered_at = first.attributes[FIRST_DELIVERED_AT_ATTRIBUTE].clone();
 
-        let retrieved = storage.get_queue("test-queue").await.unwrap();
-        assert!(retrieved.is_some());
-        assert_eq!(retrieved.unwrap().name, "test-queue");
+        // Redelivery after the visibility timeout replaces the count
+        storage.requeue_timed_out().await.unwrap();
+        let second = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(second.attributes[DELIVERY_COUNT_ATTRIBUTE], "2");
+        assert_eq!(
+            second.attributes[FIRST_DELIVERED_AT_ATTRIBUTE],
+            first_delivered_at
+        );
+        assert_eq!(second.attributes.len(), 2);
     }
 
     #[tokio::test]
-    async fn test_push_and_pop_message() {
+    async fn test_max_total_in_flight_spans_queues() {
+        let storage = MemoryStorage::new().with_max_total_in_flight(3);
+        for name in ["a", "b"] {
+            storage.create_queue(Queue::new(name)).await.unwrap();
+            for i in 0..3 {
+                storage
+                    .push_message(name, Message::new(format!("{}{}", name, i)))
+                    .await
+                    .unwrap();
+            }
+        }
+
+        assert_eq!(storage.pop_messages("a", 2).await.unwrap().len(), 2);
+        let held = storage.pop_message("b").await.unwrap().unwrap();
+        assert_eq!(storage.total_in_flight(), 3);
+
+        // Saturated: neither queue delivers, though both have pending messages
+        assert!(storage.pop_message("a").await.unwrap().is_none());
+        assert!(storage.pop_messages("b", 5).await.unwrap().is_empty());
+
+        storage.ack_message("b", &held.id).await.unwrap();
+        assert_eq!(storage.total_in_flight(), 2);
+        assert!(storage.pop_message("a").await.unwrap().is_some());
+        assert!(storage.pop_message("b").await.unwrap().is_none());
+
+        // Deleting a queue frees its slots
+        storage.delete_queue("a").await.unwrap();
+        assert_eq!(storage.total_in_flight(), 0);
+        assert_eq!(storage.pop_messages("b", 5).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_nack_with_priority_changes_order() {
         let storage = MemoryStorage::new();
         storage.create_queue(Queue::new("test")).await.unwrap();
 
-        let msg = Message::new("Hello, World!");
-        let msg_id = storage.push_message("test", msg).await.unwrap();
+        storage
+            .push_message("test", Message::new("first"))
+            .await
+            .unwrap();
+        storage
+            .push_message("test", Message::new("second"))
+            .await
+            .unwrap();
 
-        let received = storage.pop_message("test").await.unwrap();
-        assert!(received.is_some());
+        // Boosted retry jumps ahead of the requeue position
+        let received = storage.pop_message("test").await.unwrap().unwrap();
+        storage
+            .nack_with_priority("test", &received.id, 42)
+            .await
+            .unwrap();
+        let msg = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(msg.id, received.id);
+        assert_eq!(msg.priority, 10);
 
-        let received = received.unwrap();
-        assert_eq!(received.id, msg_id);
-        assert_eq!(received.body_as_str(), Some("Hello, World!"));
-        assert_eq!(received.delivery_count, 1);
+        // Deprioritized retry goes behind everything else
+        storage
+            .nack_with_priority("test", &msg.id, 0)
+            .await
+            .unwrap();
+        storage
+            .push_message("test", Message::new("third"))
+            .await
+            .unwrap();
+
+        let order: Vec<String> = storage
+            .pop_messages("test", 3)
+            .await
+            .unwrap()
+            .iter()
+            .map(|m| m.body_as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(order, vec!["second", "third", "first"]);
     }
 
     #[tokio::test]
-    async fn test_ack_message() {
+    async fn test_indexed_attribute_search() {
         let storage = MemoryStorage::new();
-        storage.create_queue(Queue::new("test")).await.unwrap();
+        let config = QueueConfig {
+            indexed_attributes: vec!["orderId".to_string()],
+            ..Default::default()
+        };
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
 
-        let msg = Message::new("test");
-        storage.push_message("test", msg).await.unwrap();
+        for (order, body) in [("1", "a"), ("2", "b"), ("1", "c")] {
+            storage
+                .push_message("test", Message::new(body).with_attribute("orderId", order))
+                .await
+                .unwrap();
+        }
 
+        let criteria = SearchCriteria {
+            attributes: [("orderId".to_string(), "1".to_string())].into(),
+            ..Default::default()
+        };
+        let found = storage.search_messages("test", &criteria).await.unwrap();
+        let bodies: Vec<_> = found.iter().map(|m| m.body_as_str().unwrap()).collect();
+        assert_eq!(bodies, vec!["a", "c"]);
+
+        // Acked messages drop out of the index
         let received = storage.pop_message("test").await.unwrap().unwrap();
+        assert_eq!(received.body_as_str(), Some("a"));
         storage.ack_message("test", &received.id).await.unwrap();
 
-        // Message should be gone
+        let found = storage.search_messages("test", &criteria).await.unwrap();
+        let bodies: Vec<_> = found.iter().map(|m| m.body_as_str().unwrap()).collect();
+        assert_eq!(bodies, vec!["c"]);
+
+        // Purge empties the index
+        storage.purge_queue("test").await.unwrap();
+        let found = storage.search_messages("test", &criteria).await.unwrap();
+        assert!(found.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_by_attribute() {
+        let storage = MemoryStorage::new();
+        storage.create_queue(Queue::new("test")).await.unwrap();
+
+        storage
+            .push_message("test", Message::new("a").with_attribute("orderId", "123"))
+            .await
+            .unwrap();
+        storage
+            .push_message("test", Message::new("b").with_attribute("orderId", "456"))
+            .await
+            .unwrap();
+
+        // Move the first message in-flight, it should still be found
+        storage.pop_message("test").await.unwrap().unwrap();
+
+        let criteria = SearchCriteria {
+            attributes: [("orderId".to_string(), "123".to_string())].into(),
+            ..Default::default()
+        };
+        let found = storage.search_messages("test", &criteria).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].body_as_str(), Some("a"));
+
+        // Searching must not consume anything
         let stats = storage.get_queue_stats("test").await.unwrap();
-        assert_eq!(stats.message_count, 0);
+        assert_eq!(stats.message_count, 2);
     }
 
     #[tokio::test]
-    async fn test_nack_message() {
+    async fn test_search_by_body_with_paging() {
         let storage = MemoryStorage::new();
         storage.create_queue(Queue::new("test")).await.unwrap();
 
-        let msg = Message::new("test");
-        storage.push_message("test", msg).await.unwrap();
+        for i in 0..5 {
+            storage
+                .push_message("test", Message::new(format!("needle {}", i)))
+                .await
+                .unwrap();
+        }
+        storage
+            .push_message("test", Message::new("haystack"))
+            .await
+            .unwrap();
 
-        let received = storage.pop_message("test").await.unwrap().unwrap();
-        storage.nack_message("test", &received.id).await.unwrap();
+        let criteria = SearchCriteria {
+            body_contains: Some("needle".to_string()),
+            offset: 1,
+            limit: 2,
+            ..Default::default()
+        };
+        let found = storage.search_messages("test", &criteria).await.unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].body_as_str(), Some("needle 1"));
+        assert_eq!(found[1].body_as_str(), Some("needle 2"));
+    }
 
-        // Message should be back in queue
+    #[tokio::test]
+    async fn test_count_messages() {
+        let config = QueueConfig {
+            indexed_attributes: vec!["status".to_string()],
+            ..Default::default()
+        };
+        let storage = MemoryStorage::new();
+        storage
+            .create_queue(Queue::with_config("test", config))
+            .await
+            .unwrap();
+
+        for status in ["retry", "new", "retry", "new", "retry"] {
+            let message = Message::new("job").with_attribute("status", status);
+            storage.push_message("test", message).await.unwrap();
+        }
+        storage
+            .push_message("test", Message::new("plain"))
+            .await
+            .unwrap();
+
+        // In-flight messages are counted too
+        storage.pop_messages("test", 2).await.unwrap();
+
+        let all = storage
+            .count_messages("test", &SearchCriteria::default())
+            .await
+            .unwrap();
         let stats = storage.get_queue_stats("test").await.unwrap();
-        assert_eq!(stats.pending_count, 1);
+        assert_eq!(all, stats.message_count);
+        assert_eq!(all, 6);
+
+        let retry = SearchCriteria {
+            attributes: [("status".to_string(), "retry".to_string())].into(),
+            limit: 1,
+            ..Default::default()
+        };
+        assert_eq!(storage.count_messages("test", &retry).await.unwrap(), 3);
+
+        let unindexed = SearchCriteria {
+            body_contains: Some("plain".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(storage.count_messages("test", &unindexed).await.unwrap(), 1);
     }
 }
@@ -3,28 +3,86 @@
 //! Defines the interface that all storage backends must implement.
 
 use async_trait::async_trait;
-use flowq_types::{Message, MessageId, Queue, QueueStats, Result};
+use chrono::{DateTime, Utc};
+use flowq_types::{
+    Error, Message, MessageId, MessagePage, MessageStateFilter, PopFilter, Queue, QueueConfig,
+    QueueStats, Result, SearchCriteria,
+};
 
 /// Storage engine trait - all backends implement this
 #[async_trait]
 pub trait StorageEngine: Send + Sync {
+    /// Short name of the backend, e.g. `memory`, for diagnostics
+    fn backend_name(&self) -> &'static str {
+        "unknown"
+    }
+
     // ==================== Queue Operations ====================
 
     /// Create a new queue
     async fn create_queue(&self, queue: Queue) -> Result<Queue>;
 
+    /// Create a queue together with its dead letter queue `dlq`, unless that
+    /// already exists
+    ///
+    /// Fails with `QueueAlreadyExists` if `queue` exists. The default creates
+    /// the dead letter queue first and leaves it in place if creating `queue`
+    /// then fails; backends that can should do both in one step, so a failed
+    /// call creates nothing.
+    async fn create_queue_with_dlq(&self, queue: Queue, dlq: Queue) -> Result<Queue> {
+        match self.create_queue(dlq).await {
+            Ok(_) | Err(Error::QueueAlreadyExists(_)) => {}
+            Err(e) => return Err(e),
+        }
+        self.create_queue(queue).await
+    }
+
     /// Get a queue by name
     async fn get_queue(&self, name: &str) -> Result<Option<Queue>>;
 
     /// List all queues
     async fn list_queues(&self) -> Result<Vec<Queue>>;
 
+    /// Number of queues
+    ///
+    /// The default lists them; backends can override it to count directly.
+    async fn queue_count(&self) -> Result<usize> {
+        Ok(self.list_queues().await?.len())
+    }
+
     /// Delete a queue and all its messages
     async fn delete_queue(&self, name: &str) -> Result<()>;
 
+    /// Rename a queue, keeping its messages, and return the updated queue
+    ///
+    /// Fails with `QueueAlreadyExists` if `new_name` is taken.
+    async fn rename_queue(&self, name: &str, new_name: &str) -> Result<Queue>;
+
     /// Get queue statistics
     async fn get_queue_stats(&self, name: &str) -> Result<QueueStats>;
 
+    /// List all queues together with their statistics
+    ///
+    /// The default looks up each queue's stats separately, skipping queues
+    /// deleted in between; backends can override it to answer in one pass.
+    async fn list_queues_with_stats(&self) -> Result<Vec<(Queue, QueueStats)>> {
+        let mut listed = Vec::new();
+        for queue in self.list_queues().await? {
+            match self.get_queue_stats(&queue.name).await {
+                Ok(stats) => listed.push((queue, stats)),
+                Err(Error::QueueNotFound(_)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(listed)
+    }
+
+    /// Reset rate counters and other cumulative stats without touching messages
+    async fn reset_metrics(&self, name: &str) -> Result<()>;
+
+    /// Replace a queue's whole config, keeping its messages
+    async fn replace_queue_config(&self, name: &str, config: QueueConfig) -> Result<Queue>;
+
     // ==================== Message Operations ====================
 
     /// Store a message in a queue
@@ -36,23 +94,207 @@ pub trait StorageEngine: Send + Sync {
     /// Get multiple messages from a queue
     async fn pop_messages(&self, queue_name: &str, max: usize) -> Result<Vec<Message>>;
 
+    /// Pop up to `max` messages matching `filter`, leaving the rest in place
+    async fn pop_messages_filtered(
+        &self,
+        queue_name: &str,
+        max: usize,
+        filter: &PopFilter,
+    ) -> Result<Vec<Message>>;
+
+    /// Pop up to `max` messages on behalf of a consumer token
+    ///
+    /// With `reclaim`, messages still in flight to the same token (e.g. from
+    /// before a reconnect) are returned first with a fresh visibility deadline,
+    /// counting towards `max`.
+    async fn pop_messages_as(
+        &self,
+        queue_name: &str,
+        max: usize,
+        consumer: &str,
+        reclaim: bool,
+    ) -> Result<Vec<Message>>;
+
     /// Peek at a message without removing it
     async fn peek_message(&self, queue_name: &str) -> Result<Option<Message>>;
 
+    /// Peek at up to `max` pending messages, oldest first, without removing them
+    async fn peek_messages(&self, queue_name: &str, max: usize) -> Result<Vec<Message>>;
+
+    /// Peek at up to `limit` pending messages in arrival order, starting after
+    /// the message whose `sequence` is `cursor` (from the beginning if None)
+    ///
+    /// Sequence numbers stay with a message, so pages neither skip nor repeat
+    /// messages as the head of the queue is consumed.
+    async fn peek_page(
+        &self,
+        queue_name: &str,
+        cursor: Option<u64>,
+        limit: usize,
+    ) -> Result<MessagePage>;
+
     /// Acknowledge a message (mark as processed, remove from queue)
     async fn ack_message(&self, queue_name: &str, message_id: &MessageId) -> Result<()>;
 
     /// Negative acknowledge (return to queue for retry)
     async fn nack_message(&self, queue_name: &str, message_id: &MessageId) -> Result<()>;
 
+    /// Negative acknowledge, requeueing the message with a new priority
+    /// (clamped to 1 through the queue's `max_priority`)
+    async fn nack_with_priority(
+        &self,
+        queue_name: &str,
+        message_id: &MessageId,
+        priority: u8,
+    ) -> Result<()>;
+
+    /// Reset a pending or in-flight message's delivery count so it gets fresh retries
+    async fn reset_delivery_count(&self, queue_name: &str, message_id: &MessageId) -> Result<()>;
+
+    /// Move an in-flight message's visibility deadline to `timeout_secs` from now,
+    /// returning the new deadline
+    async fn change_visibility(
+        &self,
+        queue_name: &str,
+        message_id: &MessageId,
+        timeout_secs: u64,
+    ) -> Result<DateTime<Utc>>;
+
+    /// Move an in-flight message to the queue's dead letter queue
+    async fn move_to_dlq(&self, queue_name: &str, message_id: &MessageId) -> Result<()>;
+
+    /// List messages quarantined after repeated failures
+    async fn list_quarantined(&self, queue_name: &str) -> Result<Vec<Message>>;
+
+    /// List acked messages still inside the queue's `acked_retention_secs`, oldest ack first
+    async fn list_acked(&self, queue_name: &str) -> Result<Vec<Message>>;
+
+    /// Release all quarantined messages back to the queue with a fresh delivery count
+    async fn release_quarantined(&self, queue_name: &str) -> Result<u64>;
+
     /// Get a specific message by ID
-    async fn get_message(&self, queue_name: &str, message_id: &MessageId) -> Result<Option<Message>>;
+    async fn get_message(
+        &self,
+        queue_name: &str,
+        message_id: &MessageId,
+    ) -> Result<Option<Message>>;
+
+    /// Get all pending and in-flight messages in redelivery order without consuming them
+    async fn export_messages(&self, queue_name: &str) -> Result<Vec<Message>>;
+
+    /// Add a snapshotted message back exactly as it was stored, skipping
+    /// publish-time handling: no limits, dedup, attribute TTL or new sequence
+    ///
+    /// Quarantined messages go back into quarantine; the rest become pending.
+    async fn restore_message(&self, queue_name: &str, message: Message) -> Result<()>;
 
     /// Delete all messages from a queue
     async fn purge_queue(&self, queue_name: &str) -> Result<u64>;
 
+    /// Delete pending messages created before `cutoff`, returning how many were removed
+    async fn purge_older_than(&self, queue_name: &str, cutoff: DateTime<Utc>) -> Result<u64>;
+
+    /// Find pending and in-flight messages matching the criteria without consuming them
+    async fn search_messages(
+        &self,
+        queue_name: &str,
+        criteria: &SearchCriteria,
+    ) -> Result<Vec<Message>>;
+
+    /// Count pending and in-flight messages matching the criteria, ignoring
+    /// its `offset` and `limit`
+    async fn count_messages(&self, queue_name: &str, criteria: &SearchCriteria) -> Result<u64>;
+
+    /// List message IDs without bodies, pending first, then in-flight
+    async fn list_message_ids(
+        &self,
+        queue_name: &str,
+        filter: MessageStateFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<MessageId>>;
+
+    // ==================== Durability ====================
+
+    /// Flush buffered writes to durable storage
+    ///
+    /// Backends without durable storage can rely on the default no-op.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
     // ==================== Maintenance ====================
 
-    /// Clean up expired messages
-    async fn cleanup_expired(&self) -> Result<u64>;
+    /// Clean up at most `limit` expired messages (0 = unlimited)
+    ///
+    /// Returns how many were removed; anything left over is picked up by the
+    /// next call, which starts with the queues this one did not reach.
+    async fn cleanup_expired(&self, limit: usize) -> Result<u64>;
+
+    /// Return in-flight messages whose visibility deadline has passed to their
+    /// queues, returning how many were requeued
+    async fn requeue_timed_out(&self) -> Result<u64>;
+
+    /// Return retry-queue messages whose delay has passed to the queues they
+    /// were nacked from, returning how many were moved
+    async fn promote_retries(&self) -> Result<u64>;
+
+    /// Return every in-flight message to its queue, returning how many were requeued
+    ///
+    /// Called on graceful shutdown so another broker sharing the backend can
+    /// deliver them without waiting for their visibility timeouts.
+    async fn requeue_all_inflight(&self) -> Result<u64>;
+
+    /// Move messages in flight longer than their queue's
+    /// `max_inflight_duration_secs`, and pending messages past their
+    /// `deadline`, to the queue's dead letter queue, returning how many were
+    /// moved
+    ///
+    /// Backends that do not support the ceiling or deadlines can rely on the
+    /// default no-op.
+    async fn dead_letter_overdue(&self) -> Result<u64> {
+        Ok(0)
+    }
+
+    /// Names of empty queues idle for longer than their
+    /// `auto_delete_after_idle_secs`
+    ///
+    /// Backends that do not track queue activity can rely on the default,
+    /// which reports none.
+    async fn idle_queues(&self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Delete a queue only if it is still idle, returning whether it was
+    /// deleted
+    ///
+    /// The queue may have been used since `idle_queues` listed it, so the
+    /// check and the delete happen atomically.
+    async fn delete_idle_queue(&self, _name: &str) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Forget deduplication IDs older than their queue's `dedup_window_secs`,
+    /// returning how many were evicted
+    ///
+    /// Backends that do not track dedup IDs can rely on the default no-op.
+    async fn cleanup_dedup(&self) -> Result<u64> {
+        Ok(0)
+    }
+
+    /// Drop acked messages older than their queue's `acked_retention_secs`,
+    /// returning how many were pruned
+    ///
+    /// Backends that do not retain acked messages can rely on the default no-op.
+    async fn prune_acked(&self) -> Result<u64> {
+        Ok(0)
+    }
+
+    /// Release memory held by emptied internal structures, returning how many
+    /// queues were compacted
+    ///
+    /// Backends without in-memory buffers can rely on the default no-op.
+    async fn compact(&self) -> Result<u64> {
+        Ok(0)
+    }
 }
@@ -11,10 +11,18 @@ pub enum Error {
     #[error("Queue not found: {0}")]
     QueueNotFound(String),
 
+    /// Queue was deleted while an operation was waiting on it
+    #[error("Queue deleted: {0}")]
+    QueueDeleted(String),
+
     /// Queue already exists
     #[error("Queue already exists: {0}")]
     QueueAlreadyExists(String),
 
+    /// Broker-wide queue limit reached
+    #[error("Queue limit reached: at most {0} queues allowed")]
+    QueueLimitReached(usize),
+
     /// Message not found
     #[error("Message not found: {0}")]
     MessageNotFound(String),
@@ -31,6 +39,18 @@ pub enum Error {
     #[error("Invalid message: {0}")]
     InvalidMessage(String),
 
+    /// Message body exceeds the queue's per-message limit
+    #[error("Message too large: {0}")]
+    MessageTooLarge(String),
+
+    /// Invalid queue configuration
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    /// Invalid or incompatible snapshot
+    #[error("Snapshot error: {0}")]
+    Snapshot(String),
+
     /// Storage error
     #[error("Storage error: {0}")]
     Storage(String),
@@ -39,6 +59,18 @@ pub enum Error {
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 
+    /// Consumer identity not on the queue's `allowed_consumers` list
+    #[error("Consumer not allowed: {0}")]
+    ConsumerNotAllowed(String),
+
+    /// Mutating operation attempted on a read-only broker
+    #[error("Broker is read-only: {0} is not allowed")]
+    ReadOnly(String),
+
+    /// Storage operation did not finish within the broker's timeout
+    #[error("Operation timed out: {0}")]
+    Timeout(String),
+
     /// Internal error
     #[error("Internal error: {0}")]
     Internal(String),
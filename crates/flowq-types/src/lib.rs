@@ -5,8 +5,19 @@
 pub mod error;
 pub mod message;
 pub mod queue;
+pub mod snapshot;
 
 // Re-export commonly used types
 pub use error::{Error, Result};
-pub use message::{Message, MessageId, MessageStatus};
-pub use queue::{Queue, QueueConfig, QueueId, QueueStats};
+pub use message::{
+    AttributeValue, BodyEncoding, DeadLetterReason, Durability, IdGenerator, Message, MessageId,
+    MessagePage, MessageStateFilter, MessageStatus, PopFilter, QuarantineReason, QueueSample,
+    RandomIdGenerator, ReconcileSnapshot, SearchCriteria, TimeOrderedIdGenerator,
+    BROKER_ATTRIBUTES, DEAD_LETTERED_FROM_ATTRIBUTE, DEFAULT_RESERVED_ATTRIBUTE_PREFIX,
+    DELIVERY_COUNT_ATTRIBUTE, DLQ_OVERRIDE_ATTRIBUTE, DLQ_REASON_ATTRIBUTE, EXPIRED_FROM_ATTRIBUTE,
+    FIRST_DELIVERED_AT_ATTRIBUTE, RETRY_FROM_ATTRIBUTE,
+};
+pub use queue::{
+    DeliveryStrategy, Queue, QueueConfig, QueueId, QueueSelector, QueueStats, RequeuePosition,
+};
+pub use snapshot::{QueueSnapshot, Snapshot, SnapshotMessage, SNAPSHOT_VERSION};
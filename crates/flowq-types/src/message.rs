@@ -6,11 +6,48 @@ use bytes::Bytes;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::Error;
+
+/// Attribute set on expired messages routed to an expiry queue, naming the source queue
+pub const EXPIRED_FROM_ATTRIBUTE: &str = "x-expired-from";
+
+/// Attribute set on nacked messages held in a retry queue, naming the queue they return to
+pub const RETRY_FROM_ATTRIBUTE: &str = "x-retry-from";
+
+/// Attribute set on messages moved to a dead letter queue, naming the source queue
+pub const DEAD_LETTERED_FROM_ATTRIBUTE: &str = "x-dead-lettered-from";
+
+/// Attribute set on messages moved to a dead letter queue, naming why (e.g. `max_retries`)
+pub const DLQ_REASON_ATTRIBUTE: &str = "x-dlq-reason";
+
+/// Attribute naming the dead letter queue to use for this message instead of its queue's
+pub const DLQ_OVERRIDE_ATTRIBUTE: &str = "x-dlq-override";
+
+/// Attribute carrying the current delivery count, with `inject_delivery_attributes`
+pub const DELIVERY_COUNT_ATTRIBUTE: &str = "x-delivery-count";
+
+/// Attribute carrying the first delivery time (RFC 3339), with `inject_delivery_attributes`
+pub const FIRST_DELIVERED_AT_ATTRIBUTE: &str = "x-first-delivered-at";
+
+/// Attributes set by the broker, which producers may not supply
+pub const BROKER_ATTRIBUTES: &[&str] = &[
+    EXPIRED_FROM_ATTRIBUTE,
+    RETRY_FROM_ATTRIBUTE,
+    DEAD_LETTERED_FROM_ATTRIBUTE,
+    DLQ_REASON_ATTRIBUTE,
+    DELIVERY_COUNT_ATTRIBUTE,
+    FIRST_DELIVERED_AT_ATTRIBUTE,
+];
+
+/// Attribute key prefix reserved for broker metadata unless configured otherwise
+pub const DEFAULT_RESERVED_ATTRIBUTE_PREFIX: &str = "x-flowq-";
+
 /// Unique identifier for a message
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema)]
 pub struct MessageId(pub Uuid);
 
 impl MessageId {
@@ -32,11 +69,49 @@ impl std::fmt::Display for MessageId {
     }
 }
 
+/// Source of new message IDs
+pub trait IdGenerator: Send + Sync {
+    /// Generate a new unique message ID
+    fn generate(&self) -> MessageId;
+}
+
+/// Random UUIDv4 IDs (the default)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomIdGenerator;
+
+impl IdGenerator for RandomIdGenerator {
+    fn generate(&self) -> MessageId {
+        MessageId::new()
+    }
+}
+
+/// Time-ordered UUIDv7 IDs, strictly increasing within this generator
+#[derive(Debug, Default)]
+pub struct TimeOrderedIdGenerator {
+    /// Last ID handed out, used to stay monotonic within a millisecond
+    last: Mutex<u128>,
+}
+
+impl IdGenerator for TimeOrderedIdGenerator {
+    fn generate(&self) -> MessageId {
+        let candidate = Uuid::now_v7().as_u128();
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        let next = if candidate > *last {
+            candidate
+        } else {
+            *last + 1
+        };
+        *last = next;
+        MessageId(Uuid::from_u128(next))
+    }
+}
+
 /// Status of a message in the queue
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum MessageStatus {
     /// Message is waiting to be consumed
+    #[default]
     Pending,
     /// Message has been delivered to a consumer (awaiting ack)
     Delivered,
@@ -44,14 +119,130 @@ pub enum MessageStatus {
     Acked,
     /// Message processing failed
     Failed,
+    /// Message was quarantined after repeated failures and is paused from delivery
+    Quarantined,
 }
 
-impl Default for MessageStatus {
-    fn default() -> Self {
-        Self::Pending
+/// Why a message was quarantined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QuarantineReason {
+    /// Delivery count reached the queue's `quarantine_after`
+    Retries,
+    /// Nacked repeatedly within the queue's poison window
+    Poison,
+}
+
+/// Why a message was moved to a dead letter queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeadLetterReason {
+    /// Moved by an operator via the API
+    Manual,
+    /// Delivery count reached the queue's `max_retries`
+    #[serde(rename = "max_retries")]
+    MaxRetries,
+    /// In flight longer than the queue's `max_inflight_duration_secs`
+    #[serde(rename = "inflight_timeout")]
+    InflightTimeout,
+    /// Nacked while marked `no_retry`
+    #[serde(rename = "no_retry")]
+    NoRetry,
+    /// Reached the head of the queue after its `deadline`
+    #[serde(rename = "deadline_missed")]
+    DeadlineMissed,
+}
+
+impl DeadLetterReason {
+    /// The reason as it appears in JSON and in the `x-dlq-reason` attribute
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Manual => "manual",
+            Self::MaxRetries => "max_retries",
+            Self::InflightTimeout => "inflight_timeout",
+            Self::NoRetry => "no_retry",
+            Self::DeadlineMissed => "deadline_missed",
+        }
     }
 }
 
+/// Typed message attribute value
+///
+/// Serialized as `{"type": "number", "value": 42.5}`; binary values are base64 strings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum AttributeValue {
+    /// UTF-8 string
+    String(String),
+    /// Numeric value
+    Number(f64),
+    /// Boolean flag
+    Bool(bool),
+    /// Arbitrary bytes
+    Binary(#[serde(with = "base64_serde")] Vec<u8>),
+}
+
+impl AttributeValue {
+    /// The value as a number, if it is one
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            AttributeValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        AttributeValue::String(value)
+    }
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        AttributeValue::String(value.to_string())
+    }
+}
+
+impl From<f64> for AttributeValue {
+    fn from(value: f64) -> Self {
+        AttributeValue::Number(value)
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> Self {
+        AttributeValue::Bool(value)
+    }
+}
+
+impl From<Vec<u8>> for AttributeValue {
+    fn from(value: Vec<u8>) -> Self {
+        AttributeValue::Binary(value)
+    }
+}
+
+/// Durability guarantee requested when publishing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Durability {
+    /// Return once the backend has accepted the message
+    #[default]
+    None,
+    /// Return only after the backend has flushed the message to durable storage
+    Fsync,
+}
+
+/// How a message body is encoded when rendered as text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyEncoding {
+    /// Body is valid UTF-8 and returned as-is
+    Utf8,
+    /// Body is binary and returned base64-encoded
+    Base64,
+}
+
 /// A message in the queue
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Message {
@@ -67,10 +258,19 @@ pub struct Message {
     pub content_type: Option<String>,
 
     /// Custom attributes/headers
+    ///
+    /// Shared between copies of a message, such as consumer group fan-out;
+    /// changing one copy's attributes (`Arc::make_mut`) clones them for it.
     #[serde(default)]
-    pub attributes: HashMap<String, String>,
+    pub attributes: Arc<HashMap<String, String>>,
+
+    /// Typed attributes, kept alongside the string `attributes` and shared
+    /// the same way
+    #[serde(default)]
+    pub typed_attributes: Arc<HashMap<String, AttributeValue>>,
 
-    /// Message priority (1-10, higher = more important)
+    /// Message priority (1 up to the queue's `max_priority`, default 10;
+    /// higher = more important)
     #[serde(default = "default_priority")]
     pub priority: u8,
 
@@ -82,14 +282,60 @@ pub struct Message {
     #[serde(default)]
     pub delivery_count: u32,
 
+    /// Never redeliver: a nack dead-letters (or fails) the message right away
+    #[serde(default)]
+    pub no_retry: bool,
+
+    /// Arrival order in its current queue, counting from 1 (set on enqueue)
+    #[serde(default)]
+    pub sequence: Option<u64>,
+
     /// When the message was created
     pub created_at: DateTime<Utc>,
 
     /// When the message expires (optional)
     pub expires_at: Option<DateTime<Utc>>,
 
+    /// Latest time the message is worth processing (optional); unlike
+    /// `expires_at`, a missed deadline dead-letters the message instead of
+    /// dropping it. Cleared on dead letter copies.
+    #[serde(default)]
+    pub deadline: Option<DateTime<Utc>>,
+
     /// Deduplication ID (optional)
     pub dedup_id: Option<String>,
+
+    /// CRC32 of the body as 8 hex digits (optional)
+    #[serde(default)]
+    pub checksum: Option<String>,
+
+    /// When a delivered message becomes eligible for redelivery (set on receive)
+    #[serde(default)]
+    pub visible_until: Option<DateTime<Utc>>,
+
+    /// When the current delivery started; unlike `visible_until`, not moved by extensions
+    #[serde(default)]
+    pub delivered_at: Option<DateTime<Utc>>,
+
+    /// When the message was first delivered; kept across requeues
+    #[serde(default)]
+    pub first_delivered_at: Option<DateTime<Utc>>,
+
+    /// Consumer token the message was delivered to (set while in flight)
+    #[serde(default)]
+    pub consumer_token: Option<String>,
+
+    /// Earliest time the message may be delivered (None = immediately)
+    #[serde(default)]
+    pub available_at: Option<DateTime<Utc>>,
+
+    /// Why the message was quarantined (set while quarantined)
+    #[serde(default)]
+    pub quarantine_reason: Option<QuarantineReason>,
+
+    /// Why the message was moved to a dead letter queue (set on DLQ copies)
+    #[serde(default)]
+    pub dead_letter_reason: Option<DeadLetterReason>,
 }
 
 fn default_priority() -> u8 {
@@ -103,14 +349,47 @@ impl Message {
             id: MessageId::new(),
             body: body.into(),
             content_type: None,
-            attributes: HashMap::new(),
+            attributes: Arc::default(),
+            typed_attributes: Arc::default(),
             priority: 5,
             status: MessageStatus::Pending,
             delivery_count: 0,
+            no_retry: false,
+            sequence: None,
             created_at: Utc::now(),
             expires_at: None,
+            deadline: None,
             dedup_id: None,
+            checksum: None,
+            visible_until: None,
+            delivered_at: None,
+            first_delivered_at: None,
+            consumer_token: None,
+            available_at: None,
+            quarantine_reason: None,
+            dead_letter_reason: None,
+        }
+    }
+
+    /// Create a message, rejecting bodies over `max_bytes` (0 = unlimited)
+    /// with `Error::MessageTooLarge`
+    pub fn new_checked(body: impl Into<Bytes>, max_bytes: u64) -> Result<Self, Error> {
+        let message = Self::new(body);
+        message.check_body_size(max_bytes)?;
+        Ok(message)
+    }
+
+    /// Fail with `Error::MessageTooLarge` if the body is over `max_bytes`
+    /// (0 = unlimited)
+    pub fn check_body_size(&self, max_bytes: u64) -> Result<(), Error> {
+        if max_bytes > 0 && self.body.len() as u64 > max_bytes {
+            return Err(Error::MessageTooLarge(format!(
+                "{} bytes exceeds limit of {} bytes",
+                self.body.len(),
+                max_bytes
+            )));
         }
+        Ok(())
     }
 
     /// Create a new message with JSON content
@@ -127,15 +406,33 @@ impl Message {
         self
     }
 
-    /// Set priority (clamped to 1-10)
+    /// Set priority (at least 1)
+    ///
+    /// The queue clamps it to its `max_priority` on publish.
     pub fn with_priority(mut self, priority: u8) -> Self {
-        self.priority = priority.clamp(1, 10);
+        self.priority = priority.max(1);
         self
     }
 
     /// Add an attribute
     pub fn with_attribute(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
-        self.attributes.insert(key.into(), value.into());
+        self.attributes_mut().insert(key.into(), value.into());
+        self
+    }
+
+    /// Mutable access to the attributes, cloning them first if another copy
+    /// of the message shares them
+    pub fn attributes_mut(&mut self) -> &mut HashMap<String, String> {
+        Arc::make_mut(&mut self.attributes)
+    }
+
+    /// Add a typed attribute
+    pub fn with_typed_attribute(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<AttributeValue>,
+    ) -> Self {
+        Arc::make_mut(&mut self.typed_attributes).insert(key.into(), value.into());
         self
     }
 
@@ -145,17 +442,83 @@ impl Message {
         self
     }
 
+    /// Set the processing deadline
+    pub fn with_deadline(mut self, deadline: DateTime<Utc>) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
     /// Set deduplication ID
     pub fn with_dedup_id(mut self, dedup_id: impl Into<String>) -> Self {
         self.dedup_id = Some(dedup_id.into());
         self
     }
 
+    /// Mark the message as never to be redelivered after a nack
+    pub fn with_no_retry(mut self) -> Self {
+        self.no_retry = true;
+        self
+    }
+
+    /// Set the expected body checksum (CRC32 as hex)
+    pub fn with_checksum(mut self, checksum: impl Into<String>) -> Self {
+        self.checksum = Some(checksum.into());
+        self
+    }
+
+    /// Key for consumers to deduplicate side effects by
+    ///
+    /// The same across every redelivery of the message, so a consumer that
+    /// stores the tokens it has processed can skip a redelivered message
+    /// whose ack was lost. Currently the message ID.
+    pub fn processing_token(&self) -> String {
+        self.id.to_string()
+    }
+
+    /// Dead letter queue named by the message's `x-dlq-override` attribute, if any
+    pub fn dead_letter_override(&self) -> Option<&str> {
+        self.attributes
+            .get(DLQ_OVERRIDE_ATTRIBUTE)
+            .map(String::as_str)
+            .filter(|dlq| !dlq.is_empty())
+    }
+
+    /// CRC32 of the body as 8 lowercase hex digits
+    pub fn compute_checksum(&self) -> String {
+        format!("{:08x}", crc32fast::hash(&self.body))
+    }
+
+    /// Check the body against `checksum`; true when no checksum is set
+    pub fn verify_checksum(&self) -> bool {
+        match &self.checksum {
+            Some(checksum) => checksum.eq_ignore_ascii_case(&self.compute_checksum()),
+            None => true,
+        }
+    }
+
+    /// Priority adjusted for time spent waiting
+    ///
+    /// Adds one level per `aging_secs` since creation, capped at
+    /// `max_priority` (or the message priority, if higher). With
+    /// `aging_secs == 0` this is just the message priority.
+    pub fn effective_priority(&self, aging_secs: u64, max_priority: u8, now: DateTime<Utc>) -> u8 {
+        if aging_secs == 0 {
+            return self.priority;
+        }
+        let age_secs = (now - self.created_at).num_seconds().max(0) as u64;
+        let boost = age_secs / aging_secs;
+        let cap = max_priority.max(self.priority);
+        (u64::from(self.priority) + boost).min(u64::from(cap)) as u8
+    }
+
     /// Check if the message has expired
     pub fn is_expired(&self) -> bool {
-        self.expires_at
-            .map(|exp| Utc::now() > exp)
-            .unwrap_or(false)
+        self.is_expired_at(Utc::now())
+    }
+
+    /// Check if the message has expired as of `now`
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|exp| now > exp)
     }
 
     /// Get the body as a string (if valid UTF-8)
@@ -163,12 +526,140 @@ impl Message {
         std::str::from_utf8(&self.body).ok()
     }
 
+    /// Get the body as text, base64-encoding it if it is not valid UTF-8
+    pub fn encoded_body(&self) -> (String, BodyEncoding) {
+        match self.body_as_str() {
+            Some(s) => (s.to_string(), BodyEncoding::Utf8),
+            None => {
+                use base64::Engine;
+                let encoded = base64::engine::general_purpose::STANDARD.encode(&self.body);
+                (encoded, BodyEncoding::Base64)
+            }
+        }
+    }
+
     /// Deserialize the body as JSON
     pub fn body_as_json<T: for<'de> Deserialize<'de>>(&self) -> Result<T, serde_json::Error> {
         serde_json::from_slice(&self.body)
     }
 }
 
+/// Which messages a message-id listing covers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageStateFilter {
+    /// Messages waiting to be delivered
+    Pending,
+    /// Messages delivered but not yet acked
+    InFlight,
+    /// Pending followed by in-flight messages
+    #[default]
+    All,
+}
+
+/// A page of pending messages from a cursor-based peek
+#[derive(Debug, Clone, Default)]
+pub struct MessagePage {
+    /// Pending messages in arrival (`sequence`) order
+    pub messages: Vec<Message>,
+    /// Cursor for the following page, or None after the last page
+    pub next_cursor: Option<u64>,
+}
+
+/// Counts and sample message IDs from one queue of a reconciliation
+#[derive(Debug, Clone, Default)]
+pub struct QueueSample {
+    /// Messages waiting to be delivered
+    pub pending: u64,
+    /// Messages delivered but not yet acked
+    pub in_flight: u64,
+    /// Up to the requested number of IDs, pending before in-flight
+    pub sample_ids: Vec<MessageId>,
+}
+
+/// A reconciliation snapshot of a queue and its dead letter queue
+#[derive(Debug, Clone, Default)]
+pub struct ReconcileSnapshot {
+    /// The queue itself
+    pub main: QueueSample,
+    /// Its dead letter queue
+    pub dlq: QueueSample,
+}
+
+/// Maximum number of results a single search may return
+pub const MAX_SEARCH_RESULTS: usize = 100;
+
+/// Criteria for searching messages without consuming them
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct SearchCriteria {
+    /// Attributes that must all match exactly
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
+
+    /// Substring that must appear in the (UTF-8) body
+    #[serde(default)]
+    pub body_contains: Option<String>,
+
+    /// Number of matching messages to skip
+    #[serde(default)]
+    pub offset: usize,
+
+    /// Maximum number of results (capped at `MAX_SEARCH_RESULTS`, 0 = cap)
+    #[serde(default)]
+    pub limit: usize,
+}
+
+impl SearchCriteria {
+    /// Check whether a message matches these criteria
+    pub fn matches(&self, message: &Message) -> bool {
+        let attrs_match = self
+            .attributes
+            .iter()
+            .all(|(k, v)| message.attributes.get(k) == Some(v));
+
+        let body_match = match &self.body_contains {
+            Some(needle) => message
+                .body_as_str()
+                .map(|body| body.contains(needle.as_str()))
+                .unwrap_or(false),
+            None => true,
+        };
+
+        attrs_match && body_match
+    }
+
+    /// Effective result limit after applying the cap
+    pub fn effective_limit(&self) -> usize {
+        if self.limit == 0 {
+            MAX_SEARCH_RESULTS
+        } else {
+            self.limit.min(MAX_SEARCH_RESULTS)
+        }
+    }
+}
+
+/// Which pending messages a receive may take
+///
+/// The default takes any available message.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PopFilter {
+    /// Skip messages below this priority
+    pub min_priority: u8,
+    /// Skip messages created at or after this time
+    pub published_before: Option<DateTime<Utc>>,
+}
+
+impl PopFilter {
+    /// Check whether a message may be taken
+    pub fn matches(&self, message: &Message) -> bool {
+        let published_in_time = match self.published_before {
+            Some(cutoff) => message.created_at < cutoff,
+            None => true,
+        };
+        message.priority >= self.min_priority && published_in_time
+    }
+}
+
 /// Custom serialization for Bytes (as base64 or raw)
 mod bytes_serde {
     use bytes::Bytes;
@@ -197,10 +688,49 @@ mod bytes_serde {
     }
 }
 
+/// Base64 serialization for binary attribute values
+mod base64_serde {
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        base64::engine::general_purpose::STANDARD
+            .encode(bytes)
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        base64::engine::general_purpose::STANDARD
+            .decode(s)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_new_checked_body_size() {
+        let message = Message::new_checked("hello", 5).unwrap();
+        assert_eq!(message.body_as_str(), Some("hello"));
+        assert!(Message::new_checked(vec![0u8; 1024], 0).is_ok());
+
+        let err = Message::new_checked("hello!", 5).unwrap_err();
+        assert!(matches!(err, Error::MessageTooLarge(_)));
+        assert_eq!(
+            err.to_string(),
+            "Message too large: 6 bytes exceeds limit of 5 bytes"
+        );
+    }
+
     #[test]
     fn test_message_creation() {
         let msg = Message::new("Hello, World!");
@@ -240,4 +770,125 @@ mod tests {
         let parsed: TestData = msg.body_as_json().unwrap();
         assert_eq!(parsed, data);
     }
+
+    #[test]
+    fn test_time_ordered_ids_increase() {
+        let generator = TimeOrderedIdGenerator::default();
+
+        let ids: Vec<MessageId> = (0..1000).map(|_| generator.generate()).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids.iter().all(|id| id.0.get_version_num() == 7));
+    }
+
+    #[test]
+    fn test_effective_priority_aging() {
+        let now = Utc::now();
+        let mut msg = Message::new("old").with_priority(1);
+        msg.created_at = now - chrono::Duration::seconds(35);
+
+        assert_eq!(msg.effective_priority(0, 10, now), 1);
+        assert_eq!(msg.effective_priority(10, 10, now), 4);
+        assert_eq!(msg.effective_priority(1, 10, now), 10);
+        assert_eq!(msg.effective_priority(1, 100, now), 36);
+    }
+
+    #[test]
+    fn test_encoded_body() {
+        let msg = Message::new("plain text");
+        assert_eq!(
+            msg.encoded_body(),
+            ("plain text".to_string(), BodyEncoding::Utf8)
+        );
+
+        let binary = vec![0xff, 0xfe, 0x00, 0x80];
+        let msg = Message::new(binary.clone());
+        let (body, encoding) = msg.encoded_body();
+        assert_eq!(encoding, BodyEncoding::Base64);
+
+        use base64::Engine;
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(body)
+            .unwrap();
+        assert_eq!(decoded, binary);
+    }
+
+    #[test]
+    fn test_checksum() {
+        let msg = Message::new("123456789");
+        assert_eq!(msg.compute_checksum(), "cbf43926");
+        assert!(msg.verify_checksum());
+
+        assert!(msg.clone().with_checksum("CBF43926").verify_checksum());
+        assert!(!msg.with_checksum("00000000").verify_checksum());
+    }
+
+    #[test]
+    fn test_typed_attributes_round_trip() {
+        let msg = Message::new("typed")
+            .with_attribute("region", "eu")
+            .with_typed_attribute("name", "order")
+            .with_typed_attribute("amount", 42.5)
+            .with_typed_attribute("urgent", true)
+            .with_typed_attribute("sig", vec![0u8, 159, 255]);
+
+        let json = serde_json::to_value(&msg).unwrap();
+        let typed = &json["typed_attributes"];
+        assert_eq!(
+            typed["name"],
+            serde_json::json!({"type": "string", "value": "order"})
+        );
+        assert_eq!(
+            typed["amount"],
+            serde_json::json!({"type": "number", "value": 42.5})
+        );
+        assert_eq!(
+            typed["urgent"],
+            serde_json::json!({"type": "bool", "value": true})
+        );
+        assert_eq!(
+            typed["sig"],
+            serde_json::json!({"type": "binary", "value": "AJ//"})
+        );
+        assert_eq!(json["attributes"]["region"], "eu");
+
+        let decoded: Message = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded.typed_attributes, msg.typed_attributes);
+        assert_eq!(decoded.typed_attributes["amount"].as_number(), Some(42.5));
+        assert_eq!(decoded.attributes, msg.attributes);
+
+        // Messages serialized before typed attributes existed still load
+        let legacy = serde_json::json!({
+            "id": msg.id,
+            "body": "old",
+            "content_type": null,
+            "created_at": msg.created_at,
+            "expires_at": null,
+            "dedup_id": null,
+        });
+        let legacy: Message = serde_json::from_value(legacy).unwrap();
+        assert!(legacy.typed_attributes.is_empty());
+    }
+
+    #[test]
+    fn test_search_criteria_matches() {
+        let msg = Message::new("order shipped").with_attribute("orderId", "123");
+
+        let by_attr = SearchCriteria {
+            attributes: HashMap::from([("orderId".to_string(), "123".to_string())]),
+            ..Default::default()
+        };
+        assert!(by_attr.matches(&msg));
+
+        let by_body = SearchCriteria {
+            body_contains: Some("shipped".to_string()),
+            ..Default::default()
+        };
+        assert!(by_body.matches(&msg));
+
+        let miss = SearchCriteria {
+            body_contains: Some("cancelled".to_string()),
+            ..Default::default()
+        };
+        assert!(!miss.matches(&msg));
+    }
 }
@@ -7,6 +7,8 @@ use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::error::{Error, Result};
+
 /// Unique identifier for a queue
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
 pub struct QueueId(pub Uuid);
@@ -30,8 +32,34 @@ impl std::fmt::Display for QueueId {
     }
 }
 
+/// Where a nacked message is placed when it returns to the queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RequeuePosition {
+    /// Head of the queue, redelivered next
+    Front,
+    /// Tail of the queue, avoids head-of-line blocking
+    #[default]
+    Back,
+}
+
+/// How the next message to deliver is chosen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStrategy {
+    /// Highest (aged) priority first, oldest first within a priority
+    #[default]
+    Priority,
+    /// Earliest `expires_at` first, so messages are handled before they
+    /// expire; messages without expiry come last, in priority order
+    EarliestExpiry,
+}
+
 /// Queue configuration
+///
+/// The OpenAPI schema documents each field's default, taken from `Default`.
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[schema(default)]
 pub struct QueueConfig {
     /// Maximum number of messages in the queue (0 = unlimited)
     #[serde(default)]
@@ -41,6 +69,10 @@ pub struct QueueConfig {
     #[serde(default)]
     pub max_size_bytes: u64,
 
+    /// Maximum size of a single message body in bytes (0 = unlimited)
+    #[serde(default)]
+    pub max_message_bytes: u64,
+
     /// Default message TTL in seconds (0 = no expiry)
     #[serde(default)]
     pub message_ttl_secs: u64,
@@ -56,6 +88,18 @@ pub struct QueueConfig {
     /// Dead letter queue name (optional)
     pub dead_letter_queue: Option<String>,
 
+    /// Queue that receives expired messages instead of dropping them (optional)
+    #[serde(default)]
+    pub expiry_queue: Option<String>,
+
+    /// Holding queue for nacked messages; they return here after `retry_delay_secs`
+    #[serde(default)]
+    pub retry_queue: Option<String>,
+
+    /// Seconds a nacked message waits in the retry queue before returning
+    #[serde(default)]
+    pub retry_delay_secs: u64,
+
     /// Enable deduplication
     #[serde(default)]
     pub dedup_enabled: bool,
@@ -63,6 +107,94 @@ pub struct QueueConfig {
     /// Deduplication window in seconds
     #[serde(default = "default_dedup_window")]
     pub dedup_window_secs: u64,
+
+    /// Where nacked messages are requeued
+    #[serde(default)]
+    pub nack_requeue_position: RequeuePosition,
+
+    /// Quarantine messages on nack once their delivery count reaches this (0 = disabled)
+    #[serde(default)]
+    pub quarantine_after: u32,
+    /// Quarantine a message as poison once it is nacked this many times
+    /// within `poison_window_secs` (0 = disabled)
+    #[serde(default)]
+    pub poison_threshold: u32,
+    /// Sliding window in seconds for counting nacks towards `poison_threshold`
+    #[serde(default = "default_poison_window")]
+    pub poison_window_secs: u64,
+    /// Attribute keys indexed for fast attribute search
+    #[serde(default)]
+    pub indexed_attributes: Vec<String>,
+    /// Seconds of waiting per +1 effective priority when selecting the next message (0 = off)
+    #[serde(default)]
+    pub priority_aging_secs: u64,
+    /// Delivery count at which a message counts towards `high_attempt_count` (0 = off)
+    #[serde(default)]
+    pub high_attempt_threshold: u32,
+    /// Reject message bodies that are not valid UTF-8
+    #[serde(default)]
+    pub require_utf8: bool,
+    /// Seconds acked messages stay queryable before being pruned (0 = drop on ack)
+    #[serde(default)]
+    pub acked_retention_secs: u64,
+    /// Store a CRC32 checksum of each body on publish
+    #[serde(default)]
+    pub compute_checksum: bool,
+    /// Dead-letter messages in flight longer than this since their first
+    /// delivery, however often their visibility is extended or they are
+    /// redelivered (0 = no limit, requires `dead_letter_queue`)
+    #[serde(default)]
+    pub max_inflight_duration_secs: u64,
+    /// Delay requeued messages by a random 0..=N seconds so mass timeouts
+    /// do not all become visible at once (0 = off)
+    #[serde(default)]
+    pub requeue_jitter_secs: u64,
+    /// Serialize pops so each batch receives consecutive messages in queue order
+    ///
+    /// Concurrent receivers on the queue wait for each other, so throughput
+    /// drops to that of a single consumer.
+    #[serde(default)]
+    pub strict_ordering: bool,
+    /// Set `x-delivery-count` and `x-first-delivered-at` attributes on each delivery
+    #[serde(default)]
+    pub inject_delivery_attributes: bool,
+    /// URL messages are POSTed to as they arrive; a 2xx response acks, anything
+    /// else nacks (optional)
+    #[serde(default)]
+    pub delivery_webhook: Option<String>,
+    /// Most webhook requests in flight at once for this queue
+    #[serde(default = "default_webhook_concurrency")]
+    pub delivery_webhook_concurrency: u32,
+    /// This queue's share of the server-wide webhook delivery pool relative
+    /// to other webhook queues; a queue with weight 3 gets three deliveries
+    /// for each one of a weight-1 queue while both have messages waiting
+    #[serde(default = "default_webhook_weight")]
+    pub delivery_webhook_weight: u32,
+    /// Delete the queue once it has been empty, with no publishes or receives,
+    /// for this many seconds (0 = never)
+    #[serde(default)]
+    pub auto_delete_after_idle_secs: u64,
+    /// How the next message to deliver is chosen
+    #[serde(default)]
+    pub delivery_strategy: DeliveryStrategy,
+    /// Slots under `max_messages` held back for high-priority messages; lower
+    /// priorities are rejected once only this many remain (0 = off)
+    #[serde(default)]
+    pub high_priority_reserved: u64,
+    /// Lowest priority allowed into the `high_priority_reserved` slots
+    #[serde(default = "default_high_priority_threshold")]
+    pub high_priority_threshold: u8,
+    /// Highest message priority; higher priorities are clamped to it on publish
+    #[serde(default = "default_max_priority")]
+    pub max_priority: u8,
+    /// Attribute whose value, in seconds, sets a published message's expiry
+    /// instead of the queue TTL, e.g. `x-ttl-secs` (0 = no expiry)
+    #[serde(default)]
+    pub attribute_ttl_key: Option<String>,
+    /// Consumer identities, as established by authentication, allowed to
+    /// receive from the queue (empty = any consumer)
+    #[serde(default)]
+    pub allowed_consumers: Vec<String>,
 }
 
 fn default_visibility_timeout() -> u64 {
@@ -77,19 +209,169 @@ fn default_dedup_window() -> u64 {
     300 // 5 minutes
 }
 
+fn default_poison_window() -> u64 {
+    60 // 1 minute
+}
+
+fn default_webhook_concurrency() -> u32 {
+    4
+}
+
+fn default_webhook_weight() -> u32 {
+    1
+}
+
+fn default_high_priority_threshold() -> u8 {
+    8
+}
+
+fn default_max_priority() -> u8 {
+    10
+}
+
 impl Default for QueueConfig {
     fn default() -> Self {
         Self {
             max_messages: 0,
             max_size_bytes: 0,
+            max_message_bytes: 0,
             message_ttl_secs: 0,
             visibility_timeout_secs: default_visibility_timeout(),
             max_retries: default_max_retries(),
             dead_letter_queue: None,
+            expiry_queue: None,
+            retry_queue: None,
+            retry_delay_secs: 0,
             dedup_enabled: false,
             dedup_window_secs: default_dedup_window(),
+            nack_requeue_position: RequeuePosition::default(),
+            quarantine_after: 0,
+            poison_threshold: 0,
+            poison_window_secs: default_poison_window(),
+            indexed_attributes: Vec::new(),
+            priority_aging_secs: 0,
+            high_attempt_threshold: 0,
+            require_utf8: false,
+            acked_retention_secs: 0,
+            compute_checksum: false,
+            max_inflight_duration_secs: 0,
+            requeue_jitter_secs: 0,
+            strict_ordering: false,
+            inject_delivery_attributes: false,
+            delivery_webhook: None,
+            delivery_webhook_concurrency: default_webhook_concurrency(),
+            delivery_webhook_weight: default_webhook_weight(),
+            auto_delete_after_idle_secs: 0,
+            delivery_strategy: DeliveryStrategy::default(),
+            high_priority_reserved: 0,
+            high_priority_threshold: default_high_priority_threshold(),
+            max_priority: default_max_priority(),
+            attribute_ttl_key: None,
+            allowed_consumers: Vec::new(),
+        }
+    }
+}
+
+impl QueueConfig {
+    /// Whether a consumer, identified or not, may receive from the queue
+    pub fn allows_consumer(&self, identity: Option<&str>) -> bool {
+        self.allowed_consumers.is_empty()
+            || identity.is_some_and(|identity| self.allowed_consumers.iter().any(|c| c == identity))
+    }
+
+    /// Check that the configuration is internally consistent
+    pub fn validate(&self) -> Result<()> {
+        if self.max_message_bytes > 0
+            && self.max_size_bytes > 0
+            && self.max_message_bytes > self.max_size_bytes
+        {
+            return Err(Error::InvalidConfig(format!(
+                "max_message_bytes ({}) exceeds max_size_bytes ({})",
+                self.max_message_bytes, self.max_size_bytes
+            )));
+        }
+        if self.poison_threshold > 0 && self.poison_window_secs == 0 {
+            return Err(Error::InvalidConfig(
+                "poison_threshold requires a non-zero poison_window_secs".to_string(),
+            ));
+        }
+        if self.max_inflight_duration_secs > 0 && self.dead_letter_queue.is_none() {
+            return Err(Error::InvalidConfig(
+                "max_inflight_duration_secs requires a dead_letter_queue".to_string(),
+            ));
+        }
+        if self.high_priority_reserved > 0 && self.high_priority_reserved >= self.max_messages {
+            return Err(Error::InvalidConfig(format!(
+                "high_priority_reserved ({}) must be below a non-zero max_messages ({})",
+                self.high_priority_reserved, self.max_messages
+            )));
+        }
+        if self.max_priority == 0 {
+            return Err(Error::InvalidConfig(
+                "max_priority must be at least 1".to_string(),
+            ));
+        }
+        if self.attribute_ttl_key.as_deref() == Some("") {
+            return Err(Error::InvalidConfig(
+                "attribute_ttl_key must not be empty".to_string(),
+            ));
+        }
+        if self.allowed_consumers.iter().any(String::is_empty) {
+            return Err(Error::InvalidConfig(
+                "allowed_consumers must not contain empty identities".to_string(),
+            ));
         }
+        if let Some(url) = &self.delivery_webhook {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(Error::InvalidConfig(format!(
+                    "delivery_webhook must be an http(s) URL, got {}",
+                    url
+                )));
+            }
+            if self.delivery_webhook_concurrency == 0 {
+                return Err(Error::InvalidConfig(
+                    "delivery_webhook_concurrency must be at least 1".to_string(),
+                ));
+            }
+            if self.delivery_webhook_weight == 0 {
+                return Err(Error::InvalidConfig(
+                    "delivery_webhook_weight must be at least 1".to_string(),
+                ));
+            }
+        }
+        Ok(())
     }
+
+    /// Apply a partial JSON config on top of this one
+    ///
+    /// Fields present in `overrides` replace the corresponding fields; the
+    /// rest keep their current values.
+    pub fn merged(&self, overrides: &serde_json::Value) -> Result<QueueConfig> {
+        let serde_json::Value::Object(overrides) = overrides else {
+            return Err(Error::InvalidConfig(
+                "config overrides must be a JSON object".to_string(),
+            ));
+        };
+
+        let mut merged =
+            serde_json::to_value(self).map_err(|e| Error::InvalidConfig(e.to_string()))?;
+        if let Some(fields) = merged.as_object_mut() {
+            for (key, value) in overrides {
+                fields.insert(key.clone(), value.clone());
+            }
+        }
+        serde_json::from_value(merged)
+            .map_err(|e| Error::InvalidConfig(format!("Invalid config overrides: {}", e)))
+    }
+}
+
+/// Queues targeted by a bulk operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueSelector {
+    /// Exactly these queue names
+    Names(Vec<String>),
+    /// Every queue whose name starts with this prefix
+    Prefix(String),
 }
 
 /// Queue metadata and state
@@ -149,6 +431,14 @@ pub struct QueueStats {
     /// Number of messages being processed
     pub in_flight_count: u64,
 
+    /// Number of quarantined messages (not counted in `message_count`)
+    #[serde(default)]
+    pub quarantined_count: u64,
+
+    /// Pending and in-flight messages at or above the queue's `high_attempt_threshold`
+    #[serde(default)]
+    pub high_attempt_count: u64,
+
     /// Total size of all messages in bytes
     pub size_bytes: u64,
 
@@ -160,6 +450,10 @@ pub struct QueueStats {
 
     /// Messages consumed per second (recent average)
     pub consume_rate: f64,
+
+    /// Pending messages in the configured dead letter queue, if it exists
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dlq_pending: Option<u64>,
 }
 
 #[cfg(test)]
@@ -172,6 +466,28 @@ mod tests {
         assert_eq!(queue.name, "test-queue");
         assert_eq!(queue.config.visibility_timeout_secs, 30);
         assert_eq!(queue.config.max_retries, 5);
+        assert_eq!(queue.config.nack_requeue_position, RequeuePosition::Back);
+    }
+
+    #[test]
+    fn test_config_merged() {
+        let base = QueueConfig {
+            max_retries: 9,
+            visibility_timeout_secs: 120,
+            ..Default::default()
+        };
+
+        let merged = base
+            .merged(&serde_json::json!({"max_retries": 2, "dead_letter_queue": "dlq"}))
+            .unwrap();
+        assert_eq!(merged.max_retries, 2);
+        assert_eq!(merged.dead_letter_queue.as_deref(), Some("dlq"));
+        assert_eq!(merged.visibility_timeout_secs, 120);
+
+        assert!(base
+            .merged(&serde_json::json!({"max_retries": "many"}))
+            .is_err());
+        assert!(base.merged(&serde_json::json!([1, 2])).is_err());
     }
 
     #[test]
@@ -185,4 +501,89 @@ mod tests {
         assert_eq!(queue.config.max_messages, 1000);
         assert_eq!(queue.config.message_ttl_secs, 3600);
     }
+
+    #[test]
+    fn test_allows_consumer() {
+        let open = QueueConfig::default();
+        assert!(open.allows_consumer(None));
+        assert!(open.allows_consumer(Some("anyone")));
+
+        let restricted = QueueConfig {
+            allowed_consumers: vec!["billing".to_string()],
+            ..Default::default()
+        };
+        assert!(restricted.allows_consumer(Some("billing")));
+        assert!(!restricted.allows_consumer(Some("reporting")));
+        assert!(!restricted.allows_consumer(None));
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(QueueConfig::default().validate().is_ok());
+
+        let config = QueueConfig {
+            max_size_bytes: 1024,
+            max_message_bytes: 2048,
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(Error::InvalidConfig(_))));
+
+        let config = QueueConfig {
+            max_size_bytes: 4096,
+            max_message_bytes: 2048,
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let config = QueueConfig {
+            poison_threshold: 3,
+            poison_window_secs: 0,
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(Error::InvalidConfig(_))));
+
+        let config = QueueConfig {
+            delivery_webhook: Some("ftp://example.com/hook".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(Error::InvalidConfig(_))));
+
+        let config = QueueConfig {
+            delivery_webhook: Some("https://example.com/hook".to_string()),
+            delivery_webhook_weight: 0,
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(Error::InvalidConfig(_))));
+
+        // A reservation needs a bounded queue with room for other messages
+        for max_messages in [0, 10] {
+            let config = QueueConfig {
+                max_messages,
+                high_priority_reserved: 10,
+                ..Default::default()
+            };
+            assert!(matches!(config.validate(), Err(Error::InvalidConfig(_))));
+        }
+
+        let config = QueueConfig {
+            max_priority: 0,
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_stats_from_older_servers() {
+        // Written before quarantine and attempt counts were reported
+        let json = r#"{
+            "message_count": 3, "pending_count": 2, "in_flight_count": 1,
+            "size_bytes": 30, "consumer_count": 1,
+            "publish_rate": 0.5, "consume_rate": 0.25
+        }"#;
+        let stats: QueueStats = serde_json::from_str(json).unwrap();
+        assert_eq!(stats.message_count, 3);
+        assert_eq!(stats.quarantined_count, 0);
+        assert_eq!(stats.high_attempt_count, 0);
+        assert_eq!(stats.dlq_pending, None);
+    }
 }